@@ -0,0 +1,37 @@
+use dashboard::{probe, view::ShopView};
+use ice_cream_shop::{
+    messages::auth::{Keyring, Role},
+    shop_values::N_ROBOTS,
+    trace::trace_context::now_us,
+};
+use std::{
+    env,
+    error::Error,
+    io::{self, Write},
+    thread,
+    time::Duration,
+};
+
+/// How often the dashboard asks the robots for their state, unless told otherwise.
+const REFRESH: Duration = Duration::from_secs(1);
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let refresh = match env::args().nth(1) {
+        Some(ms) => Duration::from_millis(
+            ms.parse()
+                .map_err(|_| "Use: shop-dashboard [refresh_in_ms]")?,
+        ),
+        None => REFRESH,
+    };
+    let keyring = Keyring::from_env(Role::Admin)?;
+
+    loop {
+        let states = (0..N_ROBOTS).map(|id| (id, probe::robot_state(id, keyring.as_ref())));
+        let view = ShopView::new(states.collect::<Vec<_>>(), now_us());
+
+        // Moves the cursor home and clears the screen before drawing the new frame.
+        print!("\x1b[H\x1b[2J{view}");
+        io::stdout().flush()?;
+        thread::sleep(refresh);
+    }
+}
@@ -0,0 +1,41 @@
+use ice_cream_shop::{
+    endpoint::Endpoint,
+    id_to_addr,
+    messages::{
+        admin_msg::{AdminMsg, RobotState},
+        auth::{open, seal_request, Keyring},
+        fmt_msg,
+    },
+    shop_values::ROBOT_ADMIN_STARTING_PORT,
+};
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+/// How long the dashboard waits for a robot to connect and answer.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Asks a robot for its state through its admin endpoint.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the robot.
+/// * `keyring` - The keyring of the operator, if the shop has a key.
+///
+/// # Returns
+///
+/// The state of the robot, or `None` if it couldn't be reached or didn't answer in time.
+pub fn robot_state(id: u16, keyring: Option<&Keyring>) -> Option<RobotState> {
+    let addr: SocketAddr = id_to_addr(ROBOT_ADMIN_STARTING_PORT, id).parse().ok()?;
+    let mut stream = TcpStream::connect_timeout(&addr, PROBE_TIMEOUT).ok()?;
+    stream.set_read_timeout(Some(PROBE_TIMEOUT)).ok()?;
+    let endpoint = Endpoint::RobotAdmin(id);
+    let (command, answers) = seal_request(keyring, fmt_msg(AdminMsg::State), endpoint);
+    stream.write_all(&command).ok()?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).ok()?;
+    open(keyring, line.as_bytes(), answers).ok()
+}
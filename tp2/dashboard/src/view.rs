@@ -0,0 +1,336 @@
+use ice_cream_shop::{
+    flavour::Flavour,
+    messages::admin_msg::{EndedOrder, RobotState},
+    shop_values::STARTING_ICECREAM,
+    stock::{StockLevel, StockSummary},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+/// How many of the last orders the robots ended are shown.
+const RECENT_ORDERS: usize = 8;
+
+/// How many characters wide the stock bars are.
+const BAR_WIDTH: usize = 20;
+
+/// What the dashboard shows of the shop, put together from the states of the robots.
+/// - `robots`: The state of every robot that answered, by ID.
+/// - `unreachable`: The robots that didn't answer.
+/// - `now_us`: When the states were taken, in microseconds since the unix epoch.
+#[derive(Debug, Clone)]
+pub struct ShopView {
+    robots: BTreeMap<u16, RobotState>,
+    unreachable: BTreeSet<u16>,
+    now_us: u64,
+}
+
+impl ShopView {
+    /// Puts together the view of the shop.
+    ///
+    /// # Arguments
+    ///
+    /// * `states` - The state of every robot, or `None` if it didn't answer.
+    /// * `now_us` - When the states were taken, in microseconds since the unix epoch.
+    pub fn new(states: impl IntoIterator<Item = (u16, Option<RobotState>)>, now_us: u64) -> Self {
+        let mut robots = BTreeMap::new();
+        let mut unreachable = BTreeSet::new();
+        for (id, state) in states {
+            match state {
+                Some(state) => {
+                    robots.insert(id, state);
+                }
+                None => {
+                    unreachable.insert(id);
+                }
+            }
+        }
+
+        Self {
+            robots,
+            unreachable,
+            now_us,
+        }
+    }
+
+    /// Returns the robots of the ring in the order the tokens go around it, starting from
+    /// the robot with the lowest ID, as seen by the robots that answered.
+    pub fn ring(&self) -> Vec<u16> {
+        let mut ring = Vec::new();
+        let mut next = self.robots.keys().next().copied();
+
+        while let Some(id) = next.filter(|id| !ring.contains(id)) {
+            ring.push(id);
+            next = self.robots.get(&id).and_then(|state| state.next_id);
+        }
+
+        ring
+    }
+
+    /// Returns the robot that last received the order token, which is the one holding it or
+    /// the one that just passed it on.
+    pub fn order_token_holder(&self) -> Option<u16> {
+        self.robots
+            .values()
+            .filter_map(|state| Some((state.order_round?, state.id)))
+            .max()
+            .map(|(_, id)| id)
+    }
+
+    /// Returns how many orders were waiting in the order token the last time it passed by.
+    pub fn queued(&self) -> Option<usize> {
+        let holder = self.order_token_holder()?;
+        self.robots.get(&holder).map(|state| state.queued)
+    }
+
+    /// Returns the newest stock of every flavour any robot saw.
+    pub fn stock(&self) -> StockSummary {
+        let mut stock = StockSummary::new();
+        for (flavour, level) in self.robots.values().flat_map(|state| &state.stock) {
+            let known = stock.entry(*flavour).or_insert(*level);
+            if level.round > known.round {
+                *known = *level;
+            }
+        }
+
+        stock
+    }
+
+    /// Returns the last orders the robots ended, the newest last, with the robot that ended
+    /// each one.
+    pub fn recent_orders(&self) -> Vec<(u16, EndedOrder)> {
+        let mut recent: Vec<_> = self
+            .robots
+            .values()
+            .flat_map(|state| state.recent_orders.iter().map(|ended| (state.id, *ended)))
+            .collect();
+
+        recent.sort_by_key(|(_, ended)| ended.at_us);
+        let skip = recent.len().saturating_sub(RECENT_ORDERS);
+        recent.split_off(skip)
+    }
+
+    /// Writes the ring, with the robot that has the order token between brackets and the
+    /// robots that didn't answer followed by a question mark.
+    fn fmt_ring(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ring = self.ring();
+        let holder = self.order_token_holder();
+        let mut robots: Vec<_> = ring
+            .iter()
+            .map(
+                |id| match (Some(*id) == holder, self.robots.contains_key(id)) {
+                    (true, _) => format!("[{id}]"),
+                    (false, true) => id.to_string(),
+                    (false, false) => format!("{id}?"),
+                },
+            )
+            .collect();
+
+        if let Some(first) = ring.first() {
+            let last = ring.last().and_then(|id| self.robots.get(id));
+            match last.and_then(|state| state.next_id) {
+                Some(next) if next == *first => robots.push(first.to_string()),
+                Some(next) => robots.push(format!("{next}?")),
+                None => {}
+            }
+        }
+
+        writeln!(f, "Ring: {}", robots.join(" -> "))?;
+        if !self.unreachable.is_empty() {
+            let ids: Vec<_> = self.unreachable.iter().map(u16::to_string).collect();
+            writeln!(f, "Unreachable: {}", ids.join(", "))?;
+        }
+
+        let holder = holder.map_or("?".to_string(), |id| id.to_string());
+        let queued = self
+            .queued()
+            .map_or("?".to_string(), |queued| queued.to_string());
+        writeln!(f, "Order token: robot {holder}, {queued} orders queued")
+    }
+
+    /// Writes a bar with the servings left of every flavour.
+    fn fmt_stock(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "\nStock")?;
+        let stock = self.stock();
+
+        for flavour in Flavour::flavours() {
+            match stock.get(&flavour) {
+                Some(StockLevel { servings, .. }) => {
+                    let filled = (servings * BAR_WIDTH).div_ceil(STARTING_ICECREAM);
+                    let filled = filled.min(BAR_WIDTH);
+                    writeln!(
+                        f,
+                        "  {:<15} {}{} {servings}",
+                        flavour.name(),
+                        "#".repeat(filled),
+                        ".".repeat(BAR_WIDTH - filled)
+                    )?;
+                }
+                None => writeln!(f, "  {:<15} ?", flavour.name())?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes what every robot is doing.
+    fn fmt_robots(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "\nRobots")?;
+
+        for state in self.robots.values() {
+            let paused = match state.paused {
+                true => " (paused)",
+                false => "",
+            };
+
+            let order = match &state.current_order {
+                Some(order) => {
+                    let id = order.id();
+                    let mut flavours: Vec<_> = order
+                        .flavours()
+                        .map(|(flavour, servings)| format!("{} x{servings}", flavour.name()))
+                        .collect();
+                    flavours.sort();
+
+                    let left = match flavours.is_empty() {
+                        true => String::new(),
+                        false => format!(" (left: {})", flavours.join(", ")),
+                    };
+                    format!("screen {} #{}{left}", id.screen_id(), id.order_number())
+                }
+                None => "idle".to_string(),
+            };
+
+            let scooping = state
+                .serving_flavour
+                .map(|flavour| format!(", scooping {}", flavour.name()))
+                .unwrap_or_default();
+
+            writeln!(
+                f,
+                "  Robot {} {}{paused}: {order}{scooping}, {} waiting to upload",
+                state.id,
+                state.status,
+                state.new_orders.len()
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the last orders the robots ended.
+    fn fmt_recent(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "\nRecent orders")?;
+
+        for (robot, ended) in self.recent_orders().iter().rev() {
+            let ago = self.now_us.saturating_sub(ended.at_us) as f64 / 1_000_000.0;
+            writeln!(
+                f,
+                "  screen {} #{} {:?} by robot {robot}, {ago:.1}s ago",
+                ended.order.screen_id(),
+                ended.order.order_number(),
+                ended.outcome
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for ShopView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_ring(f)?;
+        self.fmt_stock(f)?;
+        self.fmt_robots(f)?;
+        self.fmt_recent(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ice_cream_shop::{messages::admin_msg::OrderOutcome, orders::OrderId};
+
+    fn state(id: u16, next_id: u16, order_round: u64) -> RobotState {
+        RobotState {
+            id,
+            prev_id: None,
+            next_id: Some(next_id),
+            status: "serving".to_string(),
+            paused: false,
+            current_order: None,
+            serving_flavour: None,
+            new_orders: Vec::new(),
+            stashed_order_token: None,
+            stashed_flavour_tokens: Vec::new(),
+            last_token: None,
+            order_round: Some(order_round),
+            queued: order_round as usize,
+            stock: StockSummary::new(),
+            recent_orders: Vec::new(),
+        }
+    }
+
+    fn ended(number: usize, at_us: u64) -> EndedOrder {
+        EndedOrder {
+            order: OrderId::new(0, number),
+            outcome: OrderOutcome::Confirmed,
+            at_us,
+        }
+    }
+
+    #[test]
+    fn test01_the_ring_follows_the_next_robots() {
+        let states = [
+            (0, Some(state(0, 3, 7))),
+            (1, None),
+            (3, Some(state(3, 4, 9))),
+            (4, Some(state(4, 0, 8))),
+        ];
+        let view = ShopView::new(states, 0);
+
+        assert_eq!(view.ring(), vec![0, 3, 4]);
+        assert_eq!(view.order_token_holder(), Some(3));
+        assert_eq!(view.queued(), Some(9));
+
+        let shown = view.to_string();
+        assert!(shown.contains("Ring: 0 -> [3] -> 4 -> 0"), "{shown}");
+        assert!(shown.contains("Unreachable: 1"), "{shown}");
+    }
+
+    #[test]
+    fn test02_the_newest_stock_and_orders_are_shown() {
+        let mut first = state(0, 1, 1);
+        first.stock = [(
+            Flavour::Menta,
+            StockLevel {
+                servings: 4,
+                round: 2,
+            },
+        )]
+        .into();
+        first.recent_orders = vec![ended(0, 10), ended(2, 30)];
+
+        let mut second = state(1, 0, 2);
+        second.stock = [(
+            Flavour::Menta,
+            StockLevel {
+                servings: 9,
+                round: 1,
+            },
+        )]
+        .into();
+        second.recent_orders = vec![ended(1, 20)];
+
+        let view = ShopView::new([(0, Some(first)), (1, Some(second))], 40);
+        assert_eq!(view.stock()[&Flavour::Menta].servings, 4);
+
+        let recent: Vec<_> = view
+            .recent_orders()
+            .iter()
+            .map(|(robot, ended)| (*robot, ended.order.order_number()))
+            .collect();
+        assert_eq!(recent, vec![(0, 0), (1, 1), (0, 2)]);
+    }
+}
@@ -1,19 +1,41 @@
-use ice_cream_shop::messages::gateway_msg::GatewayMsg;
+use ice_cream_shop::{
+    endpoint::Endpoint,
+    messages::{
+        auth::{open_request, seal, Keyring, Route},
+        gateway_msg::GatewayMsg,
+    },
+    metrics::{
+        registry,
+        shop_metrics::{CAPTURE_LATENCY, PAYMENTS},
+    },
+    trace::trace_context::now_us,
+};
 use std::{
-    io::{self, Read, Write},
+    io::{self, BufRead, Read, Write},
     net::TcpListener,
     thread,
 };
 
 /// Struct that represents a gateway that will receive messages from the screen.
+/// It only takes messages signed by a screen if it has a keyring.
 pub struct Gateway {
     port: u16,
+    keyring: Option<Keyring>,
 }
 
 impl Gateway {
     /// Creates a new gateway with the given port.
     pub fn new(port: u16) -> Self {
-        Gateway { port }
+        Gateway {
+            port,
+            keyring: None,
+        }
+    }
+
+    /// Sets the keyring the gateway signs and checks its messages with.
+    pub fn with_keyring(mut self, keyring: Option<Keyring>) -> Self {
+        self.keyring = keyring;
+        self
     }
 
     /// Function that validates if the order is valid.
@@ -25,7 +47,7 @@ impl Gateway {
     /// # Returns
     ///
     /// A boolean indicating if the order is valid.
-    fn is_order_valid(credit_card: &str) -> bool {
+    pub fn is_order_valid(credit_card: &str) -> bool {
         credit_card
             .chars()
             .next()
@@ -40,40 +62,61 @@ impl Gateway {
     /// # Arguments
     ///
     /// * `stream` - The stream to read the messages from.
+    /// * `keyring` - The keyring of the gateway, if the shop has a key.
     ///
     /// # Returns
     ///
     /// An io::Result indicating if the function was successful.
-    fn handle_screen<T: Read + Write>(mut stream: T) -> io::Result<()> {
-        let mut buffer = [0; 256];
+    fn handle_screen<T: Read + Write>(mut stream: T, keyring: Option<&Keyring>) -> io::Result<()> {
+        let mut buffer = [0; 1024];
 
         while let Ok(n) = stream.read(&mut buffer) {
             if n == 0 {
                 break;
             }
 
-            match serde_json::from_slice(&buffer[..n]) {
-                Ok(GatewayMsg::CapturePayment(order_id, credit_card)) => {
-                    let response = Self::is_order_valid(&credit_card);
-
-                    let print_msg = match response {
-                        true => format!("Capturing payment for order {order_id:?}"),
-                        false => format!("Invalid credit card for order {order_id:?}"),
-                    };
-
-                    println!("{print_msg}");
-                    stream.write_all(response.to_string().as_bytes())?;
-                }
-
-                Ok(GatewayMsg::CommitPayment(order_id)) => {
-                    println!("Committing payment for order {order_id:?}");
+            for line in buffer[..n].lines().map_while(Result::ok) {
+                let start = now_us();
+
+                match open_request(keyring, line.as_bytes(), Route::To(Endpoint::Gateway)) {
+                    Ok((GatewayMsg::CapturePayment(order_id, credit_card, trace), answers)) => {
+                        let response = Self::is_order_valid(&credit_card);
+
+                        let (print_msg, operation) = match response {
+                            true => (
+                                format!("Capturing payment for order {order_id:?} ({trace})"),
+                                "captured",
+                            ),
+                            false => (
+                                format!("Invalid credit card for order {order_id:?} ({trace})"),
+                                "rejected",
+                            ),
+                        };
+
+                        println!("{print_msg}");
+                        let response = response.to_string().into_bytes();
+                        stream.write_all(&seal(keyring, response, answers))?;
+                        trace.record("gateway_capture_payment", start);
+
+                        let elapsed = now_us().saturating_sub(start) as f64 / 1e6;
+                        registry().observe(CAPTURE_LATENCY, &[], elapsed);
+                        registry().inc(PAYMENTS, &[("operation", operation)], 1);
+                    }
+
+                    Ok((GatewayMsg::CommitPayment(order_id, trace), _)) => {
+                        println!("Committing payment for order {order_id:?} ({trace})");
+                        trace.record("gateway_commit_payment", start);
+                        registry().inc(PAYMENTS, &[("operation", "committed")], 1);
+                    }
+
+                    Ok((GatewayMsg::CancelPayment(order_id, trace), _)) => {
+                        println!("Cancelling payment for order {order_id:?} ({trace})");
+                        trace.record("gateway_cancel_payment", start);
+                        registry().inc(PAYMENTS, &[("operation", "cancelled")], 1);
+                    }
+
+                    Err(e) => eprintln!("{e}"),
                 }
-
-                Ok(GatewayMsg::CancelPayment(order_id)) => {
-                    println!("Cancelling payment for order {order_id:?}");
-                }
-
-                Err(e) => eprintln!("{e}"),
             }
         }
 
@@ -91,7 +134,8 @@ impl Gateway {
         println!("Ready to rumble!!!");
 
         for stream in listener.incoming().flatten() {
-            thread::spawn(move || Self::handle_screen(stream));
+            let keyring = self.keyring.clone();
+            thread::spawn(move || Self::handle_screen(stream, keyring.as_ref()));
         }
 
         Ok(())
@@ -101,7 +145,12 @@ impl Gateway {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use ice_cream_shop::{io_err, orders::OrderId};
+    use ice_cream_shop::{
+        io_err,
+        messages::auth::{open, Role},
+        orders::OrderId,
+        trace::TraceContext,
+    };
     use std::{cmp, convert::identity};
 
     struct MockStream {
@@ -135,7 +184,8 @@ mod tests {
     }
 
     fn capture_payment(order_id: OrderId, credit_card_number: &str) -> io::Result<bool> {
-        let req = GatewayMsg::CapturePayment(order_id, credit_card_number.to_string());
+        let trace = TraceContext::new();
+        let req = GatewayMsg::CapturePayment(order_id, credit_card_number.to_string(), trace);
         let bytes = serde_json::to_string(&req)?;
 
         let mut mock_stream = MockStream {
@@ -143,7 +193,7 @@ mod tests {
             can_read: true,
         };
 
-        Gateway::handle_screen(&mut mock_stream).unwrap();
+        Gateway::handle_screen(&mut mock_stream, None).unwrap();
         let res = String::from_utf8(mock_stream.data).unwrap();
 
         Ok(res.parse().unwrap())
@@ -166,4 +216,31 @@ mod tests {
         let response = capture_payment(OrderId::new(3, 10), "");
         assert!(response.is_ok_and(|valid| !valid));
     }
+
+    #[test]
+    fn test04_only_signed_payments_from_a_screen_are_answered_when_there_is_a_key() {
+        let keyring = |role| Keyring::new("secret", role);
+        let req =
+            GatewayMsg::CapturePayment(OrderId::new(3, 10), "1234".into(), TraceContext::new());
+        let req = serde_json::to_vec(&req).unwrap();
+
+        let seal = |role| keyring(role).seal_request(&req, Endpoint::Gateway);
+        let (to_robot, _) = keyring(Role::Screen).seal_request(&req, Endpoint::RobotOrders(0));
+        for ((data, answers), answered) in [
+            (seal(Role::Screen), true),
+            (seal(Role::Robot), false),
+            ((to_robot, Route::Back(0)), false),
+            ((req.clone(), Route::Back(0)), false),
+        ] {
+            let mut mock_stream = MockStream {
+                data,
+                can_read: true,
+            };
+
+            let gateway = keyring(Role::Gateway);
+            Gateway::handle_screen(&mut mock_stream, Some(&gateway)).unwrap();
+            let answer = open::<bool>(Some(&keyring(Role::Screen)), &mock_stream.data, answers);
+            assert_eq!(answer.is_ok_and(identity), answered);
+        }
+    }
 }
@@ -1,8 +1,26 @@
 use gateway::gateway::Gateway;
-use ice_cream_shop::shop_values::GATEWAY_PORT;
+use ice_cream_shop::{
+    messages::auth::{Keyring, Role},
+    metrics,
+    shop_values::{GATEWAY_METRICS_PORT, GATEWAY_PORT},
+    trace::Tracer,
+};
 
 fn main() {
-    let gate_way = Gateway::new(GATEWAY_PORT);
+    if let Err(e) = Tracer::init("gateway") {
+        eprintln!("Couldn't start tracing: {e}");
+    }
+
+    if let Err(e) = metrics::serve(GATEWAY_METRICS_PORT) {
+        eprintln!("Couldn't start the metrics endpoint: {e}");
+    }
+
+    let keyring = match Keyring::from_env(Role::Gateway) {
+        Ok(keyring) => keyring,
+        Err(e) => return eprintln!("{e}"),
+    };
+
+    let gate_way = Gateway::new(GATEWAY_PORT).with_keyring(keyring);
     match gate_way.receive_messages() {
         Ok(_) => println!("Gateway is running"),
         Err(e) => eprintln!("Error running gateway: {}", e),
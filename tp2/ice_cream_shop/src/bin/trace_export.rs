@@ -0,0 +1,23 @@
+use ice_cream_shop::trace::{span, Tracer};
+use std::{env, error::Error, fs, path::Path};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let (dir, output, format) = match args.as_slice() {
+        [dir, output] => (dir, output, "chrome"),
+        [dir, output, format] => (dir, output, format.as_str()),
+        _ => Err("Use: cargo run --bin trace_export <trace_dir> <output.json> [chrome|otlp]")?,
+    };
+
+    let spans = Tracer::read_spans(Path::new(dir))?;
+    let trace = match format {
+        "chrome" => span::to_chrome_trace(&spans),
+        "otlp" => span::to_otlp_trace(&spans),
+        _ => Err("The format needs to be chrome or otlp")?,
+    };
+
+    fs::write(output, serde_json::to_string(&trace)?)?;
+    println!("Exported {} spans to {output}", spans.len());
+
+    Ok(())
+}
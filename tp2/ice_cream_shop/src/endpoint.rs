@@ -0,0 +1,33 @@
+/// The places the components of the shop can be reached at.
+/// - `Robot`: Where a robot accepts connections from the other robots.
+/// - `RobotOrders`: Where a robot receives orders from the screens.
+/// - `RobotAdmin`: Where a robot answers the commands of its operators.
+/// - `Screen`: Where a screen receives the results of its orders.
+/// - `Gateway`: Where the payment gateway receives messages from the screens.
+/// - `Federation`: Where the gateway robot of a shop receives messages from other shops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    Robot(u16),
+    RobotOrders(u16),
+    RobotAdmin(u16),
+    Screen(u16),
+    Gateway,
+    Federation(u16),
+}
+
+impl Endpoint {
+    /// Returns the endpoint as bytes, so that signatures can cover it.
+    pub fn to_bytes(self) -> [u8; 3] {
+        let (kind, id) = match self {
+            Self::Robot(id) => (0, id),
+            Self::RobotOrders(id) => (1, id),
+            Self::RobotAdmin(id) => (2, id),
+            Self::Screen(id) => (3, id),
+            Self::Gateway => (4, 0),
+            Self::Federation(shop) => (5, shop),
+        };
+
+        let [high, low] = id.to_be_bytes();
+        [kind, high, low]
+    }
+}
@@ -22,4 +22,15 @@ impl Flavour {
         ]
         .into_iter()
     }
+
+    /// Returns the name of the flavour as it is written in the orders.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Flavour::DulceDeLeche => "dulce_de_leche",
+            Flavour::BananaSplit => "banana_split",
+            Flavour::Chocolate => "chocolate",
+            Flavour::Frutilla => "frutilla",
+            Flavour::Menta => "menta",
+        }
+    }
 }
@@ -1,3 +1,5 @@
+pub mod endpoint;
+
 pub mod flavour;
 
 pub mod orders;
@@ -8,6 +10,14 @@ pub mod tokens;
 
 pub mod messages;
 
+pub mod metrics;
+
+pub mod snapshot;
+
+pub mod stock;
+
+pub mod trace;
+
 #[macro_export]
 macro_rules! io_err {
     ($msg:literal) => {
@@ -0,0 +1,99 @@
+use crate::{
+    flavour::Flavour,
+    orders::{Order, OrderId},
+    stock::StockSummary,
+    tokens::{FlavourToken, OrderToken, TokenId},
+};
+use serde::{Deserialize, Serialize};
+
+/// Enum that represents the commands that a robot accepts on its admin endpoint.
+/// Every command is answered with the state of the robot after running it.
+/// - `State`: Only dumps the state.
+/// - `Drain`: Hands back the orders of the robot and leaves the ring.
+/// - `Pause`: Stops taking orders from the order token, finishing the one being served.
+/// - `Resume`: Takes orders again after a pause.
+/// - `FindNext`: Reconnects to the next robot of the ring.
+/// - `Snapshot`: Takes a snapshot of the whole ring, which is answered instead of the state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminMsg {
+    State,
+    Drain,
+    Pause,
+    Resume,
+    FindNext,
+    Snapshot,
+}
+
+/// The last token that a robot received.
+/// - `id`: Which token it was.
+/// - `sender`: The robot that sent it.
+/// - `round`: The round of the copy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeenToken {
+    pub id: TokenId,
+    pub sender: u16,
+    pub round: u64,
+}
+
+/// How an order a robot took ended.
+/// - `Confirmed`: The robot delivered it.
+/// - `Cancelled`: There wasn't enough ice cream.
+/// - `Expired`: Its deadline passed before the robot took it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderOutcome {
+    Confirmed,
+    Cancelled,
+    Expired,
+}
+
+/// An order a robot ended.
+/// - `order`: The id of the order.
+/// - `outcome`: How it ended.
+/// - `at_us`: When it ended, in microseconds since the unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EndedOrder {
+    pub order: OrderId,
+    pub outcome: OrderOutcome,
+    pub at_us: u64,
+}
+
+/// What a robot is doing, as answered on its admin endpoint.
+/// - `id`: The ID of the robot.
+/// - `prev_id`: The ID of the previous robot in the ring.
+/// - `next_id`: The ID of the next robot in the ring.
+/// - `status`: Whether the robot is joining, serving, draining or leaving the ring.
+/// - `paused`: Whether the robot stopped taking orders.
+/// - `current_order`: The order being served.
+/// - `serving_flavour`: The flavour being scooped.
+/// - `new_orders`: The orders received that weren't uploaded to the order token yet.
+/// - `stashed_order_token`: The order token in the token box, if any.
+/// - `stashed_flavour_tokens`: The flavour tokens in the token box.
+/// - `last_token`: The last token received.
+/// - `order_round`: The round of the last order token received.
+/// - `queued`: The orders that were waiting in the order token the last time it passed by.
+/// - `stock`: The stock of every flavour the last time its token passed by.
+/// - `recent_orders`: The last orders the robot ended, the newest last.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RobotState {
+    pub id: u16,
+    pub prev_id: Option<u16>,
+    pub next_id: Option<u16>,
+    pub status: String,
+    pub paused: bool,
+    pub current_order: Option<Order>,
+    pub serving_flavour: Option<Flavour>,
+    pub new_orders: Vec<Order>,
+    pub stashed_order_token: Option<OrderToken>,
+    pub stashed_flavour_tokens: Vec<FlavourToken>,
+    pub last_token: Option<SeenToken>,
+    #[serde(default)]
+    pub order_round: Option<u64>,
+    #[serde(default)]
+    pub queued: usize,
+    #[serde(default)]
+    pub stock: StockSummary,
+    #[serde(default)]
+    pub recent_orders: Vec<EndedOrder>,
+}
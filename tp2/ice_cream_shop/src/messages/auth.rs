@@ -0,0 +1,795 @@
+use super::{
+    admin_msg::{AdminMsg, RobotState},
+    federation_msg::FederationMsg,
+    fmt_msg,
+    gateway_msg::GatewayMsg,
+    protocol::{decode, Handshake, ProtocolError, BINARY_MARKER},
+    robot_msg::RobotMsg,
+    screen_msg::{Admission, ScreenMsg},
+};
+use crate::{
+    endpoint::Endpoint,
+    metrics::{registry, shop_metrics::FRAMES_REJECTED},
+    trace::trace_context::now_us,
+};
+use hmac::{Hmac, Mac};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    env,
+    error::Error,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// Environment variables with the key of every role. A component signs its frames with the
+/// key of its role and checks the frames it receives with the key of the role that claims to
+/// have signed them, so it can only pass for the roles whose keys it's given. Frames are
+/// neither signed nor checked when none of them is set.
+pub const KEY_VARS: [(Role, &str); 4] = [
+    (Role::Robot, "SHOP_KEY_ROBOT"),
+    (Role::Screen, "SHOP_KEY_SCREEN"),
+    (Role::Gateway, "SHOP_KEY_GATEWAY"),
+    (Role::Admin, "SHOP_KEY_ADMIN"),
+];
+
+/// How old a signed frame can be, in microseconds, before it's taken as a replay.
+pub const MAX_AGE_US: u64 = 30_000_000;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The kinds of components that can sign a frame.
+/// - `Robot`: A robot of the ring.
+/// - `Screen`: A screen that takes orders from the clients.
+/// - `Gateway`: The payment gateway.
+/// - `Admin`: An operator talking to the admin endpoint of a robot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Robot,
+    Screen,
+    Gateway,
+    Admin,
+}
+
+/// Where a frame goes, which its signature covers, so that a frame captured on its way to one
+/// component is rejected by every other one.
+/// - `To`: The frame goes to the component listening at the endpoint, from the one that
+///   connected to it.
+/// - `Back`: The frame answers the frame that had the nonce, which was sent with `seal_request`.
+///   Through the connections of the robots, every frame answers the first one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Route {
+    To(Endpoint),
+    Back(u64),
+}
+
+/// A frame that was opened.
+/// - `role`: The role of the component that signed it.
+/// - `nonce`: The nonce it was signed with, which the answers to it are bound to.
+/// - `frame`: The frame itself.
+#[derive(Debug)]
+pub struct Opened {
+    pub role: Role,
+    pub nonce: u64,
+    pub frame: Vec<u8>,
+}
+
+/// The messages that only some roles are allowed to send.
+pub trait Authorized {
+    /// Returns whether a component with the given role may send this message.
+    fn allowed_from(&self, role: Role) -> bool;
+}
+
+/// The keys of a component together with its role.
+/// It remembers the frames it opened recently, so that they can't be replayed.
+/// - `key`: The key of its role, which it signs its frames with.
+/// - `role`: The role of the component.
+/// - `keys`: The keys of the roles whose frames it opens, its own included.
+/// - `seen`: The frames it opened recently.
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    key: Arc<[u8]>,
+    role: Role,
+    keys: HashMap<Role, Arc<[u8]>>,
+    seen: Arc<Mutex<Seen>>,
+}
+
+/// The signatures of the frames opened recently, in the order they arrived.
+#[derive(Debug, Default)]
+struct Seen {
+    macs: HashSet<String>,
+    arrivals: VecDeque<(u64, String)>,
+}
+
+/// A frame signed by a component of the shop.
+/// - `role`: The role of the component that signed it.
+/// - `sent_at`: When it was signed, in microseconds since the epoch.
+/// - `nonce`: A random number, so that two equal frames have different signatures.
+/// - `mac`: The HMAC-SHA256 of the rest of the fields and of the route of the frame, in hex.
+/// - `frame`: The frame that was signed.
+#[derive(Serialize, Deserialize)]
+struct Sealed<F> {
+    role: Role,
+    sent_at: u64,
+    nonce: u64,
+    mac: String,
+    frame: F,
+}
+
+/// The reasons a frame is rejected.
+/// - `Unsigned`: The frame isn't signed.
+/// - `Forged`: The signature doesn't match the frame, so it was signed with another key or
+///   changed on the way.
+/// - `Stale`: The frame was signed too long ago.
+/// - `Replayed`: The frame was already received.
+/// - `Forbidden`: The role that signed the frame isn't allowed to send its message.
+/// - `Protocol`: The frame isn't a message of the expected type.
+#[derive(Debug)]
+pub enum AuthError {
+    Unsigned,
+    Forged,
+    Stale,
+    Replayed,
+    Forbidden(Role),
+    Protocol(ProtocolError),
+}
+
+impl Keyring {
+    /// Creates a keyring for a component where every role has the same key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of every role.
+    /// * `role` - The role the component signs its frames with.
+    pub fn new(key: impl AsRef<[u8]>, role: Role) -> Self {
+        let key: Arc<[u8]> = Arc::from(key.as_ref());
+        let keys = KEY_VARS
+            .iter()
+            .map(|(role, _)| (*role, key.clone()))
+            .collect();
+
+        Self {
+            key,
+            role,
+            keys,
+            seen: Arc::default(),
+        }
+    }
+
+    /// Creates a keyring for a component that only knows the key of its own role.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the role.
+    /// * `role` - The role the component signs its frames with.
+    pub fn of_role(key: impl AsRef<[u8]>, role: Role) -> Self {
+        let key: Arc<[u8]> = Arc::from(key.as_ref());
+        Self {
+            keys: HashMap::from([(role, key.clone())]),
+            key,
+            role,
+            seen: Arc::default(),
+        }
+    }
+
+    /// Returns the keyring with the key of another role, so that it opens the frames signed
+    /// with it.
+    ///
+    /// # Arguments
+    ///
+    /// * `role` - The role.
+    /// * `key` - The key of the role.
+    pub fn with_key(mut self, role: Role, key: impl AsRef<[u8]>) -> Self {
+        let key: Arc<[u8]> = Arc::from(key.as_ref());
+        if role == self.role {
+            self.key = key.clone();
+        }
+
+        self.keys.insert(role, key);
+        self
+    }
+
+    /// Reads the keys of the roles from `SHOP_KEY_ROBOT`, `SHOP_KEY_SCREEN`, `SHOP_KEY_GATEWAY`
+    /// and `SHOP_KEY_ADMIN`. The key of the role of the component is needed to sign its frames.
+    ///
+    /// # Arguments
+    ///
+    /// * `role` - The role the component signs its frames with.
+    ///
+    /// # Returns
+    ///
+    /// The keyring, `None` if none of the variables is set, or an error if one is empty or
+    /// the one of the role is missing.
+    pub fn from_env(role: Role) -> Result<Option<Self>, &'static str> {
+        let mut keys = HashMap::new();
+        for (role, var) in KEY_VARS {
+            match env::var(var) {
+                Ok(key) if key.is_empty() => return Err("The SHOP_KEY_* variables can't be empty"),
+                Ok(key) => {
+                    keys.insert(role, key);
+                }
+                Err(_) => {}
+            }
+        }
+
+        if keys.is_empty() {
+            return Ok(None);
+        }
+
+        let key = keys
+            .remove(&role)
+            .ok_or("The SHOP_KEY_* variable of the role of the component needs to be set")?;
+        let keyring = keys
+            .into_iter()
+            .fold(Self::of_role(key, role), |keyring, (role, key)| {
+                keyring.with_key(role, key)
+            });
+
+        Ok(Some(keyring))
+    }
+
+    /// Returns the role the component signs its frames with.
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Signs a frame. Binary frames stay binary and the rest are sent as a line of JSON.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - The frame to sign.
+    /// * `route` - Where the frame goes.
+    ///
+    /// # Returns
+    ///
+    /// The signed frame.
+    pub fn seal(&self, frame: &[u8], route: Route) -> Vec<u8> {
+        self.seal_at(frame, route, now_us(), rand::random())
+    }
+
+    /// Signs a frame sent to an endpoint that is answered, like the first frame of a connection.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - The frame to sign.
+    /// * `endpoint` - Where the frame goes.
+    ///
+    /// # Returns
+    ///
+    /// The signed frame, and the route its answers come back through.
+    pub fn seal_request(&self, frame: &[u8], endpoint: Endpoint) -> (Vec<u8>, Route) {
+        let nonce = rand::random();
+        let signed = self.seal_at(frame, Route::To(endpoint), now_us(), nonce);
+        (signed, Route::Back(nonce))
+    }
+
+    /// Signs a frame as if it was sent at the given time with the given nonce.
+    fn seal_at(&self, frame: &[u8], route: Route, sent_at: u64, nonce: u64) -> Vec<u8> {
+        if frame.first() == Some(&BINARY_MARKER) {
+            let mac = self.mac(self.role, sent_at, nonce, route, frame);
+            let sealed = Sealed {
+                role: self.role,
+                sent_at,
+                nonce,
+                mac,
+                frame,
+            };
+
+            let mut signed = vec![BINARY_MARKER];
+            if let Err(e) = bincode::serialize_into(&mut signed, &sealed) {
+                eprintln!("Couldn't sign a frame: {e}");
+            }
+
+            return signed;
+        }
+
+        let frame = String::from_utf8_lossy(frame.strip_suffix(b"\n").unwrap_or(frame));
+        let mac = self.mac(self.role, sent_at, nonce, route, frame.as_bytes());
+        fmt_msg(Sealed {
+            role: self.role,
+            sent_at,
+            nonce,
+            mac,
+            frame,
+        })
+    }
+
+    /// Checks the signature of a frame, that it was meant to come through the given route
+    /// and that it wasn't received before. Rejected frames are counted in the metrics.
+    ///
+    /// # Arguments
+    ///
+    /// * `signed` - The signed frame.
+    /// * `route` - Where the frame arrived.
+    ///
+    /// # Returns
+    ///
+    /// The frame, or why it was rejected.
+    pub fn open(&self, signed: &[u8], route: Route) -> Result<Opened, AuthError> {
+        self.verify(signed, route).inspect_err(AuthError::count)
+    }
+
+    /// Checks a signed frame, without counting it if it's rejected.
+    fn verify(&self, signed: &[u8], route: Route) -> Result<Opened, AuthError> {
+        let sealed: Sealed<Vec<u8>> = match signed.split_first() {
+            Some((&BINARY_MARKER, bytes)) => {
+                bincode::deserialize(bytes).map_err(|_| AuthError::Unsigned)?
+            }
+
+            _ => {
+                let sealed: Sealed<String> =
+                    serde_json::from_slice(signed).map_err(|_| AuthError::Unsigned)?;
+                Sealed {
+                    role: sealed.role,
+                    sent_at: sealed.sent_at,
+                    nonce: sealed.nonce,
+                    mac: sealed.mac,
+                    frame: sealed.frame.into_bytes(),
+                }
+            }
+        };
+
+        let key = self
+            .keys
+            .get(&sealed.role)
+            .ok_or(AuthError::Forbidden(sealed.role))?;
+        let mac = from_hex(&sealed.mac).ok_or(AuthError::Forged)?;
+        hmac(
+            key,
+            sealed.role,
+            sealed.sent_at,
+            sealed.nonce,
+            route,
+            &sealed.frame,
+        )
+        .verify_slice(&mac)
+        .map_err(|_| AuthError::Forged)?;
+
+        let now = now_us();
+        if now.abs_diff(sealed.sent_at) > MAX_AGE_US {
+            return Err(AuthError::Stale);
+        }
+
+        if !self.lock().insert(now, sealed.mac) {
+            return Err(AuthError::Replayed);
+        }
+
+        Ok(Opened {
+            role: sealed.role,
+            nonce: sealed.nonce,
+            frame: sealed.frame,
+        })
+    }
+
+    /// Computes the signature of a frame with the key of the role of the component, in hex.
+    fn mac(&self, role: Role, sent_at: u64, nonce: u64, route: Route, frame: &[u8]) -> String {
+        hmac(&self.key, role, sent_at, nonce, route, frame)
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    /// Locks the recent signatures, even if a thread panicked while holding them.
+    fn lock(&self) -> MutexGuard<'_, Seen> {
+        self.seen
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Seen {
+    /// Remembers a signature, forgetting the ones that are too old to be accepted again.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the signature was already seen.
+    fn insert(&mut self, now: u64, mac: String) -> bool {
+        while let Some((arrival, old)) = self.arrivals.front() {
+            if now.saturating_sub(*arrival) <= 2 * MAX_AGE_US {
+                break;
+            }
+
+            self.macs.remove(old);
+            self.arrivals.pop_front();
+        }
+
+        if !self.macs.insert(mac.clone()) {
+            return false;
+        }
+
+        self.arrivals.push_back((now, mac));
+        true
+    }
+}
+
+impl AuthError {
+    /// Returns the name of the reason, as used in the metrics.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::Unsigned => "unsigned",
+            Self::Forged => "forged",
+            Self::Stale => "stale",
+            Self::Replayed => "replayed",
+            Self::Forbidden(_) => "forbidden",
+            Self::Protocol(_) => "protocol",
+        }
+    }
+
+    /// Counts the rejection in the metrics.
+    pub fn count(&self) {
+        registry().inc(FRAMES_REJECTED, &[("reason", self.reason())], 1);
+    }
+}
+
+/// Feeds the signed fields of a frame and its route into an HMAC with the key of the role
+/// that signs it.
+fn hmac(
+    key: &[u8],
+    role: Role,
+    sent_at: u64,
+    nonce: u64,
+    route: Route,
+    frame: &[u8],
+) -> HmacSha256 {
+    let mut hmac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    hmac.update(&[role as u8]);
+    hmac.update(&sent_at.to_be_bytes());
+    hmac.update(&nonce.to_be_bytes());
+    match route {
+        Route::To(endpoint) => {
+            hmac.update(&[0]);
+            hmac.update(&endpoint.to_bytes());
+        }
+        Route::Back(nonce) => {
+            hmac.update(&[1]);
+            hmac.update(&nonce.to_be_bytes());
+        }
+    }
+    hmac.update(frame);
+    hmac
+}
+
+/// Parses a signature written in hex.
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Signs a frame if there's a keyring.
+///
+/// # Arguments
+///
+/// * `keyring` - The keyring of the component, if the shop has a key.
+/// * `frame` - The frame to sign.
+/// * `route` - Where the frame goes.
+///
+/// # Returns
+///
+/// The signed frame, or the frame as it was if there's no keyring.
+pub fn seal(keyring: Option<&Keyring>, frame: Vec<u8>, route: Route) -> Vec<u8> {
+    match keyring {
+        Some(keyring) => keyring.seal(&frame, route),
+        None => frame,
+    }
+}
+
+/// Signs a frame sent to an endpoint that is answered if there's a keyring.
+///
+/// # Arguments
+///
+/// * `keyring` - The keyring of the component, if the shop has a key.
+/// * `frame` - The frame to sign.
+/// * `endpoint` - Where the frame goes.
+///
+/// # Returns
+///
+/// The frame, signed if there's a keyring, and the route its answers come back through.
+pub fn seal_request(
+    keyring: Option<&Keyring>,
+    frame: Vec<u8>,
+    endpoint: Endpoint,
+) -> (Vec<u8>, Route) {
+    match keyring {
+        Some(keyring) => keyring.seal_request(&frame, endpoint),
+        None => (frame, Route::Back(0)),
+    }
+}
+
+/// Opens and decodes a frame, checking that the role that signed it is allowed to send its
+/// message. Frames are only decoded if there's no keyring.
+///
+/// # Arguments
+///
+/// * `keyring` - The keyring of the component, if the shop has a key.
+/// * `frame` - The frame received.
+/// * `route` - Where the frame arrived.
+///
+/// # Returns
+///
+/// The message, or why the frame was rejected.
+pub fn open<M>(keyring: Option<&Keyring>, frame: &[u8], route: Route) -> Result<M, AuthError>
+where
+    M: DeserializeOwned + Authorized,
+{
+    open_request(keyring, frame, route).map(|(msg, _)| msg)
+}
+
+/// Opens and decodes a frame like `open`, and returns the route to answer it through.
+///
+/// # Arguments
+///
+/// * `keyring` - The keyring of the component, if the shop has a key.
+/// * `frame` - The frame received.
+/// * `route` - Where the frame arrived.
+///
+/// # Returns
+///
+/// The message and the route of its answer, or why the frame was rejected.
+pub fn open_request<M>(
+    keyring: Option<&Keyring>,
+    frame: &[u8],
+    route: Route,
+) -> Result<(M, Route), AuthError>
+where
+    M: DeserializeOwned + Authorized,
+{
+    let Some(keyring) = keyring else {
+        let msg = decode(frame).map_err(AuthError::Protocol)?;
+        return Ok((msg, Route::Back(0)));
+    };
+
+    let opened = keyring.open(frame, route)?;
+    let msg: M = decode(&opened.frame).map_err(AuthError::Protocol)?;
+    if !msg.allowed_from(opened.role) {
+        let error = AuthError::Forbidden(opened.role);
+        error.count();
+        return Err(error);
+    }
+
+    Ok((msg, Route::Back(opened.nonce)))
+}
+
+/// Returns whether a frame is a message of the given type that the role is allowed to send.
+///
+/// # Arguments
+///
+/// * `role` - The role that signed the frame.
+/// * `frame` - The frame, already opened.
+pub fn permits<M: DeserializeOwned + Authorized>(role: Role, frame: &[u8]) -> bool {
+    decode::<M>(frame).is_ok_and(|msg| msg.allowed_from(role))
+}
+
+impl Authorized for RobotMsg {
+    fn allowed_from(&self, role: Role) -> bool {
+        match self {
+            Self::RecvOrder(_) => role == Role::Screen,
+            _ => role == Role::Robot,
+        }
+    }
+}
+
+impl Authorized for Handshake {
+    fn allowed_from(&self, role: Role) -> bool {
+        role == Role::Robot
+    }
+}
+
+impl Authorized for ScreenMsg {
+    fn allowed_from(&self, role: Role) -> bool {
+        role == Role::Robot
+    }
+}
+
+impl Authorized for Admission {
+    fn allowed_from(&self, role: Role) -> bool {
+        role == Role::Robot
+    }
+}
+
+impl Authorized for FederationMsg {
+    fn allowed_from(&self, role: Role) -> bool {
+        role == Role::Robot
+    }
+}
+
+impl Authorized for GatewayMsg {
+    fn allowed_from(&self, role: Role) -> bool {
+        role == Role::Screen
+    }
+}
+
+/// The state of a robot, answered on its admin endpoint.
+impl Authorized for RobotState {
+    fn allowed_from(&self, role: Role) -> bool {
+        role == Role::Robot
+    }
+}
+
+impl Authorized for AdminMsg {
+    fn allowed_from(&self, role: Role) -> bool {
+        role == Role::Admin
+    }
+}
+
+/// The answer of the gateway to a payment capture.
+impl Authorized for bool {
+    fn allowed_from(&self, role: Role) -> bool {
+        role == Role::Gateway
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsigned => write!(f, "The frame isn't signed"),
+            Self::Forged => write!(f, "The signature of the frame doesn't match"),
+            Self::Stale => write!(f, "The frame was signed too long ago"),
+            Self::Replayed => write!(f, "The frame was already received"),
+            Self::Forbidden(role) => write!(f, "A {role:?} isn't allowed to send the message"),
+            Self::Protocol(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for AuthError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::protocol::{Codec, Encoding, PROTOCOL_VERSION},
+        orders::{Order, OrderId},
+        trace::TraceContext,
+    };
+    use std::collections::HashMap;
+
+    const TO_ROBOT: Route = Route::To(Endpoint::RobotOrders(0));
+
+    fn order() -> RobotMsg {
+        RobotMsg::RecvOrder(Order::new(OrderId::new(0, 1), HashMap::new()))
+    }
+
+    #[test]
+    fn test01_frames_signed_with_the_shop_key_are_opened() {
+        let screen = Keyring::new("secret", Role::Screen);
+        let robot = Keyring::new("secret", Role::Robot);
+        let binary = Codec {
+            version: PROTOCOL_VERSION,
+            encoding: Encoding::Binary,
+        };
+
+        for frame in [Codec::default().encode(order()), binary.encode(order())] {
+            let signed = screen.seal(&frame, TO_ROBOT);
+            assert_eq!(signed[0] == BINARY_MARKER, frame[0] == BINARY_MARKER);
+
+            let msg: RobotMsg = open(Some(&robot), &signed, TO_ROBOT).unwrap();
+            assert!(matches!(msg, RobotMsg::RecvOrder(_)));
+        }
+    }
+
+    #[test]
+    fn test02_unsigned_forged_and_tampered_frames_are_rejected() {
+        let robot = Keyring::new("secret", Role::Robot);
+        let intruder = Keyring::new("guess", Role::Screen);
+        let frame = Codec::default().encode(order());
+
+        assert!(matches!(
+            robot.open(&frame, TO_ROBOT),
+            Err(AuthError::Unsigned)
+        ));
+        assert!(matches!(
+            robot.open(&intruder.seal(&frame, TO_ROBOT), TO_ROBOT),
+            Err(AuthError::Forged)
+        ));
+
+        let signed = Keyring::new("secret", Role::Robot).seal(&frame, TO_ROBOT);
+        let signed = String::from_utf8(signed).unwrap();
+        let tampered = signed.replacen("\"role\":\"robot\"", "\"role\":\"screen\"", 1);
+        assert!(matches!(
+            robot.open(tampered.as_bytes(), TO_ROBOT),
+            Err(AuthError::Forged)
+        ));
+    }
+
+    #[test]
+    fn test03_frames_are_only_opened_once_and_while_fresh() {
+        let robot = Keyring::new("secret", Role::Robot);
+        let screen = Keyring::new("secret", Role::Screen);
+        let frame = Codec::default().encode(order());
+
+        let signed = screen.seal(&frame, TO_ROBOT);
+        assert!(robot.open(&signed, TO_ROBOT).is_ok());
+        assert!(matches!(
+            robot.open(&signed, TO_ROBOT),
+            Err(AuthError::Replayed)
+        ));
+        assert!(robot.open(&screen.seal(&frame, TO_ROBOT), TO_ROBOT).is_ok());
+
+        let old = screen.seal_at(&frame, TO_ROBOT, now_us() - 2 * MAX_AGE_US, 0);
+        assert!(matches!(robot.open(&old, TO_ROBOT), Err(AuthError::Stale)));
+    }
+
+    #[test]
+    fn test04_roles_can_only_send_their_own_messages() {
+        let keyring = |role| Keyring::new("secret", role);
+        let confirm = ScreenMsg::ConfirmOrder(OrderId::new(0, 1), TraceContext::new());
+        let confirm = Codec::default().encode(confirm);
+
+        let to_screen = Route::To(Endpoint::Screen(0));
+
+        let from_screen = keyring(Role::Screen).seal(&confirm, to_screen);
+        assert!(matches!(
+            open::<ScreenMsg>(Some(&keyring(Role::Screen)), &from_screen, to_screen),
+            Err(AuthError::Forbidden(Role::Screen))
+        ));
+
+        let from_robot = keyring(Role::Robot).seal(&confirm, to_screen);
+        assert!(open::<ScreenMsg>(Some(&keyring(Role::Screen)), &from_robot, to_screen).is_ok());
+
+        let order = Codec::default().encode(order());
+        assert!(permits::<RobotMsg>(Role::Screen, &order));
+        assert!(!permits::<RobotMsg>(Role::Robot, &order));
+        assert!(!permits::<RobotMsg>(Role::Screen, b"\"Disconnect\""));
+    }
+
+    #[test]
+    fn test05_a_component_can_only_sign_for_the_roles_whose_keys_it_holds() {
+        let robot = Keyring::of_role("robot-key", Role::Robot)
+            .with_key(Role::Screen, "screen-key")
+            .with_key(Role::Admin, "admin-key");
+        let screen =
+            Keyring::of_role("screen-key", Role::Screen).with_key(Role::Robot, "robot-key");
+        let gateway = Keyring::of_role("gateway-key", Role::Gateway);
+
+        let order = Codec::default().encode(order());
+        assert!(open::<RobotMsg>(Some(&robot), &screen.seal(&order, TO_ROBOT), TO_ROBOT).is_ok());
+
+        let state = Codec::default().encode(AdminMsg::State);
+        let to_admin = Route::To(Endpoint::RobotAdmin(0));
+        let posing_as_admin = Keyring::of_role("screen-key", Role::Admin).seal(&state, to_admin);
+        assert!(matches!(
+            open::<AdminMsg>(Some(&robot), &posing_as_admin, to_admin),
+            Err(AuthError::Forged)
+        ));
+
+        assert!(matches!(
+            robot.open(&gateway.seal(&order, TO_ROBOT), TO_ROBOT),
+            Err(AuthError::Forbidden(Role::Gateway))
+        ));
+    }
+
+    #[test]
+    fn test06_frames_are_only_opened_where_they_were_sent() {
+        let screen = Keyring::new("secret", Role::Screen);
+        let first = Keyring::new("secret", Role::Robot);
+        let second = Keyring::new("secret", Role::Robot);
+        let frame = Codec::default().encode(order());
+
+        let (signed, answers) = screen.seal_request(&frame, Endpoint::RobotOrders(0));
+        let to_second = Route::To(Endpoint::RobotOrders(1));
+        assert!(matches!(
+            second.open(&signed, to_second),
+            Err(AuthError::Forged)
+        ));
+        let opened = first.open(&signed, TO_ROBOT).unwrap();
+        assert_eq!(answers, Route::Back(opened.nonce));
+
+        let answer = first.seal(b"true", answers);
+        let (_, other_answers) = screen.seal_request(&frame, Endpoint::RobotOrders(0));
+        assert!(matches!(
+            screen.open(&answer, other_answers),
+            Err(AuthError::Forged)
+        ));
+        assert!(screen.open(&answer, answers).is_ok());
+    }
+}
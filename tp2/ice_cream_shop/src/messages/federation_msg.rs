@@ -0,0 +1,68 @@
+use crate::flavour::Flavour;
+use serde::{Deserialize, Serialize};
+
+/// Identifies a transfer of servings between shops.
+/// - `requester`: The shop that asked for the servings.
+/// - `seq`: A number the requester never used for another transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct TransferId {
+    pub requester: u16,
+    pub seq: u64,
+}
+
+/// Servings of a flavour that move from one shop to another.
+/// - `id`: The id of the transfer.
+/// - `donor`: The shop asked to give the servings.
+/// - `flavour`: The flavour of the servings.
+/// - `servings`: How many servings move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transfer {
+    pub id: TransferId,
+    pub donor: u16,
+    pub flavour: Flavour,
+    pub servings: usize,
+}
+
+/// Enum that represents the messages that the gateway robots of the shops send each other.
+/// A transfer is done in two phases: the donor takes the servings out of its flavour token and
+/// holds them there, and drops them only once the requester added them to its own token.
+/// - `Request`: The requester asks the donor for servings.
+/// - `Prepared`: The donor holds the servings for the requester.
+/// - `Refused`: The donor can't spare the servings.
+/// - `Committed`: The requester added the servings to its token.
+/// - `Done`: The donor dropped the servings it held, so the requester can forget the transfer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FederationMsg {
+    Request(Transfer),
+    Prepared(Transfer),
+    Refused(Transfer),
+    Committed(Transfer),
+    Done(Transfer),
+}
+
+impl Transfer {
+    /// Returns the shop the other one of the transfer is, as seen from the given shop.
+    ///
+    /// # Arguments
+    ///
+    /// * `shop` - The shop looking at the transfer.
+    pub fn other(&self, shop: u16) -> u16 {
+        match shop == self.donor {
+            true => self.id.requester,
+            false => self.donor,
+        }
+    }
+}
+
+impl FederationMsg {
+    /// Returns the transfer the message is about.
+    pub fn transfer(&self) -> &Transfer {
+        match self {
+            Self::Request(transfer)
+            | Self::Prepared(transfer)
+            | Self::Refused(transfer)
+            | Self::Committed(transfer)
+            | Self::Done(transfer) => transfer,
+        }
+    }
+}
@@ -1,10 +1,10 @@
-use crate::orders::OrderId;
+use crate::{orders::OrderId, trace::TraceContext};
 use serde::{Deserialize, Serialize};
 
 /// Enum that represents the messages that the gateway can receive
 #[derive(Serialize, Deserialize)]
 pub enum GatewayMsg {
-    CapturePayment(OrderId, String),
-    CommitPayment(OrderId),
-    CancelPayment(OrderId),
+    CapturePayment(OrderId, String, TraceContext),
+    CommitPayment(OrderId, TraceContext),
+    CancelPayment(OrderId, TraceContext),
 }
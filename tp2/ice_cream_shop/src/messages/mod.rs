@@ -1,4 +1,8 @@
+pub mod admin_msg;
+pub mod auth;
+pub mod federation_msg;
 pub mod gateway_msg;
+pub mod protocol;
 pub mod robot_msg;
 pub mod screen_msg;
 
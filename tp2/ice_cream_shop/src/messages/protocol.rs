@@ -0,0 +1,372 @@
+use super::fmt_msg;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{env, error::Error, fmt, str::FromStr};
+
+/// Environment variable that selects the encoding a robot prefers for the connections it opens:
+/// `json` or `binary`. JSON is used when it isn't set.
+pub const ENCODING_VAR: &str = "SHOP_ENCODING";
+
+/// The version of the protocol spoken by this build.
+/// - `1`: Every message is sent as bare JSON, one per line. It's no longer spoken.
+/// - `2`: Every message is wrapped in an envelope with its version, in JSON or binary.
+pub const PROTOCOL_VERSION: u16 = 2;
+
+/// The oldest version of the protocol this build still speaks between robots.
+pub const MIN_PROTOCOL_VERSION: u16 = 2;
+
+/// The first byte of a binary frame. It can't start a JSON message, so a frame tells how it
+/// was encoded by itself.
+pub const BINARY_MARKER: u8 = 0xB1;
+
+/// The ways a message can be encoded.
+/// - `Json`: One JSON object per line, easy to read when debugging.
+/// - `Binary`: A compact bincode frame.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    #[default]
+    Json,
+    Binary,
+}
+
+/// The messages a robot exchanges with the robot it connects to before it introduces itself.
+/// - `Hello`: The robot that connects tells the versions it speaks and the encodings it
+///   accepts, in order of preference.
+/// - `Accept`: The robot that accepts the connection tells what was agreed on.
+/// - `Reject`: The robot that accepts the connection tells why they can't talk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Handshake {
+    Hello {
+        min_version: u16,
+        version: u16,
+        encodings: Vec<Encoding>,
+    },
+    Accept(Codec),
+    Reject(String),
+}
+
+/// How the messages of a connection are encoded, as agreed on in the handshake.
+/// - `version`: The version of the protocol used.
+/// - `encoding`: The encoding used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Codec {
+    pub version: u16,
+    pub encoding: Encoding,
+}
+
+/// A message together with the version of the protocol it was sent with.
+#[derive(Serialize, Deserialize)]
+struct Envelope<M> {
+    v: u16,
+    msg: M,
+}
+
+/// The ways a frame can fail to be decoded.
+/// - `Json`: The frame isn't a JSON message of the expected type.
+/// - `Binary`: The frame isn't a binary message of the expected type.
+/// - `Version`: The message was sent with a version this build doesn't speak.
+#[derive(Debug)]
+pub enum ProtocolError {
+    Json(serde_json::Error),
+    Binary(bincode::Error),
+    Version(u16),
+}
+
+impl Encoding {
+    /// Reads the preferred encoding from `SHOP_ENCODING`.
+    ///
+    /// # Returns
+    ///
+    /// The selected encoding, JSON if the variable isn't set, or an error if it has an unknown value.
+    pub fn from_env() -> Result<Self, &'static str> {
+        match env::var(ENCODING_VAR) {
+            Ok(value) => value.parse(),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "json" => Ok(Self::Json),
+            "binary" => Ok(Self::Binary),
+            _ => Err("SHOP_ENCODING needs to be json or binary"),
+        }
+    }
+}
+
+impl Handshake {
+    /// Creates the hello of a robot that prefers the given encoding. JSON is always accepted.
+    ///
+    /// # Arguments
+    ///
+    /// * `encoding` - The preferred encoding.
+    pub fn hello(encoding: Encoding) -> Self {
+        let mut encodings = vec![encoding];
+        if encoding != Encoding::Json {
+            encodings.push(Encoding::Json);
+        }
+
+        Self::Hello {
+            min_version: MIN_PROTOCOL_VERSION,
+            version: PROTOCOL_VERSION,
+            encodings,
+        }
+    }
+
+    /// Answers a hello: the newest version both ends speak and the first encoding offered
+    /// that can be used with it.
+    ///
+    /// # Returns
+    ///
+    /// An `Accept` with the agreed codec, or a `Reject` if there's no version in common or
+    /// the message isn't a hello.
+    pub fn answer(&self) -> Self {
+        let Self::Hello {
+            min_version,
+            version,
+            encodings,
+        } = self
+        else {
+            return Self::Reject("Expected a hello".to_string());
+        };
+
+        let agreed = (*version).min(PROTOCOL_VERSION);
+        if agreed < (*min_version).max(MIN_PROTOCOL_VERSION) {
+            return Self::Reject(format!(
+                "No version in common: {min_version}..={version} and \
+                 {MIN_PROTOCOL_VERSION}..={PROTOCOL_VERSION}"
+            ));
+        }
+
+        let encoding = encodings.first().copied().unwrap_or_default();
+
+        Self::Accept(Codec {
+            version: agreed,
+            encoding,
+        })
+    }
+}
+
+impl Codec {
+    /// The codec of the messages that aren't versioned, like the handshake itself, which are
+    /// sent as bare JSON so that any version can read them.
+    pub const BARE: Self = Self {
+        version: 1,
+        encoding: Encoding::Json,
+    };
+
+    /// Encodes a message into a frame.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The message to encode.
+    ///
+    /// # Returns
+    ///
+    /// The frame: a line of JSON, or a binary frame that starts with `BINARY_MARKER`.
+    pub fn encode<M: Serialize>(&self, msg: M) -> Vec<u8> {
+        if self.version < 2 {
+            return fmt_msg(msg);
+        }
+
+        let envelope = Envelope {
+            v: self.version,
+            msg,
+        };
+
+        match self.encoding {
+            Encoding::Json => fmt_msg(envelope),
+            Encoding::Binary => {
+                let mut frame = vec![BINARY_MARKER];
+                if let Err(e) = bincode::serialize_into(&mut frame, &envelope) {
+                    eprintln!("Couldn't encode a message: {e}");
+                }
+
+                frame
+            }
+        }
+    }
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            encoding: Encoding::Json,
+        }
+    }
+}
+
+/// Decodes a frame sent with any version and encoding this build understands, or as bare
+/// JSON if it isn't versioned.
+///
+/// # Arguments
+///
+/// * `frame` - The frame to decode.
+///
+/// # Returns
+///
+/// The message, or an error if the frame isn't a message of the expected type.
+pub fn decode<M: DeserializeOwned>(frame: &[u8]) -> Result<M, ProtocolError> {
+    let envelope = match frame.split_first() {
+        Some((&BINARY_MARKER, bytes)) => {
+            bincode::deserialize::<Envelope<M>>(bytes).map_err(ProtocolError::Binary)?
+        }
+
+        _ => match serde_json::from_slice::<Envelope<M>>(frame) {
+            Ok(envelope) => envelope,
+            Err(_) => return serde_json::from_slice(frame).map_err(ProtocolError::Json),
+        },
+    };
+
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&envelope.v) {
+        return Err(ProtocolError::Version(envelope.v));
+    }
+
+    Ok(envelope.msg)
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json(e) => write!(f, "Invalid JSON message: {e}"),
+            Self::Binary(e) => write!(f, "Invalid binary message: {e}"),
+            Self::Version(version) => write!(f, "Unsupported protocol version {version}"),
+        }
+    }
+}
+
+impl Error for ProtocolError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        flavour::Flavour,
+        messages::robot_msg::RobotMsg,
+        orders::{Order, OrderId},
+        tokens::{OrderToken, TokenId},
+    };
+    use std::collections::HashMap;
+
+    fn order_token() -> OrderToken {
+        let mut token = OrderToken::new(0);
+        let orders = (0..20).map(|i| Order::new(OrderId::new(i % 3, i as usize), HashMap::new()));
+        token.upload_new_orders(orders);
+        for robot in 0..5 {
+            if let Some(order) = token.next_order() {
+                token.add_in_progress(robot, 1, order);
+            }
+        }
+
+        token
+    }
+
+    fn round_trip(codec: Codec) -> OrderToken {
+        let frame = codec.encode(RobotMsg::RecvOrderToken(order_token()));
+        match decode(&frame) {
+            Ok(RobotMsg::RecvOrderToken(token)) => token,
+            other => panic!("Unexpected message: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test01_every_codec_decodes_what_it_encodes() {
+        let binary = Codec {
+            version: PROTOCOL_VERSION,
+            encoding: Encoding::Binary,
+        };
+
+        for codec in [Codec::BARE, Codec::default(), binary] {
+            let token = round_trip(codec);
+            assert_eq!(token.orders().count(), 20);
+            assert_eq!(token.queue_len(), 15);
+        }
+    }
+
+    #[test]
+    fn test02_the_binary_encoding_is_smaller() {
+        let binary = Codec {
+            version: PROTOCOL_VERSION,
+            encoding: Encoding::Binary,
+        };
+        let msg = RobotMsg::RecvOrderToken(order_token());
+
+        let json = Codec::default().encode(msg.clone());
+        let binary = binary.encode(msg);
+
+        assert_eq!(binary[0], BINARY_MARKER);
+        assert!(binary.len() * 2 < json.len());
+    }
+
+    #[test]
+    fn test03_the_newest_common_version_and_first_encoding_are_agreed() {
+        let hello = Handshake::hello(Encoding::Binary);
+        assert_eq!(
+            hello.answer(),
+            Handshake::Accept(Codec {
+                version: PROTOCOL_VERSION,
+                encoding: Encoding::Binary,
+            })
+        );
+    }
+
+    #[test]
+    fn test04_peers_without_a_version_in_common_are_rejected() {
+        let newer = Handshake::Hello {
+            min_version: PROTOCOL_VERSION + 1,
+            version: PROTOCOL_VERSION + 2,
+            encodings: vec![Encoding::Json],
+        };
+
+        assert!(matches!(newer.answer(), Handshake::Reject(_)));
+
+        let older = Handshake::Hello {
+            min_version: 1,
+            version: 1,
+            encodings: vec![Encoding::Json],
+        };
+        assert!(matches!(older.answer(), Handshake::Reject(_)));
+    }
+
+    #[test]
+    fn test05_messages_of_unknown_versions_are_not_decoded() {
+        for v in [1, PROTOCOL_VERSION + 1] {
+            let frame = fmt_msg(Envelope {
+                v,
+                msg: RobotMsg::Heartbeat(1),
+            });
+
+            assert!(matches!(
+                decode::<RobotMsg>(&frame),
+                Err(ProtocolError::Version(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test06_end_of_use_carries_its_round_in_every_encoding() {
+        let bare = b"{\"EndOfUse\":\"Order\"}\n";
+        assert!(matches!(
+            decode::<RobotMsg>(bare),
+            Err(ProtocolError::Json(_))
+        ));
+
+        let binary = Codec {
+            version: PROTOCOL_VERSION,
+            encoding: Encoding::Binary,
+        };
+        let token_id = TokenId::Flavour(Flavour::Menta);
+        for codec in [Codec::default(), binary] {
+            let frame = codec.encode(RobotMsg::EndOfUse(token_id, 7));
+            assert!(matches!(
+                decode(&frame),
+                Ok(RobotMsg::EndOfUse(id, 7)) if id == token_id
+            ));
+        }
+    }
+}
@@ -1,19 +1,32 @@
 use crate::{
     orders::Order,
-    tokens::{FlavourToken, OrderToken, TokenId},
+    snapshot::{RobotSnapshot, SnapshotId},
+    tokens::{FlavourToken, Membership, OrderToken, TokenId},
 };
 use serde::{Deserialize, Serialize};
 
 /// Enum that represents the messages that the robot can receive
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum RobotMsg {
     // Prev
     RecvOrderToken(OrderToken),
     RecvFlavourToken(FlavourToken),
+    BackupOrder(u16, Order),
+    SnapshotPart(Box<RobotSnapshot>),
+
+    // New connection
+    Join(u16),
+    Link(u16),
 
     // Next
     Disconnect,
-    EndOfUse(TokenId),
+    EndOfUse(TokenId, u64),
+    Leave(u16),
+    Members(Membership),
+    Heartbeat(u16),
+
+    // Prev and next
+    Marker(SnapshotId),
 
     // Screen
     RecvOrder(Order),
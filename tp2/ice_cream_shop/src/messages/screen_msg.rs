@@ -1,9 +1,22 @@
-use crate::orders::OrderId;
+use crate::{orders::OrderId, stock::StockSummary, trace::TraceContext};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
-/// Enum that represents the messages that the screen can receive
+/// Enum that represents the messages that the screen can receive.
+/// `Stock` is the stock of the flavours a robot saw, sent every now and then.
 #[derive(Serialize, Deserialize)]
 pub enum ScreenMsg {
-    ConfirmOrder(OrderId),
-    CancelOrder(OrderId),
+    ConfirmOrder(OrderId, TraceContext),
+    CancelOrder(OrderId, TraceContext),
+    Stock(StockSummary),
+}
+
+/// The answer of a robot to an order handed to it by a screen, with how long the robot
+/// expects the orders ahead of it to take.
+/// - `Accepted`: The robot took the order.
+/// - `Busy`: The queue of the ring is full, so the robot refused the order.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Accepted(Duration),
+    Busy(Duration),
 }
@@ -0,0 +1,6 @@
+pub mod registry;
+pub mod server;
+pub mod shop_metrics;
+
+pub use registry::{registry, Metric, MetricKind, Registry};
+pub use server::serve;
@@ -0,0 +1,272 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Mutex, OnceLock},
+};
+
+/// Upper bounds of the histogram buckets, in seconds.
+const BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+/// Returns the registry of this process.
+pub fn registry() -> &'static Registry {
+    REGISTRY.get_or_init(Registry::default)
+}
+
+/// Enum that represents the Prometheus types of metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+/// Struct that describes a metric the shop exports.
+#[derive(Debug, Clone, Copy)]
+pub struct Metric {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: MetricKind,
+}
+
+/// Enum that represents the current value of a series.
+#[derive(Debug, Clone)]
+enum Value {
+    Counter(u64),
+    Gauge(f64),
+    Histogram {
+        buckets: [u64; BUCKETS.len()],
+        sum: f64,
+        count: u64,
+    },
+}
+
+/// Struct that holds every series of a metric, by their labels.
+#[derive(Debug)]
+struct Family {
+    metric: Metric,
+    series: BTreeMap<String, Value>,
+}
+
+/// Struct that holds the metrics of a process and renders them in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Registry {
+    families: Mutex<BTreeMap<&'static str, Family>>,
+}
+
+/// Formats labels as `{key="value",...}`, or an empty string if there are none.
+fn fmt_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+
+    let labels: Vec<_> = labels
+        .iter()
+        .map(|(key, value)| format!("{key}=\"{}\"", value.replace('"', "\\\"")))
+        .collect();
+
+    format!("{{{}}}", labels.join(","))
+}
+
+/// Adds a label to an already formatted set of labels.
+fn add_label(labels: &str, label: &str) -> String {
+    match labels.strip_suffix('}') {
+        Some(labels) => format!("{labels},{label}}}"),
+        None => format!("{{{label}}}"),
+    }
+}
+
+impl Registry {
+    /// Applies a change to the series of a metric with the given labels,
+    /// creating it if it's the first time it's used.
+    fn update(
+        &self,
+        metric: Metric,
+        labels: &[(&str, &str)],
+        init: Value,
+        change: impl FnOnce(&mut Value),
+    ) {
+        let Ok(mut families) = self.families.lock() else {
+            return;
+        };
+
+        let family = families.entry(metric.name).or_insert_with(|| Family {
+            metric,
+            series: BTreeMap::new(),
+        });
+
+        change(family.series.entry(fmt_labels(labels)).or_insert(init));
+    }
+
+    /// Increments a counter.
+    ///
+    /// # Arguments
+    ///
+    /// * `metric` - The counter to increment.
+    /// * `labels` - The labels of the series.
+    /// * `by` - How much to increment it.
+    pub fn inc(&self, metric: Metric, labels: &[(&str, &str)], by: u64) {
+        self.update(metric, labels, Value::Counter(0), |value| {
+            if let Value::Counter(count) = value {
+                *count += by;
+            }
+        });
+    }
+
+    /// Sets the value of a gauge.
+    ///
+    /// # Arguments
+    ///
+    /// * `metric` - The gauge to set.
+    /// * `labels` - The labels of the series.
+    /// * `to` - The new value of the gauge.
+    pub fn set(&self, metric: Metric, labels: &[(&str, &str)], to: f64) {
+        self.update(metric, labels, Value::Gauge(to), |value| {
+            *value = Value::Gauge(to);
+        });
+    }
+
+    /// Adds an observation to a histogram.
+    ///
+    /// # Arguments
+    ///
+    /// * `metric` - The histogram to observe.
+    /// * `labels` - The labels of the series.
+    /// * `seconds` - The observed value.
+    pub fn observe(&self, metric: Metric, labels: &[(&str, &str)], seconds: f64) {
+        let init = Value::Histogram {
+            buckets: [0; BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        };
+
+        self.update(metric, labels, init, |value| {
+            if let Value::Histogram {
+                buckets,
+                sum,
+                count,
+            } = value
+            {
+                for (bucket, bound) in buckets.iter_mut().zip(BUCKETS) {
+                    if seconds <= bound {
+                        *bucket += 1;
+                    }
+                }
+
+                *sum += seconds;
+                *count += 1;
+            }
+        });
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let Ok(families) = self.families.lock() else {
+            return out;
+        };
+
+        for family in families.values() {
+            let Metric { name, help, kind } = family.metric;
+            let kind = match kind {
+                MetricKind::Counter => "counter",
+                MetricKind::Gauge => "gauge",
+                MetricKind::Histogram => "histogram",
+            };
+
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+
+            for (labels, value) in &family.series {
+                match value {
+                    Value::Counter(count) => {
+                        let _ = writeln!(out, "{name}{labels} {count}");
+                    }
+
+                    Value::Gauge(value) => {
+                        let _ = writeln!(out, "{name}{labels} {value}");
+                    }
+
+                    Value::Histogram {
+                        buckets,
+                        sum,
+                        count,
+                    } => {
+                        for (bucket, bound) in buckets.iter().zip(BUCKETS) {
+                            let le = add_label(labels, &format!("le=\"{bound}\""));
+                            let _ = writeln!(out, "{name}_bucket{le} {bucket}");
+                        }
+
+                        let le = add_label(labels, "le=\"+Inf\"");
+                        let _ = writeln!(out, "{name}_bucket{le} {count}");
+                        let _ = writeln!(out, "{name}_sum{labels} {sum}");
+                        let _ = writeln!(out, "{name}_count{labels} {count}");
+                    }
+                }
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COUNTER: Metric = Metric {
+        name: "test_total",
+        help: "A counter.",
+        kind: MetricKind::Counter,
+    };
+
+    const GAUGE: Metric = Metric {
+        name: "test_gauge",
+        help: "A gauge.",
+        kind: MetricKind::Gauge,
+    };
+
+    const HISTOGRAM: Metric = Metric {
+        name: "test_seconds",
+        help: "A histogram.",
+        kind: MetricKind::Histogram,
+    };
+
+    #[test]
+    fn test01_counters_are_rendered_by_label() {
+        let registry = Registry::default();
+        registry.inc(COUNTER, &[("flavour", "menta")], 2);
+        registry.inc(COUNTER, &[("flavour", "menta")], 1);
+        registry.inc(COUNTER, &[("flavour", "chocolate")], 1);
+
+        let text = registry.render();
+        assert!(text.contains("# TYPE test_total counter"));
+        assert!(text.contains("test_total{flavour=\"menta\"} 3"));
+        assert!(text.contains("test_total{flavour=\"chocolate\"} 1"));
+    }
+
+    #[test]
+    fn test02_gauges_keep_the_last_value() {
+        let registry = Registry::default();
+        registry.set(GAUGE, &[], 4.0);
+        registry.set(GAUGE, &[], 2.0);
+        assert!(registry.render().contains("test_gauge 2\n"));
+    }
+
+    #[test]
+    fn test03_histograms_are_cumulative() {
+        let registry = Registry::default();
+        registry.observe(HISTOGRAM, &[("step", "a")], 0.003);
+        registry.observe(HISTOGRAM, &[("step", "a")], 2.0);
+
+        let text = registry.render();
+        assert!(text.contains("test_seconds_bucket{step=\"a\",le=\"0.001\"} 0"));
+        assert!(text.contains("test_seconds_bucket{step=\"a\",le=\"0.005\"} 1"));
+        assert!(text.contains("test_seconds_bucket{step=\"a\",le=\"2.5\"} 2"));
+        assert!(text.contains("test_seconds_bucket{step=\"a\",le=\"+Inf\"} 2"));
+        assert!(text.contains("test_seconds_count{step=\"a\"} 2"));
+    }
+}
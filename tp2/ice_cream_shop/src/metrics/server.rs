@@ -0,0 +1,49 @@
+use super::registry;
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+/// Answers a single HTTP request with the metrics of the process.
+fn respond(stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+
+    let (status, body) = match request.split_whitespace().nth(1) {
+        Some("/metrics") | Some("/") => ("200 OK", registry().render()),
+        _ => ("404 Not Found", "Not found\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    reader.into_inner().write_all(response.as_bytes())
+}
+
+/// Starts a thread that serves the metrics of the process in the Prometheus
+/// text format at `http://127.0.0.1:<port>/metrics`.
+///
+/// # Arguments
+///
+/// * `port` - The local port to listen on.
+///
+/// # Returns
+///
+/// An io::Result indicating if the port could be bound.
+pub fn serve(port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(format!("127.0.0.1:{port}"))?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = respond(stream) {
+                eprintln!("Couldn't serve metrics: {e}");
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use super::{Metric, MetricKind};
+
+const fn counter(name: &'static str, help: &'static str) -> Metric {
+    Metric {
+        name,
+        help,
+        kind: MetricKind::Counter,
+    }
+}
+
+const fn gauge(name: &'static str, help: &'static str) -> Metric {
+    Metric {
+        name,
+        help,
+        kind: MetricKind::Gauge,
+    }
+}
+
+const fn histogram(name: &'static str, help: &'static str) -> Metric {
+    Metric {
+        name,
+        help,
+        kind: MetricKind::Histogram,
+    }
+}
+
+// Robot
+
+pub const ORDER_TOKENS_HANDLED: Metric = counter(
+    "robot_order_tokens_handled_total",
+    "Times this robot handled the OrderToken.",
+);
+
+pub const ORDER_TOKEN_HANDLING: Metric = histogram(
+    "robot_order_token_handling_seconds",
+    "Time spent handling the OrderToken.",
+);
+
+pub const ORDER_TOKEN_ROTATION: Metric = histogram(
+    "robot_order_token_rotation_seconds",
+    "Time between two visits of the OrderToken to this robot.",
+);
+
+pub const ORDERS_RECEIVED: Metric = counter(
+    "robot_orders_received_total",
+    "Orders received from the screens.",
+);
+
+pub const ORDERS_REFUSED: Metric = counter(
+    "robot_orders_refused_total",
+    "Orders refused because the queue of the OrderToken was full.",
+);
+
+pub const ORDERS_EXPIRED: Metric = counter(
+    "robot_orders_expired_total",
+    "Orders cancelled because their deadline passed before a robot took them.",
+);
+
+pub const ORDERS_QUEUED: Metric = gauge(
+    "robot_orders_queue_depth",
+    "Orders waiting in the OrderToken the last time it passed by.",
+);
+
+pub const SERVINGS_TAKEN: Metric = counter(
+    "robot_servings_taken_total",
+    "Servings taken from the FlavourTokens, by flavour.",
+);
+
+pub const FLAVOUR_STOCK: Metric = gauge(
+    "robot_flavour_stock_servings",
+    "Servings left in each FlavourToken the last time it passed by.",
+);
+
+pub const SCOOP_DURATION: Metric = histogram(
+    "robot_scoop_seconds",
+    "Time actually spent scooping a flavour, by flavour and robot.",
+);
+
+pub const SERVINGS_SCOOPED: Metric = counter(
+    "robot_servings_scooped_total",
+    "Servings scooped once the robot finished, by flavour and robot.",
+);
+
+pub const INVARIANT_VIOLATIONS: Metric = counter(
+    "robot_invariant_violations_total",
+    "Violations of the invariants of the tokens found by the checker, by invariant.",
+);
+
+pub const ORDERS_CONFIRMED: Metric = counter(
+    "robot_orders_confirmed_total",
+    "Orders completed and confirmed to the screens.",
+);
+
+pub const ORDERS_CANCELLED: Metric = counter(
+    "robot_orders_cancelled_total",
+    "Orders cancelled for lack of ice cream.",
+);
+
+pub const RING_PREV_ID: Metric = gauge(
+    "robot_ring_prev_id",
+    "Id of the previous robot in the ring, -1 if there is none.",
+);
+
+pub const RING_NEXT_ID: Metric = gauge(
+    "robot_ring_next_id",
+    "Id of the next robot in the ring, -1 if there is none.",
+);
+
+pub const RING_MEMBERS: Metric = gauge(
+    "robot_ring_members",
+    "Robots that are part of the ring, as seen by the robot.",
+);
+
+pub const NEXT_SUSPECTED: Metric = counter(
+    "robot_next_suspected_total",
+    "Times the next robot stopped sending heartbeats and was bypassed.",
+);
+
+pub const FEDERATION_SERVINGS: Metric = counter(
+    "robot_federation_servings_total",
+    "Servings moved between this shop and the other ones, by direction.",
+);
+
+// Auth
+
+pub const FRAMES_REJECTED: Metric = counter(
+    "auth_frames_rejected_total",
+    "Frames dropped because they weren't signed with the shop key or their sender wasn't allowed to send them, by reason.",
+);
+
+// Screen
+
+pub const VALIDATE_LATENCY: Metric = histogram(
+    "screen_validate_seconds",
+    "Time the gateway took to validate an order.",
+);
+
+pub const SCREEN_ORDERS: Metric = counter(
+    "screen_orders_total",
+    "Orders processed by the screen, by result.",
+);
+
+// Gateway
+
+pub const PAYMENTS: Metric = counter(
+    "gateway_payments_total",
+    "Payment requests handled by the gateway, by operation.",
+);
+
+pub const CAPTURE_LATENCY: Metric =
+    histogram("gateway_capture_seconds", "Time spent validating a card.");
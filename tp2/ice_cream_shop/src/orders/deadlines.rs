@@ -0,0 +1,185 @@
+use crate::trace::TraceContext;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// How long the screen remembers how an order ended, so that whatever the robots say about it
+/// later is still told apart.
+const ENDED_WINDOW: Duration = Duration::from_secs(60);
+
+/// The orders a screen handed to the robots and didn't hear back about, with when the screen
+/// gives up on each one. Once the screen gives up on an order, because it timed out or its
+/// customer withdrew it, whatever the robots say about it later is ignored, so that its
+/// customer is told how it ended only once.
+/// - `pending`: The deadline and the trace of every order that didn't end, by order number.
+/// - `settled`: The orders a robot said how they ended, with when it did.
+/// - `given_up`: The orders the screen gave up on, with when it did.
+///
+/// The orders that ended are forgotten after a while, so that they don't pile up.
+#[derive(Debug, Default)]
+pub struct Deadlines {
+    pending: HashMap<usize, (Instant, TraceContext)>,
+    settled: HashMap<usize, Instant>,
+    given_up: HashMap<usize, Instant>,
+}
+
+impl Deadlines {
+    /// Starts waiting on an order.
+    ///
+    /// # Arguments
+    ///
+    /// * `number` - The number of the order.
+    /// * `deadline` - When the screen gives up on it.
+    /// * `trace` - The trace of the order.
+    pub fn track(&mut self, number: usize, deadline: Instant, trace: TraceContext) {
+        self.pending.insert(number, (deadline, trace));
+    }
+
+    /// Stops waiting on an order that no robot took.
+    ///
+    /// # Arguments
+    ///
+    /// * `number` - The number of the order.
+    pub fn forget(&mut self, number: usize) {
+        self.pending.remove(&number);
+    }
+
+    /// Records that a robot said how an order ended.
+    ///
+    /// # Arguments
+    ///
+    /// * `number` - The number of the order.
+    ///
+    /// # Returns
+    ///
+    /// False if the screen already gave up on the order, so the result has to be ignored.
+    pub fn settle(&mut self, number: usize) -> bool {
+        let now = Instant::now();
+        self.prune(now);
+        self.pending.remove(&number);
+        self.settled.insert(number, now);
+        !self.given_up.contains_key(&number)
+    }
+
+    /// Gives up on an order before its deadline, whether the screen was waiting on it or not.
+    ///
+    /// # Arguments
+    ///
+    /// * `number` - The number of the order.
+    ///
+    /// # Returns
+    ///
+    /// False if a robot already said how the order ended or the screen already gave up on it.
+    pub fn give_up(&mut self, number: usize) -> bool {
+        let now = Instant::now();
+        self.prune(now);
+        if self.settled.contains_key(&number) || self.given_up.contains_key(&number) {
+            return false;
+        }
+
+        self.given_up.insert(number, now);
+        self.pending.remove(&number);
+        true
+    }
+
+    /// Times out the orders whose deadline passed, and forgets the ones that ended long ago.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time.
+    ///
+    /// # Returns
+    ///
+    /// The number and the trace of every order that timed out.
+    pub fn expire(&mut self, now: Instant) -> Vec<(usize, TraceContext)> {
+        self.prune(now);
+        let expired: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, (deadline, _))| *deadline <= now)
+            .map(|(number, (_, trace))| (*number, *trace))
+            .collect();
+
+        for (number, _) in &expired {
+            self.pending.remove(number);
+            self.given_up.insert(*number, now);
+        }
+
+        expired
+    }
+
+    /// Forgets the orders that ended more than `ENDED_WINDOW` before `now`.
+    fn prune(&mut self, now: Instant) {
+        let recent = |ended: &mut Instant| *ended + ENDED_WINDOW > now;
+        self.settled.retain(|_, ended| recent(ended));
+        self.given_up.retain(|_, ended| recent(ended));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test01_an_order_times_out_once_its_deadline_passes() {
+        let mut deadlines = Deadlines::default();
+        let start = Instant::now();
+        deadlines.track(0, start + Duration::from_secs(1), TraceContext::default());
+        deadlines.track(1, start + Duration::from_secs(5), TraceContext::default());
+
+        assert!(deadlines.expire(start).is_empty());
+        let expired = deadlines.expire(start + Duration::from_secs(2));
+        assert_eq!(expired, vec![(0, TraceContext::default())]);
+        assert!(deadlines.expire(start + Duration::from_secs(2)).is_empty());
+    }
+
+    #[test]
+    fn test02_the_results_of_orders_that_timed_out_are_ignored() {
+        let mut deadlines = Deadlines::default();
+        let start = Instant::now();
+        deadlines.track(0, start, TraceContext::default());
+        deadlines.track(1, start, TraceContext::default());
+
+        assert!(deadlines.settle(1));
+        deadlines.expire(start);
+        assert!(!deadlines.settle(0));
+        assert!(deadlines.settle(2));
+    }
+
+    #[test]
+    fn test03_only_orders_that_did_not_end_can_be_given_up() {
+        let mut deadlines = Deadlines::default();
+        let start = Instant::now();
+        deadlines.track(0, start + Duration::from_secs(1), TraceContext::default());
+
+        assert!(deadlines.give_up(0));
+        assert!(!deadlines.give_up(0));
+        assert!(deadlines.expire(start + Duration::from_secs(2)).is_empty());
+        assert!(!deadlines.settle(0));
+
+        assert!(deadlines.settle(1));
+        assert!(!deadlines.give_up(1));
+    }
+
+    #[test]
+    fn test04_the_orders_that_ended_long_ago_are_forgotten() {
+        let mut deadlines = Deadlines::default();
+        let start = Instant::now();
+        deadlines.track(0, start, TraceContext::default());
+        deadlines.track(1, start + ENDED_WINDOW * 2, TraceContext::default());
+
+        assert!(deadlines.settle(2));
+        assert!(deadlines.give_up(3));
+        assert_eq!(deadlines.expire(start).len(), 1);
+        assert_eq!((deadlines.settled.len(), deadlines.given_up.len()), (1, 2));
+
+        assert!(deadlines
+            .expire(start + ENDED_WINDOW + Duration::from_secs(1))
+            .is_empty());
+        assert!(deadlines.settled.is_empty());
+        assert!(deadlines.given_up.is_empty());
+        assert_eq!(deadlines.pending.len(), 1);
+    }
+}
@@ -1,7 +1,9 @@
 pub mod client_order;
+pub mod deadlines;
 pub mod order;
 pub mod order_id;
 
 pub use client_order::ClientOrder;
+pub use deadlines::Deadlines;
 pub use order::Order;
 pub use order_id::OrderId;
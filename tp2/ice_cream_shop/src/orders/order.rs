@@ -1,5 +1,5 @@
 use super::{client_order::ClientOrder, order_id::OrderId};
-use crate::flavour::Flavour;
+use crate::{flavour::Flavour, trace::TraceContext};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -9,11 +9,18 @@ use std::collections::HashMap;
 ///
 /// * `id` - The id of the order.
 /// * `flavours` - The flavours of the order.
-/// * `cup_size` - The size of the cup.
+/// * `trace` - The trace the order carries through the shop.
+/// * `deadline_us` - When the screen gives up on the order, in microseconds since the unix
+///   epoch, if it has a deadline. It's read from the wall clock of the screen and checked
+///   against the one of each robot, so their clocks have to be in sync.
 #[derive(PartialEq, Eq, Serialize, Deserialize, Debug, Clone)]
 pub struct Order {
     id: OrderId,
     flavours: HashMap<Flavour, usize>,
+    #[serde(default)]
+    trace: TraceContext,
+    #[serde(default)]
+    deadline_us: Option<u64>,
 }
 
 impl Order {
@@ -32,6 +39,8 @@ impl Order {
         Self {
             id: OrderId::new(screen_id, order_number),
             flavours: client_order.flavours,
+            trace: TraceContext::default(),
+            deadline_us: None,
         }
     }
 
@@ -46,7 +55,65 @@ impl Order {
     ///
     /// A new Order.
     pub fn new(id: OrderId, flavours: HashMap<Flavour, usize>) -> Self {
-        Self { id, flavours }
+        Self {
+            id,
+            flavours,
+            trace: TraceContext::default(),
+            deadline_us: None,
+        }
+    }
+
+    /// Sets the trace the order carries.
+    ///
+    /// # Arguments
+    ///
+    /// * `trace` - The trace context started for the order.
+    ///
+    /// # Returns
+    ///
+    /// The order with the given trace.
+    pub fn with_trace(mut self, trace: TraceContext) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Sets when the screen gives up on the order.
+    ///
+    /// # Arguments
+    ///
+    /// * `deadline_us` - The deadline, in microseconds since the unix epoch. The robots check
+    ///   it against their own wall clock, so it's only as accurate as their clocks are in sync.
+    ///
+    /// # Returns
+    ///
+    /// The order with the given deadline.
+    pub fn with_deadline(mut self, deadline_us: u64) -> Self {
+        self.deadline_us = Some(deadline_us);
+        self
+    }
+
+    /// Returns when the screen gives up on the order, if it has a deadline.
+    pub fn deadline_us(&self) -> Option<u64> {
+        self.deadline_us
+    }
+
+    /// Returns whether the deadline of the order passed.
+    ///
+    /// # Arguments
+    ///
+    /// * `now_us` - The current time, in microseconds since the unix epoch.
+    pub fn is_expired(&self, now_us: u64) -> bool {
+        self.deadline_us.is_some_and(|deadline| deadline <= now_us)
+    }
+
+    /// Returns the trace context of the order.
+    pub fn trace(&self) -> TraceContext {
+        self.trace
+    }
+
+    /// Returns a mutable reference to the trace context of the order, to record its steps.
+    pub fn trace_mut(&mut self) -> &mut TraceContext {
+        &mut self.trace
     }
 
     /// Returns the id of the order.
@@ -54,6 +121,13 @@ impl Order {
         self.id
     }
 
+    /// Returns the flavours of the order that weren't served yet, with their servings.
+    pub fn flavours(&self) -> impl Iterator<Item = (Flavour, usize)> + '_ {
+        self.flavours
+            .iter()
+            .map(|(flavour, servings)| (*flavour, *servings))
+    }
+
     /// Returns the flavours of the order.
     ///
     /// # Arguments
@@ -88,6 +162,7 @@ impl Order {
     }
 }
 
+#[allow(clippy::empty_line_after_outer_attr)]
 #[cfg(test)]
 
 mod tests {
@@ -98,6 +173,7 @@ mod tests {
     };
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test01_an_order_can_be_created_from_a_client_order() {
         let client_order = ClientOrder {
             flavours: vec![Flavour::Chocolate, Flavour::DulceDeLeche]
@@ -122,6 +198,8 @@ mod tests {
                 .into_iter()
                 .map(|flavour| (flavour, 1))
                 .collect(),
+            trace: TraceContext::default(),
+            deadline_us: None,
         };
         assert_eq!(order.cross(Flavour::Chocolate), Some(1));
         assert_eq!(order.cross(Flavour::Chocolate), None);
@@ -130,6 +208,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test03_an_order_without_flavours_is_completed() {
         let mut order = Order {
             id: OrderId::new(1, 1),
@@ -137,6 +216,8 @@ mod tests {
                 .into_iter()
                 .map(|flavour| (flavour, 1))
                 .collect(),
+            trace: TraceContext::default(),
+            deadline_us: None,
         };
         assert_eq!(order.is_completed(), false);
         order.cross(Flavour::Chocolate);
@@ -144,4 +225,14 @@ mod tests {
         order.cross(Flavour::DulceDeLeche);
         assert_eq!(order.is_completed(), true);
     }
+
+    #[test]
+    fn test04_an_order_expires_once_its_deadline_passes() {
+        let order = Order::new(OrderId::new(1, 1), HashMap::new());
+        assert!(!order.is_expired(u64::MAX));
+
+        let order = order.with_deadline(1_000);
+        assert!(!order.is_expired(999));
+        assert!(order.is_expired(1_000));
+    }
 }
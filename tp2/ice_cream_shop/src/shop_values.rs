@@ -11,3 +11,13 @@ pub const ROBOT_STARTING_PORT: u16 = 8000;
 pub const SCREEN_STARTING_PORT: u16 = 9000;
 
 pub const ROBOT_SCREEN_STARTING_PORT: u16 = ROBOT_STARTING_PORT + N_ROBOTS;
+
+pub const ROBOT_ADMIN_STARTING_PORT: u16 = 6000;
+
+pub const FEDERATION_STARTING_PORT: u16 = 9500;
+
+pub const METRICS_STARTING_PORT: u16 = 7000;
+
+pub const SCREEN_METRICS_STARTING_PORT: u16 = METRICS_STARTING_PORT + N_ROBOTS;
+
+pub const GATEWAY_METRICS_PORT: u16 = SCREEN_METRICS_STARTING_PORT + N_SCREEN;
@@ -0,0 +1,218 @@
+use crate::{
+    flavour::Flavour,
+    messages::robot_msg::RobotMsg,
+    orders::Order,
+    tokens::{FlavourToken, Membership, OrderToken},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Identifies a snapshot of the ring.
+/// - `initiator`: The robot that started it.
+/// - `incarnation`: The incarnation of the robot when it started it.
+/// - `seq`: How many snapshots the robot started before it in that incarnation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SnapshotId {
+    pub initiator: u16,
+    pub incarnation: u64,
+    pub seq: u64,
+}
+
+/// What a robot recorded for a snapshot: its own state when it first heard of the snapshot,
+/// and the messages that arrived on each of its channels after that and before the marker.
+/// - `snapshot`: The snapshot it's part of.
+/// - `id`: The ID of the robot.
+/// - `prev_id`: The ID of the previous robot in the ring.
+/// - `next_id`: The ID of the next robot in the ring.
+/// - `status`: Whether the robot was joining, serving, draining or leaving the ring.
+/// - `members`: The view of the ring of the robot.
+/// - `current_order`: The order being served.
+/// - `serving_flavour`: The flavour being scooped.
+/// - `new_orders`: The orders received that weren't uploaded to the order token yet.
+/// - `backups`: The copies of the orders of the previous robots, by robot.
+/// - `stashed_order_token`: The copy of the order token in the token box, if any.
+/// - `stashed_flavour_tokens`: The copies of the flavour tokens in the token box.
+/// - `from_prev`: The messages in flight from the previous robot.
+/// - `from_next`: The messages in flight from the next robot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RobotSnapshot {
+    pub snapshot: SnapshotId,
+    pub id: u16,
+    pub prev_id: Option<u16>,
+    pub next_id: Option<u16>,
+    pub status: String,
+    pub members: Membership,
+    pub current_order: Option<Order>,
+    pub serving_flavour: Option<Flavour>,
+    pub new_orders: Vec<Order>,
+    pub backups: BTreeMap<u16, Vec<Order>>,
+    pub stashed_order_token: Option<OrderToken>,
+    pub stashed_flavour_tokens: Vec<FlavourToken>,
+    pub from_prev: Vec<RobotMsg>,
+    pub from_next: Vec<RobotMsg>,
+}
+
+/// A consistent picture of the whole ring, put together from the parts of every robot.
+/// A token can be copied in several places, in token boxes and in flight, so the copy with
+/// the highest round is taken as the current one.
+/// - `id`: The ID of the snapshot.
+/// - `robots`: The parts of the robots, sorted by ID.
+/// - `order_token`: The current copy of the order token.
+/// - `flavour_tokens`: The current copies of the flavour tokens, sorted by flavour.
+/// - `stock`: The servings left of every flavour, by name.
+/// - `pending_orders`: The orders that aren't done yet: the ones in the order token and the
+///   ones that the robots didn't upload yet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GlobalSnapshot {
+    pub id: SnapshotId,
+    pub robots: Vec<RobotSnapshot>,
+    pub order_token: Option<OrderToken>,
+    pub flavour_tokens: Vec<FlavourToken>,
+    pub stock: BTreeMap<String, usize>,
+    pub pending_orders: Vec<Order>,
+}
+
+impl RobotSnapshot {
+    /// Returns every copy of a token that the robot held or that was in flight towards it.
+    fn token_copies(&self) -> impl Iterator<Item = TokenCopy<'_>> {
+        let stashed = self
+            .stashed_order_token
+            .iter()
+            .map(TokenCopy::Order)
+            .chain(self.stashed_flavour_tokens.iter().map(TokenCopy::Flavour));
+
+        let in_flight = self.from_prev.iter().filter_map(|msg| match msg {
+            RobotMsg::RecvOrderToken(token) => Some(TokenCopy::Order(token)),
+            RobotMsg::RecvFlavourToken(token) => Some(TokenCopy::Flavour(token)),
+            _ => None,
+        });
+
+        stashed.chain(in_flight)
+    }
+}
+
+/// A copy of a token found in a snapshot.
+enum TokenCopy<'a> {
+    Order(&'a OrderToken),
+    Flavour(&'a FlavourToken),
+}
+
+impl GlobalSnapshot {
+    /// Puts the parts of the robots together.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the snapshot.
+    /// * `robots` - The parts of every robot of the ring.
+    pub fn assemble(id: SnapshotId, mut robots: Vec<RobotSnapshot>) -> Self {
+        robots.sort_by_key(|robot| robot.id);
+
+        let mut order_token: Option<&OrderToken> = None;
+        let mut flavour_tokens: HashMap<Flavour, &FlavourToken> = HashMap::new();
+        for copy in robots.iter().flat_map(RobotSnapshot::token_copies) {
+            match copy {
+                TokenCopy::Order(token) => {
+                    if order_token.is_none_or(|current| current.round() < token.round()) {
+                        order_token = Some(token);
+                    }
+                }
+                TokenCopy::Flavour(token) => {
+                    let current = flavour_tokens.entry(token.flavour()).or_insert(token);
+                    if current.round() < token.round() {
+                        *current = token;
+                    }
+                }
+            }
+        }
+
+        let mut flavour_tokens: Vec<_> = flavour_tokens.into_values().cloned().collect();
+        flavour_tokens.sort_by_key(|token| token.flavour().name());
+        let stock = flavour_tokens
+            .iter()
+            .map(|token| (token.flavour().name().to_string(), token.servings()))
+            .collect();
+
+        let mut seen = HashSet::new();
+        let pending_orders = order_token
+            .into_iter()
+            .flat_map(OrderToken::orders)
+            .chain(robots.iter().flat_map(|robot| &robot.new_orders))
+            .filter(|order| seen.insert(order.id()))
+            .cloned()
+            .collect();
+
+        Self {
+            id,
+            order_token: order_token.cloned(),
+            flavour_tokens,
+            stock,
+            pending_orders,
+            robots,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orders::OrderId;
+
+    fn part(id: u16) -> RobotSnapshot {
+        RobotSnapshot {
+            snapshot: SnapshotId {
+                initiator: 0,
+                incarnation: 1,
+                seq: 1,
+            },
+            id,
+            prev_id: None,
+            next_id: None,
+            status: "serving".to_string(),
+            members: Membership::new(id),
+            current_order: None,
+            serving_flavour: None,
+            new_orders: Vec::new(),
+            backups: BTreeMap::new(),
+            stashed_order_token: None,
+            stashed_flavour_tokens: Vec::new(),
+            from_prev: Vec::new(),
+            from_next: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test01_the_copy_of_a_token_with_the_highest_round_is_the_current_one() {
+        let mut old = FlavourToken::new(0, Flavour::Menta, 10);
+        old.mark(0);
+        let mut new = old.clone();
+        new.take(3);
+        new.mark(1);
+
+        let mut first = part(0);
+        first.stashed_flavour_tokens.push(old);
+        let mut second = part(1);
+        second.from_prev.push(RobotMsg::RecvFlavourToken(new));
+
+        let snapshot = GlobalSnapshot::assemble(first.snapshot, vec![second, first]);
+        assert_eq!(snapshot.robots[0].id, 0);
+        assert_eq!(snapshot.stock.get("menta"), Some(&7));
+    }
+
+    #[test]
+    fn test02_the_pending_orders_are_counted_once() {
+        let order = Order::new(OrderId::new(1, 1), HashMap::new());
+        let other = Order::new(OrderId::new(1, 2), HashMap::new());
+        let mut token = OrderToken::new(0);
+        token.upload_new_orders([order.clone()].into_iter());
+
+        let mut first = part(0);
+        first.stashed_order_token = Some(token);
+        first.new_orders.push(order);
+        let mut second = part(1);
+        second.new_orders.push(other);
+
+        let snapshot = GlobalSnapshot::assemble(first.snapshot, vec![first, second]);
+        let ids: Vec<_> = snapshot.pending_orders.iter().map(Order::id).collect();
+        assert_eq!(ids, vec![OrderId::new(1, 1), OrderId::new(1, 2)]);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{flavour::Flavour, orders::ClientOrder};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, env, fmt, str::FromStr};
+
+/// Environment variable that selects what a screen does with an order that asks for more
+/// servings than the robots last said were left: `reject`, `warn` or `off`. Orders are
+/// rejected when it isn't set.
+pub const STOCK_CHECK_VAR: &str = "SHOP_STOCK_CHECK";
+
+/// What a robot saw of the stock of a flavour the last time its token passed by.
+/// - `servings`: The servings left that nobody reserved.
+/// - `round`: The round of the token, so that a newer level can be told apart from an older one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StockLevel {
+    pub servings: usize,
+    pub round: u64,
+}
+
+/// The stock of every flavour, as summarized by the robots.
+pub type StockSummary = HashMap<Flavour, StockLevel>;
+
+/// What a screen does with an order that asks for more servings than are left.
+/// - `Reject`: It turns the order away before capturing its payment.
+/// - `Warn`: It only warns that the order will probably be cancelled.
+/// - `Off`: It doesn't check the stock.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StockCheck {
+    #[default]
+    Reject,
+    Warn,
+    Off,
+}
+
+/// A flavour of an order that asks for more servings than were left.
+/// - `flavour`: The flavour.
+/// - `wanted`: The servings the order asks for.
+/// - `left`: The servings left the last time a robot saw the token of the flavour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shortage {
+    pub flavour: Flavour,
+    pub wanted: usize,
+    pub left: usize,
+}
+
+/// The latest stock a screen heard of. It's only advisory: other screens take servings and
+/// robots give back the ones they didn't scoop while the summaries travel, so an order that
+/// passes the check may still be cancelled by the robots.
+#[derive(Debug, Clone, Default)]
+pub struct StockBoard {
+    levels: StockSummary,
+}
+
+impl StockCheck {
+    /// Reads what to do with orders that ask for more than is left from `SHOP_STOCK_CHECK`.
+    ///
+    /// # Returns
+    ///
+    /// The selected check, `Reject` if the variable isn't set, or an error if it has an unknown value.
+    pub fn from_env() -> Result<Self, &'static str> {
+        match env::var(STOCK_CHECK_VAR) {
+            Ok(value) => value.parse(),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+}
+
+impl FromStr for StockCheck {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "reject" => Ok(Self::Reject),
+            "warn" => Ok(Self::Warn),
+            "off" => Ok(Self::Off),
+            _ => Err("SHOP_STOCK_CHECK needs to be reject, warn or off"),
+        }
+    }
+}
+
+impl StockBoard {
+    /// Takes in a summary sent by a robot. The level of a flavour is only replaced by one
+    /// seen in a later round of its token, so summaries that arrive out of order are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `summary` - The stock the robot saw.
+    pub fn update(&mut self, summary: StockSummary) {
+        for (flavour, level) in summary {
+            let known = self.levels.entry(flavour).or_insert(level);
+            if level.round > known.round {
+                *known = level;
+            }
+        }
+    }
+
+    /// Returns the first flavour of an order that asks for more servings than were left.
+    /// Flavours no robot said anything about are taken to have enough.
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - The order to check.
+    pub fn shortage(&self, order: &ClientOrder) -> Option<Shortage> {
+        Flavour::flavours().find_map(|flavour| {
+            let wanted = *order.flavours.get(&flavour)?;
+            let left = self.levels.get(&flavour)?.servings;
+            (wanted > left).then_some(Shortage {
+                flavour,
+                wanted,
+                left,
+            })
+        })
+    }
+}
+
+impl fmt::Display for Shortage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} servings of {} wanted, {} left the last time a robot looked",
+            self.wanted,
+            self.flavour.name(),
+            self.left
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(servings: usize, round: u64) -> StockLevel {
+        StockLevel { servings, round }
+    }
+
+    fn order(flavours: &[(Flavour, usize)]) -> ClientOrder {
+        ClientOrder {
+            flavours: flavours.iter().copied().collect(),
+            card_number: "6666-1111-2222-3333".to_string(),
+        }
+    }
+
+    #[test]
+    fn test01_only_newer_levels_replace_the_known_ones() {
+        let mut board = StockBoard::default();
+        board.update([(Flavour::Menta, level(2, 10))].into());
+        board.update([(Flavour::Menta, level(8, 4))].into());
+
+        let shortage = board.shortage(&order(&[(Flavour::Menta, 3)]));
+        assert_eq!(
+            shortage,
+            Some(Shortage {
+                flavour: Flavour::Menta,
+                wanted: 3,
+                left: 2
+            })
+        );
+
+        board.update([(Flavour::Menta, level(5, 12))].into());
+        assert_eq!(board.shortage(&order(&[(Flavour::Menta, 3)])), None);
+    }
+
+    #[test]
+    fn test02_flavours_without_a_level_are_taken_to_have_enough() {
+        let mut board = StockBoard::default();
+        board.update([(Flavour::Chocolate, level(0, 1))].into());
+
+        assert_eq!(board.shortage(&order(&[(Flavour::Frutilla, 10)])), None);
+        assert!(board
+            .shortage(&order(&[(Flavour::Frutilla, 1), (Flavour::Chocolate, 1)]))
+            .is_some());
+        assert_eq!("warn".parse(), Ok(StockCheck::Warn));
+        assert!("maybe".parse::<StockCheck>().is_err());
+    }
+}
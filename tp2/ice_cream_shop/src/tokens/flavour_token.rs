@@ -1,12 +1,62 @@
-use crate::{flavour::Flavour, tokens::TokenId};
+use crate::{
+    flavour::Flavour,
+    messages::federation_msg::{Transfer, TransferId},
+    orders::OrderId,
+    tokens::TokenId,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
 
-/// Struct that represents the token that carries a flavour
+/// How many of the last transfers from other shops that were forgotten a token remembers.
+const DONE_WINDOW: usize = 32;
+
+/// Servings that a robot claimed from the token and may still be scooping.
+///
+/// # Attributes
+///
+/// * `order_id` - The order the servings are for.
+/// * `servings` - How many servings were claimed.
+/// * `incarnation` - The incarnation of the robot that claimed them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reservation {
+    pub order_id: OrderId,
+    pub servings: usize,
+    pub incarnation: u64,
+}
+
+/// Struct that represents the token that carries a flavour.
+/// Robots claim servings by reserving them and pass the token on before scooping, so the
+/// token also carries the reservations of the robots that didn't get it back since then.
+///
+/// # Attributes
+///
+/// * `sender` - The id of the sender.
+/// * `flavour` - The flavour of the token.
+/// * `servings` - The servings left that nobody reserved.
+/// * `reservations` - The servings reserved by every robot.
+/// * `round` - How many times the token was passed.
+/// * `held` - The servings taken out of the token for other shops, until they add them to
+///   their own tokens.
+/// * `received` - The transfers from other shops that were added to the token, until the
+///   donors drop the servings they held for them.
+/// * `imported` - The servings other shops gave to this one since the token was created.
+/// * `done` - The last transfers from other shops that were forgotten, the newest last, so
+///   that an offer of them that arrives late isn't added again.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FlavourToken {
     sender: u16,
     flavour: Flavour,
     servings: usize,
+    reservations: BTreeMap<u16, Reservation>,
+    round: u64,
+    #[serde(default)]
+    held: Vec<Transfer>,
+    #[serde(default)]
+    received: Vec<Transfer>,
+    #[serde(default)]
+    imported: usize,
+    #[serde(default)]
+    done: VecDeque<TransferId>,
 }
 
 impl FlavourToken {
@@ -26,17 +76,30 @@ impl FlavourToken {
             sender: id,
             flavour,
             servings,
+            reservations: BTreeMap::new(),
+            round: 0,
+            held: Vec::new(),
+            received: Vec::new(),
+            imported: 0,
+            done: VecDeque::new(),
         }
     }
 
     /// Marks the token with the given id.
-    /// This is used to know who sent the token.
+    /// This is used to know who sent the token. It also counts one more hop of the token,
+    /// so that a copy that stayed behind can be told apart from the token.
     ///
     /// # Arguments
     ///
     /// * `id` - The id to mark the token with.
     pub fn mark(&mut self, id: u16) {
         self.sender = id;
+        self.round += 1;
+    }
+
+    /// Returns how many times the token was passed.
+    pub fn round(&self) -> u64 {
+        self.round
     }
 
     /// Returns the id of the sender.
@@ -66,6 +129,23 @@ impl FlavourToken {
         self.servings
     }
 
+    /// Returns the servings of the flavour that weren't scooped yet: the ones left, the
+    /// ones reserved by robots that didn't finish scooping them and the ones held for other
+    /// shops that didn't take them yet.
+    pub fn stock(&self) -> usize {
+        self.servings
+            + self
+                .reservations
+                .values()
+                .map(|reservation| reservation.servings)
+                .sum::<usize>()
+            + self
+                .held
+                .iter()
+                .map(|transfer| transfer.servings)
+                .sum::<usize>()
+    }
+
     /// Returns wheter or not a token has enough servings for an order.
     ///
     /// # Arguments
@@ -95,6 +175,177 @@ impl FlavourToken {
         min
     }
 
+    /// Reserves servings for an order of a robot, so that it can pass the token on and then scoop.
+    /// If the token has less servings than the given number, it will reserve all the servings.
+    ///
+    /// # Arguments
+    ///
+    /// * `robot_id` - The id of the robot.
+    /// * `incarnation` - The incarnation of the robot.
+    /// * `order_id` - The order the servings are for.
+    /// * `servings` - The number of servings to reserve.
+    ///
+    /// # Returns
+    ///
+    /// The number of servings reserved.
+    pub fn reserve(
+        &mut self,
+        robot_id: u16,
+        incarnation: u64,
+        order_id: OrderId,
+        servings: usize,
+    ) -> usize {
+        let servings = self.take(servings);
+        let reservation = Reservation {
+            order_id,
+            servings,
+            incarnation,
+        };
+
+        self.reservations.insert(robot_id, reservation);
+        servings
+    }
+
+    /// Returns the reservation of a robot, if it has one.
+    ///
+    /// # Arguments
+    ///
+    /// * `robot_id` - The id of the robot.
+    pub fn reservation(&self, robot_id: u16) -> Option<Reservation> {
+        self.reservations.get(&robot_id).copied()
+    }
+
+    /// Returns the ids of the robots that have a reservation.
+    pub fn reserved_by(&self) -> impl Iterator<Item = u16> + '_ {
+        self.reservations.keys().copied()
+    }
+
+    /// Removes the reservation of a robot that finished scooping. Its servings are gone.
+    ///
+    /// # Arguments
+    ///
+    /// * `robot_id` - The id of the robot.
+    pub fn settle(&mut self, robot_id: u16) -> Option<Reservation> {
+        self.reservations.remove(&robot_id)
+    }
+
+    /// Removes the reservation of a robot that won't scoop it, and puts its servings back.
+    ///
+    /// # Arguments
+    ///
+    /// * `robot_id` - The id of the robot.
+    pub fn release(&mut self, robot_id: u16) -> Option<Reservation> {
+        let reservation = self.reservations.remove(&robot_id)?;
+        self.servings += reservation.servings;
+        Some(reservation)
+    }
+
+    /// Takes the servings of a transfer out of the token and holds them for the shop that
+    /// asked for them. A transfer that is already held is left as it is.
+    ///
+    /// # Arguments
+    ///
+    /// * `transfer` - The transfer.
+    ///
+    /// # Returns
+    ///
+    /// Whether the servings are held, which they aren't if the token doesn't have enough.
+    pub fn hold(&mut self, transfer: Transfer) -> bool {
+        if self.held.iter().any(|held| held.id == transfer.id) {
+            return true;
+        }
+
+        if !self.has_enough(transfer.servings) {
+            return false;
+        }
+
+        self.servings -= transfer.servings;
+        self.held.push(transfer);
+        true
+    }
+
+    /// Drops the servings held for a transfer once the other shop added them to its token.
+    /// They are gone from this shop.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the transfer.
+    pub fn ship(&mut self, id: TransferId) -> Option<Transfer> {
+        let position = self.held.iter().position(|held| held.id == id)?;
+        Some(self.held.remove(position))
+    }
+
+    /// Returns the transfers whose servings are held for other shops.
+    pub fn held(&self) -> impl Iterator<Item = &Transfer> {
+        self.held.iter()
+    }
+
+    /// Adds the servings of a transfer from another shop to the token, unless they were
+    /// already added or the transfer is done.
+    ///
+    /// # Arguments
+    ///
+    /// * `transfer` - The transfer.
+    ///
+    /// # Returns
+    ///
+    /// Whether the servings were added now.
+    pub fn receive(&mut self, transfer: Transfer) -> bool {
+        if self.is_done(&transfer)
+            || self
+                .received
+                .iter()
+                .any(|received| received.id == transfer.id)
+        {
+            return false;
+        }
+
+        self.servings += transfer.servings;
+        self.imported += transfer.servings;
+        self.received.push(transfer);
+        true
+    }
+
+    /// Forgets a transfer that was added to the token once the donor dropped its servings.
+    /// The transfer is done from then on.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the transfer.
+    pub fn forget(&mut self, id: TransferId) -> Option<Transfer> {
+        let position = self
+            .received
+            .iter()
+            .position(|received| received.id == id)?;
+        if self.done.len() == DONE_WINDOW {
+            self.done.pop_front();
+        }
+
+        self.done.push_back(id);
+        Some(self.received.remove(position))
+    }
+
+    /// Returns whether a transfer from another shop was added to the token and forgotten.
+    /// Only the transfer itself is done, not the older ones of the same donor, since their
+    /// offers may still arrive.
+    ///
+    /// # Arguments
+    ///
+    /// * `transfer` - The transfer.
+    pub fn is_done(&self, transfer: &Transfer) -> bool {
+        self.done.contains(&transfer.id)
+    }
+
+    /// Returns the transfers added to the token that the donors didn't drop yet.
+    pub fn received(&self) -> impl Iterator<Item = &Transfer> {
+        self.received.iter()
+    }
+
+    /// Returns the servings other shops gave to this one since the token was created.
+    pub fn imported(&self) -> usize {
+        self.imported
+    }
+
     /// Returns the id of the token.
     ///
     /// # Returns
@@ -105,6 +356,7 @@ impl FlavourToken {
     }
 }
 
+#[allow(clippy::empty_line_after_outer_attr)]
 #[cfg(test)]
 
 mod tests {
@@ -123,6 +375,7 @@ mod tests {
         let mut token = FlavourToken::new(1, Flavour::BananaSplit, 2);
         token.mark(2);
         assert_eq!(token.sender(), 2);
+        assert_eq!(token.round(), 1);
     }
 
     #[test]
@@ -143,4 +396,117 @@ mod tests {
         assert_eq!(token.take(1), 0);
         assert_eq!(token.servings(), 0);
     }
+
+    #[test]
+    fn test05_reserved_servings_are_gone_once_settled() {
+        let mut token = FlavourToken::new(1, Flavour::BananaSplit, 5);
+        let order_id = OrderId::new(0, 1);
+        assert_eq!(token.reserve(2, 1, order_id, 2), 2);
+        assert_eq!(token.servings(), 3);
+        assert_eq!(token.reserved_by().collect::<Vec<_>>(), vec![2]);
+
+        assert_eq!(token.settle(2).map(|r| r.servings), Some(2));
+        assert_eq!(token.servings(), 3);
+        assert_eq!(token.reservation(2), None);
+    }
+
+    #[test]
+    fn test06_released_servings_go_back_to_the_token() {
+        let mut token = FlavourToken::new(1, Flavour::BananaSplit, 5);
+        token.reserve(2, 1, OrderId::new(0, 1), 2);
+        token.reserve(3, 1, OrderId::new(0, 2), 1);
+
+        assert!(token.release(2).is_some());
+        assert_eq!(token.servings(), 4);
+        assert_eq!(token.release(2), None);
+        assert_eq!(token.reserved_by().collect::<Vec<_>>(), vec![3]);
+    }
+
+    #[test]
+    fn test07_transferred_servings_move_once_between_tokens() {
+        let transfer = Transfer {
+            id: TransferId {
+                requester: 1,
+                seq: 7,
+            },
+            donor: 0,
+            flavour: Flavour::Chocolate,
+            servings: 3,
+        };
+        let mut donor = FlavourToken::new(0, Flavour::Chocolate, 5);
+        let mut requester = FlavourToken::new(0, Flavour::Chocolate, 1);
+
+        assert!(donor.hold(transfer));
+        assert!(donor.hold(transfer));
+        assert_eq!((donor.servings(), donor.stock()), (2, 5));
+        let sent: FlavourToken =
+            serde_json::from_str(&serde_json::to_string(&donor).unwrap()).unwrap();
+        assert_eq!(sent.held().collect::<Vec<_>>(), vec![&transfer]);
+
+        assert!(requester.receive(transfer));
+        assert!(!requester.receive(transfer));
+        assert_eq!((requester.servings(), requester.imported()), (4, 3));
+
+        assert!(donor.ship(transfer.id).is_some());
+        assert!(donor.ship(transfer.id).is_none());
+        assert_eq!(donor.stock(), 2);
+        assert!(requester.forget(transfer.id).is_some());
+        assert!(!FlavourToken::new(0, Flavour::Chocolate, 2).hold(transfer));
+    }
+
+    #[test]
+    fn test08_a_transfer_that_is_done_is_not_added_again() {
+        let transfer = Transfer {
+            id: TransferId {
+                requester: 1,
+                seq: 7,
+            },
+            donor: 0,
+            flavour: Flavour::Chocolate,
+            servings: 3,
+        };
+        let older = Transfer {
+            id: TransferId {
+                requester: 1,
+                seq: 6,
+            },
+            ..transfer
+        };
+        let mut requester = FlavourToken::new(0, Flavour::Chocolate, 1);
+
+        assert!(requester.receive(transfer));
+        assert!(requester.forget(transfer.id).is_some());
+        let sent: FlavourToken =
+            serde_json::from_str(&serde_json::to_string(&requester).unwrap()).unwrap();
+        assert!(sent.is_done(&transfer));
+
+        assert!(!requester.receive(transfer));
+        assert_eq!((requester.servings(), requester.imported()), (4, 3));
+        assert!(!sent.is_done(&older));
+        assert!(requester.receive(older));
+        assert_eq!((requester.servings(), requester.imported()), (7, 6));
+    }
+
+    #[test]
+    fn test09_only_the_last_transfers_that_are_done_are_remembered() {
+        let mut requester = FlavourToken::new(0, Flavour::Chocolate, 0);
+        let transfers: Vec<_> = (0..=DONE_WINDOW as u64)
+            .map(|seq| Transfer {
+                id: TransferId { requester: 1, seq },
+                donor: 0,
+                flavour: Flavour::Chocolate,
+                servings: 1,
+            })
+            .collect();
+
+        for transfer in &transfers {
+            requester.receive(*transfer);
+            requester.forget(transfer.id);
+        }
+
+        assert!(!requester.is_done(&transfers[0]));
+        assert!(transfers[1..]
+            .iter()
+            .all(|transfer| requester.is_done(transfer)));
+    }
 }
@@ -0,0 +1,244 @@
+use crate::shop_values::N_ROBOTS;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// What a view knows about a robot.
+///
+/// # Attributes
+///
+/// * `incarnation` - How many times the robot was admitted to the ring.
+/// * `alive` - Whether the robot is part of the ring in its current incarnation.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Member {
+    pub incarnation: u64,
+    pub alive: bool,
+}
+
+/// Struct that represents who is part of the ring, as seen by a robot.
+/// Every robot keeps its own view and merges it with the one that circulates with the
+/// `OrderToken`, so that joins and departures reach the whole ring.
+///
+/// Two views are merged robot by robot: the entry with the highest incarnation wins and,
+/// within the same incarnation, a departure wins over an admission. A robot that leaves
+/// and comes back is admitted with a new incarnation.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Membership {
+    members: BTreeMap<u16, Member>,
+}
+
+impl Membership {
+    /// Creates a view where the given robot is the only member.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the robot.
+    pub fn new(id: u16) -> Self {
+        let mut membership = Self::default();
+        membership.join(id);
+        membership
+    }
+
+    /// Admits a robot to the ring.
+    /// Robots that are already members are left as they are, and robots that left
+    /// are admitted with a new incarnation.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the robot.
+    ///
+    /// # Returns
+    ///
+    /// Whether the robot wasn't a member before.
+    pub fn join(&mut self, id: u16) -> bool {
+        match self.members.get_mut(&id) {
+            Some(member) if member.alive => false,
+            Some(member) => {
+                member.incarnation += 1;
+                member.alive = true;
+                true
+            }
+            None => {
+                let member = Member {
+                    incarnation: 1,
+                    alive: true,
+                };
+                self.members.insert(id, member);
+                true
+            }
+        }
+    }
+
+    /// Admits a robot that is entering the ring with a new incarnation, even if the view
+    /// thinks it's still a member. A robot that enters the ring again lost its state when it
+    /// went down, so whatever its previous incarnation left behind has to be told apart.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the robot.
+    ///
+    /// # Returns
+    ///
+    /// The incarnation the robot was admitted with.
+    pub fn admit(&mut self, id: u16) -> u64 {
+        let member = self.members.entry(id).or_insert(Member {
+            incarnation: 0,
+            alive: false,
+        });
+
+        member.incarnation += 1;
+        member.alive = true;
+        member.incarnation
+    }
+
+    /// Returns the incarnation the robot has in the view, or 0 if the view doesn't know it.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the robot.
+    pub fn incarnation(&self, id: u16) -> u64 {
+        self.members.get(&id).map_or(0, |member| member.incarnation)
+    }
+
+    /// Removes a robot from the ring in its current incarnation.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the robot.
+    ///
+    /// # Returns
+    ///
+    /// Whether the robot was a member before.
+    pub fn leave(&mut self, id: u16) -> bool {
+        match self.members.get_mut(&id) {
+            Some(member) if member.alive => {
+                member.alive = false;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Merges another view into this one.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The view to merge.
+    pub fn merge(&mut self, other: &Membership) {
+        for (id, theirs) in &other.members {
+            let entry = self.members.entry(*id).or_insert(*theirs);
+            let newer = theirs.incarnation > entry.incarnation;
+            let left = theirs.incarnation == entry.incarnation && !theirs.alive;
+
+            if newer || left {
+                *entry = *theirs;
+            }
+        }
+    }
+
+    /// Returns whether the robot is part of the ring.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the robot.
+    pub fn is_alive(&self, id: u16) -> bool {
+        self.members.get(&id).is_some_and(|member| member.alive)
+    }
+
+    /// Returns what the view knows about the robot, if anything.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the robot.
+    pub fn get(&self, id: u16) -> Option<Member> {
+        self.members.get(&id).copied()
+    }
+
+    /// Returns the ids of the robots that are part of the ring, in ascending order.
+    pub fn alive(&self) -> impl Iterator<Item = u16> + '_ {
+        self.members
+            .iter()
+            .filter(|(_, member)| member.alive)
+            .map(|(id, _)| *id)
+    }
+
+    /// Returns the members that come after the given robot in the ring, closest first.
+    /// The robot itself isn't included.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the robot.
+    pub fn successors(&self, id: u16) -> impl Iterator<Item = u16> + '_ {
+        (1..N_ROBOTS)
+            .map(move |offset| (id + offset) % N_ROBOTS)
+            .filter(|id| self.is_alive(*id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test01_a_new_view_only_has_its_robot() {
+        let membership = Membership::new(2);
+        assert_eq!(membership.alive().collect::<Vec<_>>(), vec![2]);
+        assert!(!membership.is_alive(1));
+    }
+
+    #[test]
+    fn test02_successors_wrap_around_the_ring_and_skip_the_robots_that_left() {
+        let mut membership = Membership::new(3);
+        for id in [0, 1, 4] {
+            membership.join(id);
+        }
+
+        membership.leave(4);
+        assert_eq!(membership.successors(3).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(membership.successors(1).collect::<Vec<_>>(), vec![3, 0]);
+    }
+
+    #[test]
+    fn test03_a_departure_wins_within_the_same_incarnation() {
+        let mut mine = Membership::new(0);
+        mine.join(1);
+        let mut theirs = mine.clone();
+        theirs.leave(1);
+
+        mine.merge(&theirs);
+        assert!(!mine.is_alive(1));
+
+        theirs.merge(&mine);
+        assert!(!theirs.is_alive(1));
+    }
+
+    #[test]
+    fn test04_a_readmitted_robot_wins_over_its_departure() {
+        let mut mine = Membership::new(0);
+        mine.join(1);
+        mine.leave(1);
+        let mut theirs = mine.clone();
+
+        assert!(theirs.join(1));
+        assert!(!theirs.join(1));
+        mine.merge(&theirs);
+        assert!(mine.is_alive(1));
+        assert_eq!(mine.get(1).map(|member| member.incarnation), Some(2));
+    }
+
+    #[test]
+    fn test05_a_robot_that_enters_again_gets_a_new_incarnation() {
+        let mut mine = Membership::new(0);
+        assert_eq!(mine.admit(1), 1);
+        let old = mine.clone();
+
+        assert_eq!(mine.admit(1), 2);
+        assert!(mine.is_alive(1));
+        assert_eq!(mine.incarnation(3), 0);
+
+        let mut theirs = old;
+        theirs.leave(1);
+        theirs.merge(&mine);
+        assert!(theirs.is_alive(1));
+        assert_eq!(theirs.incarnation(1), 2);
+    }
+}
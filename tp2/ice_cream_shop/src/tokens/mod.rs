@@ -1,7 +1,9 @@
 pub mod flavour_token;
+pub mod membership;
 pub mod order_token;
 pub mod token_id;
 
-pub use flavour_token::FlavourToken;
+pub use flavour_token::{FlavourToken, Reservation};
+pub use membership::Membership;
 pub use order_token::OrderToken;
 pub use token_id::TokenId;
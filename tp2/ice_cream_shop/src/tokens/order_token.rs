@@ -1,4 +1,7 @@
-use crate::{orders::Order, tokens::TokenId};
+use crate::{
+    orders::{Order, OrderId},
+    tokens::{Membership, TokenId},
+};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, VecDeque};
 
@@ -8,12 +11,21 @@ use std::collections::{HashMap, VecDeque};
 ///
 /// * `sender` - The id of the sender.
 /// * `orders_queue` - The queue of orders.
-/// * `in_progress` - The orders that are in progress.
+/// * `in_progress` - The orders that are in progress, by robot, with the incarnation of the
+///   robot that took them.
+/// * `members` - The robots that are part of the ring.
+/// * `round` - How many times the token was passed.
+/// * `max_queue` - How many orders may wait in the queue before the robots refuse new ones,
+///   if there is a limit.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct OrderToken {
     sender: u16,
     orders_queue: VecDeque<Order>,
-    in_progress: HashMap<u16, Order>,
+    in_progress: HashMap<u16, (u64, Order)>,
+    members: Membership,
+    round: u64,
+    #[serde(default)]
+    max_queue: Option<usize>,
 }
 
 impl OrderToken {
@@ -31,17 +43,41 @@ impl OrderToken {
             sender: id,
             orders_queue: VecDeque::new(),
             in_progress: HashMap::new(),
+            members: Membership::new(id),
+            round: 0,
+            max_queue: None,
         }
     }
 
+    /// Sets how many orders may wait in the queue before the robots refuse new ones.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_queue` - The limit, or `None` if the queue can grow without one.
+    pub fn set_max_queue(&mut self, max_queue: Option<usize>) {
+        self.max_queue = max_queue;
+    }
+
+    /// Returns how many orders may wait in the queue before the robots refuse new ones.
+    pub fn max_queue(&self) -> Option<usize> {
+        self.max_queue
+    }
+
     /// Marks the token with the given id.
-    /// This is used to know who sent the token.
+    /// This is used to know who sent the token. It also counts one more hop of the token,
+    /// so that a copy that stayed behind can be told apart from the token.
     ///
     /// # Arguments
     ///
     /// * `id` - The id to mark the token with.
     pub fn mark(&mut self, id: u16) {
         self.sender = id;
+        self.round += 1;
+    }
+
+    /// Returns how many times the token was passed.
+    pub fn round(&self) -> u64 {
+        self.round
     }
 
     /// Returns the id of the sender.
@@ -53,15 +89,67 @@ impl OrderToken {
         self.sender
     }
 
-    /// Uploads the new orders to the queue.
+    /// Uploads the new orders to the queue, as long as it's under its limit.
+    /// Orders that are already queued or in progress are skipped, so that an order
+    /// recovered from a backup isn't served twice.
     ///
     /// # Arguments
     ///
     /// * `orders` - The orders to upload.
-    pub fn upload_new_orders(&mut self, orders: impl Iterator<Item = Order>) {
+    ///
+    /// # Returns
+    ///
+    /// The orders that didn't fit in the queue, in the order they came.
+    pub fn upload_new_orders(&mut self, orders: impl Iterator<Item = Order>) -> Vec<Order> {
+        let mut left_out = Vec::new();
         for order in orders {
-            self.orders_queue.push_back(order);
+            if self.contains(order.id()) {
+                continue;
+            }
+
+            match self.max_queue {
+                Some(max_queue) if self.orders_queue.len() >= max_queue => left_out.push(order),
+                _ => self.orders_queue.push_back(order),
+            }
         }
+
+        left_out
+    }
+
+    /// Uploads the orders of a robot that is leaving the ring, even if the queue is over its
+    /// limit, since nobody else would serve them.
+    ///
+    /// # Arguments
+    ///
+    /// * `orders` - The orders to upload.
+    pub fn hand_over_orders(&mut self, orders: impl Iterator<Item = Order>) {
+        for order in orders {
+            if !self.contains(order.id()) {
+                self.orders_queue.push_back(order);
+            }
+        }
+    }
+
+    /// Returns whether the order with the given id is queued or in progress.
+    ///
+    /// # Arguments
+    ///
+    /// * `order_id` - The id of the order.
+    pub fn contains(&self, order_id: OrderId) -> bool {
+        self.orders_queue
+            .iter()
+            .chain(self.in_progress.values().map(|(_, order)| order))
+            .any(|order| order.id() == order_id)
+    }
+
+    /// Puts an order back at the front of the queue, so that it's the next one to be served.
+    /// This is used by robots that leave the ring before finishing their order.
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - The order to put back.
+    pub fn return_order(&mut self, order: Order) {
+        self.orders_queue.push_front(order);
     }
 
     /// Returns the next order in the queue.
@@ -73,6 +161,22 @@ impl OrderToken {
         self.orders_queue.pop_front()
     }
 
+    /// Returns the number of orders waiting in the queue.
+    pub fn queue_len(&self) -> usize {
+        self.orders_queue.len()
+    }
+
+    /// Returns the orders of the token: the ones in the queue, in order, and then the ones in
+    /// progress, sorted by robot.
+    pub fn orders(&self) -> impl Iterator<Item = &Order> {
+        let mut in_progress: Vec<_> = self.in_progress.iter().collect();
+        in_progress.sort_by_key(|(robot_id, _)| **robot_id);
+
+        self.orders_queue
+            .iter()
+            .chain(in_progress.into_iter().map(|(_, (_, order))| order))
+    }
+
     /// Returns the id of the token.
     ///
     /// # Returns
@@ -87,9 +191,24 @@ impl OrderToken {
     /// # Arguments
     ///
     /// * `robot_id` - The id of the robot.
+    /// * `incarnation` - The incarnation of the robot that takes the order.
     /// * `order` - The order to add.
-    pub fn add_in_progress(&mut self, robot_id: u16, order: Order) {
-        self.in_progress.insert(robot_id, order);
+    pub fn add_in_progress(&mut self, robot_id: u16, incarnation: u64, order: Order) {
+        self.in_progress.insert(robot_id, (incarnation, order));
+    }
+
+    /// Returns the members of the ring that travel with the token.
+    pub fn members(&self) -> &Membership {
+        &self.members
+    }
+
+    /// Replaces the members of the ring that travel with the token.
+    ///
+    /// # Arguments
+    ///
+    /// * `members` - The view of the robot that holds the token.
+    pub fn set_members(&mut self, members: Membership) {
+        self.members = members;
     }
 
     /// Removes an order from the in progress map.
@@ -98,15 +217,29 @@ impl OrderToken {
     ///
     /// * `robot_id` - The id of the robot.
     pub fn remove_in_progress(&mut self, robot_id: u16) -> Option<Order> {
-        self.in_progress.remove(&robot_id)
+        self.in_progress.remove(&robot_id).map(|(_, order)| order)
+    }
+
+    /// Removes the order in progress of a robot if an earlier incarnation of it took it.
+    /// This is the order a robot was serving before it went down and entered the ring again.
+    ///
+    /// # Arguments
+    ///
+    /// * `robot_id` - The id of the robot.
+    /// * `incarnation` - The current incarnation of the robot.
+    pub fn remove_stale_in_progress(&mut self, robot_id: u16, incarnation: u64) -> Option<Order> {
+        match self.in_progress.get(&robot_id) {
+            Some((taken_by, _)) if *taken_by < incarnation => self.remove_in_progress(robot_id),
+            _ => None,
+        }
     }
 }
 
+#[allow(clippy::empty_line_after_outer_attr)]
 #[cfg(test)]
 
 mod tests {
     use super::*;
-    use crate::orders::{Order, OrderId};
     use std::collections::HashMap;
 
     #[test]
@@ -115,6 +248,7 @@ mod tests {
         assert_eq!(order_token.sender(), 1);
         assert_eq!(order_token.orders_queue.len(), 0);
         assert_eq!(order_token.in_progress.len(), 0);
+        assert!(order_token.members().is_alive(1));
     }
 
     #[test]
@@ -122,6 +256,7 @@ mod tests {
         let mut order_token = OrderToken::new(1);
         order_token.mark(2);
         assert_eq!(order_token.sender(), 2);
+        assert_eq!(order_token.round(), 1);
     }
 
     #[test]
@@ -150,17 +285,81 @@ mod tests {
     fn test05_i_can_add_an_order_in_progress_to_the_order_token() {
         let mut order_token = OrderToken::new(1);
         let order = Order::new(OrderId::new(1, 1), HashMap::new());
-        order_token.add_in_progress(1, order.clone());
+        order_token.add_in_progress(1, 1, order.clone());
         assert_eq!(order_token.in_progress.len(), 1);
-        assert_eq!(order_token.in_progress.get(&1), Some(&order));
+        assert_eq!(order_token.in_progress.get(&1), Some(&(1, order)));
     }
 
     #[test]
     fn test06_i_can_remove_an_order_in_progress_from_order_token() {
         let mut order_token = OrderToken::new(1);
         let order = Order::new(OrderId::new(1, 1), HashMap::new());
-        order_token.add_in_progress(1, order.clone());
+        order_token.add_in_progress(1, 1, order.clone());
         assert_eq!(order_token.remove_in_progress(1), Some(order));
         assert_eq!(order_token.in_progress.len(), 0);
     }
+
+    #[test]
+    fn test07_a_returned_order_is_the_next_one_to_be_served() {
+        let mut order_token = OrderToken::new(1);
+        let order1 = Order::new(OrderId::new(1, 1), HashMap::new());
+        let order2 = Order::new(OrderId::new(1, 2), HashMap::new());
+        order_token.upload_new_orders(vec![order1.clone()].into_iter());
+        order_token.return_order(order2.clone());
+        assert_eq!(order_token.next_order(), Some(order2));
+        assert_eq!(order_token.next_order(), Some(order1));
+    }
+
+    #[test]
+    fn test08_an_order_is_only_uploaded_once() {
+        let mut order_token = OrderToken::new(1);
+        let order1 = Order::new(OrderId::new(1, 1), HashMap::new());
+        let order2 = Order::new(OrderId::new(1, 2), HashMap::new());
+        order_token.upload_new_orders(vec![order1.clone()].into_iter());
+        order_token.add_in_progress(1, 1, order2.clone());
+        order_token.upload_new_orders(vec![order1.clone(), order2.clone()].into_iter());
+        assert_eq!(order_token.queue_len(), 1);
+        assert!(order_token.contains(order1.id()));
+        assert!(order_token.contains(order2.id()));
+        assert!(!order_token.contains(OrderId::new(1, 3)));
+    }
+
+    #[test]
+    fn test09_only_the_orders_of_an_earlier_incarnation_are_stale() {
+        let mut order_token = OrderToken::new(1);
+        let order = Order::new(OrderId::new(1, 1), HashMap::new());
+        order_token.add_in_progress(1, 2, order.clone());
+        assert_eq!(order_token.remove_stale_in_progress(1, 2), None);
+        assert_eq!(order_token.remove_stale_in_progress(1, 3), Some(order));
+        assert!(!order_token.contains(OrderId::new(1, 1)));
+    }
+
+    #[test]
+    fn test10_the_max_queue_travels_with_the_token() {
+        let mut order_token = OrderToken::new(1);
+        order_token.set_max_queue(Some(3));
+        let json = serde_json::to_string(&order_token).unwrap();
+        let received: OrderToken = serde_json::from_str(&json).unwrap();
+        assert_eq!(received.max_queue(), Some(3));
+
+        let legacy = json.replace(",\"max_queue\":3", "");
+        let received: OrderToken = serde_json::from_str(&legacy).unwrap();
+        assert_eq!(received.max_queue(), None);
+    }
+
+    #[test]
+    fn test11_the_orders_over_the_limit_are_left_out_unless_handed_over() {
+        let mut order_token = OrderToken::new(1);
+        order_token.set_max_queue(Some(2));
+        let orders: Vec<_> = (1..=3)
+            .map(|i| Order::new(OrderId::new(1, i), HashMap::new()))
+            .collect();
+
+        let left_out = order_token.upload_new_orders(orders.clone().into_iter());
+        assert_eq!(left_out, vec![orders[2].clone()]);
+        assert_eq!(order_token.queue_len(), 2);
+
+        order_token.hand_over_orders(left_out.into_iter());
+        assert_eq!(order_token.queue_len(), 3);
+    }
 }
@@ -2,7 +2,7 @@ use crate::flavour::Flavour;
 use serde::{Deserialize, Serialize};
 
 /// Enum that represents the the types of tokens
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum TokenId {
     Order,
     Flavour(Flavour),
@@ -0,0 +1,7 @@
+pub mod span;
+pub mod trace_context;
+pub mod tracer;
+
+pub use span::Span;
+pub use trace_context::TraceContext;
+pub use tracer::Tracer;
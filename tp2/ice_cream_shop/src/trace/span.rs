@@ -0,0 +1,169 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Struct that represents a finished step of an order's life.
+///
+/// # Attributes
+///
+/// * `trace_id` - The id of the trace (one per order).
+/// * `span_id` - The id of the span.
+/// * `parent_id` - The id of the parent span, if any.
+/// * `name` - What was being done during the span.
+/// * `process` - The name of the process that recorded the span.
+/// * `pid` - The id of the process that recorded the span.
+/// * `start_us` - When the span started, in microseconds since the unix epoch.
+/// * `end_us` - When the span ended, in microseconds since the unix epoch.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub trace_id: u64,
+    pub span_id: u64,
+    pub parent_id: Option<u64>,
+    pub name: String,
+    pub process: String,
+    pub pid: u32,
+    pub start_us: u64,
+    pub end_us: u64,
+}
+
+impl Span {
+    /// Returns the span as a Chrome trace "complete" event.
+    /// Each trace gets its own row so that an order reads as a waterfall.
+    pub fn to_chrome_event(&self) -> Value {
+        json!({
+            "name": self.name,
+            "cat": "order",
+            "ph": "X",
+            "ts": self.start_us,
+            "dur": self.end_us.saturating_sub(self.start_us),
+            "pid": self.pid,
+            "tid": self.trace_id % 1_000_000,
+            "args": {
+                "trace_id": format!("{:016x}", self.trace_id),
+                "span_id": format!("{:016x}", self.span_id),
+                "process": self.process,
+            },
+        })
+    }
+
+    /// Returns the span in the OTLP JSON encoding.
+    pub fn to_otlp_span(&self) -> Value {
+        let mut span = json!({
+            "traceId": format!("{:032x}", self.trace_id),
+            "spanId": format!("{:016x}", self.span_id),
+            "name": self.name,
+            "kind": 1,
+            "startTimeUnixNano": (self.start_us * 1000).to_string(),
+            "endTimeUnixNano": (self.end_us * 1000).to_string(),
+        });
+
+        if let Some(parent_id) = self.parent_id {
+            span["parentSpanId"] = json!(format!("{:016x}", parent_id));
+        }
+
+        span
+    }
+}
+
+/// Exports the given spans as a Chrome trace (`chrome://tracing`, Perfetto).
+///
+/// # Arguments
+///
+/// * `spans` - The spans to export, possibly from different processes.
+///
+/// # Returns
+///
+/// The JSON document of the trace.
+pub fn to_chrome_trace(spans: &[Span]) -> Value {
+    let mut processes: Vec<_> = spans.iter().map(|span| (span.pid, &span.process)).collect();
+    processes.sort();
+    processes.dedup();
+
+    let names = processes.into_iter().map(|(pid, process)| {
+        json!({ "name": "process_name", "ph": "M", "pid": pid, "args": { "name": process } })
+    });
+
+    let events: Vec<_> = names
+        .chain(spans.iter().map(Span::to_chrome_event))
+        .collect();
+    json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+}
+
+/// Exports the given spans as an OTLP `ExportTraceServiceRequest` in JSON,
+/// grouping them by the process that recorded them.
+///
+/// # Arguments
+///
+/// * `spans` - The spans to export, possibly from different processes.
+///
+/// # Returns
+///
+/// The JSON document of the request.
+pub fn to_otlp_trace(spans: &[Span]) -> Value {
+    let mut processes: Vec<&str> = spans.iter().map(|span| span.process.as_str()).collect();
+    processes.sort();
+    processes.dedup();
+
+    let resource_spans: Vec<_> = processes
+        .into_iter()
+        .map(|process| {
+            let spans: Vec<_> = spans
+                .iter()
+                .filter(|span| span.process == process)
+                .map(Span::to_otlp_span)
+                .collect();
+
+            json!({
+                "resource": {
+                    "attributes": [
+                        { "key": "service.name", "value": { "stringValue": process } }
+                    ]
+                },
+                "scopeSpans": [
+                    { "scope": { "name": "ice_cream_shop" }, "spans": spans }
+                ]
+            })
+        })
+        .collect();
+
+    json!({ "resourceSpans": resource_spans })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(process: &str, pid: u32) -> Span {
+        Span {
+            trace_id: 1,
+            span_id: 2,
+            parent_id: Some(3),
+            name: "scoop".to_string(),
+            process: process.to_string(),
+            pid,
+            start_us: 10,
+            end_us: 25,
+        }
+    }
+
+    #[test]
+    fn test01_a_chrome_trace_names_every_process_once() {
+        let trace = to_chrome_trace(&[span("robot-1", 1), span("robot-1", 1), span("screen-0", 2)]);
+        let events = trace["traceEvents"].as_array().unwrap();
+        assert_eq!(events.iter().filter(|e| e["ph"] == "M").count(), 2);
+        assert_eq!(events.iter().filter(|e| e["ph"] == "X").count(), 3);
+        assert_eq!(events[2]["dur"], 15);
+    }
+
+    #[test]
+    fn test02_an_otlp_trace_groups_spans_by_process() {
+        let trace = to_otlp_trace(&[span("robot-1", 1), span("screen-0", 2), span("robot-1", 1)]);
+        let resources = trace["resourceSpans"].as_array().unwrap();
+        assert_eq!(resources.len(), 2);
+
+        let spans = &resources[0]["scopeSpans"][0]["spans"];
+        assert_eq!(spans.as_array().unwrap().len(), 2);
+        assert_eq!(spans[0]["traceId"], "00000000000000000000000000000001");
+        assert_eq!(spans[0]["parentSpanId"], "0000000000000003");
+        assert_eq!(spans[0]["endTimeUnixNano"], "25000");
+    }
+}
@@ -0,0 +1,138 @@
+use super::{tracer, Span};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Returns the current time in microseconds since the unix epoch.
+pub fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_micros() as u64)
+        .unwrap_or_default()
+}
+
+/// Struct that represents the trace an order carries through the shop.
+/// A context with a `trace_id` of 0 is untraced and records nothing.
+///
+/// # Attributes
+///
+/// * `trace_id` - The id of the trace.
+/// * `root_id` - The id of the span that covers the whole order.
+/// * `start_us` - When the order was created.
+/// * `mark_us` - When the last step of the order ended.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TraceContext {
+    trace_id: u64,
+    root_id: u64,
+    start_us: u64,
+    mark_us: u64,
+}
+
+impl TraceContext {
+    /// Starts a new trace.
+    pub fn new() -> Self {
+        let now = now_us();
+        Self {
+            trace_id: rand::random::<u64>().max(1),
+            root_id: rand::random(),
+            start_us: now,
+            mark_us: now,
+        }
+    }
+
+    /// Returns the id of the trace.
+    pub fn trace_id(&self) -> u64 {
+        self.trace_id
+    }
+
+    /// Returns whether the context belongs to a trace.
+    pub fn is_traced(&self) -> bool {
+        self.trace_id != 0
+    }
+
+    /// Records a span from the given start until now, without moving the mark.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - What was being done during the span.
+    /// * `start_us` - When the span started.
+    pub fn record(&self, name: &str, start_us: u64) {
+        if !self.is_traced() {
+            return;
+        }
+
+        tracer::record(|process, pid| Span {
+            trace_id: self.trace_id,
+            span_id: rand::random(),
+            parent_id: Some(self.root_id),
+            name: name.to_string(),
+            process: process.to_string(),
+            pid,
+            start_us,
+            end_us: now_us(),
+        });
+    }
+
+    /// Records a span from the end of the last step until now and moves the mark.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - What was being done since the last step.
+    pub fn step(&mut self, name: &str) {
+        self.record(name, self.mark_us);
+        self.mark_us = now_us();
+    }
+
+    /// Records the root span covering the whole order.
+    /// It should be called once, when the order is over.
+    pub fn finish(&self) {
+        if !self.is_traced() {
+            return;
+        }
+
+        tracer::record(|process, pid| Span {
+            trace_id: self.trace_id,
+            span_id: self.root_id,
+            parent_id: None,
+            name: "order".to_string(),
+            process: process.to_string(),
+            pid,
+            start_us: self.start_us,
+            end_us: now_us(),
+        });
+    }
+}
+
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "trace={:016x}", self.trace_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test01_a_new_context_is_traced() {
+        let context = TraceContext::new();
+        assert!(context.is_traced());
+        assert!(context.start_us <= context.mark_us);
+    }
+
+    #[test]
+    fn test02_the_default_context_is_untraced() {
+        assert!(!TraceContext::default().is_traced());
+    }
+
+    #[test]
+    fn test03_a_step_moves_the_mark() {
+        let mut context = TraceContext::new();
+        let mark = context.mark_us;
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        context.step("queue");
+        assert!(context.mark_us > mark);
+    }
+}
@@ -0,0 +1,99 @@
+use super::Span;
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+
+/// Environment variable with the directory where spans are written.
+/// Tracing is disabled when it isn't set.
+pub const TRACE_DIR_VAR: &str = "SHOP_TRACE_DIR";
+
+/// Extension of the files with the spans of each process.
+pub const SPANS_EXTENSION: &str = "spans.jsonl";
+
+static TRACER: OnceLock<Tracer> = OnceLock::new();
+
+/// Struct that writes the spans recorded by this process, one JSON per line.
+pub struct Tracer {
+    process: String,
+    pid: u32,
+    output: Mutex<File>,
+}
+
+impl Tracer {
+    /// Initializes the tracer of this process if `SHOP_TRACE_DIR` is set.
+    /// Spans are written to `<SHOP_TRACE_DIR>/<process>-<pid>.spans.jsonl`.
+    ///
+    /// # Arguments
+    ///
+    /// * `process` - The name of the process, e.g. `robot-3`.
+    pub fn init(process: &str) -> io::Result<()> {
+        let Ok(dir) = env::var(TRACE_DIR_VAR) else {
+            return Ok(());
+        };
+
+        fs::create_dir_all(&dir)?;
+        let pid = std::process::id();
+        let path = Path::new(&dir).join(format!("{process}-{pid}.{SPANS_EXTENSION}"));
+        let output = OpenOptions::new().create(true).append(true).open(path)?;
+
+        let _ = TRACER.set(Tracer {
+            process: process.to_string(),
+            pid,
+            output: Mutex::new(output),
+        });
+
+        Ok(())
+    }
+
+    /// Reads every span written to the given directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory the processes wrote their spans to.
+    ///
+    /// # Returns
+    ///
+    /// The spans, sorted by their start time.
+    pub fn read_spans(dir: &Path) -> io::Result<Vec<Span>> {
+        let mut spans = Vec::new();
+
+        for entry in fs::read_dir(dir)?.flatten() {
+            let path = entry.path();
+            if !path.to_string_lossy().ends_with(SPANS_EXTENSION) {
+                continue;
+            }
+
+            let reader = BufReader::new(File::open(path)?);
+            for line in reader.lines().map_while(Result::ok) {
+                if let Ok(span) = serde_json::from_str(&line) {
+                    spans.push(span);
+                }
+            }
+        }
+
+        spans.sort_by_key(|span: &Span| span.start_us);
+        Ok(spans)
+    }
+}
+
+/// Records a span if the tracer of this process is initialized.
+/// The span is built lazily from the process name and id.
+pub(crate) fn record(build: impl FnOnce(&str, u32) -> Span) {
+    let Some(tracer) = TRACER.get() else {
+        return;
+    };
+
+    let span = build(&tracer.process, tracer.pid);
+    let Ok(mut line) = serde_json::to_string(&span) else {
+        return;
+    };
+
+    line.push('\n');
+    if let Ok(mut output) = tracer.output.lock() {
+        let _ = output.write_all(line.as_bytes());
+    }
+}
@@ -0,0 +1,202 @@
+pub mod report;
+pub mod workload;
+
+use ice_cream_shop::{
+    messages::screen_msg::Admission,
+    orders::{ClientOrder, Order},
+    shop_values::GATEWAY_PORT,
+    stock::StockCheck,
+    trace::TraceContext,
+};
+use report::{LoadReport, Outcome};
+use screen::{config::ScreenConfig, expire_orders, receiver_with, screen::Screen};
+use std::{
+    collections::HashMap,
+    convert::identity,
+    io,
+    net::TcpStream,
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+use workload::Workload;
+
+/// The orders that were sent and haven't ended yet, and the report of the ones that did.
+/// - `pending`: When every order that didn't end was sent, by screen and order number.
+/// - `report`: The report of the orders that ended.
+#[derive(Debug, Default)]
+struct Progress {
+    pending: HashMap<(u16, usize), Instant>,
+    report: LoadReport,
+}
+
+/// The progress of a load, shared by the screens.
+#[derive(Debug, Clone, Default)]
+struct Tracker(Arc<Mutex<Progress>>);
+
+impl Tracker {
+    /// Records that a screen sent an order.
+    fn sent(&self, screen: u16, number: usize) {
+        let mut progress = self.lock();
+        progress.pending.insert((screen, number), Instant::now());
+        progress.report.sent += 1;
+    }
+
+    /// Records how an order ended. Orders that already ended are ignored.
+    fn ended(&self, screen: u16, number: usize, outcome: Outcome) {
+        let mut progress = self.lock();
+        let Some(sent) = progress.pending.remove(&(screen, number)) else {
+            return;
+        };
+
+        let latency =
+            matches!(outcome, Outcome::Confirmed | Outcome::Cancelled).then(|| sent.elapsed());
+        progress.report.record(outcome, latency);
+    }
+
+    /// Returns the number of orders that ended.
+    fn ended_orders(&self) -> usize {
+        self.lock().report.outcomes.values().sum()
+    }
+
+    /// Returns the report of the orders that ended.
+    fn report(&self, elapsed: Duration) -> LoadReport {
+        LoadReport {
+            elapsed,
+            ..self.lock().report.clone()
+        }
+    }
+
+    /// Locks the orders, even if a screen panicked while holding them.
+    fn lock(&self) -> MutexGuard<'_, Progress> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Puts a load on the shop through the given number of screens, which take the place of the
+/// real ones: they validate the orders with the gateway, hand them to the robots and commit
+/// or cancel the payments.
+///
+/// # Arguments
+///
+/// * `workload` - The load to put on the shop.
+/// * `config` - How the screens behave.
+///
+/// # Returns
+///
+/// The report of the load, or an error if a screen couldn't reach the gateway.
+pub fn run(workload: &Workload, config: ScreenConfig) -> io::Result<LoadReport> {
+    let tracker = Tracker::default();
+    let start = Instant::now();
+    let mut senders = Vec::new();
+
+    for id in 0..workload.screens {
+        let screen = Screen::new(id).with_config(config.clone());
+
+        let receiver = screen.clone();
+        let ended = tracker.clone();
+        thread::spawn(move || {
+            let outcome = |confirmed| match confirmed {
+                true => Outcome::Confirmed,
+                false => Outcome::Cancelled,
+            };
+
+            if let Err(e) = receiver_with(receiver, |number, confirmed| {
+                ended.ended(id, number, outcome(confirmed))
+            }) {
+                eprintln!("Screen {id} stopped receiving: {e}");
+            }
+        });
+
+        let expirer = screen.clone();
+        let timed_out = tracker.clone();
+        thread::spawn(move || {
+            if let Err(e) = expire_orders(expirer, |number| {
+                timed_out.ended(id, number, Outcome::TimedOut)
+            }) {
+                eprintln!("Screen {id} stopped timing out orders: {e}");
+            }
+        });
+
+        let orders = workload.orders(id);
+        let tracker = tracker.clone();
+        senders.push(thread::spawn(move || {
+            send_orders(screen, orders, start, tracker)
+        }));
+    }
+
+    for sender in senders {
+        sender
+            .join()
+            .map_err(|_| io::Error::other("A screen panicked"))??;
+    }
+
+    let deadline = Instant::now() + workload.wait;
+    let orders = workload.orders * workload.screens as usize;
+    while tracker.ended_orders() < orders && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    Ok(tracker.report(start.elapsed()))
+}
+
+/// Sends the orders of a screen as they arrive, recording the ones the gateway rejects, the
+/// ones the shop is too busy to take or doesn't have the stock for and the ones no robot takes.
+///
+/// # Arguments
+///
+/// * `screen` - The screen that sends the orders.
+/// * `orders` - The orders, together with when each one arrives.
+/// * `start` - When the load started.
+/// * `tracker` - Where the orders are recorded.
+///
+/// # Returns
+///
+/// An io::Result indicating if the screen could reach the gateway.
+fn send_orders(
+    screen: Screen,
+    orders: Vec<(Duration, ClientOrder)>,
+    start: Instant,
+    tracker: Tracker,
+) -> io::Result<()> {
+    let mut gateway = TcpStream::connect(format!("127.0.0.1:{GATEWAY_PORT}"))?;
+
+    for (number, (at, order)) in orders.into_iter().enumerate() {
+        thread::sleep((start + at).saturating_duration_since(Instant::now()));
+        tracker.sent(screen.id, number);
+
+        if screen.busy().is_some() {
+            tracker.ended(screen.id, number, Outcome::Busy);
+            continue;
+        }
+
+        if screen.shortage(&order).is_some() && screen.stock_check() == StockCheck::Reject {
+            tracker.ended(screen.id, number, Outcome::OutOfStock);
+            continue;
+        }
+
+        let trace = TraceContext::new();
+        let valid = screen
+            .validate(&order, number, trace, &mut gateway)
+            .is_ok_and(identity);
+
+        if !valid {
+            tracker.ended(screen.id, number, Outcome::Rejected);
+            continue;
+        }
+
+        let order = Order::from(order, screen.id, number).with_trace(trace);
+        match screen.notify_order(order) {
+            Ok(Admission::Accepted(_)) => {}
+            Ok(Admission::Busy(_)) => {
+                tracker.ended(screen.id, number, Outcome::Busy);
+                screen.cancel(number, trace, &mut gateway)?;
+            }
+            Err(_) => tracker.ended(screen.id, number, Outcome::Undelivered),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,14 @@
+use loadgen::workload::Workload;
+use screen::config::ScreenConfig;
+use std::{env, error::Error};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let workload = Workload::from_args(env::args().skip(1))?;
+    let config = ScreenConfig::from_env()?;
+
+    let report = loadgen::run(&workload, config)?;
+
+    // The screens print every order they handle, so the report goes last.
+    println!("{report}");
+    Ok(())
+}
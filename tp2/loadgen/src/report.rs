@@ -0,0 +1,164 @@
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+/// How an order of the load ended.
+/// - `Rejected`: The gateway rejected the payment.
+/// - `Undelivered`: The screen couldn't hand the order to any robot.
+/// - `Busy`: The shop was too busy to take the order, so its payment was cancelled or never
+///   captured.
+/// - `OutOfStock`: The screen turned the order away before capturing its payment, because it
+///   asked for more servings than the robots last said were left.
+/// - `TimedOut`: The screen gave up on the order and cancelled its payment, because no robot
+///   said how it ended before its deadline.
+/// - `Confirmed`: A robot delivered the order.
+/// - `Cancelled`: A robot cancelled the order because there wasn't enough ice cream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Outcome {
+    Rejected,
+    Undelivered,
+    Busy,
+    OutOfStock,
+    TimedOut,
+    Confirmed,
+    Cancelled,
+}
+
+/// What happened to the orders of a load.
+/// - `sent`: The number of orders the screens sent.
+/// - `outcomes`: How many orders ended with every outcome.
+/// - `latencies`: How long every confirmed or cancelled order took, from the moment it
+///   arrived at the screen until a robot told the screen how it ended.
+/// - `elapsed`: How long the load took.
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    pub sent: usize,
+    pub outcomes: BTreeMap<Outcome, usize>,
+    pub latencies: Vec<Duration>,
+    pub elapsed: Duration,
+}
+
+impl LoadReport {
+    /// Records how an order ended.
+    ///
+    /// # Arguments
+    ///
+    /// * `outcome` - How the order ended.
+    /// * `latency` - How long the order took, if a robot ended it.
+    pub fn record(&mut self, outcome: Outcome, latency: Option<Duration>) {
+        *self.outcomes.entry(outcome).or_default() += 1;
+        self.latencies.extend(latency);
+    }
+
+    /// Returns the number of orders that ended with the given outcome.
+    pub fn count(&self, outcome: Outcome) -> usize {
+        self.outcomes.get(&outcome).copied().unwrap_or_default()
+    }
+
+    /// Returns the number of orders that were sent but never ended.
+    pub fn unanswered(&self) -> usize {
+        self.sent.saturating_sub(self.outcomes.values().sum())
+    }
+
+    /// Returns the latency under which the given fraction of the orders ended.
+    ///
+    /// # Arguments
+    ///
+    /// * `fraction` - The fraction of the orders, between 0 and 1.
+    ///
+    /// # Returns
+    ///
+    /// The latency, or `None` if no order was ended by a robot.
+    pub fn percentile(&self, fraction: f64) -> Option<Duration> {
+        let mut latencies = self.latencies.clone();
+        latencies.sort();
+
+        let rank = (fraction.clamp(0.0, 1.0) * latencies.len() as f64).ceil() as usize;
+        latencies.get(rank.saturating_sub(1)).copied()
+    }
+
+    /// Returns the fraction of the orders ended by a robot that were confirmed.
+    pub fn confirm_ratio(&self) -> Option<f64> {
+        let confirmed = self.count(Outcome::Confirmed);
+        let ended = confirmed + self.count(Outcome::Cancelled);
+        (ended > 0).then(|| confirmed as f64 / ended as f64)
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} orders in {:.1}s",
+            self.sent,
+            self.elapsed.as_secs_f64()
+        )?;
+
+        for outcome in [
+            Outcome::Confirmed,
+            Outcome::Cancelled,
+            Outcome::Rejected,
+            Outcome::Undelivered,
+            Outcome::Busy,
+            Outcome::OutOfStock,
+            Outcome::TimedOut,
+        ] {
+            writeln!(f, "  {outcome:?}: {}", self.count(outcome))?;
+        }
+        writeln!(f, "  Unanswered: {}", self.unanswered())?;
+
+        if let Some(ratio) = self.confirm_ratio() {
+            writeln!(f, "  Confirmed/ended by a robot: {:.1}%", ratio * 100.0)?;
+        }
+
+        for (name, fraction) in [("p50", 0.5), ("p90", 0.9), ("p99", 0.99), ("max", 1.0)] {
+            if let Some(latency) = self.percentile(fraction) {
+                writeln!(f, "  {name}: {:.3}s", latency.as_secs_f64())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test01_percentiles_are_taken_from_the_ended_orders() {
+        let mut report = LoadReport::default();
+        assert_eq!(report.percentile(0.5), None);
+
+        for ms in (1..=100).rev() {
+            report.record(Outcome::Confirmed, Some(Duration::from_millis(ms)));
+        }
+        report.record(Outcome::Rejected, None);
+
+        assert_eq!(report.percentile(0.5), Some(Duration::from_millis(50)));
+        assert_eq!(report.percentile(0.99), Some(Duration::from_millis(99)));
+        assert_eq!(report.percentile(1.0), Some(Duration::from_millis(100)));
+        assert_eq!(report.percentile(0.0), Some(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn test02_the_ratio_only_counts_the_orders_ended_by_a_robot() {
+        let mut report = LoadReport {
+            sent: 10,
+            ..Default::default()
+        };
+        assert_eq!(report.confirm_ratio(), None);
+
+        for outcome in [
+            Outcome::Confirmed,
+            Outcome::Confirmed,
+            Outcome::Confirmed,
+            Outcome::Cancelled,
+            Outcome::Rejected,
+            Outcome::Undelivered,
+        ] {
+            report.record(outcome, None);
+        }
+
+        assert_eq!(report.confirm_ratio(), Some(0.75));
+        assert_eq!(report.unanswered(), 4);
+    }
+}
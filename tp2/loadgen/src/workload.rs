@@ -0,0 +1,332 @@
+use ice_cream_shop::{
+    flavour::Flavour,
+    orders::ClientOrder,
+    shop_values::{N_SCREEN, STARTING_ICECREAM},
+};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::{collections::HashMap, str::FromStr, time::Duration};
+
+/// The largest number of flavours of an order that isn't oversized.
+const MAX_FLAVOURS: usize = 3;
+
+/// How the orders of a screen arrive.
+/// - `Constant`: At a fixed rate, in orders per second.
+/// - `Poisson`: At random times with the given mean rate, in orders per second.
+/// - `Burst`: In bursts of `size` orders at once, one burst every `every`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arrivals {
+    Constant(f64),
+    Poisson(f64),
+    Burst { size: usize, every: Duration },
+}
+
+/// The load the screens put on the shop.
+/// - `seed`: The seed the orders and their arrivals are drawn from.
+/// - `screens`: How many screens send orders, up to `N_SCREEN`.
+/// - `orders`: How many orders every screen sends.
+/// - `arrivals`: How the orders of every screen arrive.
+/// - `flavours`: How likely every flavour is to be picked, relative to the others.
+/// - `invalid`: The fraction of orders paid with a card the gateway rejects.
+/// - `oversized`: The fraction of orders that ask for more servings than a flavour has.
+/// - `wait`: How long to wait for the orders to end after the last one was sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workload {
+    pub seed: u64,
+    pub screens: u16,
+    pub orders: usize,
+    pub arrivals: Arrivals,
+    pub flavours: HashMap<Flavour, f64>,
+    pub invalid: f64,
+    pub oversized: f64,
+    pub wait: Duration,
+}
+
+impl Arrivals {
+    /// Returns when every order arrives, counted from the start of the load.
+    ///
+    /// # Arguments
+    ///
+    /// * `orders` - How many orders arrive.
+    /// * `rng` - Where the random arrivals are drawn from.
+    pub fn schedule(&self, orders: usize, rng: &mut StdRng) -> Vec<Duration> {
+        let mut at = Duration::ZERO;
+
+        (0..orders)
+            .map(|i| match self {
+                Self::Constant(rate) => Duration::from_secs_f64(i as f64 / rate),
+                Self::Poisson(rate) => {
+                    let arrival = at;
+                    let uniform: f64 = rng.gen();
+                    at += Duration::from_secs_f64(-(1.0 - uniform).ln() / rate);
+                    arrival
+                }
+                Self::Burst { size, every } => *every * (i / size) as u32,
+            })
+            .collect()
+    }
+}
+
+impl Default for Arrivals {
+    fn default() -> Self {
+        Self::Constant(1.0)
+    }
+}
+
+impl FromStr for Arrivals {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let err = "arrivals needs to be constant:<per second>, poisson:<per second> or \
+                   burst:<orders>:<seconds>";
+        let positive = |number: &str| match number.parse::<f64>() {
+            Ok(number) if number > 0.0 => Ok(number),
+            _ => Err(err),
+        };
+
+        match value.split(':').collect::<Vec<_>>().as_slice() {
+            ["constant", rate] => Ok(Self::Constant(positive(rate)?)),
+            ["poisson", rate] => Ok(Self::Poisson(positive(rate)?)),
+            ["burst", size, every] => Ok(Self::Burst {
+                size: size.parse().ok().filter(|size| *size > 0).ok_or(err)?,
+                every: Duration::from_secs_f64(positive(every)?),
+            }),
+            _ => Err(err),
+        }
+    }
+}
+
+impl Workload {
+    /// Reads the load from a list of `key=value` arguments: `seed`, `screens`, `orders`,
+    /// `arrivals`, `flavours` (as `<flavour>:<weight>,...`), `invalid`, `oversized` and
+    /// `wait` (in seconds). The ones that are missing keep their default value.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - The arguments.
+    ///
+    /// # Returns
+    ///
+    /// The load, or an error if an argument is unknown or has an invalid value.
+    pub fn from_args<S: AsRef<str>>(
+        args: impl IntoIterator<Item = S>,
+    ) -> Result<Self, &'static str> {
+        let mut workload = Self::default();
+
+        for arg in args {
+            let (key, value) = arg.as_ref().split_once('=').ok_or("Use: <key>=<value>")?;
+            match key {
+                "seed" => workload.seed = value.parse().map_err(|_| "seed needs to be a number")?,
+                "screens" => {
+                    let screens: u16 = value.parse().map_err(|_| "screens needs to be a number")?;
+                    workload.screens = screens.clamp(1, N_SCREEN);
+                }
+                "orders" => {
+                    workload.orders = value.parse().map_err(|_| "orders needs to be a number")?
+                }
+                "arrivals" => workload.arrivals = value.parse()?,
+                "flavours" => workload.flavours = parse_flavours(value)?,
+                "invalid" => workload.invalid = parse_fraction(value)?,
+                "oversized" => workload.oversized = parse_fraction(value)?,
+                "wait" => {
+                    let seconds: f64 = value.parse().map_err(|_| "wait needs to be a number")?;
+                    workload.wait = Duration::from_secs_f64(seconds.max(0.0));
+                }
+                _ => return Err("Unknown argument"),
+            }
+        }
+
+        Ok(workload)
+    }
+
+    /// Generates the orders of a screen, together with when each one arrives.
+    /// The same seed and screen always give the same orders.
+    ///
+    /// # Arguments
+    ///
+    /// * `screen` - The ID of the screen.
+    pub fn orders(&self, screen: u16) -> Vec<(Duration, ClientOrder)> {
+        let mut rng = StdRng::seed_from_u64(self.seed.wrapping_add(screen as u64));
+        let arrivals = self.arrivals.schedule(self.orders, &mut rng);
+
+        arrivals
+            .into_iter()
+            .map(|at| (at, self.client_order(&mut rng)))
+            .collect()
+    }
+
+    /// Draws a single order.
+    fn client_order(&self, rng: &mut StdRng) -> ClientOrder {
+        let flavours: Vec<_> = Flavour::flavours()
+            .filter(|flavour| self.weight(flavour) > 0.0)
+            .collect();
+
+        let flavours = match rng.gen_bool(self.oversized) {
+            true => flavours
+                .choose_weighted(rng, |flavour| self.weight(flavour))
+                .map(|flavour| vec![(*flavour, STARTING_ICECREAM + 1)])
+                .unwrap_or_default(),
+            false => {
+                let n_flavours = rng.gen_range(1..=MAX_FLAVOURS.min(flavours.len()).max(1));
+                let chosen: Vec<Flavour> = flavours
+                    .choose_multiple_weighted(rng, n_flavours, |flavour| self.weight(flavour))
+                    .map(|chosen| chosen.copied().collect())
+                    .unwrap_or_default();
+
+                chosen
+                    .into_iter()
+                    .map(|flavour| (flavour, rng.gen_range(1..=2)))
+                    .collect()
+            }
+        };
+
+        let first_digit = match rng.gen_bool(self.invalid) {
+            true => 3,
+            false => rng.gen_range(4..=9),
+        };
+
+        ClientOrder {
+            flavours: flavours.into_iter().collect(),
+            card_number: format!("{first_digit}666-1111-2222-3333"),
+        }
+    }
+
+    /// Returns how likely a flavour is to be picked.
+    fn weight(&self, flavour: &Flavour) -> f64 {
+        self.flavours.get(flavour).copied().unwrap_or_default()
+    }
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            screens: 1,
+            orders: 10,
+            arrivals: Arrivals::default(),
+            flavours: Flavour::flavours().map(|flavour| (flavour, 1.0)).collect(),
+            invalid: 0.0,
+            oversized: 0.0,
+            wait: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Parses the weights of the flavours, as `<flavour>:<weight>` separated by commas.
+/// The flavours that aren't listed are never picked.
+fn parse_flavours(value: &str) -> Result<HashMap<Flavour, f64>, &'static str> {
+    let err = "flavours needs to be a list of <flavour>:<weight>";
+    let mut weights = HashMap::new();
+
+    for entry in value.split(',').filter(|entry| !entry.is_empty()) {
+        let (name, weight) = entry.split_once(':').ok_or(err)?;
+        let flavour = Flavour::flavours()
+            .find(|flavour| flavour.name() == name)
+            .ok_or(err)?;
+        let weight = weight
+            .parse()
+            .ok()
+            .filter(|weight| *weight >= 0.0)
+            .ok_or(err)?;
+        weights.insert(flavour, weight);
+    }
+
+    match weights.values().any(|weight| *weight > 0.0) {
+        true => Ok(weights),
+        false => Err(err),
+    }
+}
+
+/// Parses a fraction between 0 and 1.
+fn parse_fraction(value: &str) -> Result<f64, &'static str> {
+    value
+        .parse()
+        .ok()
+        .filter(|fraction| (0.0..=1.0).contains(fraction))
+        .ok_or("invalid and oversized need to be between 0 and 1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test01_the_arrivals_are_parsed_from_their_name() {
+        assert_eq!("constant:5".parse(), Ok(Arrivals::Constant(5.0)));
+        assert_eq!("poisson:0.5".parse(), Ok(Arrivals::Poisson(0.5)));
+        assert_eq!(
+            "burst:10:2".parse(),
+            Ok(Arrivals::Burst {
+                size: 10,
+                every: Duration::from_secs(2)
+            })
+        );
+        assert!("constant:0".parse::<Arrivals>().is_err());
+        assert!("burst:10".parse::<Arrivals>().is_err());
+    }
+
+    #[test]
+    fn test02_orders_arrive_as_configured() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let constant = Arrivals::Constant(4.0).schedule(5, &mut rng);
+        assert_eq!(constant[4], Duration::from_secs(1));
+
+        let burst = Arrivals::Burst {
+            size: 3,
+            every: Duration::from_secs(10),
+        };
+        let burst = burst.schedule(7, &mut rng);
+        assert_eq!(burst[2], Duration::ZERO);
+        assert_eq!(burst[3], Duration::from_secs(10));
+        assert_eq!(burst[6], Duration::from_secs(20));
+
+        let poisson = Arrivals::Poisson(10.0).schedule(2000, &mut rng);
+        assert!(poisson.windows(2).all(|pair| pair[0] <= pair[1]));
+        let rate = 2000.0 / poisson[1999].as_secs_f64();
+        assert!((9.0..11.0).contains(&rate), "{rate}");
+    }
+
+    #[test]
+    fn test03_the_orders_follow_the_mix_of_the_load() {
+        let workload = Workload::from_args([
+            "orders=1000",
+            "flavours=chocolate:3,menta:1",
+            "invalid=0.2",
+            "oversized=0.1",
+        ])
+        .unwrap();
+
+        let orders = workload.orders(0);
+        let invalid = orders
+            .iter()
+            .filter(|(_, order)| order.card_number.starts_with('3'))
+            .count();
+        let oversized = orders
+            .iter()
+            .filter(|(_, order)| order.flavours.values().any(|n| *n > STARTING_ICECREAM))
+            .count();
+
+        assert!((150..250).contains(&invalid), "{invalid}");
+        assert!((60..140).contains(&oversized), "{oversized}");
+        assert!(orders.iter().all(|(_, order)| !order.flavours.is_empty()
+            && order
+                .flavours
+                .keys()
+                .all(|flavour| matches!(flavour, Flavour::Chocolate | Flavour::Menta))));
+    }
+
+    #[test]
+    fn test04_the_same_seed_gives_the_same_orders() {
+        let workload = Workload::from_args(["seed=7", "arrivals=poisson:3"]).unwrap();
+        let orders = |screen| {
+            workload
+                .orders(screen)
+                .into_iter()
+                .map(|(at, order)| (at, order.card_number, order.flavours))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(orders(0), orders(0));
+        assert_ne!(orders(0), orders(1));
+        assert!(Workload::from_args(["speed=3"]).is_err());
+    }
+}
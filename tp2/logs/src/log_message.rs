@@ -1,19 +1,55 @@
-use super::{error_log::ErrorLog, sources::Sources};
+use super::{
+    error_log::ErrorLog,
+    sources::{Header, Origin, Sources, TraceIds},
+};
 
 use std::io::Write;
 
+/// Struct that represents a log sent to the logger.
+/// The origin is `None` for logs sent with the legacy source encoding, which are written
+/// back with it. The trace is `None` for logs written outside of a traced order, and
+/// can only be sent along with an origin.
+#[derive(Debug, PartialEq, Eq)]
 pub struct LogMessage {
     pub source: Sources,
+    pub origin: Option<Origin>,
+    pub trace: Option<TraceIds>,
     pub message: String,
 }
 
 impl LogMessage {
+    /// Creates a new log message coming from the current process.
     pub fn new(source: Sources, message: String) -> Self {
-        LogMessage { source, message }
+        LogMessage {
+            source,
+            origin: Some(Origin::current()),
+            trace: None,
+            message,
+        }
+    }
+
+    /// Returns the log message tagged with the trace and span it was written in.
+    pub fn with_trace(self, trace_id: u64, span_id: u64) -> Self {
+        LogMessage {
+            trace: Some(TraceIds { trace_id, span_id }),
+            ..self
+        }
     }
 
     pub fn serialize(&self, stream: &mut dyn Write) -> Result<(), ErrorLog> {
-        self.source.serialize(stream)?;
+        match (&self.origin, &self.trace) {
+            (Some(origin), Some(trace)) => {
+                self.source.serialize_traced(stream)?;
+                origin.serialize(stream)?;
+                trace.serialize(stream)?;
+            }
+            (Some(origin), None) => {
+                self.source.serialize(stream)?;
+                origin.serialize(stream)?;
+            }
+            (None, _) => self.source.serialize_legacy(stream)?,
+        }
+
         let numbre_of_bytes = self.message.len() as u32;
 
         if stream.write_all(&numbre_of_bytes.to_be_bytes()).is_err() {
@@ -31,7 +67,15 @@ impl LogMessage {
     }
 
     pub fn deserialize(stream: &mut dyn std::io::Read) -> Result<LogMessage, ErrorLog> {
-        let source = Sources::deserialize(stream)?;
+        let (source, header) = Sources::deserialize_header(stream)?;
+        let origin = match header {
+            Header::Legacy => None,
+            Header::Versioned | Header::Traced => Some(Origin::deserialize(stream)?),
+        };
+        let trace = match header {
+            Header::Traced => Some(TraceIds::deserialize(stream)?),
+            Header::Legacy | Header::Versioned => None,
+        };
 
         let mut buffer = [0; 4];
         if stream.read_exact(&mut buffer).is_err() {
@@ -49,6 +93,87 @@ impl LogMessage {
         }
         let message = String::from_utf8_lossy(&message).to_string();
 
-        Ok(LogMessage::new(source, message))
+        Ok(LogMessage {
+            source,
+            origin,
+            trace,
+            message,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn test01_every_log_message_survives_a_round_trip(
+            id in any::<u16>(),
+            hostname in "[a-z0-9.-]{0,64}",
+            pid in any::<u32>(),
+            trace in proptest::option::of((any::<u64>(), any::<u64>())),
+            message in ".{0,128}",
+        ) {
+            let log = LogMessage {
+                source: Sources::Robot(id),
+                origin: Some(Origin { hostname, pid }),
+                trace: trace.map(|(trace_id, span_id)| TraceIds { trace_id, span_id }),
+                message,
+            };
+
+            let mut buffer = Vec::new();
+            log.serialize(&mut buffer).unwrap();
+            prop_assert_eq!(LogMessage::deserialize(&mut buffer.as_slice()).unwrap(), log);
+        }
+    }
+
+    #[test]
+    fn test02_a_legacy_log_message_has_no_origin() {
+        let mut buffer = vec![1, 7];
+        buffer.extend_from_slice(&5u32.to_be_bytes());
+        buffer.extend_from_slice(b"hello");
+
+        let log = LogMessage::deserialize(&mut buffer.as_slice()).unwrap();
+        assert_eq!(log.source, Sources::Screen(7));
+        assert_eq!(log.origin, None);
+        assert_eq!(log.message, "hello");
+    }
+
+    #[test]
+    fn test03_a_legacy_log_message_is_written_back_as_it_came() {
+        let mut legacy = vec![0, 3];
+        legacy.extend_from_slice(&2u32.to_be_bytes());
+        legacy.extend_from_slice(b"hi");
+
+        let log = LogMessage::deserialize(&mut legacy.as_slice()).unwrap();
+        let mut buffer = Vec::new();
+        log.serialize(&mut buffer).unwrap();
+        assert_eq!(buffer, legacy);
+    }
+
+    #[test]
+    fn test04_an_untraced_log_message_keeps_the_first_versioned_encoding() {
+        let mut buffer = Vec::new();
+        LogMessage::new(Sources::Gateway, "hi".to_string())
+            .serialize(&mut buffer)
+            .unwrap();
+        assert_eq!(buffer[..2], [0xFF, 1]);
+
+        let mut buffer = Vec::new();
+        LogMessage::new(Sources::Gateway, "hi".to_string())
+            .with_trace(7, 9)
+            .serialize(&mut buffer)
+            .unwrap();
+        let log = LogMessage::deserialize(&mut buffer.as_slice()).unwrap();
+        assert_eq!(buffer[..2], [0xFF, 2]);
+        assert_eq!(
+            log.trace,
+            Some(TraceIds {
+                trace_id: 7,
+                span_id: 9
+            })
+        );
     }
 }
@@ -32,7 +32,14 @@ impl<W: Write> LoggerReceiver<W> {
     }
 
     fn format_message(message: LogMessage) -> String {
-        format!("[{}] {}", message.source, message.message)
+        match (message.origin, message.trace) {
+            (Some(origin), Some(trace)) => format!(
+                "[{}@{}] [{}] {}",
+                message.source, origin, trace, message.message
+            ),
+            (Some(origin), None) => format!("[{}@{}] {}", message.source, origin, message.message),
+            (None, _) => format!("[{}] {}", message.source, message.message),
+        }
     }
 
     pub fn receive_logs(&mut self) -> Result<(), ErrorLog> {
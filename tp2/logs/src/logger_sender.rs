@@ -31,11 +31,11 @@ impl LoggerSender {
         Ok(())
     }
 
-    pub fn send_robot_log(&self, id: u8, message: String) -> Result<(), ErrorLog> {
+    pub fn send_robot_log(&self, id: u16, message: String) -> Result<(), ErrorLog> {
         self.send_log(LogMessage::new(Sources::Robot(id), message))
     }
 
-    pub fn send_screen_log(&self, id: u8, message: String) -> Result<(), ErrorLog> {
+    pub fn send_screen_log(&self, id: u16, message: String) -> Result<(), ErrorLog> {
         self.send_log(LogMessage::new(Sources::Screen(id), message))
     }
 
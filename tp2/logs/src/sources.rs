@@ -1,10 +1,68 @@
 use crate::error_log::ErrorLog;
-use std::io::Write;
+use std::io::{Read, Write};
 
+/// Marker byte that starts a versioned source header.
+/// Legacy headers start with the component kind (0, 1 or 2) instead.
+const VERSIONED_MARKER: u8 = 0xFF;
+
+/// Current version of the source encoding.
+const SOURCE_VERSION: u8 = 1;
+
+/// Version of the source encoding used by logs that also carry a trace context.
+/// Logs without one keep `SOURCE_VERSION` so that older loggers can still read them.
+const TRACED_SOURCE_VERSION: u8 = 2;
+
+const ROBOT_KIND: u16 = 0;
+const SCREEN_KIND: u16 = 1;
+const GATEWAY_KIND: u16 = 2;
+const ADMIN_KIND: u16 = 3;
+const LOGGER_RELAY_KIND: u16 = 4;
+
+/// Enum that represents the component that sent a log.
+/// Kinds not known by this version are kept as `Other` so that
+/// new components can identify themselves without breaking old loggers.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Sources {
-    Robot(u8),
-    Screen(u8),
+    Robot(u16),
+    Screen(u16),
     Gateway,
+    Admin(u32),
+    LoggerRelay(u32),
+    Other(OtherSource),
+}
+
+/// Struct that represents a component this version doesn't know, or a known one whose id
+/// doesn't fit its variant. It can only be built through `Sources::new`, so that it never
+/// holds a source that would be read back as a known variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OtherSource {
+    kind: u16,
+    id: u32,
+}
+
+/// Encoding a source header came with, which tells what follows it in a log message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Header {
+    /// `[kind, u8 id]`, followed by nothing else.
+    Legacy,
+    /// Versioned header followed by an `Origin`.
+    Versioned,
+    /// Versioned header followed by an `Origin` and a trace context.
+    Traced,
+}
+
+/// Struct that represents the machine and process a log comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    pub hostname: String,
+    pub pid: u32,
+}
+
+/// Struct that represents the trace and span a log was written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceIds {
+    pub trace_id: u64,
+    pub span_id: u64,
 }
 
 impl std::fmt::Display for Sources {
@@ -13,39 +71,271 @@ impl std::fmt::Display for Sources {
             Sources::Robot(id) => write!(f, "ROBOT({})", id),
             Sources::Screen(id) => write!(f, "SCREEN({})", id),
             Sources::Gateway => write!(f, "GATEWAY"),
+            Sources::Admin(id) => write!(f, "ADMIN({})", id),
+            Sources::LoggerRelay(id) => write!(f, "LOGGER_RELAY({})", id),
+            Sources::Other(other) => write!(f, "COMPONENT_{}({})", other.kind, other.id),
         }
     }
 }
 
+impl std::fmt::Display for TraceIds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "trace={:016x} span={:016x}", self.trace_id, self.span_id)
+    }
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.hostname, self.pid)
+    }
+}
+
+fn write_bytes(stream: &mut dyn Write, bytes: &[u8], what: &str) -> Result<(), ErrorLog> {
+    stream
+        .write_all(bytes)
+        .map_err(|_| ErrorLog::SerializationError(format!("Error serializing {what} to buffer")))
+}
+
+fn read_bytes<const N: usize>(stream: &mut dyn Read, what: &str) -> Result<[u8; N], ErrorLog> {
+    let mut buffer = [0; N];
+    stream.read_exact(&mut buffer).map_err(|_| {
+        ErrorLog::SerializationError(format!("Error deserializing {what} from buffer"))
+    })?;
+    Ok(buffer)
+}
+
 impl Sources {
-    pub fn serialize(&self, stream: &mut dyn Write) -> Result<(), ErrorLog> {
-        let value: [u8; 2] = match self {
-            Sources::Robot(id) => [0, *id],
-            Sources::Screen(id) => [1, *id],
-            Sources::Gateway => [2, 0],
-        };
-        if stream.write_all(&value[..]).is_err() {
-            return Err(ErrorLog::SerializationError(
-                "Error serializing source to buffer".to_string(),
-            ));
+    /// Builds a source from its kind and id, as the known variant when there's one.
+    /// Robot and screen ids that don't fit in a u16 and gateways with an id other than 0
+    /// are kept as `Other`, so every source survives a round trip.
+    pub fn new(kind: u16, id: u32) -> Sources {
+        match (kind, u16::try_from(id)) {
+            (ROBOT_KIND, Ok(id)) => Sources::Robot(id),
+            (SCREEN_KIND, Ok(id)) => Sources::Screen(id),
+            (GATEWAY_KIND, _) if id == 0 => Sources::Gateway,
+            (ADMIN_KIND, _) => Sources::Admin(id),
+            (LOGGER_RELAY_KIND, _) => Sources::LoggerRelay(id),
+            _ => Sources::Other(OtherSource { kind, id }),
         }
-        Ok(())
     }
 
-    pub fn deserialize(stream: &mut dyn std::io::Read) -> Result<Sources, ErrorLog> {
-        let mut buffer = [0; 2];
-        if stream.read_exact(&mut buffer).is_err() {
-            return Err(ErrorLog::SerializationError(
-                "Error deserializing source from buffer".to_string(),
-            ));
+    /// Returns the kind and id used to encode the source.
+    fn kind_and_id(&self) -> (u16, u32) {
+        match self {
+            Sources::Robot(id) => (ROBOT_KIND, *id as u32),
+            Sources::Screen(id) => (SCREEN_KIND, *id as u32),
+            Sources::Gateway => (GATEWAY_KIND, 0),
+            Sources::Admin(id) => (ADMIN_KIND, *id),
+            Sources::LoggerRelay(id) => (LOGGER_RELAY_KIND, *id),
+            Sources::Other(other) => (other.kind, other.id),
         }
-        match buffer[0] {
-            0 => Ok(Sources::Robot(buffer[1])),
-            1 => Ok(Sources::Screen(buffer[1])),
-            2 => Ok(Sources::Gateway),
+    }
+
+    /// Serializes the source with the versioned encoding:
+    /// `[0xFF, version, kind (u16 BE), id (u32 BE)]`.
+    pub fn serialize(&self, stream: &mut dyn Write) -> Result<(), ErrorLog> {
+        self.serialize_with_version(stream, SOURCE_VERSION)
+    }
+
+    /// Serializes the source with the versioned encoding of a log that carries a trace context.
+    pub(crate) fn serialize_traced(&self, stream: &mut dyn Write) -> Result<(), ErrorLog> {
+        self.serialize_with_version(stream, TRACED_SOURCE_VERSION)
+    }
+
+    fn serialize_with_version(&self, stream: &mut dyn Write, version: u8) -> Result<(), ErrorLog> {
+        let (kind, id) = self.kind_and_id();
+        write_bytes(stream, &[VERSIONED_MARKER, version], "source version")?;
+        write_bytes(stream, &kind.to_be_bytes(), "source kind")?;
+        write_bytes(stream, &id.to_be_bytes(), "source id")
+    }
+
+    /// Serializes the source with the legacy encoding: `[kind, u8 id]`.
+    /// Only robots and screens with ids that fit in a byte and the gateway can be written this way.
+    pub fn serialize_legacy(&self, stream: &mut dyn Write) -> Result<(), ErrorLog> {
+        let header = match self {
+            Sources::Robot(id) => u8::try_from(*id).map(|id| [ROBOT_KIND as u8, id]),
+            Sources::Screen(id) => u8::try_from(*id).map(|id| [SCREEN_KIND as u8, id]),
+            Sources::Gateway => Ok([GATEWAY_KIND as u8, 0]),
+            _ => {
+                return Err(ErrorLog::SerializationError(format!(
+                    "{self} has no legacy encoding"
+                )))
+            }
+        };
+
+        let header = header.map_err(|_| {
+            ErrorLog::SerializationError(format!("The id of {self} doesn't fit a legacy encoding"))
+        })?;
+        write_bytes(stream, &header, "source")
+    }
+
+    /// Deserializes a source, accepting both the versioned encoding and
+    /// the legacy `[kind, u8 id]` one.
+    pub fn deserialize(stream: &mut dyn Read) -> Result<Sources, ErrorLog> {
+        Self::deserialize_header(stream).map(|(source, _)| source)
+    }
+
+    /// Deserializes a source and returns the header it came with,
+    /// which tells what follows it in a log message.
+    pub(crate) fn deserialize_header(stream: &mut dyn Read) -> Result<(Sources, Header), ErrorLog> {
+        let [first, second] = read_bytes::<2>(stream, "source")?;
+
+        match (first, second) {
+            (VERSIONED_MARKER, version @ (SOURCE_VERSION | TRACED_SOURCE_VERSION)) => {
+                let kind = u16::from_be_bytes(read_bytes(stream, "source kind")?);
+                let id = u32::from_be_bytes(read_bytes(stream, "source id")?);
+                let header = match version {
+                    SOURCE_VERSION => Header::Versioned,
+                    _ => Header::Traced,
+                };
+                Ok((Sources::new(kind, id), header))
+            }
+
+            (0, id) => Ok((Sources::Robot(id as u16), Header::Legacy)),
+            (1, id) => Ok((Sources::Screen(id as u16), Header::Legacy)),
+            (2, _) => Ok((Sources::Gateway, Header::Legacy)),
             _ => Err(ErrorLog::SerializationError(
                 "Error deserializing source from buffer".to_string(),
             )),
         }
     }
 }
+
+impl TraceIds {
+    /// Serializes the trace context as `[trace id (u64 BE), span id (u64 BE)]`.
+    pub fn serialize(&self, stream: &mut dyn Write) -> Result<(), ErrorLog> {
+        write_bytes(stream, &self.trace_id.to_be_bytes(), "trace id")?;
+        write_bytes(stream, &self.span_id.to_be_bytes(), "span id")
+    }
+
+    /// Deserializes a trace context.
+    pub fn deserialize(stream: &mut dyn Read) -> Result<TraceIds, ErrorLog> {
+        Ok(TraceIds {
+            trace_id: u64::from_be_bytes(read_bytes(stream, "trace id")?),
+            span_id: u64::from_be_bytes(read_bytes(stream, "span id")?),
+        })
+    }
+}
+
+impl Origin {
+    /// Returns the origin of the current process.
+    pub fn current() -> Self {
+        let hostname = std::env::var("HOSTNAME")
+            .ok()
+            .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "localhost".to_string());
+
+        Origin {
+            hostname,
+            pid: std::process::id(),
+        }
+    }
+
+    /// Serializes the origin as `[hostname len (u8), hostname, pid (u32 BE)]`.
+    /// Hostnames longer than 255 bytes are truncated on a char boundary.
+    pub fn serialize(&self, stream: &mut dyn Write) -> Result<(), ErrorLog> {
+        let mut len = self.hostname.len().min(u8::MAX as usize);
+        while !self.hostname.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        write_bytes(stream, &[len as u8], "hostname len")?;
+        write_bytes(stream, &self.hostname.as_bytes()[..len], "hostname")?;
+        write_bytes(stream, &self.pid.to_be_bytes(), "pid")
+    }
+
+    /// Deserializes an origin.
+    pub fn deserialize(stream: &mut dyn Read) -> Result<Origin, ErrorLog> {
+        let [len] = read_bytes::<1>(stream, "hostname len")?;
+
+        let mut hostname = vec![0; len as usize];
+        stream.read_exact(&mut hostname).map_err(|_| {
+            ErrorLog::SerializationError("Error deserializing hostname from buffer".to_string())
+        })?;
+
+        Ok(Origin {
+            hostname: String::from_utf8_lossy(&hostname).to_string(),
+            pid: u32::from_be_bytes(read_bytes(stream, "pid")?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn any_source() -> impl Strategy<Value = Sources> {
+        prop_oneof![
+            any::<u16>().prop_map(Sources::Robot),
+            any::<u16>().prop_map(Sources::Screen),
+            Just(Sources::Gateway),
+            any::<u32>().prop_map(Sources::Admin),
+            any::<u32>().prop_map(Sources::LoggerRelay),
+            (0..=LOGGER_RELAY_KIND + 1, 0..=u16::MAX as u32 + 1)
+                .prop_map(|(kind, id)| Sources::new(kind, id)),
+            (any::<u16>(), any::<u32>()).prop_map(|(kind, id)| Sources::new(kind, id)),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test01_every_source_survives_a_round_trip(source in any_source()) {
+            let mut buffer = Vec::new();
+            source.serialize(&mut buffer).unwrap();
+            prop_assert_eq!(Sources::deserialize(&mut buffer.as_slice()).unwrap(), source);
+        }
+
+        #[test]
+        fn test02_every_origin_survives_a_round_trip(hostname in "[a-z0-9.-]{0,255}", pid in any::<u32>()) {
+            let origin = Origin { hostname, pid };
+            let mut buffer = Vec::new();
+            origin.serialize(&mut buffer).unwrap();
+            prop_assert_eq!(Origin::deserialize(&mut buffer.as_slice()).unwrap(), origin);
+        }
+
+        #[test]
+        fn test03_legacy_sources_are_still_readable(kind in 0..=1u8, id in any::<u8>()) {
+            let source = Sources::deserialize(&mut [kind, id].as_slice()).unwrap();
+            let expected = match kind {
+                0 => Sources::Robot(id as u16),
+                _ => Sources::Screen(id as u16),
+            };
+            prop_assert_eq!(source, expected);
+        }
+    }
+
+    #[test]
+    fn test04_robot_ids_over_255_are_not_truncated() {
+        let mut buffer = Vec::new();
+        Sources::Robot(300).serialize(&mut buffer).unwrap();
+        let source = Sources::deserialize(&mut buffer.as_slice()).unwrap();
+        assert_eq!(source, Sources::Robot(300));
+    }
+
+    #[test]
+    fn test05_long_hostnames_are_truncated() {
+        let origin = Origin {
+            hostname: "ñ".repeat(200),
+            pid: 1,
+        };
+
+        let mut buffer = Vec::new();
+        origin.serialize(&mut buffer).unwrap();
+        let origin = Origin::deserialize(&mut buffer.as_slice()).unwrap();
+        assert_eq!(origin.hostname, "ñ".repeat(127));
+    }
+
+    #[test]
+    fn test06_sources_of_known_kinds_are_built_as_their_variant() {
+        assert_eq!(Sources::new(ROBOT_KIND, 3), Sources::Robot(3));
+        assert_eq!(Sources::new(GATEWAY_KIND, 0), Sources::Gateway);
+        assert!(matches!(Sources::new(GATEWAY_KIND, 7), Sources::Other(_)));
+        assert!(matches!(
+            Sources::new(SCREEN_KIND, u32::MAX),
+            Sources::Other(_)
+        ));
+    }
+}
@@ -0,0 +1,31 @@
+use robot::{config::RobotConfig, record::read_trace, replay::Replay};
+use std::{env, error::Error};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let [id, path] = args.as_slice() else {
+        Err("Use: cargo run --bin robot-replay <id> <trace>")?
+    };
+
+    let id: u16 = id.parse().map_err(|_| "id needs to be a number")?;
+    let trace = read_trace(path)?;
+    let replay = Replay::run(id, &trace, RobotConfig::from_env()?)?;
+
+    println!("\nSteps");
+    for step in &replay.steps {
+        println!("{}", step.line(replay.start_us));
+    }
+
+    let differences = replay.differences(&trace);
+    if differences.is_empty() {
+        println!("\nThe robot sent the same messages as in the trace");
+        return Ok(());
+    }
+
+    println!("\nDifferences");
+    for difference in &differences {
+        println!("  {difference}");
+    }
+
+    Err("The robot didn't send the same messages as in the trace")?
+}
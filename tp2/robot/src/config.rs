@@ -0,0 +1,87 @@
+use crate::{
+    detector::DetectorConfig, federation::FederationConfig, invariants::InvariantChecker,
+    service::ServiceModel,
+};
+use ice_cream_shop::{messages::protocol::Encoding, shop_values::N_ROBOTS};
+use std::env;
+
+/// Environment variable that limits how many orders may wait in the queue of the order token.
+/// The queue can grow without a limit when it isn't set.
+pub const MAX_QUEUE_VAR: &str = "SHOP_MAX_QUEUE";
+
+/// Environment variable with the ids of the robots a new robot introduces itself to,
+/// separated by commas. Every other robot of the shop is a seed when it isn't set.
+pub const SEEDS_VAR: &str = "SHOP_SEEDS";
+
+/// How a robot behaves, besides the network it uses.
+/// - `detector`: How the robot decides that the next robot is dead.
+/// - `service`: How long the robot takes to scoop.
+/// - `encoding`: The encoding the robot prefers for the connections it opens to other robots.
+/// - `invariants`: The checker the robot feeds the tokens it takes into, if any.
+/// - `max_queue`: How many orders may wait in the queue before the robots refuse new ones,
+///   written into the order token if it doesn't carry a limit yet.
+/// - `federation`: How the robot trades servings with other shops, if it's the gateway of its shop.
+/// - `seeds`: The robots a new robot introduces itself to, in order, or `None` to try every
+///   other robot of the shop.
+/// - `start_us`: The time the clock of the robot starts at, in microseconds since the unix
+///   epoch, or `None` to start it at the time of the system.
+#[derive(Debug, Clone, Default)]
+pub struct RobotConfig {
+    pub detector: DetectorConfig,
+    pub service: ServiceModel,
+    pub encoding: Encoding,
+    pub invariants: Option<InvariantChecker>,
+    pub max_queue: Option<usize>,
+    pub federation: Option<FederationConfig>,
+    pub seeds: Option<Vec<u16>>,
+    pub start_us: Option<u64>,
+}
+
+impl RobotConfig {
+    /// Reads the configuration from `SHOP_DETECTOR`, `SHOP_SERVICE`, `SHOP_ENCODING`,
+    /// `SHOP_INVARIANTS`, `SHOP_MAX_QUEUE`, `SHOP_FEDERATION` and `SHOP_SEEDS`.
+    ///
+    /// # Returns
+    ///
+    /// The configuration, or an error if any of the variables has an invalid value.
+    pub fn from_env() -> Result<Self, &'static str> {
+        Ok(Self {
+            detector: DetectorConfig::from_env()?,
+            service: ServiceModel::from_env()?,
+            encoding: Encoding::from_env()?,
+            invariants: InvariantChecker::from_env(),
+            max_queue: max_queue_from_env()?,
+            federation: FederationConfig::from_env()?,
+            seeds: seeds_from_env()?,
+            start_us: None,
+        })
+    }
+}
+
+/// Reads the limit of the queue of the order token from `SHOP_MAX_QUEUE`.
+fn max_queue_from_env() -> Result<Option<usize>, &'static str> {
+    match env::var(MAX_QUEUE_VAR) {
+        Ok(value) => match value.parse() {
+            Ok(max_queue) if max_queue > 0 => Ok(Some(max_queue)),
+            _ => Err("SHOP_MAX_QUEUE needs to be a positive number"),
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+/// Reads the robots a new robot introduces itself to from `SHOP_SEEDS`.
+fn seeds_from_env() -> Result<Option<Vec<u16>>, &'static str> {
+    let Ok(value) = env::var(SEEDS_VAR) else {
+        return Ok(None);
+    };
+
+    let err = "SHOP_SEEDS needs to be a list of robot ids separated by commas";
+    value
+        .split(',')
+        .map(|id| match id.trim().parse() {
+            Ok(id) if id < N_ROBOTS => Ok(id),
+            _ => Err(err),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+}
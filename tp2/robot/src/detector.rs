@@ -0,0 +1,208 @@
+use std::{collections::VecDeque, env, str::FromStr, time::Duration};
+use tokio::time::Instant;
+
+/// Environment variable that selects how a robot decides that its next robot is dead:
+/// `timeout`, `timeout:<ms>`, `phi` or `phi:<threshold>`. A timeout is used when it isn't set.
+pub const DETECTOR_VAR: &str = "SHOP_DETECTOR";
+
+/// How often a robot tells the previous one that it's alive.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long the timeout detector waits for a heartbeat by default, long enough for a robot
+/// that is busy for a while not to be taken for dead.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The phi above which the phi accrual detector suspects the robot by default.
+const DEFAULT_THRESHOLD: f64 = 8.0;
+
+/// How many intervals between heartbeats the phi accrual detector remembers.
+const WINDOW: usize = 100;
+
+/// The smallest standard deviation the phi accrual detector uses, so that a robot that
+/// was very regular isn't suspected as soon as a heartbeat is a little late.
+const MIN_STD_DEV: Duration = Duration::from_millis(200);
+
+/// A pause the phi accrual detector always accepts on top of the usual interval,
+/// so that a robot that is busy for a while isn't suspected.
+const ACCEPTABLE_PAUSE: Duration = Duration::from_secs(5);
+
+/// The ways a robot can decide that its next robot is dead.
+/// - `Timeout`: The robot is suspected if no heartbeat arrives for the given time.
+/// - `PhiAccrual`: The robot is suspected when the phi of the time since its last heartbeat,
+///   given the intervals seen so far, goes above the threshold.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DetectorConfig {
+    Timeout(Duration),
+    PhiAccrual(f64),
+}
+
+/// Keeps track of the heartbeats of a robot to tell whether it's still alive.
+/// - `config`: How the robot is suspected.
+/// - `last`: When the last heartbeat arrived, or when the detector was created.
+/// - `intervals`: The last intervals between heartbeats, in seconds.
+#[derive(Debug, Clone)]
+pub struct FailureDetector {
+    config: DetectorConfig,
+    last: Instant,
+    intervals: VecDeque<f64>,
+}
+
+impl DetectorConfig {
+    /// Reads the detector from `SHOP_DETECTOR`.
+    ///
+    /// # Returns
+    ///
+    /// The selected detector, a timeout if the variable isn't set, or an error if it has an unknown value.
+    pub fn from_env() -> Result<Self, &'static str> {
+        match env::var(DETECTOR_VAR) {
+            Ok(value) => value.parse(),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// Creates a detector for a robot that was just connected to.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - When the robot was connected to, which counts as its first heartbeat.
+    pub fn detector(&self, now: Instant) -> FailureDetector {
+        FailureDetector {
+            config: *self,
+            last: now,
+            intervals: VecDeque::with_capacity(WINDOW),
+        }
+    }
+}
+
+impl Default for DetectorConfig {
+    fn default() -> Self {
+        Self::Timeout(DEFAULT_TIMEOUT)
+    }
+}
+
+impl FromStr for DetectorConfig {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let err = "SHOP_DETECTOR needs to be timeout, timeout:<ms>, phi or phi:<threshold>";
+        match value.split_once(':') {
+            None if value == "timeout" => Ok(Self::Timeout(DEFAULT_TIMEOUT)),
+            None if value == "phi" => Ok(Self::PhiAccrual(DEFAULT_THRESHOLD)),
+            Some(("timeout", ms)) => ms
+                .parse()
+                .map(|ms| Self::Timeout(Duration::from_millis(ms)))
+                .map_err(|_| err),
+            Some(("phi", threshold)) => match threshold.parse() {
+                Ok(threshold) if threshold > 0.0 => Ok(Self::PhiAccrual(threshold)),
+                _ => Err(err),
+            },
+            _ => Err(err),
+        }
+    }
+}
+
+impl FailureDetector {
+    /// Records a heartbeat.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - When the heartbeat arrived.
+    pub fn heartbeat(&mut self, now: Instant) {
+        if self.intervals.len() == WINDOW {
+            self.intervals.pop_front();
+        }
+
+        let interval = now.saturating_duration_since(self.last);
+        self.intervals.push_back(interval.as_secs_f64());
+        self.last = now;
+    }
+
+    /// Returns how suspicious the silence of the robot is: a phi of 1 means that a heartbeat
+    /// would have arrived by now with a probability of 90%, a phi of 2 of 99%, and so on.
+    /// The intervals are assumed to be normally distributed, with the acceptable pause added
+    /// to their mean.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time.
+    pub fn phi(&self, now: Instant) -> f64 {
+        let (mean, std_dev) = match self.intervals.len() {
+            0 => (HEARTBEAT_INTERVAL.as_secs_f64(), MIN_STD_DEV.as_secs_f64()),
+            n => {
+                let mean = self.intervals.iter().sum::<f64>() / n as f64;
+                let variance = self
+                    .intervals
+                    .iter()
+                    .map(|i| (i - mean).powi(2))
+                    .sum::<f64>()
+                    / n as f64;
+                (mean, variance.sqrt().max(MIN_STD_DEV.as_secs_f64()))
+            }
+        };
+
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        let y = (elapsed - mean - ACCEPTABLE_PAUSE.as_secs_f64()) / std_dev;
+        let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+
+        // Logistic approximation of the normal distribution, as used by Akka.
+        match y > 0.0 {
+            true => -(e / (1.0 + e)).log10(),
+            false => -(1.0 - 1.0 / (1.0 + e)).log10(),
+        }
+    }
+
+    /// Returns whether the robot is suspected to be dead.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time.
+    pub fn is_suspected(&self, now: Instant) -> bool {
+        match self.config {
+            DetectorConfig::Timeout(timeout) => now.saturating_duration_since(self.last) > timeout,
+            DetectorConfig::PhiAccrual(threshold) => self.phi(now) > threshold,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test01_the_detector_is_parsed_from_its_name() {
+        assert_eq!("timeout".parse(), Ok(DetectorConfig::default()));
+        assert_eq!(
+            "timeout:250".parse(),
+            Ok(DetectorConfig::Timeout(Duration::from_millis(250)))
+        );
+        assert_eq!("phi:3.5".parse(), Ok(DetectorConfig::PhiAccrual(3.5)));
+        assert!("phi:-1".parse::<DetectorConfig>().is_err());
+        assert!("gossip".parse::<DetectorConfig>().is_err());
+    }
+
+    #[test]
+    fn test02_the_timeout_detector_suspects_a_silent_robot() {
+        let start = Instant::now();
+        let config = DetectorConfig::Timeout(Duration::from_secs(2));
+        let mut detector = config.detector(start);
+
+        detector.heartbeat(start + Duration::from_secs(1));
+        assert!(!detector.is_suspected(start + Duration::from_secs(3)));
+        assert!(detector.is_suspected(start + Duration::from_millis(3_100)));
+    }
+
+    #[test]
+    fn test03_phi_grows_while_the_robot_is_silent() {
+        let start = Instant::now();
+        let mut detector = DetectorConfig::PhiAccrual(DEFAULT_THRESHOLD).detector(start);
+        for beat in 1..=20 {
+            detector.heartbeat(start + HEARTBEAT_INTERVAL * beat);
+        }
+
+        let last = start + HEARTBEAT_INTERVAL * 20;
+        let phi = |after: u64| detector.phi(last + Duration::from_millis(after));
+        assert!(phi(500) < phi(5_000) && phi(5_000) < phi(7_000));
+        assert!(!detector.is_suspected(last + Duration::from_secs(5)));
+        assert!(detector.is_suspected(last + Duration::from_secs(8)));
+    }
+}
@@ -0,0 +1,597 @@
+use crate::transport::{Endpoint, FrameSender, SharedTransport};
+use ice_cream_shop::{
+    flavour::Flavour,
+    messages::{
+        federation_msg::{FederationMsg, Transfer, TransferId},
+        fmt_msg,
+    },
+    metrics::{registry, shop_metrics::FEDERATION_SERVINGS},
+    tokens::FlavourToken,
+    trace::trace_context::now_us,
+};
+use std::{collections::HashMap, env, str::FromStr, time::Duration};
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    task,
+    time::Instant,
+};
+
+/// Environment variable that makes a robot the gateway of its shop to other shops:
+/// `<shop>:<peer>,<peer>`. The shop doesn't trade servings when it isn't set.
+pub const FEDERATION_VAR: &str = "SHOP_FEDERATION";
+
+/// How many servings of a flavour a shop keeps before asking other shops for more by default.
+/// A shop only gives servings away if it keeps more than these.
+pub const LOW_STOCK: usize = 3;
+
+/// How many servings a shop asks for at a time by default.
+pub const TRANSFER_SERVINGS: usize = 5;
+
+/// How long the gateway waits for an answer before asking another shop, or before sending
+/// again a message of a transfer that didn't end, by default.
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How a shop trades servings with other shops.
+/// - `shop`: The number of this shop.
+/// - `peers`: The shops it trades with, asked in turns.
+/// - `low_stock`: How many servings of a flavour it keeps before asking for more.
+/// - `servings`: How many servings it asks for at a time.
+/// - `retry`: How long it waits for an answer before asking or sending again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FederationConfig {
+    pub shop: u16,
+    pub peers: Vec<u16>,
+    pub low_stock: usize,
+    pub servings: usize,
+    pub retry: Duration,
+}
+
+/// What the gateway robot of a shop knows about the transfers with other shops. The state
+/// of every transfer lives in the flavour tokens, so that nothing is lost if the gateway
+/// crashes; this only keeps what's needed to talk to the other shops.
+/// - `config`: How the shop trades.
+/// - `links`: The tasks that send messages to every other shop.
+/// - `inbox`: The messages from other shops waiting for the token of their flavour.
+/// - `requested`: When the shop last asked for servings of every flavour it didn't get yet.
+/// - `sent`: When the last message of every transfer that didn't end was sent.
+/// - `next_peer`: The turn of the shop to ask next.
+/// - `seq`: The last number given to a transfer of this shop.
+#[derive(Debug)]
+pub struct Federation {
+    config: FederationConfig,
+    links: HashMap<u16, UnboundedSender<FederationMsg>>,
+    inbox: Vec<FederationMsg>,
+    requested: HashMap<Flavour, Instant>,
+    sent: HashMap<TransferId, Instant>,
+    next_peer: usize,
+    seq: u64,
+}
+
+impl FederationConfig {
+    /// Creates the configuration of a shop with the default thresholds.
+    ///
+    /// # Arguments
+    ///
+    /// * `shop` - The number of this shop.
+    /// * `peers` - The shops it trades with.
+    pub fn new(shop: u16, peers: Vec<u16>) -> Self {
+        Self {
+            shop,
+            peers,
+            low_stock: LOW_STOCK,
+            servings: TRANSFER_SERVINGS,
+            retry: RETRY_INTERVAL,
+        }
+    }
+
+    /// Reads the configuration from `SHOP_FEDERATION`.
+    ///
+    /// # Returns
+    ///
+    /// The configuration, `None` if the variable isn't set, or an error if it has an invalid value.
+    pub fn from_env() -> Result<Option<Self>, &'static str> {
+        match env::var(FEDERATION_VAR) {
+            Ok(value) => value.parse().map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+impl FromStr for FederationConfig {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let err = "SHOP_FEDERATION needs to be <shop>:<peer>,<peer>";
+        let (shop, peers) = value.split_once(':').ok_or(err)?;
+        let shop = shop.parse().map_err(|_| err)?;
+        let peers = peers
+            .split(',')
+            .map(|peer| peer.trim().parse())
+            .collect::<Result<Vec<u16>, _>>()
+            .map_err(|_| err)?;
+
+        if peers.contains(&shop) {
+            return Err("SHOP_FEDERATION can't list the shop as its own peer");
+        }
+
+        Ok(Self::new(shop, peers))
+    }
+}
+
+impl Federation {
+    /// Starts the links to the other shops. It needs to be called from a task.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - How the shop trades.
+    /// * `transport` - The network used to reach the other shops.
+    pub fn new(config: FederationConfig, transport: &SharedTransport) -> Self {
+        let links = config
+            .peers
+            .iter()
+            .map(|&peer| {
+                let (tx, rx) = mpsc::unbounded_channel();
+                task::spawn(link(transport.clone(), peer, rx));
+                (peer, tx)
+            })
+            .collect();
+
+        Self {
+            config,
+            links,
+            inbox: Vec::new(),
+            requested: HashMap::new(),
+            sent: HashMap::new(),
+            next_peer: 0,
+            seq: now_us(),
+        }
+    }
+
+    /// Keeps a message from another shop until the token of its flavour passes by.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The message.
+    pub fn deliver(&mut self, msg: FederationMsg) {
+        self.inbox.push(msg);
+    }
+
+    /// Trades the servings of a flavour token: it answers the messages that arrived for its
+    /// flavour, sends again the ones of the transfers that didn't end, and asks another shop
+    /// for servings if the flavour runs low.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The flavour token, which the robot holds.
+    pub fn trade(&mut self, token: &mut FlavourToken) {
+        let flavour = token.flavour();
+        let (arrived, rest) = self
+            .inbox
+            .drain(..)
+            .partition(|msg| msg.transfer().flavour == flavour);
+        self.inbox = rest;
+
+        for msg in arrived {
+            self.answer(token, msg);
+        }
+
+        self.resend(token);
+        self.request(token);
+    }
+
+    /// Answers a message about a transfer of the flavour of the token. Every message can
+    /// arrive more than once, so they all leave the token as it is if they were already applied.
+    fn answer(&mut self, token: &mut FlavourToken, msg: FederationMsg) {
+        let shop = self.config.shop;
+        let flavour = token.flavour().name();
+
+        match msg {
+            FederationMsg::Request(transfer) if transfer.donor == shop => {
+                let held = token.held().any(|held| held.id == transfer.id);
+                let spare =
+                    token.servings().saturating_sub(transfer.servings) > self.config.low_stock;
+                if held || (spare && token.hold(transfer)) {
+                    self.sent.insert(transfer.id, Instant::now());
+                    self.send(FederationMsg::Prepared(transfer));
+                } else {
+                    self.send(FederationMsg::Refused(transfer));
+                }
+            }
+
+            FederationMsg::Prepared(transfer) if transfer.id.requester == shop => {
+                self.requested.remove(&transfer.flavour);
+                if token.receive(transfer) {
+                    let labels = [("direction", "in"), ("flavour", flavour)];
+                    registry().inc(FEDERATION_SERVINGS, &labels, transfer.servings as u64);
+                }
+
+                if token.received().any(|received| received.id == transfer.id) {
+                    self.sent.insert(transfer.id, Instant::now());
+                    self.send(FederationMsg::Committed(transfer));
+                } else if token.is_done(&transfer) {
+                    // The offer arrived late, so the donor is told again to stop holding it.
+                    self.send(FederationMsg::Committed(transfer));
+                }
+            }
+
+            FederationMsg::Refused(transfer) if transfer.id.requester == shop => {
+                println!("Shop {} can't spare {flavour}", transfer.donor);
+            }
+
+            FederationMsg::Committed(transfer) if transfer.donor == shop => {
+                if token.ship(transfer.id).is_some() {
+                    let labels = [("direction", "out"), ("flavour", flavour)];
+                    registry().inc(FEDERATION_SERVINGS, &labels, transfer.servings as u64);
+                }
+
+                self.sent.remove(&transfer.id);
+                self.send(FederationMsg::Done(transfer));
+            }
+
+            FederationMsg::Done(transfer) if transfer.id.requester == shop => {
+                token.forget(transfer.id);
+                self.sent.remove(&transfer.id);
+            }
+
+            msg => eprintln!("Federation message for another shop ignored: {msg:?}"),
+        }
+    }
+
+    /// Sends again the last message of every transfer in the token that didn't end, in case
+    /// it was lost: the servings held are offered again, and the servings received are
+    /// confirmed again.
+    fn resend(&mut self, token: &FlavourToken) {
+        let pending = token
+            .held()
+            .map(|transfer| FederationMsg::Prepared(*transfer))
+            .chain(
+                token
+                    .received()
+                    .map(|transfer| FederationMsg::Committed(*transfer)),
+            );
+
+        let mut due = Vec::new();
+        for msg in pending {
+            let id = msg.transfer().id;
+            let last = self.sent.entry(id).or_insert_with(Instant::now);
+            if last.elapsed() >= self.config.retry {
+                *last = Instant::now();
+                due.push(msg);
+            }
+        }
+
+        for msg in due {
+            self.send(msg);
+        }
+    }
+
+    /// Asks the next shop for servings if the flavour of the token runs low and no other
+    /// shop was asked recently.
+    fn request(&mut self, token: &FlavourToken) {
+        let flavour = token.flavour();
+        if token.servings() > self.config.low_stock || self.config.peers.is_empty() {
+            return;
+        }
+
+        let waiting = self.requested.get(&flavour);
+        if waiting.is_some_and(|at| at.elapsed() < self.config.retry) {
+            return;
+        }
+
+        let donor = self.config.peers[self.next_peer % self.config.peers.len()];
+        self.next_peer += 1;
+        self.seq += 1;
+
+        let transfer = Transfer {
+            id: TransferId {
+                requester: self.config.shop,
+                seq: self.seq,
+            },
+            donor,
+            flavour,
+            servings: self.config.servings,
+        };
+
+        self.requested.insert(flavour, Instant::now());
+        self.send(FederationMsg::Request(transfer));
+    }
+
+    /// Hands a message to the link of the other shop of its transfer.
+    fn send(&self, msg: FederationMsg) {
+        let peer = msg.transfer().other(self.config.shop);
+        match self.links.get(&peer) {
+            Some(link) => {
+                let _ = link.send(msg);
+            }
+            None => eprintln!("Shop {peer} isn't a peer of this shop"),
+        }
+    }
+}
+
+/// Sends the messages for another shop to its gateway robot, connecting when there's something
+/// to send. A message that can't be sent is dropped, since every transfer that didn't end is
+/// sent again when the token of its flavour passes by.
+async fn link(transport: SharedTransport, shop: u16, mut rx: UnboundedReceiver<FederationMsg>) {
+    let mut connection: Option<Box<dyn FrameSender>> = None;
+
+    while let Some(msg) = rx.recv().await {
+        if connection.is_none() {
+            connection = match transport.connect(Endpoint::Federation(shop)).await {
+                Ok(connection) => Some(connection.tx),
+                Err(e) => {
+                    eprintln!("Couldn't connect to shop {shop}: {e}");
+                    continue;
+                }
+            };
+        }
+
+        let Some(tx) = connection.as_mut() else {
+            continue;
+        };
+
+        if tx.send(&fmt_msg(msg)).await.is_err() {
+            connection = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::RobotConfig,
+        robot::Robot,
+        transport::{BoxFuture, ChannelTransport, Connection, Listener, RingTransport},
+    };
+    use ice_cream_shop::{
+        messages::admin_msg::AdminMsg, shop_values::STARTING_ICECREAM, snapshot::GlobalSnapshot,
+    };
+    use std::{
+        io,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+    use tokio::time;
+
+    /// The network of a shop: its own ring, and the network shared by the gateways of all shops.
+    #[derive(Debug)]
+    struct Shop {
+        ring: SharedTransport,
+        federation: SharedTransport,
+    }
+
+    /// A network that loses every third message sent through it.
+    #[derive(Debug)]
+    struct Flaky {
+        inner: SharedTransport,
+        sent: Arc<AtomicUsize>,
+    }
+
+    /// The sending half of a connection of a flaky network.
+    #[derive(Debug)]
+    struct FlakySender {
+        tx: Box<dyn FrameSender>,
+        sent: Arc<AtomicUsize>,
+    }
+
+    impl Shop {
+        fn route(&self, endpoint: Endpoint) -> &SharedTransport {
+            match endpoint {
+                Endpoint::Federation(_) => &self.federation,
+                _ => &self.ring,
+            }
+        }
+    }
+
+    impl RingTransport for Shop {
+        fn listen(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Box<dyn Listener>>> {
+            self.route(endpoint).listen(endpoint)
+        }
+
+        fn connect(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Connection>> {
+            self.route(endpoint).connect(endpoint)
+        }
+    }
+
+    impl RingTransport for Flaky {
+        fn listen(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Box<dyn Listener>>> {
+            self.inner.listen(endpoint)
+        }
+
+        fn connect(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Connection>> {
+            Box::pin(async move {
+                let connection = self.inner.connect(endpoint).await?;
+                Ok(Connection {
+                    tx: Box::new(FlakySender {
+                        tx: connection.tx,
+                        sent: self.sent.clone(),
+                    }),
+                    rx: connection.rx,
+                })
+            })
+        }
+    }
+
+    impl FrameSender for FlakySender {
+        fn send<'a>(&'a mut self, frame: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+            match self.sent.fetch_add(1, Ordering::Relaxed) % 3 {
+                2 => Box::pin(async { Err(io::ErrorKind::BrokenPipe.into()) }),
+                _ => self.tx.send(frame),
+            }
+        }
+    }
+
+    /// Starts a shop of a single robot, which is its gateway, and returns its ring.
+    async fn start_shop(config: FederationConfig, federation: &SharedTransport) -> SharedTransport {
+        let ring = SharedTransport::new(ChannelTransport::default());
+        let transport = SharedTransport::new(Shop {
+            ring: ring.clone(),
+            federation: federation.clone(),
+        });
+        let config = RobotConfig {
+            federation: Some(config),
+            ..RobotConfig::default()
+        };
+
+        Robot::start(0, transport, config).await.unwrap();
+        ring
+    }
+
+    /// Returns the flavour tokens of a shop, taken from a snapshot of its ring.
+    async fn flavour_tokens(ring: &SharedTransport) -> Vec<FlavourToken> {
+        let mut connection = ring.connect(Endpoint::RobotAdmin(0)).await.unwrap();
+        connection
+            .tx
+            .send(&fmt_msg(AdminMsg::Snapshot))
+            .await
+            .unwrap();
+        let frame = connection.rx.recv().await.unwrap().unwrap();
+        let snapshot: GlobalSnapshot = serde_json::from_slice(&frame).unwrap();
+        snapshot.flavour_tokens
+    }
+
+    /// Starts a shop that has plenty and one that wants more of every flavour, and waits until
+    /// every transfer between them ended.
+    ///
+    /// # Returns
+    ///
+    /// The flavour tokens of the donor and of the requester.
+    async fn trade(federation: SharedTransport) -> (Vec<FlavourToken>, Vec<FlavourToken>) {
+        let retry = Duration::from_millis(50);
+        let donor = FederationConfig {
+            retry,
+            ..FederationConfig::new(0, vec![1])
+        };
+        let requester = FederationConfig {
+            low_stock: STARTING_ICECREAM,
+            retry,
+            ..FederationConfig::new(1, vec![0])
+        };
+
+        let donor = start_shop(donor, &federation).await;
+        let requester = start_shop(requester, &federation).await;
+        let ended = |tokens: &[FlavourToken]| {
+            tokens.len() == Flavour::flavours().count()
+                && tokens
+                    .iter()
+                    .all(|token| token.held().count() + token.received().count() == 0)
+        };
+
+        for _ in 0..100 {
+            time::sleep(Duration::from_millis(100)).await;
+            let tokens = (
+                flavour_tokens(&donor).await,
+                flavour_tokens(&requester).await,
+            );
+            if tokens.1.iter().all(|token| token.imported() > 0)
+                && ended(&tokens.0)
+                && ended(&tokens.1)
+            {
+                return tokens;
+            }
+        }
+
+        panic!("The transfers didn't end");
+    }
+
+    #[test]
+    fn test01_the_federation_is_parsed_from_the_shop_and_its_peers() {
+        assert_eq!("2:0,1".parse(), Ok(FederationConfig::new(2, vec![0, 1])));
+        assert!("2".parse::<FederationConfig>().is_err());
+        assert!("2:".parse::<FederationConfig>().is_err());
+        assert!("2:1,2".parse::<FederationConfig>().is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test02_a_shop_that_runs_low_gets_servings_from_another() {
+        let federation = SharedTransport::new(ChannelTransport::default());
+        let (donor, requester) = trade(federation).await;
+
+        for (donor, requester) in donor.iter().zip(&requester) {
+            assert_eq!(
+                donor.servings() + requester.servings(),
+                2 * STARTING_ICECREAM
+            );
+            assert_eq!(requester.imported(), TRANSFER_SERVINGS);
+            assert!(requester.servings() > STARTING_ICECREAM);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test03_no_servings_are_lost_or_created_when_messages_are_lost() {
+        let federation = SharedTransport::new(Flaky {
+            inner: SharedTransport::new(ChannelTransport::default()),
+            sent: Arc::default(),
+        });
+        let (donor, requester) = trade(federation).await;
+
+        for (donor, requester) in donor.iter().zip(&requester) {
+            assert_eq!(
+                donor.servings() + requester.servings(),
+                2 * STARTING_ICECREAM
+            );
+            assert_eq!(requester.imported(), TRANSFER_SERVINGS);
+        }
+    }
+
+    #[tokio::test]
+    async fn test04_an_offer_that_arrives_after_the_transfer_is_done_is_ignored() {
+        let federation = SharedTransport::new(ChannelTransport::default());
+        let mut requester = Federation::new(FederationConfig::new(1, vec![0]), &federation);
+        let mut token = FlavourToken::new(0, Flavour::Chocolate, 1);
+        let transfer = Transfer {
+            id: TransferId {
+                requester: 1,
+                seq: 7,
+            },
+            donor: 0,
+            flavour: Flavour::Chocolate,
+            servings: 3,
+        };
+
+        requester.answer(&mut token, FederationMsg::Prepared(transfer));
+        requester.answer(&mut token, FederationMsg::Done(transfer));
+        requester.answer(&mut token, FederationMsg::Prepared(transfer));
+
+        assert_eq!((token.servings(), token.imported()), (4, 3));
+        assert_eq!(token.received().count(), 0);
+        assert!(requester.sent.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test05_an_offer_that_arrives_after_a_newer_transfer_ended_is_added() {
+        let federation = SharedTransport::new(ChannelTransport::default());
+        let mut donor = Federation::new(FederationConfig::new(0, vec![1]), &federation);
+        let mut requester = Federation::new(FederationConfig::new(1, vec![0]), &federation);
+        let mut donor_token = FlavourToken::new(0, Flavour::Chocolate, 20);
+        let mut requester_token = FlavourToken::new(0, Flavour::Chocolate, 1);
+        let [older, newer] = [7, 8].map(|seq| Transfer {
+            id: TransferId { requester: 1, seq },
+            donor: 0,
+            flavour: Flavour::Chocolate,
+            servings: 3,
+        });
+
+        for transfer in [older, newer] {
+            donor.answer(&mut donor_token, FederationMsg::Request(transfer));
+        }
+
+        requester.answer(&mut requester_token, FederationMsg::Prepared(newer));
+        donor.answer(&mut donor_token, FederationMsg::Committed(newer));
+        requester.answer(&mut requester_token, FederationMsg::Done(newer));
+
+        requester.answer(&mut requester_token, FederationMsg::Prepared(older));
+        donor.answer(&mut donor_token, FederationMsg::Committed(older));
+        requester.answer(&mut requester_token, FederationMsg::Done(older));
+        requester.answer(&mut requester_token, FederationMsg::Prepared(older));
+
+        assert_eq!(donor_token.stock() + requester_token.stock(), 21);
+        assert_eq!(requester_token.imported(), 6);
+        assert_eq!(
+            donor_token.held().count() + requester_token.received().count(),
+            0
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use ice_cream_shop::{
+    flavour::Flavour,
+    metrics::{registry, shop_metrics::INVARIANT_VIOLATIONS},
+    orders::OrderId,
+    tokens::{FlavourToken, OrderToken, TokenId},
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env, fmt,
+    sync::{Arc, Mutex},
+};
+
+/// Environment variable that turns on the invariant checker of the robots of a process
+/// when it's set to `1`.
+pub const INVARIANTS_VAR: &str = "SHOP_INVARIANTS";
+
+/// How many rounds of every token the checker remembers.
+const HISTORY: usize = 64;
+
+/// The ways the tokens of the ring can go wrong.
+/// - `DuplicateToken`: Two robots sent the same round of a token and both copies were taken,
+///   so there are two of it going around the ring.
+/// - `StockIncreased`: A flavour token has more servings than an earlier round of it, besides
+///   the ones other shops gave in between, which means that ice cream was created out of nothing.
+/// - `DuplicateOrder`: An order is more than once in the order token, queued or in progress.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    DuplicateToken {
+        token: TokenId,
+        round: u64,
+        senders: (u16, u16),
+    },
+    StockIncreased {
+        flavour: Flavour,
+        round: u64,
+        from: usize,
+        to: usize,
+    },
+    DuplicateOrder {
+        order: OrderId,
+        round: u64,
+    },
+}
+
+/// A round of a token that a robot took.
+/// - `sender`: The robot that sent it.
+/// - `stock`: The stock of the flavour, for flavour tokens.
+/// - `imported`: The servings other shops gave, for flavour tokens.
+#[derive(Debug, Clone, Copy)]
+struct Received {
+    sender: u16,
+    stock: Option<usize>,
+    imported: usize,
+}
+
+/// What the checker saw so far.
+/// - `rounds`: The last rounds of every token that were taken.
+/// - `violations`: The violations found.
+#[derive(Debug, Default)]
+struct Observations {
+    rounds: HashMap<TokenId, BTreeMap<u64, Received>>,
+    violations: Vec<Violation>,
+}
+
+/// Checks the invariants of the tokens with what the robots feed into it every time they
+/// take a token that isn't an old copy. Robots that share a checker are checked together, so
+/// it can tell when two copies of the same token are going around the ring.
+///
+/// A robot that resends a stashed token to a new next robot sends the same round again, so
+/// only copies of a round sent by different robots are duplicates.
+#[derive(Debug, Clone, Default)]
+pub struct InvariantChecker(Arc<Mutex<Observations>>);
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DuplicateToken {
+                token,
+                round,
+                senders: (a, b),
+            } => write!(
+                f,
+                "Robots {a} and {b} both sent round {round} of the {token:?} token"
+            ),
+            Self::StockIncreased {
+                flavour,
+                round,
+                from,
+                to,
+            } => write!(
+                f,
+                "The stock of {flavour:?} went from {from} to {to} servings in round {round}"
+            ),
+            Self::DuplicateOrder { order, round } => write!(
+                f,
+                "Order {order:?} is more than once in round {round} of the order token"
+            ),
+        }
+    }
+}
+
+impl Violation {
+    /// Returns the name of the invariant, for the metrics.
+    fn name(&self) -> &'static str {
+        match self {
+            Self::DuplicateToken { .. } => "duplicate_token",
+            Self::StockIncreased { .. } => "stock_increased",
+            Self::DuplicateOrder { .. } => "duplicate_order",
+        }
+    }
+}
+
+impl InvariantChecker {
+    /// Creates a checker if `SHOP_INVARIANTS` is set to `1`.
+    pub fn from_env() -> Option<Self> {
+        env::var(INVARIANTS_VAR)
+            .is_ok_and(|value| value == "1")
+            .then(Self::default)
+    }
+
+    /// Checks the order token that a robot took: no order can be both queued and in
+    /// progress, and no other robot can have sent the same round of it.
+    ///
+    /// # Arguments
+    ///
+    /// * `robot` - The robot that took it.
+    /// * `token` - The token, as it was received.
+    pub fn order_token_received(&self, robot: u16, token: &OrderToken) {
+        let mut found = Vec::new();
+        let mut seen = HashSet::new();
+        for order in token.orders() {
+            if !seen.insert(order.id()) {
+                found.push(Violation::DuplicateOrder {
+                    order: order.id(),
+                    round: token.round(),
+                });
+            }
+        }
+
+        let received = Received {
+            sender: token.sender(),
+            stock: None,
+            imported: 0,
+        };
+
+        self.observe(robot, token.id(), token.round(), received, found);
+    }
+
+    /// Checks the flavour token that a robot took: its stock can't be higher than in an
+    /// earlier round, and no other robot can have sent the same round of it.
+    ///
+    /// # Arguments
+    ///
+    /// * `robot` - The robot that took it.
+    /// * `token` - The token, as it was received.
+    pub fn flavour_token_received(&self, robot: u16, token: &FlavourToken) {
+        let received = Received {
+            sender: token.sender(),
+            stock: Some(token.stock()),
+            imported: token.imported(),
+        };
+
+        self.observe(robot, token.id(), token.round(), received, Vec::new());
+    }
+
+    /// Returns the violations found so far.
+    pub fn violations(&self) -> Vec<Violation> {
+        self.lock().violations.clone()
+    }
+
+    /// Records a round of a token and reports the violations it shows.
+    fn observe(
+        &self,
+        robot: u16,
+        token: TokenId,
+        round: u64,
+        received: Received,
+        mut found: Vec<Violation>,
+    ) {
+        let mut observations = self.lock();
+        let rounds = observations.rounds.entry(token).or_default();
+
+        if let Some(earlier) = rounds.get(&round) {
+            if earlier.sender != received.sender {
+                found.push(Violation::DuplicateToken {
+                    token,
+                    round,
+                    senders: (earlier.sender, received.sender),
+                });
+            }
+        }
+
+        if let (TokenId::Flavour(flavour), Some(_)) = (token, received.stock) {
+            let before = rounds.range(..round).next_back();
+            let after = rounds.range(round + 1..).next();
+
+            let increases = [
+                before.map(|(_, earlier)| (*earlier, received, round)),
+                after.map(|(later_round, later)| (received, *later, *later_round)),
+            ];
+
+            // The servings other shops gave in between don't count as an increase.
+            for (earlier, later, round) in increases.into_iter().flatten() {
+                let (Some(from), Some(to)) = (earlier.stock, later.stock) else {
+                    continue;
+                };
+
+                if to + earlier.imported > from + later.imported {
+                    found.push(Violation::StockIncreased {
+                        flavour,
+                        round,
+                        from,
+                        to,
+                    });
+                }
+            }
+        }
+
+        rounds.entry(round).or_insert(received);
+        while rounds.len() > HISTORY {
+            rounds.pop_first();
+        }
+
+        for violation in found {
+            eprintln!("Invariant violated at robot {robot}: {violation}");
+            registry().inc(INVARIANT_VIOLATIONS, &[("invariant", violation.name())], 1);
+            observations.violations.push(violation);
+        }
+    }
+
+    /// Locks the observations, even if a robot panicked while holding them.
+    fn lock(&self) -> std::sync::MutexGuard<'_, Observations> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ice_cream_shop::orders::Order;
+
+    /// Returns a copy of the token sent once more by the given robot.
+    fn send(token: &FlavourToken, robot: u16) -> FlavourToken {
+        let mut token = token.clone();
+        token.mark(robot);
+        token
+    }
+
+    #[test]
+    fn test01_a_token_passed_around_the_ring_is_fine() {
+        let checker = InvariantChecker::default();
+        let mut token = FlavourToken::new(0, Flavour::Menta, 10);
+        for (sender, robot) in [(0, 1), (1, 2), (2, 0), (0, 1)] {
+            token = send(&token, sender);
+            checker.flavour_token_received(robot, &token);
+            token.take(1);
+        }
+
+        assert_eq!(checker.violations(), vec![]);
+    }
+
+    #[test]
+    fn test02_a_resent_token_is_not_a_duplicate() {
+        let checker = InvariantChecker::default();
+        let token = send(&FlavourToken::new(0, Flavour::Menta, 10), 0);
+        checker.flavour_token_received(1, &token);
+        checker.flavour_token_received(2, &token);
+
+        assert_eq!(checker.violations(), vec![]);
+    }
+
+    #[test]
+    fn test03_two_robots_sending_the_same_round_is_a_duplicate() {
+        let checker = InvariantChecker::default();
+        let token = send(&FlavourToken::new(0, Flavour::Menta, 10), 0);
+        checker.flavour_token_received(1, &send(&token, 1));
+        checker.flavour_token_received(0, &send(&token, 2));
+
+        assert!(matches!(
+            checker.violations()[..],
+            [Violation::DuplicateToken {
+                senders: (1, 2),
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn test04_the_stock_can_not_increase() {
+        let checker = InvariantChecker::default();
+        let first = send(&FlavourToken::new(0, Flavour::Menta, 10), 0);
+        let mut second = first.clone();
+        second.take(4);
+        let second = send(&second, 1);
+        let forged = send(&send(&first, 1), 2);
+
+        checker.flavour_token_received(2, &second);
+        checker.flavour_token_received(1, &first);
+        checker.flavour_token_received(0, &forged);
+
+        assert!(matches!(
+            checker.violations()[..],
+            [Violation::StockIncreased {
+                from: 6,
+                to: 10,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn test05_an_order_can_not_be_queued_and_in_progress() {
+        let checker = InvariantChecker::default();
+        let order = Order::new(OrderId::new(1, 1), HashMap::new());
+        let mut token = OrderToken::new(0);
+        token.upload_new_orders([order.clone()].into_iter());
+        token.add_in_progress(0, 1, order);
+        checker.order_token_received(1, &token);
+
+        assert!(matches!(
+            checker.violations()[..],
+            [Violation::DuplicateOrder { .. }]
+        ));
+    }
+}
@@ -1,24 +1,64 @@
+pub mod config;
+pub mod detector;
+pub mod federation;
+pub mod invariants;
 pub mod message;
+pub mod record;
+pub mod replay;
 pub mod robot;
+pub mod service;
+pub mod snapshot;
 pub mod token_box;
+pub mod transport;
+pub mod wall_clock;
 
 use actix::prelude::*;
-use ice_cream_shop::messages::robot_msg::RobotMsg;
+use ice_cream_shop::messages::{
+    admin_msg::AdminMsg, federation_msg::FederationMsg, fmt_msg, protocol::decode,
+    robot_msg::RobotMsg,
+};
 use message::*;
 use robot::Robot;
-use std::io::BufRead;
 use tokio::{
-    io::{AsyncReadExt, ReadHalf},
-    net::{TcpListener, TcpStream},
+    signal::{
+        self,
+        unix::{self as unix_signal, SignalKind},
+    },
+    task,
 };
+use transport::{handshake, Connection, FrameReceiver, Listener};
 
 /// Starts a TCP listener that will listen for robots that want to connect to the robot.
-/// When they try to join, it sends a message to the robot for it to handle the connection.
-async fn new_connections_receiver(robot_addr: Addr<Robot>, listener: TcpListener) {
+/// Every robot agrees with this one on the version and encoding of the protocol, and then
+/// introduces itself with a `Join` if it's entering the ring or a `Link` if it's already part
+/// of it. Then it sends a message to the robot for it to handle the connection.
+async fn new_connections_receiver(robot_addr: Addr<Robot>, mut listener: Box<dyn Listener>) {
     println!("0: new_connections_receiver started");
 
-    while let Ok((stream, _)) = listener.accept().await {
-        robot_addr.do_send(Connect { stream });
+    while let Ok(mut connection) = listener.accept().await {
+        let (codec, frame) = match handshake::welcome(&mut connection).await {
+            Ok(welcome) => welcome,
+            Err(e) => {
+                eprintln!("Couldn't agree on the protocol at new_connections_receiver: {e}");
+                continue;
+            }
+        };
+
+        let (id, joining) = match decode(&frame) {
+            Ok(RobotMsg::Join(id)) => (id, true),
+            Ok(RobotMsg::Link(id)) => (id, false),
+            _ => {
+                eprintln!("Invalid message received at new_connections_receiver");
+                continue;
+            }
+        };
+
+        robot_addr.do_send(Connect {
+            id,
+            joining,
+            codec,
+            connection,
+        });
     }
 
     println!("0: new_connections_receiver ended");
@@ -26,49 +66,70 @@ async fn new_connections_receiver(robot_addr: Addr<Robot>, listener: TcpListener
 
 /// Continuously reads from the stream of the robot that is before this robot in the chain
 /// to receive messages from it. It will send the messages to the robot to handle them.
-async fn prev_robot_receiver(robot: Addr<Robot>, mut stream: ReadHalf<TcpStream>) {
+/// When the stream closes, it tells the robot which robot it was connected to and whether
+/// it said goodbye before closing, so that the robot can adopt its orders if it crashed.
+async fn prev_robot_receiver(robot: Addr<Robot>, mut rx: Box<dyn FrameReceiver>) {
     println!("1: prev_robot_receiver started");
 
-    let mut bytes = [0; 2048];
-    while let Ok(n) = stream.read(&mut bytes).await {
-        if n == 0 {
-            break;
-        }
+    let mut prev_id = None;
+    let mut graceful = false;
+
+    while let Ok(Some(frame)) = rx.recv().await {
+        match decode(&frame) {
+            Ok(RobotMsg::RecvOrderToken(token)) => {
+                prev_id = Some(token.sender());
+                robot.do_send(RecvOrderToken { token })
+            }
+
+            Ok(RobotMsg::RecvFlavourToken(token)) => {
+                prev_id = Some(token.sender());
+                robot.do_send(RecvFlavourToken { token })
+            }
 
-        for line in bytes[..n].lines().map_while(Result::ok) {
-            match serde_json::from_str(&line) {
-                Ok(RobotMsg::RecvOrderToken(token)) => robot.do_send(RecvOrderToken { token }),
-                Ok(RobotMsg::RecvFlavourToken(token)) => robot.do_send(RecvFlavourToken { token }),
-                _ => eprintln!("Invalid message received at prev_robot_receiver"),
+            Ok(RobotMsg::BackupOrder(origin, order)) => {
+                prev_id = Some(origin);
+                robot.do_send(BackupOrder { origin, order })
             }
+
+            Ok(RobotMsg::Marker(snapshot)) => robot.do_send(Marker {
+                snapshot,
+                from_next: false,
+            }),
+
+            Ok(RobotMsg::SnapshotPart(part)) => robot.do_send(SnapshotPart { part: *part }),
+
+            Ok(RobotMsg::Disconnect) => graceful = true,
+            _ => eprintln!("Invalid message received at prev_robot_receiver"),
         }
     }
 
+    robot.do_send(PrevClosed { prev_id, graceful });
+
     println!("1: prev_robot_receiver ended");
 }
 
 /// Continuously reads from the stream of the robot that is after this robot in the chain
 /// to receive messages from it. It will send the messages to the robot to handle them.
-async fn next_robot_receiver(robot: Addr<Robot>, mut stream: ReadHalf<TcpStream>) {
+async fn next_robot_receiver(robot: Addr<Robot>, mut rx: Box<dyn FrameReceiver>) {
     println!("2: next_robot_receiver started");
 
-    let mut bytes = [0; 2048];
-    while let Ok(n) = stream.read(&mut bytes).await {
-        if n == 0 {
-            break;
-        }
-
-        for line in bytes[..n].lines().map_while(Result::ok) {
-            match serde_json::from_str(&line) {
-                Ok(RobotMsg::EndOfUse(token_id)) => robot.do_send(EndOfUse { token_id }),
-                Ok(RobotMsg::Disconnect) => {
-                    robot.do_send(FindNext);
-                    println!("2: next_robot_receiver ended");
-                    return;
-                }
-
-                _ => eprintln!("Invalid message received at next_robot_receiver"),
+    while let Ok(Some(frame)) = rx.recv().await {
+        match decode(&frame) {
+            Ok(RobotMsg::EndOfUse(token_id, round)) => robot.do_send(EndOfUse { token_id, round }),
+            Ok(RobotMsg::Members(members)) => robot.do_send(MergeMembers { members }),
+            Ok(RobotMsg::Leave(id)) => robot.do_send(MemberLeft { id }),
+            Ok(RobotMsg::Heartbeat(id)) => robot.do_send(Heartbeat { id }),
+            Ok(RobotMsg::Marker(snapshot)) => robot.do_send(Marker {
+                snapshot,
+                from_next: true,
+            }),
+            Ok(RobotMsg::Disconnect) => {
+                robot.do_send(NextDisconnected);
+                println!("2: next_robot_receiver ended");
+                return;
             }
+
+            _ => eprintln!("Invalid message received at next_robot_receiver"),
         }
     }
 
@@ -79,21 +140,96 @@ async fn next_robot_receiver(robot: Addr<Robot>, mut stream: ReadHalf<TcpStream>
 }
 
 /// Starts a TCP listener that will listen for orders that are sent to the robot.
-/// When an order is received, it sends a message to the robot for it to handle the order.
-async fn new_orders_receiver(robot_addr: Addr<Robot>, listener: TcpListener) {
+/// When an order is received, it sends a message to the robot for it to handle the order
+/// and answers the screen with whether the robot took it.
+async fn new_orders_receiver(robot_addr: Addr<Robot>, mut listener: Box<dyn Listener>) {
     println!("3: new_orders_receiver started");
 
-    let mut bytes = [0; 2048];
-    while let Ok((mut stream, _)) = listener.accept().await {
-        let Ok(n) = stream.read(&mut bytes).await else {
+    while let Ok(mut connection) = listener.accept().await {
+        let Ok(Some(frame)) = connection.rx.recv().await else {
             continue;
         };
 
-        match serde_json::from_slice(&bytes[..n]) {
-            Ok(RobotMsg::RecvOrder(order)) => robot_addr.do_send(RecvOrder { order }),
-            _ => eprintln!("Invalid message received at new_orders_receiver"),
-        }
+        let admission = match decode(&frame) {
+            Ok(RobotMsg::RecvOrder(order)) => match robot_addr.send(RecvOrder { order }).await {
+                Ok(admission) => admission,
+                Err(_) => break,
+            },
+            _ => {
+                eprintln!("Invalid message received at new_orders_receiver");
+                continue;
+            }
+        };
+
+        let _ = connection.tx.send(&fmt_msg(admission)).await;
     }
 
     println!("3: new_orders_receiver ended");
 }
+
+/// Starts a listener for the operators of the robot. Every connection can send any number of
+/// `AdminMsg` commands, and every command is answered with the state of the robot.
+async fn admin_receiver(robot_addr: Addr<Robot>, mut listener: Box<dyn Listener>) {
+    while let Ok(connection) = listener.accept().await {
+        task::spawn(admin_session(robot_addr.clone(), connection));
+    }
+}
+
+/// Answers the commands sent through a connection to the admin endpoint until it's closed.
+/// A snapshot is answered once every robot sent its part, or with an error if it doesn't
+/// end in time.
+async fn admin_session(robot_addr: Addr<Robot>, mut connection: Connection) {
+    while let Ok(Some(frame)) = connection.rx.recv().await {
+        let reply = match decode(&frame) {
+            Ok(AdminMsg::Snapshot) => match robot_addr.send(TakeSnapshot).await {
+                Ok(snapshot) => match snapshot.await {
+                    Ok(snapshot) => fmt_msg(snapshot),
+                    Err(_) => fmt_msg("Snapshot incomplete"),
+                },
+                Err(_) => return,
+            },
+            Ok(command) => match robot_addr.send(Admin { command }).await {
+                Ok(state) => fmt_msg(state),
+                Err(_) => return,
+            },
+            Err(_) => fmt_msg("Invalid command"),
+        };
+
+        if connection.tx.send(&reply).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Starts a listener for the gateway robots of other shops. Every connection can send any
+/// number of `FederationMsg`, which are handed to the robot. They are answered through the
+/// connection this shop opens to the other one.
+async fn federation_receiver(robot_addr: Addr<Robot>, mut listener: Box<dyn Listener>) {
+    while let Ok(mut connection) = listener.accept().await {
+        let robot_addr = robot_addr.clone();
+        task::spawn(async move {
+            while let Ok(Some(frame)) = connection.rx.recv().await {
+                match decode::<FederationMsg>(&frame) {
+                    Ok(msg) => robot_addr.do_send(Federated { msg }),
+                    Err(_) => eprintln!("Invalid message received at federation_receiver"),
+                }
+            }
+        });
+    }
+}
+
+/// Waits for a SIGTERM or a Ctrl-C and tells the robot to drain and leave the ring.
+async fn drain_on_signal(robot_addr: Addr<Robot>) {
+    let Ok(mut terminate) = unix_signal::signal(SignalKind::terminate()) else {
+        eprintln!("Couldn't listen for SIGTERM");
+        return;
+    };
+
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = signal::ctrl_c() => {}
+    }
+
+    println!("Draining before leaving the ring");
+    robot_addr.do_send(Drain);
+}
@@ -1,23 +1,56 @@
-use ice_cream_shop::shop_values::N_ROBOTS;
-use robot::robot::Robot;
+use ice_cream_shop::{
+    messages::auth::{Keyring, Role},
+    metrics,
+    shop_values::{METRICS_STARTING_PORT, N_ROBOTS},
+    trace::Tracer,
+};
+use robot::{config::RobotConfig, record::Recorder, robot::Robot, transport::TransportConfig};
 use std::env;
 
 #[actix_rt::main]
 async fn main() -> Result<(), &'static str> {
-    let robot_id: u16 = env::args()
-        .nth(1)
-        .ok_or("args: <id>")?
-        .parse()
-        .map_err(|_| "The provided id needs to be a numeric value")?;
+    let robot_ids = env::args()
+        .skip(1)
+        .map(|id| id.parse::<u16>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| "The provided ids need to be numeric values")?;
 
-    if N_ROBOTS <= robot_id {
+    if robot_ids.is_empty() {
+        return Err("args: <id> [<id>...]");
+    }
+
+    if robot_ids.iter().any(|id| N_ROBOTS <= *id) {
         return Err("The provided id is out of range");
     }
 
-    Robot::spawn(robot_id)
-        .await?
-        .await
-        .map_err(|_| "New connections receiver failed")?;
+    // The robots of the same process share their tracer and their metrics,
+    // which are served on the port of the first one.
+    let names: Vec<_> = robot_ids.iter().map(u16::to_string).collect();
+    if Tracer::init(&format!("robot-{}", names.join("-"))).is_err() {
+        eprintln!("Couldn't start tracing");
+    }
+
+    if metrics::serve(METRICS_STARTING_PORT + robot_ids[0]).is_err() {
+        eprintln!("Couldn't start the metrics endpoint");
+    }
+
+    let keyring = Keyring::from_env(Role::Robot)?;
+    let transport = TransportConfig::from_env()?.build().authenticated(keyring);
+    let mut config = RobotConfig::from_env()?;
+    let mut shutdowns = Vec::new();
+    for robot_id in robot_ids {
+        let transport = transport.clone().recorded(Recorder::from_env(robot_id)?);
+        shutdowns.push(Robot::spawn(robot_id, transport, config.clone()).await?);
+
+        // Only the first robot of the process is the gateway of the shop to the other ones.
+        config.federation = None;
+    }
+
+    for shutdown in shutdowns {
+        shutdown
+            .await
+            .map_err(|_| "The robot stopped unexpectedly")?;
+    }
 
     Ok(())
 }
@@ -1,9 +1,17 @@
+use crate::transport::Connection;
 use actix::prelude::*;
 use ice_cream_shop::{
+    messages::{
+        admin_msg::{AdminMsg, RobotState},
+        federation_msg::FederationMsg,
+        protocol::Codec,
+        screen_msg::Admission,
+    },
     orders::Order,
-    tokens::{FlavourToken, OrderToken, TokenId},
+    snapshot::{GlobalSnapshot, RobotSnapshot, SnapshotId},
+    tokens::{FlavourToken, Membership, OrderToken, TokenId},
 };
-use tokio::net::TcpStream;
+use tokio::sync::oneshot;
 
 /// A message that tells the robot to check if it is alone in the token ring.
 #[derive(Message, Debug)]
@@ -11,17 +19,77 @@ use tokio::net::TcpStream;
 pub struct IsAlone;
 
 /// A message that tells the robot to update its prev_tx.
+/// `joining` is true if the robot that connected is entering the ring.
+/// `codec` is how the messages of the connection are encoded, as agreed on in the handshake.
 #[derive(Message, Debug)]
 #[rtype(result = "()")]
 pub struct Connect {
-    pub stream: TcpStream,
+    pub id: u16,
+    pub joining: bool,
+    pub codec: Codec,
+    pub connection: Connection,
 }
 
+/// A message that tells a new robot to look for a robot of the ring to connect to.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct JoinRing;
+
 /// A message that tells the robot to find a new robot to connect to (next_tx).
 #[derive(Message, Debug)]
 #[rtype(result = "()")]
 pub struct FindNext;
 
+/// A message that tells the robot that the next robot sent a Disconnect.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct NextDisconnected;
+
+/// A message that tells the robot that the stream from the previous robot was closed.
+/// `graceful` is false if the previous robot closed it without sending a Disconnect.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct PrevClosed {
+    pub prev_id: Option<u16>,
+    pub graceful: bool,
+}
+
+// Membership
+
+/// A message that tells the robot to merge the view of the ring of another robot into its own.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct MergeMembers {
+    pub members: Membership,
+}
+
+/// A message that tells the robot that the next robot is leaving the ring.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct MemberLeft {
+    pub id: u16,
+}
+
+/// A message that tells the robot that a robot after it sent a heartbeat.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct Heartbeat {
+    pub id: u16,
+}
+
+// Shutdown
+
+/// A message that tells the robot to stop accepting orders, hand back the one it's
+/// serving and leave the ring once it doesn't hold anything that others need.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct Drain;
+
+/// A message that tells a draining robot to leave the ring if it's idle.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct Leave;
+
 // OrderToken
 
 /// A message that tells the robot to receive an OrderToken.
@@ -58,10 +126,12 @@ pub struct ReleaseFlavourToken {
 // TokenBox
 
 /// A message that tells the robot that the next robot stopped using a token.
+/// `round` is the round of the copy that the next robot received.
 #[derive(Message, Debug)]
 #[rtype(result = "()")]
 pub struct EndOfUse {
     pub token_id: TokenId,
+    pub round: u64,
 }
 
 /// A message that tells the robot to check if it has any tokens to release.
@@ -70,8 +140,60 @@ pub struct EndOfUse {
 pub struct CheckTokenBox;
 
 /// A message that tells the robot to receive a new order.
+/// It's answered with whether the robot took it.
 #[derive(Message, Debug)]
-#[rtype(result = "()")]
+#[rtype(result = "Admission")]
 pub struct RecvOrder {
     pub order: Order,
 }
+
+/// A message that tells the robot to keep a copy of an order received by the previous robot.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct BackupOrder {
+    pub origin: u16,
+    pub order: Order,
+}
+
+// Admin
+
+/// A message that tells the robot to run a command received on its admin endpoint
+/// and answer with its state.
+#[derive(Message, Debug)]
+#[rtype(result = "RobotState")]
+pub struct Admin {
+    pub command: AdminMsg,
+}
+
+// Snapshot
+
+/// A message that tells the robot that the marker of a snapshot arrived.
+/// `from_next` is true if it came from the next robot.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct Marker {
+    pub snapshot: SnapshotId,
+    pub from_next: bool,
+}
+
+/// A message that tells the robot that the part of a robot of a snapshot arrived,
+/// on its way to the robot that started the snapshot.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct SnapshotPart {
+    pub part: RobotSnapshot,
+}
+
+/// A message that tells the robot to start a snapshot of the ring.
+/// The receiver resolves with the snapshot, or is dropped if it doesn't end in time.
+#[derive(Message, Debug)]
+#[rtype(result = "oneshot::Receiver<GlobalSnapshot>")]
+pub struct TakeSnapshot;
+
+/// A message that hands the robot a message from the gateway robot of another shop,
+/// to be answered the next time the token of its flavour passes by.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct Federated {
+    pub msg: FederationMsg,
+}
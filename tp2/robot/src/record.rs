@@ -0,0 +1,198 @@
+use ice_cream_shop::{messages::robot_msg::RobotMsg, trace::trace_context::now_us};
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    fmt::{self, Debug},
+    fs::{self, File},
+    io::{self, BufRead, BufReader, LineWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// Environment variable with the directory where every robot writes the messages it sends
+/// and receives through the ring. Nothing is recorded when it isn't set.
+pub const RECORD_VAR: &str = "SHOP_RECORD";
+
+/// Whether a message reached the robot or left it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    In,
+    Out,
+}
+
+/// The connection a message went through.
+/// - `Prev`: The one with the previous robot, which the previous robot opened.
+/// - `Next`: The one with the next robot, which this robot opened.
+/// - `Orders`: The ones the screens opened to send orders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Prev,
+    Next,
+    Orders,
+}
+
+/// A message a robot sent or received, as written in its trace.
+/// - `at_us`: When it went through, in microseconds since the unix epoch.
+/// - `direction`: Whether it reached the robot or left it.
+/// - `channel`: The connection it went through.
+/// - `peer`: The robot at the other end of the connection, if it's known.
+/// - `msg`: The message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recorded {
+    pub at_us: u64,
+    pub direction: Direction,
+    pub channel: Channel,
+    pub peer: Option<u16>,
+    pub msg: RobotMsg,
+}
+
+/// Writes the messages of a robot to its trace, one JSON line each.
+/// Clones write to the same trace.
+#[derive(Clone)]
+pub struct Recorder {
+    path: PathBuf,
+    file: Arc<Mutex<LineWriter<File>>>,
+}
+
+impl Recorder {
+    /// Creates the trace, or empties it if it already exists.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Where to write the trace.
+    pub fn create(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let file = File::create(&path)?;
+
+        Ok(Self {
+            path,
+            file: Arc::new(Mutex::new(LineWriter::new(file))),
+        })
+    }
+
+    /// Creates the trace of a robot in the directory set in `SHOP_RECORD`, named
+    /// `robot-<id>.trace.jsonl`.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the robot.
+    ///
+    /// # Returns
+    ///
+    /// The recorder, `None` if the variable isn't set, or an error if the trace can't be created.
+    pub fn from_env(id: u16) -> Result<Option<Self>, &'static str> {
+        let Ok(dir) = env::var(RECORD_VAR) else {
+            return Ok(None);
+        };
+
+        let err = "Couldn't create the trace in the directory set in SHOP_RECORD";
+        fs::create_dir_all(&dir).map_err(|_| err)?;
+        let path = Path::new(&dir).join(format!("robot-{id}.trace.jsonl"));
+        Self::create(path).map(Some).map_err(|_| err)
+    }
+
+    /// Writes a message to the trace. The line is flushed right away, so that the trace is
+    /// complete up to the last message even if the robot crashes.
+    ///
+    /// # Arguments
+    ///
+    /// * `direction` - Whether the message reached the robot or left it.
+    /// * `channel` - The connection it went through.
+    /// * `peer` - The robot at the other end of the connection, if it's known.
+    /// * `msg` - The message.
+    pub fn record(&self, direction: Direction, channel: Channel, peer: Option<u16>, msg: RobotMsg) {
+        let recorded = Recorded {
+            at_us: now_us(),
+            direction,
+            channel,
+            peer,
+            msg,
+        };
+
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let written = serde_json::to_writer(&mut *file, &recorded)
+            .map_err(io::Error::from)
+            .and_then(|_| file.write_all(b"\n"));
+
+        if let Err(e) = written {
+            eprintln!("Couldn't write to the trace: {e}");
+        }
+    }
+}
+
+impl Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorder")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+/// Reads a trace written by a `Recorder`.
+///
+/// # Arguments
+///
+/// * `path` - Where the trace is.
+///
+/// # Returns
+///
+/// The messages in the order they were written, or an error if the trace can't be read or
+/// has a line that isn't a message.
+pub fn read_trace(path: impl AsRef<Path>) -> io::Result<Vec<Recorded>> {
+    let file = File::open(path)?;
+    let mut trace = Vec::new();
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        trace.push(serde_json::from_str(&line)?);
+    }
+
+    Ok(trace)
+}
+
+/// Describes a message in a single line, with only what tells it apart from the other
+/// messages of the same kind.
+///
+/// # Arguments
+///
+/// * `msg` - The message.
+pub fn describe(msg: &RobotMsg) -> String {
+    match msg {
+        RobotMsg::RecvOrderToken(token) => format!(
+            "RecvOrderToken(round {}, {} queued)",
+            token.round(),
+            token.queue_len()
+        ),
+        RobotMsg::RecvFlavourToken(token) => format!(
+            "RecvFlavourToken({}, round {}, {} servings)",
+            token.flavour().name(),
+            token.round(),
+            token.servings()
+        ),
+        RobotMsg::BackupOrder(origin, order) => format!(
+            "BackupOrder({origin}, screen {} #{})",
+            order.id().screen_id(),
+            order.id().order_number()
+        ),
+        RobotMsg::SnapshotPart(part) => format!("SnapshotPart({})", part.id),
+        RobotMsg::Members(members) => {
+            let alive: Vec<_> = members.alive().map(|id| id.to_string()).collect();
+            format!("Members({})", alive.join(", "))
+        }
+        RobotMsg::RecvOrder(order) => format!(
+            "RecvOrder(screen {} #{})",
+            order.id().screen_id(),
+            order.id().order_number()
+        ),
+        msg => format!("{msg:?}"),
+    }
+}
@@ -0,0 +1,515 @@
+use crate::{
+    config::RobotConfig,
+    message::Admin,
+    record::{describe, Channel, Direction, Recorded},
+    robot::Robot,
+    transport::{
+        handshake, ChannelTransport, Endpoint, FrameReceiver, Listener, MsgSender, SharedTransport,
+    },
+};
+use actix::System;
+use ice_cream_shop::messages::{
+    admin_msg::{AdminMsg, RobotState},
+    fmt_msg,
+    protocol::{decode, Encoding},
+    robot_msg::RobotMsg,
+};
+use std::{
+    cell::Cell,
+    collections::BTreeSet,
+    fmt, io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    runtime,
+    sync::mpsc::{self, UnboundedSender},
+    task,
+    time::{self, Instant},
+};
+
+/// How far the clock of the replay is moved forward at a time.
+const STEP: Duration = Duration::from_millis(1);
+
+/// How many times the other tasks are let run after a message is fed to the robot, so that
+/// it reaches the robot before its state is taken.
+const SETTLE: usize = 8;
+
+/// The messages the robot sent during a replay.
+type Outbound = Arc<Mutex<Vec<Recorded>>>;
+
+/// A message of the trace that was fed to the robot.
+/// - `recorded`: The message, as it was recorded.
+/// - `state`: The state of the robot right after it, or `None` if it couldn't be fed because
+///   the robot wasn't connected to the robot that sent it.
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub recorded: Recorded,
+    pub state: Option<RobotState>,
+}
+
+/// What a robot did when the messages it received in a trace were fed to it again.
+/// - `id`: The ID of the robot.
+/// - `start_us`: When the trace starts, in microseconds since the unix epoch.
+/// - `steps`: The messages fed to the robot, in order.
+/// - `outbound`: The messages the robot sent to its neighbours, timed as if it were running
+///   when the trace was recorded.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub id: u16,
+    pub start_us: u64,
+    pub steps: Vec<Step>,
+    pub outbound: Vec<Recorded>,
+}
+
+/// A message the robot sent during the replay that isn't the one it sent in the trace.
+/// - `channel`: The connection the message went through.
+/// - `index`: Its position among the messages the robot sent through that connection.
+/// - `expected`: The message in the trace, if the robot sent one there.
+/// - `replayed`: The message in the replay, if the robot sent one there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub channel: Channel,
+    pub index: usize,
+    pub expected: Option<String>,
+    pub replayed: Option<String>,
+}
+
+/// Turns the instants of the replay into the times of the trace.
+#[derive(Debug, Clone, Copy)]
+struct Clock {
+    start: Instant,
+    start_us: u64,
+}
+
+impl Clock {
+    /// Returns the instant of the replay at which something recorded at `at_us` happens.
+    fn instant(&self, at_us: u64) -> Instant {
+        self.start + Duration::from_micros(at_us.saturating_sub(self.start_us))
+    }
+
+    /// Returns the time of the trace the replay is at.
+    fn now_us(&self) -> u64 {
+        self.start_us + self.start.elapsed().as_micros() as u64
+    }
+}
+
+impl Replay {
+    /// Feeds the messages a robot received in a trace to a new robot with the same ID, at
+    /// the same times they were received. The robot runs alone over an in-memory network on a
+    /// paused clock that starts at the time the trace starts, so the same trace always drives
+    /// it through the same states.
+    /// The replay plays the neighbours of the robot: it connects to it as the previous robots
+    /// of the trace did, and accepts its connections as the next robots of the trace did.
+    /// The messages the robot sent to itself, when it was alone in the ring, aren't fed,
+    /// since the robot sends them again.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the robot that recorded the trace.
+    /// * `trace` - The messages the robot sent and received.
+    /// * `config` - How the robot behaves, which should be how it behaved when it recorded it.
+    ///
+    /// # Returns
+    ///
+    /// What the robot did, or an error if it couldn't be started.
+    pub fn run(id: u16, trace: &[Recorded], config: RobotConfig) -> io::Result<Self> {
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()?;
+
+        // Actix takes the runtime from a factory it calls only once.
+        let runtime = Cell::new(Some(runtime));
+        let factory = || {
+            runtime
+                .take()
+                .expect("The replay runtime was already taken")
+        };
+        System::with_tokio_rt(factory).block_on(play(id, trace, config))
+    }
+
+    /// Compares the messages the robot sent to its neighbours during the replay with the
+    /// ones it sent in the trace, connection by connection and in order.
+    /// Heartbeats aren't compared, since they depend on when the robot was started.
+    ///
+    /// # Arguments
+    ///
+    /// * `trace` - The trace that was replayed.
+    ///
+    /// # Returns
+    ///
+    /// Every position where the messages differ.
+    pub fn differences(&self, trace: &[Recorded]) -> Vec<Difference> {
+        let mut differences = Vec::new();
+
+        for channel in [Channel::Prev, Channel::Next] {
+            let expected = self.compared(trace, channel);
+            let replayed = self.compared(&self.outbound, channel);
+
+            for index in 0..expected.len().max(replayed.len()) {
+                let expected = expected.get(index).cloned();
+                let replayed = replayed.get(index).cloned();
+                if expected != replayed {
+                    differences.push(Difference {
+                        channel,
+                        index,
+                        expected,
+                        replayed,
+                    });
+                }
+            }
+        }
+
+        differences
+    }
+
+    /// Returns the messages the robot sent to its neighbours through a connection, described.
+    fn compared(&self, messages: &[Recorded], channel: Channel) -> Vec<String> {
+        messages
+            .iter()
+            .filter(|recorded| recorded.direction == Direction::Out)
+            .filter(|recorded| recorded.channel == channel && recorded.peer != Some(self.id))
+            .filter(|recorded| !matches!(recorded.msg, RobotMsg::Heartbeat(_)))
+            .map(|recorded| describe(&recorded.msg))
+            .collect()
+    }
+}
+
+/// Starts the robot and feeds it the messages of the trace.
+async fn play(id: u16, trace: &[Recorded], mut config: RobotConfig) -> io::Result<Replay> {
+    let transport = SharedTransport::new(ChannelTransport::default());
+    let outbound = Outbound::default();
+    let clock = Clock {
+        start: Instant::now(),
+        start_us: trace.first().map_or(0, |recorded| recorded.at_us),
+    };
+
+    let next_peers: BTreeSet<_> = trace
+        .iter()
+        .filter(|recorded| recorded.channel == Channel::Next)
+        .filter_map(|recorded| recorded.peer)
+        .filter(|peer| *peer != id)
+        .collect();
+
+    let (connections_tx, mut connections) = mpsc::unbounded_channel();
+    for peer in next_peers {
+        let listener = transport.listen(Endpoint::Robot(peer)).await?;
+        let connections_tx = connections_tx.clone();
+        task::spawn(play_next(
+            peer,
+            listener,
+            clock,
+            outbound.clone(),
+            connections_tx,
+        ));
+    }
+
+    config.start_us = Some(clock.now_us());
+    let (addr, _shutdown) = Robot::start(id, transport.clone(), config)
+        .await
+        .map_err(io::Error::other)?;
+
+    let mut prev: Option<MsgSender> = None;
+    let mut next: Option<(u16, MsgSender)> = None;
+    let mut steps = Vec::new();
+
+    let inbound = trace
+        .iter()
+        .filter(|recorded| recorded.direction == Direction::In && recorded.peer != Some(id));
+
+    for recorded in inbound {
+        run_until(clock.instant(recorded.at_us)).await;
+        while let Ok(connection) = connections.try_recv() {
+            next = Some(connection);
+        }
+
+        let msg = recorded.msg.clone();
+        let fed = match recorded.channel {
+            Channel::Prev => {
+                if let RobotMsg::Join(peer) | RobotMsg::Link(peer) = msg {
+                    prev = play_prev(&transport, id, peer, clock, outbound.clone())
+                        .await
+                        .ok();
+                }
+
+                match &mut prev {
+                    Some(tx) => tx.send(msg).await.is_ok(),
+                    None => false,
+                }
+            }
+
+            Channel::Next => match &mut next {
+                Some((peer, tx)) if recorded.peer == Some(*peer) => tx.send(msg).await.is_ok(),
+                _ => false,
+            },
+
+            Channel::Orders => send_order(&transport, id, msg).await.is_ok(),
+        };
+
+        for _ in 0..SETTLE {
+            task::yield_now().await;
+        }
+
+        let state = match fed {
+            true => addr
+                .send(Admin {
+                    command: AdminMsg::State,
+                })
+                .await
+                .ok(),
+            false => None,
+        };
+
+        steps.push(Step {
+            recorded: recorded.clone(),
+            state,
+        });
+    }
+
+    if let Some(last) = trace.last() {
+        run_until(clock.instant(last.at_us) + STEP).await;
+    }
+
+    let outbound = outbound
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone();
+
+    Ok(Replay {
+        id,
+        start_us: clock.start_us,
+        steps,
+        outbound,
+    })
+}
+
+/// Lets the robot run until the given instant. The clock is moved forward a little at a time
+/// instead of letting it jump when the robot is idle, since a robot that is alone passes the
+/// tokens to itself without ever being idle.
+async fn run_until(instant: Instant) {
+    while Instant::now() < instant {
+        time::advance(STEP.min(instant - Instant::now())).await;
+    }
+}
+
+/// Connects to the robot as its previous robot, and keeps the messages the robot sends back.
+///
+/// # Returns
+///
+/// The sending half of the connection, or an error if the robot didn't answer the hello.
+async fn play_prev(
+    transport: &SharedTransport,
+    id: u16,
+    peer: u16,
+    clock: Clock,
+    outbound: Outbound,
+) -> io::Result<MsgSender> {
+    let mut connection = transport.connect(Endpoint::Robot(id)).await?;
+    let codec = handshake::hello(&mut connection, Encoding::Json).await?;
+    task::spawn(collect(connection.rx, Channel::Prev, peer, clock, outbound));
+    Ok(MsgSender::new(connection.tx, codec))
+}
+
+/// Accepts the connections the robot opens to one of the next robots of the trace, keeps the
+/// messages the robot sends through them, and hands over their sending halves.
+async fn play_next(
+    peer: u16,
+    mut listener: Box<dyn Listener>,
+    clock: Clock,
+    outbound: Outbound,
+    connections: UnboundedSender<(u16, MsgSender)>,
+) {
+    while let Ok(mut connection) = listener.accept().await {
+        let Ok((codec, introduction)) = handshake::welcome(&mut connection).await else {
+            continue;
+        };
+
+        if let Ok(msg) = decode::<RobotMsg>(&introduction) {
+            keep(&outbound, clock, Channel::Next, peer, msg);
+        }
+
+        task::spawn(collect(
+            connection.rx,
+            Channel::Next,
+            peer,
+            clock,
+            outbound.clone(),
+        ));
+        if connections
+            .send((peer, MsgSender::new(connection.tx, codec)))
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Sends an order to the robot as a screen would, and waits for it to be answered.
+async fn send_order(transport: &SharedTransport, id: u16, msg: RobotMsg) -> io::Result<()> {
+    let mut connection = transport.connect(Endpoint::RobotOrders(id)).await?;
+    connection.tx.send(&fmt_msg(msg)).await?;
+    task::spawn(async move { connection.rx.recv().await });
+    Ok(())
+}
+
+/// Keeps every message the robot sends through a connection until it's closed.
+async fn collect(
+    mut rx: Box<dyn FrameReceiver>,
+    channel: Channel,
+    peer: u16,
+    clock: Clock,
+    outbound: Outbound,
+) {
+    while let Ok(Some(frame)) = rx.recv().await {
+        if let Ok(msg) = decode::<RobotMsg>(&frame) {
+            keep(&outbound, clock, channel, peer, msg);
+        }
+    }
+}
+
+/// Keeps a message the robot sent.
+fn keep(outbound: &Outbound, clock: Clock, channel: Channel, peer: u16, msg: RobotMsg) {
+    outbound
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .push(Recorded {
+            at_us: clock.now_us(),
+            direction: Direction::Out,
+            channel,
+            peer: Some(peer),
+            msg,
+        });
+}
+
+impl Step {
+    /// Writes the step as a line, timed from the start of the trace.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_us` - When the trace starts.
+    pub fn line(&self, start_us: u64) -> String {
+        let at = self.recorded.at_us.saturating_sub(start_us) as f64 / 1_000_000.0;
+        let from = match self.recorded.peer {
+            Some(peer) => format!("{:?} {peer}", self.recorded.channel),
+            None => format!("{:?}", self.recorded.channel),
+        };
+
+        let state = match &self.state {
+            Some(state) => {
+                let id = |id: Option<u16>| id.map_or("?".to_string(), |id| id.to_string());
+                let order = match &state.current_order {
+                    Some(order) => format!(
+                        "screen {} #{}",
+                        order.id().screen_id(),
+                        order.id().order_number()
+                    ),
+                    None => "idle".to_string(),
+                };
+
+                format!(
+                    "{}, prev {}, next {}, {order}, {} waiting to upload",
+                    state.status,
+                    id(state.prev_id),
+                    id(state.next_id),
+                    state.new_orders.len()
+                )
+            }
+            None => "not fed, the robot wasn't connected to its sender".to_string(),
+        };
+
+        format!(
+            "{at:>9.3}s {from:<8} {} -> {state}",
+            describe(&self.recorded.msg)
+        )
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shown = |msg: &Option<String>| msg.clone().unwrap_or_else(|| "nothing".to_string());
+        write!(
+            f,
+            "{:?} #{}: expected {}, replayed {}",
+            self.channel,
+            self.index,
+            shown(&self.expected),
+            shown(&self.replayed)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ice_cream_shop::tokens::{Membership, OrderToken};
+
+    fn received(at_ms: u64, channel: Channel, peer: u16, msg: RobotMsg) -> Recorded {
+        Recorded {
+            at_us: 1_000_000 + at_ms * 1000,
+            direction: Direction::In,
+            channel,
+            peer: Some(peer),
+            msg,
+        }
+    }
+
+    /// The trace of robot 0 joining robot 1 and getting the order token from it.
+    fn trace() -> Vec<Recorded> {
+        let mut members = Membership::new(1);
+        members.join(0);
+
+        let mut token = OrderToken::new(1);
+        token.set_members(members.clone());
+        token.mark(1);
+
+        let mut sent = received(0, Channel::Next, 1, RobotMsg::Join(0));
+        sent.direction = Direction::Out;
+
+        vec![
+            sent,
+            received(1, Channel::Next, 1, RobotMsg::Members(members)),
+            received(2, Channel::Prev, 1, RobotMsg::Link(1)),
+            received(3, Channel::Prev, 1, RobotMsg::RecvOrderToken(token)),
+        ]
+    }
+
+    #[test]
+    fn test01_the_robot_is_driven_by_the_neighbours_of_the_trace() {
+        let replay = Replay::run(0, &trace(), RobotConfig::default()).unwrap();
+
+        assert_eq!(replay.steps.len(), 3);
+        let state = replay.steps[2].state.clone().unwrap();
+        assert_eq!(state.prev_id, Some(1));
+        assert_eq!(state.next_id, Some(1));
+        assert!(state.order_round.is_some());
+
+        let sent: Vec<_> = replay
+            .outbound
+            .iter()
+            .filter(|recorded| !matches!(recorded.msg, RobotMsg::Heartbeat(_)))
+            .map(|recorded| (recorded.channel, describe(&recorded.msg)))
+            .collect();
+        assert_eq!(sent[0], (Channel::Next, "Join(0)".to_string()));
+    }
+
+    #[test]
+    fn test02_replaying_a_trace_again_sends_the_same_messages() {
+        let trace = trace();
+        let first = Replay::run(0, &trace, RobotConfig::default()).unwrap();
+
+        let mut recorded = trace.clone();
+        recorded.extend(first.outbound.iter().skip(1).cloned());
+        recorded.sort_by_key(|recorded| recorded.at_us);
+
+        let second = Replay::run(0, &recorded, RobotConfig::default()).unwrap();
+        assert_eq!(second.differences(&recorded), Vec::new());
+
+        let mut tampered = recorded.clone();
+        tampered.push(Recorded {
+            direction: Direction::Out,
+            ..trace[0].clone()
+        });
+        assert_eq!(second.differences(&tampered).len(), 1);
+    }
+}
@@ -1,49 +1,169 @@
 use crate::{
-    message::*, new_connections_receiver, new_orders_receiver, next_robot_receiver,
-    prev_robot_receiver, token_box::TokenBox,
+    admin_receiver,
+    config::RobotConfig,
+    detector::{DetectorConfig, FailureDetector, HEARTBEAT_INTERVAL},
+    drain_on_signal,
+    federation::Federation,
+    federation_receiver,
+    invariants::InvariantChecker,
+    message::*,
+    new_connections_receiver, new_orders_receiver, next_robot_receiver, prev_robot_receiver,
+    service::ServiceClock,
+    snapshot::{Snapshots, SNAPSHOT_TIMEOUT},
+    token_box::TokenBox,
+    transport::{handshake, Endpoint, FrameReceiver, MsgSender, SharedTransport},
+    wall_clock::WallClock,
 };
 use actix::prelude::*;
 use ice_cream_shop::{
     flavour::Flavour,
-    id_to_addr,
-    messages::{fmt_msg, robot_msg::RobotMsg, screen_msg::ScreenMsg},
-    orders::Order,
-    shop_values::{
-        N_ROBOTS, N_SCREEN, ROBOT_SCREEN_STARTING_PORT, ROBOT_STARTING_PORT, SCREEN_STARTING_PORT,
-        STARTING_ICECREAM,
+    messages::{
+        admin_msg::{AdminMsg, EndedOrder, OrderOutcome, RobotState, SeenToken},
+        protocol::{Codec, Encoding},
+        robot_msg::RobotMsg,
+        screen_msg::{Admission, ScreenMsg},
     },
-    tokens::{FlavourToken, OrderToken, TokenId},
+    metrics::{registry, shop_metrics::*},
+    orders::{Order, OrderId},
+    shop_values::{N_ROBOTS, N_SCREEN, STARTING_ICECREAM},
+    snapshot::{RobotSnapshot, SnapshotId},
+    stock::{StockLevel, StockSummary},
+    tokens::{FlavourToken, Membership, OrderToken, TokenId},
+};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    future::Future,
+    mem,
+    time::Instant,
 };
-use std::future::Future;
 use tokio::{
-    io::{self, AsyncWriteExt, WriteHalf},
-    net::{TcpListener, TcpStream},
-    task::{self, JoinHandle},
+    sync::oneshot,
+    task::{self, AbortHandle},
     time::{self, Duration},
 };
 
+/// The sending half of the connection to a neighbour in the ring, if connected.
+type Link = Option<MsgSender>;
+
+/// How long a leaving robot waits for its neighbours to reconnect around it before exiting anyway.
+const LEAVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often a robot tells the screens the stock it saw, if it changed since the last time.
+const STOCK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many of the last orders it ended a robot remembers for its operators.
+const RECENT_ORDERS: usize = 10;
+
+/// The stage of the life of a robot in the ring.
+/// - `Joining`: The robot connected to the ring and waits for the next robot to admit it.
+///   It only forwards tokens, since it doesn't know its incarnation yet.
+/// - `Serving`: The robot takes and serves orders.
+/// - `Draining`: The robot doesn't take orders anymore and waits for the order token
+///   to hand back the one it's serving.
+/// - `Leaving`: The robot asked its neighbours to reconnect around it and only forwards tokens.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Joining,
+    #[default]
+    Serving,
+    Draining,
+    Leaving,
+}
+
 /// The `Robot` struct represents a robot that is part of a token ring network of robots.
 /// It contains the following fields:
 /// - `id`: The ID of the robot.
 /// - `prev_id`: The ID of the previous robot in the ring.
 /// - `next_id`: The ID of the next robot in the ring.
-/// - `prev_tx`: The write half of the TCP stream to the previous robot.
-/// - `next_tx`: The write half of the TCP stream to the next robot.
+/// - `members`: The robots that are part of the ring, as far as this robot knows.
+/// - `admitted`: The incarnation the robot was admitted to the ring with.
+/// - `transport`: The network used to reach the other robots and the screens.
+/// - `detector`: How the robot decides that the next robot is dead.
+/// - `service`: How long the robot takes to scoop.
+/// - `encoding`: The encoding the robot prefers for the connections it opens to other robots.
+/// - `invariants`: The checker of the invariants of the tokens, if it's turned on.
+/// - `next_heartbeats`: The heartbeats of the next robot, if connected.
+/// - `next_receiver`: The task that receives messages from the next robot.
+/// - `prev_tx`: The sending half of the connection to the previous robot.
+/// - `next_tx`: The sending half of the connection to the next robot.
 /// - `new_orders`: A list of new orders received by the robot.
+/// - `max_queue`: How many orders may wait in the order token before the robot refuses new
+///   ones, as carried by the token, or as configured until the token passes by.
+/// - `queued`: How many orders were waiting in the order token the last time it passed by.
+/// - `backlog`: How long the orders of the order token were expected to take the last time
+///   it passed by.
+/// - `stock`: The stock of every flavour the last time its token passed by.
+/// - `stock_changed`: Whether the stock changed since it was last sent to the screens.
+/// - `backups`: The orders received by the previous robots that weren't uploaded yet, by robot.
 /// - `current_order`: The current order being served by the robot.
-/// - `serving_flavour`: A flag indicating if the robot is currently serving an ice cream flavour.
+/// - `serving_flavour`: The flavour the robot is scooping, if any.
+/// - `reserved`: The flavours whose tokens still carry a reservation of this robot.
+/// - `expired_orders`: The orders that expired while the robot served them, whose
+///   reservations go back to their tokens when they pass by.
 /// - `token_box`: A token box containing the order and flavour tokens that the next robot has not finished using.
+/// - `rounds`: The round of the last copy of every token that the robot received.
+/// - `last_order_token`: When the order token was last received, to measure its rotation.
+/// - `seeds`: The robots a new robot introduces itself to, in order.
+/// - `status`: Whether the robot is serving, draining or leaving the ring.
+/// - `paused`: Whether the robot was told to stop taking orders from the order token.
+/// - `last_token`: The last token that the robot received.
+/// - `recent_orders`: The last orders the robot ended, the newest last.
+/// - `clock`: The wall clock the robot checks deadlines and times the orders it ended with.
+/// - `snapshots`: The snapshots of the ring the robot is recording or putting together.
+/// - `orders_listener`: The task that receives orders from the screens.
+/// - `connections_listener`: The task that receives connections from other robots.
+/// - `admin_listener`: The task that receives commands from the operators, if it could listen.
+/// - `federation`: What the robot knows about the transfers with other shops, if it's the
+///   gateway of its shop.
+/// - `federation_listener`: The task that receives messages from other shops, if it's the gateway.
+/// - `shutdown`: The channel used to tell the process that the robot left the ring.
+/// - `next_left`: Whether the next robot disconnected from a leaving robot.
+/// - `prev_closed`: Whether the previous robot closed its stream to a leaving robot.
 #[derive(Debug, Default)]
 pub struct Robot {
     id: u16,
     prev_id: Option<u16>,
     next_id: Option<u16>,
-    prev_tx: Option<WriteHalf<TcpStream>>,
-    next_tx: Option<WriteHalf<TcpStream>>,
+    members: Membership,
+    admitted: u64,
+    transport: SharedTransport,
+    detector: DetectorConfig,
+    service: ServiceClock,
+    encoding: Encoding,
+    invariants: Option<InvariantChecker>,
+    next_heartbeats: Option<FailureDetector>,
+    next_receiver: Option<AbortHandle>,
+    prev_tx: Link,
+    next_tx: Link,
     new_orders: Vec<Order>,
+    max_queue: Option<usize>,
+    queued: usize,
+    backlog: Duration,
+    stock: StockSummary,
+    stock_changed: bool,
+    backups: HashMap<u16, Vec<Order>>,
     current_order: Option<Order>,
-    serving_flavour: bool,
+    serving_flavour: Option<Flavour>,
+    reserved: HashSet<Flavour>,
+    expired_orders: HashSet<OrderId>,
     token_box: TokenBox,
+    rounds: HashMap<TokenId, u64>,
+    last_order_token: Option<Instant>,
+    seeds: Vec<u16>,
+    status: Status,
+    paused: bool,
+    last_token: Option<SeenToken>,
+    recent_orders: VecDeque<EndedOrder>,
+    clock: WallClock,
+    snapshots: Snapshots,
+    orders_listener: Option<AbortHandle>,
+    connections_listener: Option<AbortHandle>,
+    admin_listener: Option<AbortHandle>,
+    federation: Option<Federation>,
+    federation_listener: Option<AbortHandle>,
+    shutdown: Option<oneshot::Sender<()>>,
+    next_left: bool,
+    prev_closed: bool,
 }
 
 /// The `Robot` struct implements the `Actor` trait.
@@ -54,42 +174,133 @@ impl Actor for Robot {
 
 impl Robot {
     /// Initializes a new Robot with the given id.
-    fn new(id: u16) -> Self {
+    fn new(id: u16, transport: SharedTransport, config: RobotConfig) -> Self {
         Self {
             id,
+            members: Membership::new(id),
+            transport,
+            detector: config.detector,
+            service: config.service.clock(id),
+            encoding: config.encoding,
+            invariants: config.invariants,
+            max_queue: config.max_queue,
+            seeds: config.seeds.unwrap_or_else(|| {
+                (1..N_ROBOTS)
+                    .map(|offset| (id + offset) % N_ROBOTS)
+                    .collect()
+            }),
+            clock: config
+                .start_us
+                .map_or_else(WallClock::default, WallClock::starting_at),
             ..Default::default()
         }
     }
 
-    /// Spawns a new robot with the given ID.
+    /// Spawns a new robot with the given ID over the given transport.
+    /// Besides the tasks started by `start`, it spawns a task that drains the robot
+    /// when the process is asked to terminate.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the robot.
+    /// * `transport` - The network used to reach the other robots and the screens.
+    /// * `config` - How the robot detects failures and how long it takes to scoop.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a receiver that resolves when the robot leaves the ring.
+    pub async fn spawn(
+        id: u16,
+        transport: SharedTransport,
+        config: RobotConfig,
+    ) -> Result<oneshot::Receiver<()>, &'static str> {
+        let (addr, shutdown) = Self::start(id, transport, config).await?;
+        task::spawn(drain_on_signal(addr));
+        Ok(shutdown)
+    }
+
+    /// Starts a new robot with the given ID over the given transport.
     /// It spawns two tasks that will run in parallel:
     /// - A task that listens for new connections.
     /// - A task that listens for new orders.
-    /// It also sends a `FindNext` message to the robot.
+    ///
+    /// Every `HEARTBEAT_INTERVAL` the robot tells the previous one that it's alive and checks
+    /// whether the next one is.
+    /// It also sends a `JoinRing` message to the robot.
     /// If the robot is alone, it sends the necessary messages to intialize the token ring with the appropriate tokens.
     ///
     /// # Arguments
     ///
     /// * `id` - The ID of the robot.
+    /// * `transport` - The network used to reach the other robots and the screens.
+    /// * `config` - How the robot detects failures and how long it takes to scoop.
     ///
     /// # Returns
     ///
-    /// A `Result` containing a `JoinHandle` for the spawned tasks that will run in parallel.
-    pub async fn spawn(id: u16) -> Result<JoinHandle<()>, &'static str> {
-        let ip = id_to_addr(ROBOT_STARTING_PORT, id);
+    /// A `Result` containing the address of the robot and a receiver that resolves when it leaves the ring.
+    pub async fn start(
+        id: u16,
+        transport: SharedTransport,
+        config: RobotConfig,
+    ) -> Result<(Addr<Self>, oneshot::Receiver<()>), &'static str> {
         let err = "Coudn't connect to my reserved robot ip address";
-        let new_con_listener = TcpListener::bind(ip).await.map_err(|_| err)?;
+        let new_con_listener = transport
+            .listen(Endpoint::Robot(id))
+            .await
+            .map_err(|_| err)?;
 
-        let ip = id_to_addr(ROBOT_SCREEN_STARTING_PORT, id);
         let err = "Couldn't connect to my reserved robot_screen ip address";
-        let new_orders_listener = TcpListener::bind(ip).await.map_err(|_| err)?;
+        let new_orders_listener = transport
+            .listen(Endpoint::RobotOrders(id))
+            .await
+            .map_err(|_| err)?;
+
+        let admin_listener = transport.listen(Endpoint::RobotAdmin(id)).await;
+        if admin_listener.is_err() {
+            eprintln!("Couldn't start the admin endpoint");
+        }
 
-        let addr = Self::new(id).start();
-        task::spawn(new_orders_receiver(addr.clone(), new_orders_listener));
-        let new_con_recv = task::spawn(new_connections_receiver(addr.clone(), new_con_listener));
+        let mut federation_listener = None;
+        if let Some(federation) = &config.federation {
+            match transport
+                .listen(Endpoint::Federation(federation.shop))
+                .await
+            {
+                Ok(listener) => federation_listener = Some(listener),
+                Err(_) => eprintln!("Couldn't start the federation endpoint"),
+            }
+        }
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let addr = Self::create(|ctx| {
+            let addr = ctx.address();
+            let admin = admin_listener
+                .ok()
+                .map(|listener| task::spawn(admin_receiver(addr.clone(), listener)));
+            let federation = federation_listener
+                .map(|listener| task::spawn(federation_receiver(addr.clone(), listener)));
+            let orders = task::spawn(new_orders_receiver(addr.clone(), new_orders_listener));
+            let connections = task::spawn(new_connections_receiver(addr, new_con_listener));
+            ctx.run_interval(HEARTBEAT_INTERVAL, |robot, ctx| robot.heartbeat(ctx));
+            ctx.run_interval(STOCK_INTERVAL, |robot, ctx| robot.publish_stock(ctx));
+
+            Self {
+                status: Status::Joining,
+                orders_listener: Some(orders.abort_handle()),
+                connections_listener: Some(connections.abort_handle()),
+                admin_listener: admin.as_ref().map(task::JoinHandle::abort_handle),
+                federation: config
+                    .federation
+                    .clone()
+                    .map(|federation| Federation::new(federation, &transport)),
+                federation_listener: federation.as_ref().map(task::JoinHandle::abort_handle),
+                shutdown: Some(shutdown_tx),
+                ..Self::new(id, transport, config)
+            }
+        });
 
-        let err = "Couldn't send FindNext";
-        addr.send(FindNext).await.map_err(|_| err)?;
+        let err = "Couldn't send JoinRing";
+        addr.send(JoinRing).await.map_err(|_| err)?;
 
         let err = "Couldn't determine if I was alone";
         if addr.send(IsAlone).await.map_err(|_| err)? {
@@ -102,27 +313,36 @@ impl Robot {
             }
         }
 
-        Ok(new_con_recv)
+        Ok((addr, shutdown_rx))
     }
 
     /// Disconnects from the previous robot.
     ///
+    /// # Arguments
+    ///
+    /// * `announcement` - A message that tells the previous robot why it's being disconnected,
+    ///   so that it knows which robot to connect to next.
+    ///
     /// # Returns
     ///
     /// A future that resolves when the disconnection is complete.
-    fn disconnect_prev(&mut self) -> impl Future<Output = ()> {
+    fn disconnect_prev(&mut self, announcement: RobotMsg) -> impl Future<Output = ()> {
         let prev_tx = self.prev_tx.take();
         self.prev_id = None;
 
         async move {
             if let Some(mut tx) = prev_tx {
-                let msg = fmt_msg(RobotMsg::Disconnect);
-                let _ = tx.write_all(&msg).await;
+                for msg in [announcement, RobotMsg::Disconnect] {
+                    if tx.send(msg).await.is_err() {
+                        break;
+                    }
+                }
             }
         }
     }
 
-    /// Sends a token to the next robot.
+    /// Sends a token to the next robot, and tells the previous robot which copy of the token
+    /// this robot stopped using.
     ///
     /// # Arguments
     ///
@@ -138,25 +358,19 @@ impl Robot {
         &mut self,
         token_id: TokenId,
         msg: RobotMsg,
-    ) -> impl Future<
-        Output = Result<
-            (Option<WriteHalf<TcpStream>>, Option<WriteHalf<TcpStream>>),
-            Option<WriteHalf<TcpStream>>,
-        >,
-    > {
+    ) -> impl Future<Output = Result<(Link, Link), Link>> {
         let mut next_tx = self.next_tx.take();
         let mut prev_tx = self.prev_tx.take();
+        let round = self.rounds.get(&token_id).copied().unwrap_or_default();
 
         async move {
             if let Some(tx) = next_tx.as_mut() {
-                let recv_token = fmt_msg(msg);
-                if tx.write_all(&recv_token).await.is_err() {
+                if tx.send(msg).await.is_err() {
                     return Err(prev_tx);
                 }
 
                 if let Some(tx) = prev_tx.as_mut() {
-                    let eou = fmt_msg(RobotMsg::EndOfUse(token_id));
-                    let _ = tx.write_all(&eou).await;
+                    let _ = tx.send(RobotMsg::EndOfUse(token_id, round)).await;
                 }
             }
 
@@ -164,6 +378,59 @@ impl Robot {
         }
     }
 
+    /// Sends a copy of the given orders to the next robot, so that they aren't lost
+    /// if this robot dies before uploading them.
+    ///
+    /// # Arguments
+    ///
+    /// * `orders` - The orders to back up.
+    ///
+    /// # Returns
+    ///
+    /// A future that resolves when the orders are sent.
+    /// It returns the next write half.
+    fn send_backups(&mut self, orders: Vec<Order>) -> impl Future<Output = Link> {
+        let mut next_tx = self.next_tx.take();
+        let robot_id = self.id;
+
+        async move {
+            if let Some(tx) = next_tx.as_mut() {
+                for order in orders {
+                    if tx
+                        .send(RobotMsg::BackupOrder(robot_id, order))
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            }
+
+            next_tx
+        }
+    }
+
+    /// Backs up the given orders in the next robot.
+    ///
+    /// # Arguments
+    ///
+    /// * `orders` - The orders to back up.
+    /// * `ctx` - The context of the actor.
+    fn backup_orders(&mut self, orders: Vec<Order>, ctx: &mut Context<Self>) {
+        if orders.is_empty() || self.is_alone() {
+            return;
+        }
+
+        self.send_backups(orders)
+            .into_actor(self)
+            .map(|next_tx, robot, _| {
+                if next_tx.is_some() {
+                    robot.next_tx = next_tx;
+                }
+            })
+            .wait(ctx);
+    }
+
     /// Sends a screen message to the screen with the given ID.
     ///
     /// # Arguments
@@ -174,12 +441,13 @@ impl Robot {
     ///
     /// A future that resolves when the message is sent.
     fn send_screen(&self, msg: ScreenMsg, id: u16) -> impl Future<Output = ()> {
+        let transport = self.transport.clone();
+
         async move {
-            let msg = fmt_msg(msg);
+            let msg = Codec::default().encode(msg);
             for screen in (0..N_SCREEN).map(|i| (i + id) % N_SCREEN) {
-                let ip = id_to_addr(SCREEN_STARTING_PORT, screen);
-                if let Ok(mut stream) = TcpStream::connect(ip).await {
-                    if stream.write_all(&msg).await.is_ok() {
+                if let Ok(mut connection) = transport.connect(Endpoint::Screen(screen)).await {
+                    if connection.tx.send(&msg).await.is_ok() {
                         break;
                     }
                 }
@@ -187,47 +455,326 @@ impl Robot {
         }
     }
 
-    /// Returns an iterator over the intermediate robot IDs.
+    /// Merges the view of another robot into this one.
+    /// If the other robot thinks this one left the ring while it's still serving, for instance
+    /// because it couldn't reach it for a while, the robot admits itself again.
     ///
-    /// # Returns
+    /// # Arguments
     ///
-    /// An iterator over the intermediate robot IDs.
-    fn intermediate_ids(&self) -> impl Iterator<Item = u16> {
-        let robot_id = self.id;
-        let next_id = self.next_id;
+    /// * `members` - The view of the other robot.
+    fn merge_members(&mut self, members: &Membership) {
+        self.members.merge(members);
+        if !self.members.is_alive(self.id) && self.status != Status::Leaving {
+            self.members.join(self.id);
+        }
+
+        self.report_ring();
+    }
+
+    /// Returns the robots between this one and the next one that left the ring, which are the
+    /// ones this robot bypassed.
+    fn bypassed_ids(&self) -> impl Iterator<Item = u16> + '_ {
+        let next_id = self.next_id.unwrap_or(self.id);
 
         (1..=N_ROBOTS)
-            .map(move |offset| (robot_id + offset) % N_ROBOTS)
-            .take_while(move |&id| id != next_id.unwrap_or(robot_id))
+            .map(move |offset| (self.id + offset) % N_ROBOTS)
+            .take_while(move |&id| id != next_id)
+            .filter(|id| !self.members.is_alive(*id))
     }
 
     /// If the robot is not currently serving an order, it downloads a now order from the token.
-    /// If there is an uncompleted order from another robot, it recovers it.
+    /// If an earlier incarnation of this robot left an order in progress, it resumes it.
+    /// If a robot that this one bypassed had an uncompleted order, it recovers it. Only the
+    /// robot before it does, so that a token that was sent twice while the ring was being
+    /// repaired doesn't hand the same order to two robots.
     /// If there is no order in progress, it downloads the next order from the token.
+    /// Orders whose deadline passed are cancelled instead of served.
     ///
     /// # Arguments
     ///
     /// * `token` - The order token.
+    /// * `ctx` - The context of the actor.
     ///
     /// # Returns
     ///
     /// A future that resolves when the order is downloaded.
-    fn download_current_order(&mut self, token: &mut OrderToken) {
+    fn download_current_order(&mut self, token: &mut OrderToken, ctx: &mut Context<Self>) {
         if self.current_order.is_none() {
+            let incarnation = self.admitted;
+            if let Some(mut order) = token.remove_stale_in_progress(self.id, incarnation) {
+                println!("Resumed the order I was serving before rejoining the ring");
+                order.trace_mut().step("resumed_after_rejoin");
+                token.add_in_progress(self.id, incarnation, order.clone());
+                self.current_order = Some(order);
+                return;
+            }
+
             token.remove_in_progress(self.id);
 
-            for id in self.intermediate_ids() {
-                if let Some(order) = token.remove_in_progress(id) {
+            let bypassed: Vec<_> = self.bypassed_ids().collect();
+            for id in bypassed {
+                let recovered = token.remove_in_progress(id);
+                if let Some(mut order) = recovered.and_then(|order| self.unless_expired(order, ctx))
+                {
                     //println!("Recovered a lost order started by {id}");
-                    token.add_in_progress(self.id, order.clone());
+                    order.trace_mut().step("recovered_from_dead_robot");
+                    token.add_in_progress(self.id, incarnation, order.clone());
                     self.current_order = Some(order);
                     return;
                 }
             }
 
-            if let Some(order) = token.next_order() {
-                token.add_in_progress(self.id, order.clone());
+            while let Some(order) = token.next_order() {
+                let Some(mut order) = self.unless_expired(order, ctx) else {
+                    continue;
+                };
+
+                order.trace_mut().step("orders_queue");
+                token.add_in_progress(self.id, incarnation, order.clone());
                 self.current_order = Some(order);
+                break;
+            }
+        }
+    }
+
+    /// Cancels an order whose deadline passed and tells its screen, since the screen already
+    /// gave up on it.
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - The order.
+    /// * `ctx` - The context of the actor.
+    ///
+    /// # Returns
+    ///
+    /// The order, if its deadline didn't pass.
+    fn unless_expired(&mut self, order: Order, ctx: &mut Context<Self>) -> Option<Order> {
+        if !order.is_expired(self.clock.now_us()) {
+            return Some(order);
+        }
+
+        let order_id = order.id();
+        println!(
+            "Order {} of screen {} expired before it was served",
+            order_id.order_number(),
+            order_id.screen_id()
+        );
+        registry().inc(ORDERS_EXPIRED, &[], 1);
+        self.record_ending(order_id, OrderOutcome::Expired);
+        let msg = ScreenMsg::CancelOrder(order_id, order.trace());
+        self.send_screen(msg, order_id.screen_id())
+            .into_actor(self)
+            .spawn(ctx);
+        None
+    }
+
+    /// Cancels the current order if its deadline passed while the robot was serving it, so
+    /// that no more servings go to an order its screen gave up on. The servings it reserved
+    /// go back to their tokens when they pass by.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The context of the actor.
+    fn expire_current_order(&mut self, ctx: &mut Context<Self>) {
+        let Some(order) = self.current_order.take() else {
+            return;
+        };
+
+        let order_id = order.id();
+        self.current_order = self.unless_expired(order, ctx);
+        if self.current_order.is_none() {
+            self.expired_orders.insert(order_id);
+        }
+    }
+
+    /// Returns true if the token is an old copy of one that this robot already received.
+    /// This happens when a robot dies right after passing a token: the robot before it sends
+    /// the copy it kept in its token box again, but the token already went on.
+    /// Otherwise the token is remembered as the last one seen.
+    ///
+    /// # Arguments
+    ///
+    /// * `token_id` - The ID of the token.
+    /// * `sender` - The robot that sent the token.
+    /// * `round` - The round of the copy that arrived.
+    fn is_duplicate(&mut self, token_id: TokenId, sender: u16, round: u64) -> bool {
+        if self
+            .rounds
+            .get(&token_id)
+            .is_some_and(|last| round <= *last)
+        {
+            println!("Dropped an old copy of the {token_id:?} token");
+            return true;
+        }
+
+        self.rounds.insert(token_id, round);
+        self.last_token = Some(SeenToken {
+            id: token_id,
+            sender,
+            round,
+        });
+        false
+    }
+
+    /// Returns what the robot is doing, for its admin endpoint.
+    fn state(&self) -> RobotState {
+        RobotState {
+            id: self.id,
+            prev_id: self.prev_id,
+            next_id: self.next_id,
+            status: format!("{:?}", self.status).to_lowercase(),
+            paused: self.paused,
+            current_order: self.current_order.clone(),
+            serving_flavour: self.serving_flavour,
+            new_orders: self.new_orders.clone(),
+            stashed_order_token: self.token_box.order_token().cloned(),
+            stashed_flavour_tokens: self
+                .token_box
+                .flavour_tokens()
+                .into_iter()
+                .cloned()
+                .collect(),
+            last_token: self.last_token,
+            order_round: self.rounds.get(&TokenId::Order).copied(),
+            queued: self.queued,
+            stock: self.stock.clone(),
+            recent_orders: self.recent_orders.iter().copied().collect(),
+        }
+    }
+
+    /// Remembers how an order ended, for the operators of the robot.
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - The id of the order.
+    /// * `outcome` - How it ended.
+    fn record_ending(&mut self, order: OrderId, outcome: OrderOutcome) {
+        if self.recent_orders.len() == RECENT_ORDERS {
+            self.recent_orders.pop_front();
+        }
+
+        self.recent_orders.push_back(EndedOrder {
+            order,
+            outcome,
+            at_us: self.clock.now_us(),
+        });
+    }
+
+    /// Records the state of the robot for a snapshot and sends the marker of the snapshot to
+    /// both neighbours, so that they record theirs. The messages that arrive from now on are
+    /// recorded until the marker comes back from each side.
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - The ID of the snapshot.
+    /// * `ctx` - The context of the actor.
+    fn record_snapshot(&mut self, snapshot: SnapshotId, ctx: &mut Context<Self>) {
+        self.snapshots.record_state(RobotSnapshot {
+            snapshot,
+            id: self.id,
+            prev_id: self.prev_id,
+            next_id: self.next_id,
+            status: format!("{:?}", self.status).to_lowercase(),
+            members: self.members.clone(),
+            current_order: self.current_order.clone(),
+            serving_flavour: self.serving_flavour,
+            new_orders: self.new_orders.clone(),
+            backups: self.backups.clone().into_iter().collect(),
+            stashed_order_token: self.token_box.order_token().cloned(),
+            stashed_flavour_tokens: self
+                .token_box
+                .flavour_tokens()
+                .into_iter()
+                .cloned()
+                .collect(),
+            from_prev: Vec::new(),
+            from_next: Vec::new(),
+        });
+        ctx.run_later(SNAPSHOT_TIMEOUT, move |robot, _| {
+            robot.snapshots.expire(snapshot)
+        });
+
+        let mut next_tx = self.next_tx.take();
+        let mut prev_tx = self.prev_tx.take();
+
+        async move {
+            for tx in [next_tx.as_mut(), prev_tx.as_mut()].into_iter().flatten() {
+                let _ = tx.send(RobotMsg::Marker(snapshot)).await;
+            }
+
+            (prev_tx, next_tx)
+        }
+        .into_actor(self)
+        .map(|(prev_tx, next_tx), robot, _| {
+            robot.prev_tx = prev_tx;
+            robot.next_tx = next_tx;
+        })
+        .wait(ctx);
+    }
+
+    /// Sends the part of a robot towards the robot that started the snapshot, which keeps it.
+    ///
+    /// # Arguments
+    ///
+    /// * `part` - The part of the robot.
+    /// * `ctx` - The context of the actor.
+    fn send_snapshot_part(&mut self, part: RobotSnapshot, ctx: &mut Context<Self>) {
+        if part.snapshot.initiator == self.id {
+            return self.snapshots.add_part(part);
+        }
+
+        if let Some(mut tx) = self.next_tx.take() {
+            let msg = RobotMsg::SnapshotPart(Box::new(part));
+
+            async move {
+                let _ = tx.send(msg).await;
+                tx
+            }
+            .into_actor(self)
+            .map(|tx, robot, _| robot.next_tx = Some(tx))
+            .wait(ctx);
+        }
+    }
+
+    /// Settles the reservations of the flavour token that nobody is going to scoop anymore.
+    /// The reservation of this robot is settled once it finished scooping, or released if an
+    /// earlier incarnation of it made it. The reservations of the robots that left the ring
+    /// are released, since whoever recovers their orders reserves the servings again.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The flavour token.
+    fn settle_reservations(&mut self, token: &mut FlavourToken) {
+        let flavour = token.flavour();
+        if let Some(reservation) = token.reservation(self.id) {
+            if reservation.incarnation < self.admitted
+                || self.expired_orders.contains(&reservation.order_id)
+            {
+                token.release(self.id);
+            } else if self.serving_flavour != Some(flavour) {
+                token.settle(self.id);
+            }
+        }
+
+        if token.reservation(self.id).is_none() {
+            self.reserved.remove(&flavour);
+        }
+
+        if self.reserved.is_empty() {
+            self.expired_orders.clear();
+        }
+
+        let gone: Vec<_> = token
+            .reserved_by()
+            .filter(|id| *id != self.id && !self.members.is_alive(*id))
+            .collect();
+
+        for id in gone {
+            if let Some(reservation) = token.release(id) {
+                println!(
+                    "Released {} servings of {flavour:?} reserved by robot {id}",
+                    reservation.servings
+                );
             }
         }
     }
@@ -236,6 +783,224 @@ impl Robot {
     fn clear_order(&mut self) {
         self.current_order = None;
     }
+
+    /// Puts the current order back in the order token so that another robot serves it.
+    /// The order isn't returned while a flavour is being served for it.
+    /// The robot is removed from the orders in progress even if it had already delivered its
+    /// order, so that no other robot recovers it once this one leaves. An order left by an
+    /// earlier incarnation of the robot is returned too.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The order token.
+    fn return_current_order(&mut self, token: &mut OrderToken) {
+        if let Some(mut order) = token.remove_stale_in_progress(self.id, self.admitted) {
+            order.trace_mut().step("released_after_rejoin");
+            token.return_order(order);
+        }
+
+        if self.serving_flavour.is_some() {
+            return;
+        }
+
+        token.remove_in_progress(self.id);
+        if let Some(mut order) = self.current_order.take() {
+            order.trace_mut().step("returned_by_leaving_robot");
+            token.return_order(order);
+        }
+    }
+
+    /// Returns how long the robot expects to take to scoop the flavours of an order.
+    fn expected(&self, order: &Order) -> Duration {
+        order
+            .flavours()
+            .map(|(flavour, servings)| self.service.expected(flavour, servings))
+            .sum()
+    }
+
+    /// Returns how long the orders waiting in the ring should take, shared among its robots:
+    /// the ones in the order token the last time it passed by and the ones the robot didn't
+    /// upload yet.
+    fn estimated_wait(&self) -> Duration {
+        let new_orders: Duration = self
+            .new_orders
+            .iter()
+            .map(|order| self.expected(order))
+            .sum();
+        let robots = self.members.alive().count().max(1) as u32;
+        (self.backlog + new_orders) / robots
+    }
+
+    /// Returns true if the robot doesn't hold any order nor any reservation.
+    fn is_idle(&self) -> bool {
+        self.current_order.is_none()
+            && self.serving_flavour.is_none()
+            && self.new_orders.is_empty()
+            && self.reserved.is_empty()
+    }
+
+    /// Returns true if the robot is the only one in the ring.
+    fn is_alone(&self) -> bool {
+        self.next_id.map(|next| next == self.id).unwrap_or_default()
+    }
+
+    /// Exits if the robot is leaving and its neighbours already reconnected around it.
+    fn try_exit(&mut self, ctx: &mut Context<Self>) {
+        if self.status == Status::Leaving && self.next_left && self.prev_closed {
+            self.exit(ctx);
+        }
+    }
+
+    /// Tells the next robot that this robot is disconnecting and stops the robot.
+    fn exit(&mut self, ctx: &mut Context<Self>) {
+        let next_tx = self.next_tx.take();
+
+        async move {
+            if let Some(mut tx) = next_tx {
+                let _ = tx.send(RobotMsg::Disconnect).await;
+            }
+        }
+        .into_actor(self)
+        .map(|_, robot, ctx| {
+            println!("Left the ring");
+            let listeners = [
+                robot.admin_listener.take(),
+                robot.federation_listener.take(),
+            ];
+            for listener in listeners.into_iter().flatten() {
+                listener.abort();
+            }
+
+            if let Some(shutdown) = robot.shutdown.take() {
+                let _ = shutdown.send(());
+            }
+
+            ctx.stop();
+        })
+        .wait(ctx);
+    }
+
+    /// Replaces the connection to the next robot, telling the old next robot that this one
+    /// is leaving it, and backs up the orders that weren't uploaded yet in the new one.
+    /// The new next robot starts being watched for heartbeats.
+    ///
+    /// # Arguments
+    ///
+    /// * `tx` - The sending half of the connection to the new next robot.
+    /// * `id` - The ID of the new next robot.
+    /// * `receiver` - The task that receives messages from the new next robot.
+    /// * `ctx` - The context of the actor.
+    fn set_next(&mut self, tx: MsgSender, id: u16, receiver: AbortHandle, ctx: &mut Context<Self>) {
+        self.next_id = Some(id);
+        self.next_receiver = Some(receiver);
+        self.next_heartbeats = Some(self.detector.detector(time::Instant::now()));
+        if let Some(mut old_tx) = self.next_tx.replace(tx) {
+            async move {
+                let _ = old_tx.send(RobotMsg::Disconnect).await;
+            }
+            .into_actor(self)
+            .spawn(ctx);
+        }
+
+        self.report_ring();
+        self.backup_orders(self.new_orders.clone(), ctx);
+    }
+
+    /// Tells the previous robot that this one is alive and checks whether the next one is.
+    /// Since it runs on the robot itself, a robot that hangs stops sending heartbeats even
+    /// if its connections are still open.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The context of the actor.
+    fn heartbeat(&mut self, ctx: &mut Context<Self>) {
+        if let Some(mut tx) = self.prev_tx.take() {
+            let msg = RobotMsg::Heartbeat(self.id);
+
+            async move {
+                let _ = tx.send(msg).await;
+                tx
+            }
+            .into_actor(self)
+            .map(|tx, robot, _| robot.prev_tx = Some(tx))
+            .wait(ctx);
+        }
+
+        let now = time::Instant::now();
+        let suspected = self
+            .next_heartbeats
+            .as_ref()
+            .is_some_and(|detector| detector.is_suspected(now));
+
+        if suspected && !self.is_alone() && self.status != Status::Leaving {
+            self.bypass_next(ctx);
+        }
+    }
+
+    /// Bypasses a next robot that stopped sending heartbeats, as if it had disconnected:
+    /// it's removed from the view, so that the robot looks for the one after it and recovers
+    /// its orders in progress, and the tokens it didn't finish using are sent again.
+    /// It's told goodbye in case it's only slow, so that it doesn't adopt the orders of this one.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The context of the actor.
+    fn bypass_next(&mut self, ctx: &mut Context<Self>) {
+        self.next_heartbeats = None;
+        if let Some(receiver) = self.next_receiver.take() {
+            receiver.abort();
+        }
+
+        if let Some(id) = self.next_id {
+            if self.members.leave(id) {
+                println!("Bypassing robot {id}, it stopped sending heartbeats");
+            }
+        }
+
+        registry().inc(NEXT_SUSPECTED, &[], 1);
+        if let Some(mut tx) = self.next_tx.take() {
+            async move {
+                let _ = tx.send(RobotMsg::Disconnect).await;
+            }
+            .into_actor(self)
+            .spawn(ctx);
+        }
+
+        ctx.address().do_send(FindNext);
+        ctx.address().do_send(CheckTokenBox);
+    }
+
+    /// Tells every screen the stock of the flavours the robot saw, if it changed since the
+    /// last time. The screens use it to turn away orders that can't be served.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The context of the actor.
+    fn publish_stock(&mut self, ctx: &mut Context<Self>) {
+        if !std::mem::take(&mut self.stock_changed) {
+            return;
+        }
+
+        let msg = Codec::default().encode(ScreenMsg::Stock(self.stock.clone()));
+        let transport = self.transport.clone();
+        async move {
+            for screen in 0..N_SCREEN {
+                if let Ok(mut connection) = transport.connect(Endpoint::Screen(screen)).await {
+                    let _ = connection.tx.send(&msg).await;
+                }
+            }
+        }
+        .into_actor(self)
+        .spawn(ctx);
+    }
+
+    /// Updates the metrics with the position of the robot in the ring.
+    fn report_ring(&self) {
+        let id = |id: Option<u16>| id.map(f64::from).unwrap_or(-1.0);
+        registry().set(RING_PREV_ID, &[], id(self.prev_id));
+        registry().set(RING_NEXT_ID, &[], id(self.next_id));
+        registry().set(RING_MEMBERS, &[], self.members.alive().count() as f64);
+    }
 }
 
 /// Implements the handler trait for the `Robot` struct to handle the IsAlone message.
@@ -245,7 +1010,7 @@ impl Handler<IsAlone> for Robot {
     /// Handles the IsAlone message.
     /// It returns `true` if the robot is alone in the ring, `false` otherwise.
     fn handle(&mut self, _: IsAlone, _: &mut Self::Context) -> Self::Result {
-        self.next_id.map(|next| next == self.id).unwrap_or_default()
+        self.is_alone()
     }
 }
 
@@ -254,24 +1019,144 @@ impl Handler<Connect> for Robot {
     type Result = ();
 
     /// Handles the Connect message.
-    /// It disconnects from the previous robot and connects to the new robot.
+    /// A robot that is entering the ring is admitted with a new incarnation, even if this robot
+    /// thought it was still a member, since it may have crashed and come back before anyone
+    /// noticed. It's welcomed with the view, so that it learns its incarnation and starts
+    /// serving. A robot that was already part of the ring is only admitted if it wasn't a member.
+    /// Then the robot disconnects from the previous robot and connects to the new robot. The
+    /// previous robot is told the view first, so that it connects to the new robot instead of
+    /// this one.
     /// It also spawns a task to listen for messages from the new robot.
+    /// Leaving robots drop the connection so that the new robot keeps looking.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The Connect message.
+    /// * `ctx` - The context of the actor.
+    ///
+    /// # Returns
+    ///
+    /// A future that resolves when the connection is complete.
+    fn handle(&mut self, msg: Connect, ctx: &mut Self::Context) -> Self::Result {
+        if self.status == Status::Leaving {
+            return;
+        }
+
+        let known = self.members.get(msg.id).is_some();
+        if msg.joining && msg.id != self.id {
+            match self.members.admit(msg.id) {
+                1 if !known => println!("Robot {} joined the ring", msg.id),
+                incarnation => println!("Robot {} rejoined the ring ({incarnation})", msg.id),
+            }
+        } else if self.members.join(msg.id) {
+            println!("Robot {} was admitted to the ring", msg.id);
+        }
+
+        self.report_ring();
+
+        let members = RobotMsg::Members(self.members.clone());
+        self.disconnect_prev(members).into_actor(self).wait(ctx);
+
+        let connection = msg.connection;
+        let mut tx = MsgSender::new(connection.tx, msg.codec);
+        let welcome = msg.joining.then(|| RobotMsg::Members(self.members.clone()));
+
+        async move {
+            if let Some(welcome) = welcome {
+                let _ = tx.send(welcome).await;
+            }
+
+            tx
+        }
+        .into_actor(self)
+        .map(|tx, robot, _| robot.prev_tx = Some(tx))
+        .wait(ctx);
+
+        task::spawn(prev_robot_receiver(ctx.address(), connection.rx));
+    }
+}
+
+/// Connects to a robot, agrees with it on how to encode the messages and introduces this
+/// robot to it.
+///
+/// # Arguments
+///
+/// * `transport` - The network used to reach the robot.
+/// * `id` - The ID of the robot to connect to.
+/// * `encoding` - The encoding this robot prefers.
+/// * `introduction` - The `Join` or `Link` this robot introduces itself with.
+///
+/// # Returns
+///
+/// Both halves of the connection, or `None` if the robot couldn't be reached or they
+/// couldn't agree on the protocol.
+async fn introduce(
+    transport: &SharedTransport,
+    id: u16,
+    encoding: Encoding,
+    introduction: RobotMsg,
+) -> Option<(MsgSender, Box<dyn FrameReceiver>)> {
+    let mut connection = transport.connect(Endpoint::Robot(id)).await.ok()?;
+    let codec = match handshake::hello(&mut connection, encoding).await {
+        Ok(codec) => codec,
+        Err(e) => {
+            eprintln!("Couldn't agree on the protocol with robot {id}: {e}");
+            return None;
+        }
+    };
+
+    let mut tx = MsgSender::new(connection.tx, codec);
+    tx.send(introduction).await.ok()?;
+    Some((tx, connection.rx))
+}
+
+/// Implements the handler trait for the `Robot` struct to handle the JoinRing message.
+impl Handler<JoinRing> for Robot {
+    type Result = ();
+
+    /// Handles the JoinRing message.
+    /// A new robot only knows the members of its view and its seeds, so it introduces itself
+    /// with a `Join` to the first of them that answers, members first. If nobody answers, it
+    /// connects to itself and starts a ring of its own.
+    /// The robot keeps joining until the robot it connected to welcomes it with the view, and
+    /// then links to the successor the view gives it if that's another robot.
     ///
     /// # Arguments
     ///
-    /// * `msg` - The Connect message.
+    /// * `msg` - The JoinRing message.
     /// * `ctx` - The context of the actor.
-    ///
-    /// # Returns
-    ///
-    /// A future that resolves when the connection is complete.
-    fn handle(&mut self, msg: Connect, ctx: &mut Self::Context) -> Self::Result {
-        self.disconnect_prev().into_actor(self).wait(ctx);
+    fn handle(&mut self, _: JoinRing, ctx: &mut Self::Context) -> Self::Result {
+        let robot_id = self.id;
+        let addr = ctx.address();
+        let transport = self.transport.clone();
+        let encoding = self.encoding;
+        let mut candidates: Vec<_> = self.members.successors(robot_id).collect();
+        for seed in &self.seeds {
+            if *seed != robot_id && !candidates.contains(seed) {
+                candidates.push(*seed);
+            }
+        }
+        candidates.push(robot_id);
 
-        let (rx, tx) = io::split(msg.stream);
-        self.prev_tx = Some(tx);
+        async move {
+            for id in candidates {
+                let join = RobotMsg::Join(robot_id);
+                if let Some((tx, rx)) = introduce(&transport, id, encoding, join).await {
+                    let receiver = tokio::spawn(next_robot_receiver(addr, rx));
+                    return Ok((tx, id, receiver.abort_handle()));
+                }
+            }
 
-        task::spawn(prev_robot_receiver(ctx.address(), rx));
+            Err("Couldn't find a robot to join")
+        }
+        .into_actor(self)
+        .map(|res, robot, ctx| {
+            if let Ok((tx, id, receiver)) = res {
+                robot.members.join(id);
+                robot.set_next(tx, id, receiver, ctx);
+            }
+        })
+        .wait(ctx);
     }
 }
 
@@ -280,9 +1165,11 @@ impl Handler<FindNext> for Robot {
     type Result = ();
 
     /// Handles the FindNext message.
-    /// It tries to connect to the next robot in the ring.
-    /// If it succeeds, it spawns a task to listen for new connections.
-    /// If it fails, it tries to connect to the next robot in the ring and repeats the process.
+    /// It tries to connect to the members of the ring that come after it, closest first, and
+    /// introduces itself with a `Link` to the first one that answers.
+    /// The members that don't answer are bypassed: they are removed from the view, so that the
+    /// rest of the ring learns they left through the order token and recovers their orders.
+    /// If no member answers, the robot connects to itself.
     ///
     /// # Arguments
     ///
@@ -295,25 +1182,39 @@ impl Handler<FindNext> for Robot {
     fn handle(&mut self, _: FindNext, ctx: &mut Self::Context) -> Self::Result {
         let robot_id = self.id;
         let addr = ctx.address();
+        let transport = self.transport.clone();
+        let encoding = self.encoding;
+        let candidates: Vec<_> = self
+            .members
+            .successors(robot_id)
+            .chain([robot_id])
+            .collect();
 
         async move {
-            for offset in 1..=N_ROBOTS {
-                let id = (robot_id + offset) % N_ROBOTS;
-                let ip = id_to_addr(ROBOT_STARTING_PORT, id);
-
-                if let Ok((rx, tx)) = TcpStream::connect(ip).await.map(io::split) {
-                    tokio::spawn(next_robot_receiver(addr, rx));
-                    return Ok((tx, id));
+            let mut unreachable = Vec::new();
+            for id in candidates {
+                let link = RobotMsg::Link(robot_id);
+                if let Some((tx, rx)) = introduce(&transport, id, encoding, link).await {
+                    let receiver = tokio::spawn(next_robot_receiver(addr, rx));
+                    return (Some((tx, id, receiver.abort_handle())), unreachable);
                 }
+
+                unreachable.push(id);
             }
 
-            Err("Couldn't find next robot")
+            (None, unreachable)
         }
         .into_actor(self)
-        .map(|res, robot, _| {
-            if let Ok((tx, id)) = res {
-                robot.next_id = Some(id);
-                robot.next_tx = Some(tx);
+        .map(|(next, unreachable), robot, ctx| {
+            for id in unreachable.into_iter().filter(|id| *id != robot.id) {
+                if robot.members.leave(id) {
+                    println!("Bypassing robot {id}, it can't be reached");
+                }
+            }
+
+            match next {
+                Some((tx, id, receiver)) => robot.set_next(tx, id, receiver, ctx),
+                None => robot.report_ring(),
             }
         })
         .wait(ctx);
@@ -325,10 +1226,14 @@ impl Handler<RecvOrderToken> for Robot {
     type Result = ();
 
     /// Handles the RecvOrderToken message.
-    /// It receives an order token and processes it.
+    /// It receives an order token and processes it, unless it's an old copy of it.
     /// If the robot is not currently serving an order, it downloads a new order from the token.
     /// It checks if the current order is completed and sends a confirmation message to the screen in case it is.
-    /// It also uploads new orders to the token.
+    /// It also uploads new orders to the token and discards the backups of the orders that
+    /// the previous robots already uploaded. The new orders that don't fit under the limit of
+    /// the queue wait for the next round, unless the robot is leaving the ring.
+    /// The view of the ring is merged with the one in the token, which carries the merged
+    /// view to the next robot.
     ///
     /// # Arguments
     ///
@@ -340,9 +1245,26 @@ impl Handler<RecvOrderToken> for Robot {
     /// A future that resolves when the order token is processed.
     fn handle(&mut self, msg: RecvOrderToken, ctx: &mut Self::Context) -> Self::Result {
         let mut token = msg.token;
+        self.snapshots
+            .record(false, || RobotMsg::RecvOrderToken(token.clone()));
+        if self.is_duplicate(token.id(), token.sender(), token.round()) {
+            return;
+        }
+
+        if let Some(checker) = &self.invariants {
+            checker.order_token_received(self.id, &token);
+        }
+
+        let start = Instant::now();
+
+        if let Some(last) = self.last_order_token.replace(start) {
+            registry().observe(ORDER_TOKEN_ROTATION, &[], last.elapsed().as_secs_f64());
+        }
 
         self.prev_id = Some(token.sender());
         token.mark(self.id);
+        self.merge_members(token.members());
+        token.set_members(self.members.clone());
 
         println!(
             "me: {}, prev: {:?}, next: {:?}",
@@ -351,15 +1273,37 @@ impl Handler<RecvOrderToken> for Robot {
 
         //println!("Recibí el order token de  {:?}", self.prev_id);
 
-        token.upload_new_orders(self.new_orders.drain(..));
+        for orders in self.backups.values_mut() {
+            orders.retain(|order| !token.contains(order.id()));
+        }
+
+        match token.max_queue() {
+            Some(max_queue) => self.max_queue = Some(max_queue),
+            None => token.set_max_queue(self.max_queue),
+        }
+
+        // The orders that don't fit in the queue wait for the next round, still backed up.
+        let new_orders = mem::take(&mut self.new_orders)
+            .into_iter()
+            .map(|mut order| {
+                order.trace_mut().step("waiting_order_token");
+                order
+            });
+        match self.status {
+            Status::Draining | Status::Leaving => token.hand_over_orders(new_orders),
+            _ => self.new_orders = token.upload_new_orders(new_orders),
+        }
 
-        if !self.serving_flavour {
+        if self.serving_flavour.is_none() {
             if let Some(order) = self.current_order.as_mut() {
                 if order.is_completed() {
                     let order_id = order.id();
-                    let msg = ScreenMsg::ConfirmOrder(order.id());
+                    order.trace_mut().step("waiting_to_deliver");
+                    let msg = ScreenMsg::ConfirmOrder(order.id(), order.trace());
+                    registry().inc(ORDERS_CONFIRMED, &[], 1);
 
                     self.clear_order();
+                    self.record_ending(order_id, OrderOutcome::Confirmed);
                     self.send_screen(msg, order_id.screen_id())
                         .into_actor(self)
                         .spawn(ctx);
@@ -367,8 +1311,26 @@ impl Handler<RecvOrderToken> for Robot {
             }
         }
 
-        self.download_current_order(&mut token);
-        ctx.address().do_send(ReleaseOrderToken { token });
+        match self.status {
+            Status::Joining => {}
+            Status::Serving if !self.paused => self.download_current_order(&mut token, ctx),
+            Status::Serving => {}
+            _ => self.return_current_order(&mut token),
+        }
+
+        self.queued = token.queue_len();
+        self.backlog = token.orders().map(|order| self.expected(order)).sum();
+
+        registry().set(ORDERS_QUEUED, &[], token.queue_len() as f64);
+        registry().inc(ORDER_TOKENS_HANDLED, &[], 1);
+        registry().observe(ORDER_TOKEN_HANDLING, &[], start.elapsed().as_secs_f64());
+
+        // Released right away, so that a snapshot always finds the token in the token box.
+        self.handle(ReleaseOrderToken { token }, ctx);
+
+        if self.status == Status::Draining {
+            ctx.address().do_send(Leave);
+        }
     }
 }
 
@@ -409,66 +1371,117 @@ impl Handler<ReleaseOrderToken> for Robot {
 
 /// Implements the handler trait for the `Robot` struct to handle the RecvFlavourToken message.
 impl Handler<RecvFlavourToken> for Robot {
-    type Result = ResponseActFuture<Self, ()>;
+    type Result = ();
 
     /// Handles the RecvFlavourToken message.
-    /// It receives a flavour token and processes it.
-    /// If the robot is not currently serving a flavour, it checks if the current order requires the flavour and serves it.
+    /// It receives a flavour token and processes it, unless it's an old copy of it.
+    /// If the robot is not currently serving a flavour, it checks if the current order requires the flavour and
+    /// reserves the servings. The token is passed on right away and the robot scoops afterwards, so that
+    /// other robots can serve the same flavour in the meantime.
+    /// Robots that are draining don't start serving new flavours.
     ///
     /// # Arguments
     ///
     /// * `msg` - The RecvFlavourToken message.
     /// * `ctx` - The context of the actor.
-    ///
-    /// # Returns
-    ///
-    /// A future that resolves when the flavour token is processed.
     fn handle(&mut self, msg: RecvFlavourToken, ctx: &mut Self::Context) -> Self::Result {
         let mut token = msg.token;
-        let mut duration = 0;
+        self.snapshots
+            .record(false, || RobotMsg::RecvFlavourToken(token.clone()));
+        if self.is_duplicate(token.id(), token.sender(), token.round()) {
+            return;
+        }
+
+        if let Some(checker) = &self.invariants {
+            checker.flavour_token_received(self.id, &token);
+        }
 
-        /* println!(
-            "flavour_token: {}\t{:?}   \t{}",
-            token.sender(),
-            token.flavour(),
-            token.servings()
-        ); */
+        let mut servings_taken = 0;
+        let flavour = token.flavour();
 
         self.prev_id = Some(token.sender());
         token.mark(self.id);
+        if self.serving_flavour.is_none() {
+            self.expire_current_order(ctx);
+        }
+
+        self.settle_reservations(&mut token);
+        if let Some(federation) = self.federation.as_mut() {
+            federation.trade(&mut token);
+        }
 
-        if !self.serving_flavour {
+        if self.serving_flavour.is_none() && self.status == Status::Serving {
             if let Some(order) = self.current_order.as_mut() {
-                if let Some(servings) = order.cross(token.flavour()) {
+                if let Some(servings) = order.cross(flavour) {
+                    order
+                        .trace_mut()
+                        .step(&format!("waiting_flavour_token {flavour:?}"));
+
                     if !token.has_enough(servings) {
                         let order_id = order.id();
-                        let msg = ScreenMsg::CancelOrder(order_id);
+                        let msg = ScreenMsg::CancelOrder(order_id, order.trace());
+                        registry().inc(ORDERS_CANCELLED, &[], 1);
 
                         self.clear_order();
+                        self.record_ending(order_id, OrderOutcome::Cancelled);
                         self.send_screen(msg, order_id.screen_id())
                             .into_actor(self)
                             .spawn(ctx);
                     } else {
-                        duration = token.take(servings);
-                        self.serving_flavour = true;
+                        servings_taken =
+                            token.reserve(self.id, self.admitted, order.id(), servings);
+                        self.serving_flavour = Some(flavour);
+                        self.reserved.insert(flavour);
+                        registry().inc(
+                            SERVINGS_TAKEN,
+                            &[("flavour", flavour.name())],
+                            servings_taken as u64,
+                        );
                     }
                 }
             }
         }
 
-        self.token_box.discard_flavour_token(token.flavour());
-        let fut = time::sleep(Duration::from_secs(duration as u64))
+        let labels = [("flavour", flavour.name())];
+        registry().set(FLAVOUR_STOCK, &labels, token.servings() as f64);
+
+        let level = StockLevel {
+            servings: token.servings(),
+            round: token.round(),
+        };
+        let previous = self.stock.insert(flavour, level);
+        self.stock_changed |= previous.map(|previous| previous.servings) != Some(level.servings);
+
+        self.token_box.discard_flavour_token(flavour, token.round());
+        self.handle(ReleaseFlavourToken { token }, ctx);
+
+        if servings_taken == 0 {
+            return;
+        }
+
+        let scoop = self.service.scoop(flavour, servings_taken);
+        let started = time::Instant::now();
+        time::sleep(scoop)
             .into_actor(self)
             .map(move |_, robot, ctx| {
-                if duration > 0 {
-                    robot.serving_flavour = false;
-                    println!("Took {duration} seconds to serve {:?}", token.flavour());
+                robot.serving_flavour = None;
+                let took = started.elapsed().as_secs_f64();
+                let id = robot.id.to_string();
+                let labels = [("flavour", flavour.name()), ("robot", id.as_str())];
+                registry().observe(SCOOP_DURATION, &labels, took);
+                registry().inc(SERVINGS_SCOOPED, &labels, servings_taken as u64);
+
+                match robot.current_order.as_mut().map(Order::trace_mut) {
+                    Some(trace) => {
+                        trace.step(&format!("scoop {flavour:?}"));
+                        println!("Took {took:.2} seconds to serve {flavour:?} ({trace})");
+                    }
+                    None => println!("Took {took:.2} seconds to serve {flavour:?}"),
                 }
 
-                ctx.address().do_send(ReleaseFlavourToken { token })
-            });
-
-        Box::pin(fut)
+                robot.expire_current_order(ctx);
+            })
+            .spawn(ctx);
     }
 }
 
@@ -510,7 +1523,9 @@ impl Handler<EndOfUse> for Robot {
     type Result = ();
 
     /// Handles the EndOfUse message.
-    /// It discards the token if the previous and next IDs are different.
+    /// It discards the token if the previous and next IDs are different, unless the token in
+    /// the token box is newer than the copy the next robot stopped using, since the message
+    /// may arrive after the token was passed on again.
     ///
     /// # Arguments
     ///
@@ -521,10 +1536,14 @@ impl Handler<EndOfUse> for Robot {
     ///
     /// A future that resolves when the token is discarded.
     fn handle(&mut self, msg: EndOfUse, _: &mut Self::Context) -> Self::Result {
+        self.snapshots
+            .record(true, || RobotMsg::EndOfUse(msg.token_id, msg.round));
         if self.prev_id != self.next_id {
             match msg.token_id {
-                TokenId::Order => self.token_box.discard_order_token(),
-                TokenId::Flavour(flavour) => self.token_box.discard_flavour_token(flavour),
+                TokenId::Order => self.token_box.discard_order_token(msg.round),
+                TokenId::Flavour(flavour) => {
+                    self.token_box.discard_flavour_token(flavour, msg.round)
+                }
             }
         }
     }
@@ -561,10 +1580,12 @@ impl Handler<CheckTokenBox> for Robot {
 
 /// Implements the handler trait for the `Robot` struct to handle the RecvOrder message.
 impl Handler<RecvOrder> for Robot {
-    type Result = ();
+    type Result = MessageResult<RecvOrder>;
 
     /// Handles the RecvOrder message.
-    /// It receives a new order and adds it to the list of new orders.
+    /// It receives a new order, adds it to the list of new orders and backs it up in the next robot.
+    /// The order is refused if the orders queued the last time the order token passed by,
+    /// together with the ones the robot didn't upload yet, already reach the limit of the queue.
     ///
     /// # Arguments
     ///
@@ -573,8 +1594,547 @@ impl Handler<RecvOrder> for Robot {
     ///
     /// # Returns
     ///
-    /// A future that resolves when the order is received.
-    fn handle(&mut self, msg: RecvOrder, _ctx: &mut Self::Context) -> Self::Result {
-        self.new_orders.push(msg.order);
+    /// Whether the robot took the order, with how long the orders ahead of it should take.
+    fn handle(&mut self, msg: RecvOrder, ctx: &mut Self::Context) -> Self::Result {
+        let wait = self.estimated_wait();
+        let queued = self.queued + self.new_orders.len();
+        if self.max_queue.is_some_and(|max_queue| queued >= max_queue) {
+            registry().inc(ORDERS_REFUSED, &[], 1);
+            return MessageResult(Admission::Busy(wait));
+        }
+
+        let mut order = msg.order;
+        order.trace_mut().step("robot_handoff");
+        registry().inc(ORDERS_RECEIVED, &[], 1);
+        self.new_orders.push(order.clone());
+        self.backup_orders(vec![order], ctx);
+        MessageResult(Admission::Accepted(wait))
+    }
+}
+
+/// Implements the handler trait for the `Robot` struct to handle the NextDisconnected message.
+impl Handler<NextDisconnected> for Robot {
+    type Result = ();
+
+    /// Handles the NextDisconnected message.
+    /// If the robot is leaving, the next robot disconnecting means the previous one already
+    /// reconnected around this robot. Otherwise, it looks for a new next robot.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The NextDisconnected message.
+    /// * `ctx` - The context of the actor.
+    fn handle(&mut self, _: NextDisconnected, ctx: &mut Self::Context) -> Self::Result {
+        match self.status {
+            Status::Leaving => {
+                self.next_left = true;
+                self.try_exit(ctx);
+            }
+
+            _ => ctx.address().do_send(FindNext),
+        }
+    }
+}
+
+/// Implements the handler trait for the `Robot` struct to handle the PrevClosed message.
+impl Handler<PrevClosed> for Robot {
+    type Result = ();
+
+    /// Handles the PrevClosed message.
+    /// If the previous robot closed its stream without saying goodbye, it crashed, so the robot
+    /// adopts the orders it received and didn't upload, and backs them up in the next robot.
+    /// A leaving robot can't receive any more tokens once the previous robot closes its stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The PrevClosed message.
+    /// * `ctx` - The context of the actor.
+    fn handle(&mut self, msg: PrevClosed, ctx: &mut Self::Context) -> Self::Result {
+        let backups = msg
+            .prev_id
+            .and_then(|id| self.backups.remove(&id))
+            .unwrap_or_default();
+
+        if !msg.graceful {
+            let mut adopted = Vec::new();
+            for mut order in backups {
+                if self.new_orders.iter().all(|new| new.id() != order.id()) {
+                    order.trace_mut().step("recovered_from_backup");
+                    self.new_orders.push(order.clone());
+                    adopted.push(order);
+                }
+            }
+
+            if !adopted.is_empty() {
+                println!("Recovered {} orders from a dead robot", adopted.len());
+            }
+
+            self.backup_orders(adopted, ctx);
+        }
+
+        if self.status == Status::Leaving {
+            self.prev_closed = true;
+            self.try_exit(ctx);
+        }
+    }
+}
+
+/// Implements the handler trait for the `Robot` struct to handle the Drain message.
+impl Handler<Drain> for Robot {
+    type Result = ();
+
+    /// Handles the Drain message.
+    /// It stops receiving orders from the screens. The orders it already received and the
+    /// one it's serving are handed back the next time the order token passes by.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The Drain message.
+    /// * `ctx` - The context of the actor.
+    fn handle(&mut self, _: Drain, ctx: &mut Self::Context) -> Self::Result {
+        if !matches!(self.status, Status::Joining | Status::Serving) {
+            return;
+        }
+
+        self.status = Status::Draining;
+        if let Some(listener) = self.orders_listener.take() {
+            listener.abort();
+        }
+
+        ctx.address().do_send(Leave);
+    }
+}
+
+/// Implements the handler trait for the `Robot` struct to handle the Leave message.
+impl Handler<Leave> for Robot {
+    type Result = ();
+
+    /// Handles the Leave message.
+    /// Once a draining robot is idle, it stops accepting robots, removes itself from its view
+    /// and announces that it's leaving to the previous one, which reconnects to the next one. The robot keeps forwarding the tokens
+    /// that were already on their way until both neighbours reconnect around it, and then exits.
+    /// If it's alone in the ring it exits right away.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The Leave message.
+    /// * `ctx` - The context of the actor.
+    fn handle(&mut self, _: Leave, ctx: &mut Self::Context) -> Self::Result {
+        if self.status != Status::Draining || !self.is_idle() {
+            return;
+        }
+
+        self.status = Status::Leaving;
+        if let Some(listener) = self.connections_listener.take() {
+            listener.abort();
+        }
+
+        if self.is_alone() {
+            return self.exit(ctx);
+        }
+
+        self.members.leave(self.id);
+        self.disconnect_prev(RobotMsg::Leave(self.id))
+            .into_actor(self)
+            .wait(ctx);
+        ctx.run_later(LEAVE_TIMEOUT, |robot, ctx| robot.exit(ctx));
+    }
+}
+
+/// Implements the handler trait for the `Robot` struct to handle the BackupOrder message.
+impl Handler<BackupOrder> for Robot {
+    type Result = ();
+
+    /// Handles the BackupOrder message.
+    /// It keeps a copy of the order until the previous robot uploads it to the order token.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The BackupOrder message.
+    /// * `ctx` - The context of the actor.
+    fn handle(&mut self, msg: BackupOrder, _: &mut Self::Context) -> Self::Result {
+        self.snapshots.record(false, || {
+            RobotMsg::BackupOrder(msg.origin, msg.order.clone())
+        });
+        let orders = self.backups.entry(msg.origin).or_default();
+        orders.retain(|order| order.id() != msg.order.id());
+        orders.push(msg.order);
+    }
+}
+
+/// Implements the handler trait for the `Robot` struct to handle the MergeMembers message.
+impl Handler<MergeMembers> for Robot {
+    type Result = ();
+
+    /// Handles the MergeMembers message.
+    /// The next robot sends its view before disconnecting this one, so that it knows about the
+    /// robot that joined between them. It also sends it to welcome a robot that is joining,
+    /// which starts serving with the incarnation it was admitted with.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The MergeMembers message.
+    /// * `ctx` - The context of the actor.
+    fn handle(&mut self, msg: MergeMembers, ctx: &mut Self::Context) -> Self::Result {
+        self.snapshots
+            .record(true, || RobotMsg::Members(msg.members.clone()));
+        self.merge_members(&msg.members);
+
+        if self.status == Status::Joining {
+            self.status = Status::Serving;
+            self.admitted = self.members.incarnation(self.id);
+            println!("Admitted to the ring ({})", self.admitted);
+
+            let successor = self.members.successors(self.id).next();
+            if successor.is_some() && successor != self.next_id {
+                ctx.notify(FindNext);
+            }
+        }
+    }
+}
+
+/// Implements the handler trait for the `Robot` struct to handle the MemberLeft message.
+impl Handler<MemberLeft> for Robot {
+    type Result = ();
+
+    /// Handles the MemberLeft message.
+    /// It removes the next robot from the view, since it announced that it's leaving the ring.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The MemberLeft message.
+    /// * `ctx` - The context of the actor.
+    fn handle(&mut self, msg: MemberLeft, _: &mut Self::Context) -> Self::Result {
+        self.snapshots.record(true, || RobotMsg::Leave(msg.id));
+        if self.members.leave(msg.id) {
+            println!("Robot {} left the ring", msg.id);
+        }
+
+        self.report_ring();
+    }
+}
+
+/// Implements the handler trait for the `Robot` struct to handle the Heartbeat message.
+impl Handler<Heartbeat> for Robot {
+    type Result = ();
+
+    /// Handles the Heartbeat message.
+    /// It records the heartbeat if it comes from the next robot. Heartbeats from a robot that
+    /// was the next one before are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The Heartbeat message.
+    /// * `ctx` - The context of the actor.
+    fn handle(&mut self, msg: Heartbeat, _: &mut Self::Context) -> Self::Result {
+        if self.next_id != Some(msg.id) {
+            return;
+        }
+
+        if let Some(detector) = self.next_heartbeats.as_mut() {
+            detector.heartbeat(time::Instant::now());
+        }
+    }
+}
+
+/// Implements the handler trait for the `Robot` struct to handle the Federated message.
+impl Handler<Federated> for Robot {
+    type Result = ();
+
+    /// Handles the Federated message.
+    /// The message from the other shop waits for the token of its flavour, since every transfer
+    /// is applied to the token.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The Federated message.
+    /// * `_ctx` - The context of the actor.
+    fn handle(&mut self, msg: Federated, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(federation) = self.federation.as_mut() {
+            federation.deliver(msg.msg);
+        }
+    }
+}
+
+/// Implements the handler trait for the `Robot` struct to handle the Admin message.
+impl Handler<Admin> for Robot {
+    type Result = MessageResult<Admin>;
+
+    /// Handles the Admin message.
+    /// A paused robot keeps passing the tokens and finishes the order it's serving, but
+    /// doesn't take new ones until it's resumed.
+    /// Only the commands that change the robot are logged, since the state is polled.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The Admin message.
+    /// * `ctx` - The context of the actor.
+    ///
+    /// # Returns
+    ///
+    /// The state of the robot after running the command.
+    fn handle(&mut self, msg: Admin, ctx: &mut Self::Context) -> Self::Result {
+        if !matches!(msg.command, AdminMsg::State | AdminMsg::Snapshot) {
+            println!("Admin command: {:?}", msg.command);
+        }
+
+        match msg.command {
+            AdminMsg::State | AdminMsg::Snapshot => {}
+            AdminMsg::Drain => self.handle(Drain, ctx),
+            AdminMsg::Pause => self.paused = true,
+            AdminMsg::Resume => self.paused = false,
+            AdminMsg::FindNext => ctx.notify(FindNext),
+        }
+
+        MessageResult(self.state())
+    }
+}
+
+/// Implements the handler trait for the `Robot` struct to handle the TakeSnapshot message.
+impl Handler<TakeSnapshot> for Robot {
+    type Result = MessageResult<TakeSnapshot>;
+
+    /// Handles the TakeSnapshot message.
+    /// It starts a snapshot of the ring and waits for the part of every robot it knows of.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The TakeSnapshot message.
+    /// * `ctx` - The context of the actor.
+    ///
+    /// # Returns
+    ///
+    /// A receiver that resolves with the snapshot.
+    fn handle(&mut self, _: TakeSnapshot, ctx: &mut Self::Context) -> Self::Result {
+        let expected = self.members.alive().collect();
+        let (snapshot, receiver) = self.snapshots.collect(self.id, self.admitted, expected);
+        println!("Taking snapshot {snapshot:?}");
+
+        self.record_snapshot(snapshot, ctx);
+        MessageResult(receiver)
+    }
+}
+
+/// Implements the handler trait for the `Robot` struct to handle the Marker message.
+impl Handler<Marker> for Robot {
+    type Result = ();
+
+    /// Handles the Marker message.
+    /// The first marker of a snapshot makes the robot record its state. Once the marker
+    /// arrived from both neighbours, the part of the robot is complete.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The Marker message.
+    /// * `ctx` - The context of the actor.
+    fn handle(&mut self, msg: Marker, ctx: &mut Self::Context) -> Self::Result {
+        if !self.snapshots.knows(msg.snapshot) {
+            self.record_snapshot(msg.snapshot, ctx);
+        }
+
+        if let Some(part) = self.snapshots.close(msg.snapshot, msg.from_next) {
+            self.send_snapshot_part(part, ctx);
+        }
+    }
+}
+
+/// Implements the handler trait for the `Robot` struct to handle the SnapshotPart message.
+impl Handler<SnapshotPart> for Robot {
+    type Result = ();
+
+    /// Handles the SnapshotPart message.
+    /// It keeps the part if this robot started the snapshot, and passes it on otherwise.
+    /// A part that went around the ring, because the robot that started the snapshot
+    /// isn't part of it anymore, is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The SnapshotPart message.
+    /// * `ctx` - The context of the actor.
+    fn handle(&mut self, msg: SnapshotPart, ctx: &mut Self::Context) -> Self::Result {
+        let part = msg.part;
+        let initiator = part.snapshot.initiator;
+        if initiator != self.id && (part.id == self.id || !self.members.is_alive(initiator)) {
+            return;
+        }
+
+        self.send_snapshot_part(part, ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{service::ServiceModel, transport::ChannelTransport};
+    use ice_cream_shop::{
+        messages::{admin_msg::RobotState, fmt_msg},
+        snapshot::GlobalSnapshot,
+        trace::trace_context::now_us,
+    };
+    use serde::de::DeserializeOwned;
+
+    /// Sends a command to the admin endpoint of robot 0 and returns its answer.
+    async fn command<T: DeserializeOwned>(transport: &SharedTransport, command: AdminMsg) -> T {
+        command_robot(transport, 0, command).await
+    }
+
+    /// Sends a command to the admin endpoint of a robot and returns its answer.
+    async fn command_robot<T: DeserializeOwned>(
+        transport: &SharedTransport,
+        id: u16,
+        command: AdminMsg,
+    ) -> T {
+        let mut connection = transport.connect(Endpoint::RobotAdmin(id)).await.unwrap();
+        connection.tx.send(&fmt_msg(command)).await.unwrap();
+        let frame = connection.rx.recv().await.unwrap().unwrap();
+        serde_json::from_slice(&frame).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn test01_the_admin_endpoint_answers_with_the_state_of_the_robot() {
+        let transport = SharedTransport::new(ChannelTransport::default());
+        let (_addr, shutdown) = Robot::start(0, transport.clone(), RobotConfig::default())
+            .await
+            .unwrap();
+
+        let state: RobotState = command(&transport, AdminMsg::Pause).await;
+        assert_eq!((state.id, state.next_id), (0, Some(0)));
+        assert!(state.paused);
+
+        time::sleep(Duration::from_millis(100)).await;
+        let state: RobotState = command(&transport, AdminMsg::Resume).await;
+        assert!(!state.paused && state.last_token.is_some());
+
+        let state: RobotState = command(&transport, AdminMsg::Drain).await;
+        assert_ne!(state.status, "serving");
+        assert!(shutdown.await.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn test02_a_snapshot_finds_every_robot_and_token() {
+        let transport = SharedTransport::new(ChannelTransport::default());
+        for id in 0..2 {
+            Robot::start(id, transport.clone(), RobotConfig::default())
+                .await
+                .unwrap();
+        }
+
+        time::sleep(Duration::from_secs(1)).await;
+        let snapshot: GlobalSnapshot = command(&transport, AdminMsg::Snapshot).await;
+
+        let ids: Vec<_> = snapshot.robots.iter().map(|robot| robot.id).collect();
+        assert_eq!(ids, vec![0, 1]);
+        assert!(snapshot.order_token.is_some());
+        assert_eq!(snapshot.stock.len(), Flavour::flavours().count());
+        assert!(snapshot
+            .stock
+            .values()
+            .all(|servings| *servings == STARTING_ICECREAM));
+    }
+
+    #[actix_rt::test]
+    async fn test03_robots_that_join_through_a_seed_take_their_place_in_the_ring() {
+        let transport = SharedTransport::new(ChannelTransport::default());
+        let config = RobotConfig {
+            seeds: Some(vec![0]),
+            ..RobotConfig::default()
+        };
+
+        for id in [0, 3, 1] {
+            Robot::start(id, transport.clone(), config.clone())
+                .await
+                .unwrap();
+            time::sleep(Duration::from_millis(500)).await;
+        }
+
+        time::sleep(Duration::from_secs(1)).await;
+        let state: RobotState = command(&transport, AdminMsg::State).await;
+        assert_eq!(state.next_id, Some(1));
+
+        let snapshot: GlobalSnapshot = command(&transport, AdminMsg::Snapshot).await;
+        let ring: Vec<_> = snapshot
+            .robots
+            .iter()
+            .map(|robot| (robot.id, robot.next_id))
+            .collect();
+        assert_eq!(ring, vec![(0, Some(1)), (1, Some(3)), (3, Some(0))]);
+    }
+
+    #[actix_rt::test]
+    async fn test04_the_queue_never_grows_past_its_limit_with_several_robots() {
+        let transport = SharedTransport::new(ChannelTransport::default());
+        let config = RobotConfig {
+            max_queue: Some(2),
+            ..RobotConfig::default()
+        };
+
+        for id in 0..3 {
+            Robot::start(id, transport.clone(), config.clone())
+                .await
+                .unwrap();
+        }
+
+        time::sleep(Duration::from_secs(1)).await;
+        for id in 0..3 {
+            let _: RobotState = command_robot(&transport, id, AdminMsg::Pause).await;
+        }
+
+        // Sent at once, so that the robots accept them before they see the queue fill up.
+        let sends: Vec<_> = (0..9u16)
+            .map(|i| {
+                let transport = transport.clone();
+                task::spawn(async move {
+                    let order = Order::new(OrderId::new(0, i as usize), HashMap::new());
+                    let endpoint = Endpoint::RobotOrders(i % 3);
+                    let mut connection = transport.connect(endpoint).await.unwrap();
+                    let frame = fmt_msg(RobotMsg::RecvOrder(order));
+                    connection.tx.send(&frame).await.unwrap();
+
+                    let frame = connection.rx.recv().await.unwrap().unwrap();
+                    matches!(serde_json::from_slice(&frame), Ok(Admission::Accepted(_)))
+                })
+            })
+            .collect();
+
+        let mut accepted = 0;
+        for send in sends {
+            accepted += send.await.unwrap() as usize;
+        }
+
+        time::sleep(Duration::from_secs(1)).await;
+        let snapshot: GlobalSnapshot = command(&transport, AdminMsg::Snapshot).await;
+        let order_token = snapshot.order_token.unwrap();
+        assert!(accepted >= 2);
+        assert_eq!(order_token.queue_len(), 2);
+        assert_eq!(snapshot.pending_orders.len(), accepted);
+    }
+
+    #[actix_rt::test]
+    async fn test05_an_order_that_expires_while_it_is_served_gives_its_servings_back() {
+        let transport = SharedTransport::new(ChannelTransport::default());
+        let config = RobotConfig {
+            service: ServiceModel::default().with_per_serving(Duration::from_millis(500)),
+            ..RobotConfig::default()
+        };
+        Robot::start(0, transport.clone(), config).await.unwrap();
+        time::sleep(Duration::from_millis(200)).await;
+
+        let flavours = HashMap::from([(Flavour::Chocolate, 1), (Flavour::DulceDeLeche, 1)]);
+        let order = Order::new(OrderId::new(0, 1), flavours).with_deadline(now_us() + 300_000);
+        let mut connection = transport.connect(Endpoint::RobotOrders(0)).await.unwrap();
+        let frame = fmt_msg(RobotMsg::RecvOrder(order));
+        connection.tx.send(&frame).await.unwrap();
+        connection.rx.recv().await.unwrap();
+
+        time::sleep(Duration::from_secs(2)).await;
+        let state: RobotState = command(&transport, AdminMsg::State).await;
+        let outcomes: Vec<_> = state.recent_orders.iter().map(|o| o.outcome).collect();
+        assert_eq!(outcomes, vec![OrderOutcome::Expired]);
+
+        let snapshot: GlobalSnapshot = command(&transport, AdminMsg::Snapshot).await;
+        assert!(snapshot
+            .flavour_tokens
+            .iter()
+            .all(|token| token.stock() == STARTING_ICECREAM));
     }
 }
@@ -0,0 +1,262 @@
+use ice_cream_shop::flavour::Flavour;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{collections::HashMap, env, str::FromStr, time::Duration};
+
+/// Environment variable that configures how long robots take to scoop, as a comma separated
+/// list of `serving=<ms>`, `jitter=<fraction>`, `accel=<factor>`, `robot<id>=<factor>` and
+/// `<flavour>=<factor>`. Every serving takes a second when it isn't set.
+pub const SERVICE_VAR: &str = "SHOP_SERVICE";
+
+/// How long a serving takes by default.
+const DEFAULT_PER_SERVING: Duration = Duration::from_secs(1);
+
+/// The longest a scoop may take, so that extreme factors can't overflow the timers.
+const MAX_SCOOP: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long robots take to scoop the servings of an order.
+/// The time of a scoop is `per_serving * servings * flavour factor / robot speed / acceleration`,
+/// moved up or down at random by up to `jitter` of itself.
+/// - `per_serving`: How long a serving takes at the usual speed.
+/// - `flavours`: How many times harder than usual every flavour is to scoop.
+/// - `speeds`: How many times faster than usual every robot scoops.
+/// - `jitter`: The largest fraction of the time a scoop may be moved up or down.
+/// - `acceleration`: How many times faster the time passes, to run long scenarios quickly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceModel {
+    per_serving: Duration,
+    flavours: HashMap<Flavour, f64>,
+    speeds: HashMap<u16, f64>,
+    jitter: f64,
+    acceleration: f64,
+}
+
+/// The service model of a robot together with the random numbers used for its jitter.
+/// - `id`: The ID of the robot.
+/// - `model`: How long the scoops take.
+/// - `rng`: The random number generator, seeded with the ID of the robot.
+#[derive(Debug)]
+pub struct ServiceClock {
+    id: u16,
+    model: ServiceModel,
+    rng: StdRng,
+}
+
+impl ServiceModel {
+    /// Reads the service model from `SHOP_SERVICE`.
+    ///
+    /// # Returns
+    ///
+    /// The configured model, the default one if the variable isn't set, or an error if it has an invalid value.
+    pub fn from_env() -> Result<Self, &'static str> {
+        match env::var(SERVICE_VAR) {
+            Ok(value) => value.parse(),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// Sets how long a serving takes at the usual speed.
+    pub fn with_per_serving(mut self, per_serving: Duration) -> Self {
+        self.per_serving = per_serving;
+        self
+    }
+
+    /// Sets how many times harder than usual a flavour is to scoop.
+    pub fn with_flavour(mut self, flavour: Flavour, factor: f64) -> Self {
+        self.flavours.insert(flavour, factor);
+        self
+    }
+
+    /// Sets how many times faster than usual a robot scoops.
+    pub fn with_speed(mut self, id: u16, speed: f64) -> Self {
+        self.speeds.insert(id, speed);
+        self
+    }
+
+    /// Sets the largest fraction of the time a scoop may be moved up or down, between 0 and 1.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets how many times faster the time passes.
+    pub fn with_acceleration(mut self, acceleration: f64) -> Self {
+        self.acceleration = acceleration;
+        self
+    }
+
+    /// Returns how long a scoop takes without jitter, at most `MAX_SCOOP`.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the robot that scoops.
+    /// * `flavour` - The flavour that is scooped.
+    /// * `servings` - How many servings are scooped.
+    pub fn expected(&self, id: u16, flavour: Flavour, servings: usize) -> Duration {
+        let factor = self.flavours.get(&flavour).unwrap_or(&1.0);
+        let speed = self.speeds.get(&id).unwrap_or(&1.0);
+        let scale = servings as f64 * factor / speed / self.acceleration;
+        Duration::try_from_secs_f64(self.per_serving.as_secs_f64() * scale)
+            .map_or(MAX_SCOOP, |time| time.min(MAX_SCOOP))
+    }
+
+    /// Creates the clock a robot uses to time its scoops.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the robot.
+    pub fn clock(&self, id: u16) -> ServiceClock {
+        ServiceClock {
+            id,
+            model: self.clone(),
+            rng: StdRng::seed_from_u64(id as u64),
+        }
+    }
+}
+
+impl Default for ServiceModel {
+    fn default() -> Self {
+        Self {
+            per_serving: DEFAULT_PER_SERVING,
+            flavours: HashMap::new(),
+            speeds: HashMap::new(),
+            jitter: 0.0,
+            acceleration: 1.0,
+        }
+    }
+}
+
+impl FromStr for ServiceModel {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let err = "SHOP_SERVICE needs to be a list of serving=<ms>, jitter=<fraction>, \
+                   accel=<factor>, robot<id>=<factor> or <flavour>=<factor>";
+        let mut model = Self::default();
+
+        for entry in value.split(',').filter(|entry| !entry.is_empty()) {
+            let (key, value) = entry.trim().split_once('=').ok_or(err)?;
+            let number = match value.parse::<f64>() {
+                Ok(number) if !number.is_finite() => return Err(err),
+                Ok(number) if number > 0.0 || (key == "jitter" && number == 0.0) => number,
+                _ => return Err(err),
+            };
+
+            model = match key {
+                "serving" => match Duration::try_from_secs_f64(number / 1000.0) {
+                    Ok(per_serving) => model.with_per_serving(per_serving),
+                    Err(_) => return Err(err),
+                },
+                "jitter" if number <= 1.0 => model.with_jitter(number),
+                "accel" => model.with_acceleration(number),
+                _ => match key.strip_prefix("robot").map(str::parse) {
+                    Some(Ok(id)) => model.with_speed(id, number),
+                    Some(Err(_)) => return Err(err),
+                    None => match Flavour::flavours().find(|flavour| flavour.name() == key) {
+                        Some(flavour) => model.with_flavour(flavour, number),
+                        None => return Err(err),
+                    },
+                },
+            };
+        }
+
+        Ok(model)
+    }
+}
+
+impl ServiceClock {
+    /// Returns how long the robot expects to take to scoop, without jitter.
+    ///
+    /// # Arguments
+    ///
+    /// * `flavour` - The flavour that is scooped.
+    /// * `servings` - How many servings are scooped.
+    pub fn expected(&self, flavour: Flavour, servings: usize) -> Duration {
+        self.model.expected(self.id, flavour, servings)
+    }
+
+    /// Returns how long the robot will take to scoop, with the jitter applied.
+    ///
+    /// # Arguments
+    ///
+    /// * `flavour` - The flavour that is scooped.
+    /// * `servings` - How many servings are scooped.
+    pub fn scoop(&mut self, flavour: Flavour, servings: usize) -> Duration {
+        let expected = self.expected(flavour, servings);
+        match self.model.jitter > 0.0 {
+            true => {
+                let jitter = self.model.jitter;
+                expected.mul_f64(1.0 + self.rng.gen_range(-jitter..=jitter))
+            }
+            false => expected,
+        }
+    }
+}
+
+impl Default for ServiceClock {
+    fn default() -> Self {
+        ServiceModel::default().clock(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test01_the_model_is_parsed_from_its_entries() {
+        let model = "serving=500,jitter=0.1,accel=10,robot2=2,chocolate=3".parse();
+        let expected = ServiceModel::default()
+            .with_per_serving(Duration::from_millis(500))
+            .with_jitter(0.1)
+            .with_acceleration(10.0)
+            .with_speed(2, 2.0)
+            .with_flavour(Flavour::Chocolate, 3.0);
+
+        assert_eq!(model, Ok(expected));
+        assert_eq!("".parse(), Ok(ServiceModel::default()));
+        assert!("jitter=2".parse::<ServiceModel>().is_err());
+        assert!("accel=0".parse::<ServiceModel>().is_err());
+        assert!("pistachio=2".parse::<ServiceModel>().is_err());
+        assert!("robotx=2".parse::<ServiceModel>().is_err());
+        assert!("serving=inf".parse::<ServiceModel>().is_err());
+        assert!("serving=1e300".parse::<ServiceModel>().is_err());
+        assert!("accel=NaN".parse::<ServiceModel>().is_err());
+        assert!("robot1=inf".parse::<ServiceModel>().is_err());
+    }
+
+    #[test]
+    fn test02_the_time_grows_with_servings_and_flavour_and_shrinks_with_speed() {
+        let model = ServiceModel::default()
+            .with_flavour(Flavour::Chocolate, 2.0)
+            .with_speed(1, 4.0)
+            .with_acceleration(2.0);
+
+        let time = |id, flavour, servings| model.expected(id, flavour, servings).as_secs_f64();
+        assert_eq!(time(0, Flavour::Frutilla, 3), 1.5);
+        assert_eq!(time(0, Flavour::Chocolate, 3), 3.0);
+        assert_eq!(time(1, Flavour::Chocolate, 3), 0.75);
+    }
+
+    #[test]
+    fn test03_the_jitter_stays_within_its_fraction() {
+        let mut clock = ServiceModel::default().with_jitter(0.2).clock(3);
+        let times: Vec<_> = (0..100).map(|_| clock.scoop(Flavour::Menta, 5)).collect();
+
+        assert!(times
+            .iter()
+            .all(|time| (4.0..=6.0).contains(&time.as_secs_f64())));
+        assert!(times.iter().any(|time| *time != times[0]));
+    }
+
+    #[test]
+    fn test04_extreme_factors_are_capped_instead_of_overflowing() {
+        let model: ServiceModel = "serving=1e15,accel=1e-300,chocolate=1e300".parse().unwrap();
+        assert_eq!(model.expected(0, Flavour::Chocolate, usize::MAX), MAX_SCOOP);
+
+        let model: ServiceModel = "accel=1e300".parse().unwrap();
+        assert_eq!(model.expected(0, Flavour::Menta, 1), Duration::ZERO);
+
+        let mut clock = ServiceModel::default().with_speed(0, 0.0).clock(0);
+        assert_eq!(clock.scoop(Flavour::Menta, 1), MAX_SCOOP);
+    }
+}
@@ -0,0 +1,250 @@
+use ice_cream_shop::{
+    messages::robot_msg::RobotMsg,
+    snapshot::{GlobalSnapshot, RobotSnapshot, SnapshotId},
+};
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::Duration,
+};
+use tokio::sync::oneshot;
+
+/// How long a robot waits for the parts of a snapshot before giving up on it.
+pub const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The part of a snapshot that a robot is recording.
+/// - `id`: The ID of the snapshot.
+/// - `part`: The state of the robot and the messages recorded so far.
+/// - `prev_open`: Whether the marker from the previous robot didn't arrive yet.
+/// - `next_open`: Whether the marker from the next robot didn't arrive yet.
+#[derive(Debug)]
+struct Recording {
+    id: SnapshotId,
+    part: RobotSnapshot,
+    prev_open: bool,
+    next_open: bool,
+}
+
+/// The parts of a snapshot that the robot that started it is waiting for.
+/// - `expected`: The robots that were part of the ring when it started.
+/// - `parts`: The parts that arrived.
+/// - `reply`: Where the assembled snapshot is sent.
+#[derive(Debug)]
+struct Collection {
+    expected: BTreeSet<u16>,
+    parts: Vec<RobotSnapshot>,
+    reply: oneshot::Sender<GlobalSnapshot>,
+}
+
+/// The snapshots of the ring that a robot takes part in, following Chandy-Lamport:
+/// a robot records its state the first time it hears of a snapshot, sends a marker to both
+/// neighbours, and records the messages that arrive on each channel until the marker of that
+/// channel arrives.
+/// - `recordings`: The snapshots the robot is recording, by ID.
+/// - `ended`: The incarnation and sequence number of the last snapshot of every initiator that
+///   the robot stopped recording, so that its late markers don't start it again.
+/// - `collections`: The snapshots the robot started and is putting together, by ID.
+/// - `started`: How many snapshots the robot started.
+#[derive(Debug, Default)]
+pub struct Snapshots {
+    recordings: HashMap<SnapshotId, Recording>,
+    ended: HashMap<u16, (u64, u64)>,
+    collections: HashMap<SnapshotId, Collection>,
+    started: u64,
+}
+
+impl Snapshots {
+    /// Prepares a new snapshot started by this robot.
+    ///
+    /// # Arguments
+    ///
+    /// * `initiator` - The ID of the robot.
+    /// * `incarnation` - The incarnation of the robot.
+    /// * `expected` - The robots that have to send their parts.
+    ///
+    /// # Returns
+    ///
+    /// The ID of the snapshot and a receiver that resolves with it once every part arrived.
+    pub fn collect(
+        &mut self,
+        initiator: u16,
+        incarnation: u64,
+        expected: BTreeSet<u16>,
+    ) -> (SnapshotId, oneshot::Receiver<GlobalSnapshot>) {
+        self.started += 1;
+        let id = SnapshotId {
+            initiator,
+            incarnation,
+            seq: self.started,
+        };
+
+        let (reply, receiver) = oneshot::channel();
+        let collection = Collection {
+            expected,
+            parts: Vec::new(),
+            reply,
+        };
+
+        self.collections.insert(id, collection);
+        (id, receiver)
+    }
+
+    /// Returns whether the robot already recorded its state for the snapshot, even if it
+    /// stopped recording it.
+    pub fn knows(&self, id: SnapshotId) -> bool {
+        self.recordings.contains_key(&id)
+            || self
+                .ended
+                .get(&id.initiator)
+                .is_some_and(|last| (id.incarnation, id.seq) <= *last)
+    }
+
+    /// Stops recording a snapshot.
+    fn end(&mut self, id: SnapshotId) -> Option<Recording> {
+        let last = self.ended.entry(id.initiator).or_default();
+        *last = (*last).max((id.incarnation, id.seq));
+        self.recordings.remove(&id)
+    }
+
+    /// Starts recording the channels of the robot for a snapshot.
+    ///
+    /// # Arguments
+    ///
+    /// * `part` - The state of the robot.
+    pub fn record_state(&mut self, part: RobotSnapshot) {
+        let recording = Recording {
+            id: part.snapshot,
+            part,
+            prev_open: true,
+            next_open: true,
+        };
+
+        self.recordings.insert(recording.id, recording);
+    }
+
+    /// Records a message that arrived on a channel, in every snapshot that is still
+    /// waiting for the marker of that channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `from_next` - Whether the message came from the next robot.
+    /// * `msg` - Creates the message, only if it needs to be recorded.
+    pub fn record(&mut self, from_next: bool, msg: impl Fn() -> RobotMsg) {
+        for recording in self.recordings.values_mut() {
+            let open = match from_next {
+                true => recording.next_open,
+                false => recording.prev_open,
+            };
+
+            match (open, from_next) {
+                (false, _) => {}
+                (true, true) => recording.part.from_next.push(msg()),
+                (true, false) => recording.part.from_prev.push(msg()),
+            }
+        }
+    }
+
+    /// Stops recording a channel because its marker arrived.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the snapshot.
+    /// * `from_next` - Whether the marker came from the next robot.
+    ///
+    /// # Returns
+    ///
+    /// The part of the robot, once the markers of both channels arrived.
+    pub fn close(&mut self, id: SnapshotId, from_next: bool) -> Option<RobotSnapshot> {
+        let recording = self.recordings.get_mut(&id)?;
+        match from_next {
+            true => recording.next_open = false,
+            false => recording.prev_open = false,
+        }
+
+        match recording.prev_open || recording.next_open {
+            true => None,
+            false => self.end(id).map(|recording| recording.part),
+        }
+    }
+
+    /// Adds the part of a robot to a snapshot started by this robot, and sends the whole
+    /// snapshot once every part arrived.
+    ///
+    /// # Arguments
+    ///
+    /// * `part` - The part of the robot.
+    pub fn add_part(&mut self, part: RobotSnapshot) {
+        let id = part.snapshot;
+        let Some(collection) = self.collections.get_mut(&id) else {
+            return;
+        };
+
+        collection.parts.retain(|other| other.id != part.id);
+        collection.parts.push(part);
+
+        let arrived: BTreeSet<_> = collection.parts.iter().map(|part| part.id).collect();
+        if arrived.is_superset(&collection.expected) {
+            if let Some(collection) = self.collections.remove(&id) {
+                let snapshot = GlobalSnapshot::assemble(id, collection.parts);
+                let _ = collection.reply.send(snapshot);
+            }
+        }
+    }
+
+    /// Gives up on a snapshot that didn't end in time. Its markers are ignored from now on,
+    /// and whoever waits for it learns that it won't arrive.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the snapshot.
+    pub fn expire(&mut self, id: SnapshotId) {
+        self.end(id);
+        self.collections.remove(&id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ice_cream_shop::tokens::Membership;
+    use std::collections::BTreeMap;
+
+    fn part(seq: u64) -> RobotSnapshot {
+        RobotSnapshot {
+            snapshot: SnapshotId {
+                initiator: 0,
+                incarnation: 1,
+                seq,
+            },
+            id: 1,
+            prev_id: Some(0),
+            next_id: Some(0),
+            status: "serving".to_string(),
+            members: Membership::new(1),
+            current_order: None,
+            serving_flavour: None,
+            new_orders: Vec::new(),
+            backups: BTreeMap::new(),
+            stashed_order_token: None,
+            stashed_flavour_tokens: Vec::new(),
+            from_prev: Vec::new(),
+            from_next: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test01_closed_and_expired_snapshots_are_forgotten_but_still_known() {
+        let mut snapshots = Snapshots::default();
+        let (closed, expired) = (part(1).snapshot, part(2).snapshot);
+        snapshots.record_state(part(1));
+        snapshots.record_state(part(2));
+
+        assert!(snapshots.close(closed, true).is_none());
+        assert!(snapshots.close(closed, false).is_some());
+        snapshots.expire(expired);
+
+        assert!(snapshots.recordings.is_empty());
+        assert!(snapshots.knows(closed) && snapshots.knows(expired));
+        assert!(!snapshots.knows(part(3).snapshot));
+        assert!(snapshots.close(expired, true).is_none());
+    }
+}
@@ -28,9 +28,20 @@ impl TokenBox {
         self.order_token = Some(token);
     }
 
-    /// Discards the OrderToken from the TokenBox.
-    pub fn discard_order_token(&mut self) {
-        self.order_token = None;
+    /// Discards the OrderToken from the TokenBox, unless it's newer than the given round.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The TokenBox.
+    /// * `round` - The round of the copy that stopped being used.
+    pub fn discard_order_token(&mut self, round: u64) {
+        if self
+            .order_token
+            .as_ref()
+            .is_some_and(|token| token.round() <= round)
+        {
+            self.order_token = None;
+        }
     }
 
     /// Takes the OrderToken from the TokenBox.
@@ -38,6 +49,18 @@ impl TokenBox {
         self.order_token.take()
     }
 
+    /// Returns the OrderToken in the TokenBox, if any.
+    pub fn order_token(&self) -> Option<&OrderToken> {
+        self.order_token.as_ref()
+    }
+
+    /// Returns the FlavourTokens in the TokenBox, sorted by flavour.
+    pub fn flavour_tokens(&self) -> Vec<&FlavourToken> {
+        let mut tokens: Vec<_> = self.flavour_tokens.values().collect();
+        tokens.sort_by_key(|token| token.flavour().name());
+        tokens
+    }
+
     /// Stashes a FlavourToken in the TokenBox.
     ///
     /// # Arguments
@@ -48,17 +71,25 @@ impl TokenBox {
         self.flavour_tokens.insert(token.flavour(), token);
     }
 
-    /// Discards a FlavourToken from the TokenBox.
+    /// Discards a FlavourToken from the TokenBox, unless it's newer than the given round.
     ///
     /// # Arguments
     ///
     /// * `self` - The TokenBox.
     /// * `flavour` - The Flavour of the token to discard.
-    pub fn discard_flavour_token(&mut self, flavour: Flavour) {
-        let _ = self.flavour_tokens.remove(&flavour);
+    /// * `round` - The round of the copy that stopped being used.
+    pub fn discard_flavour_token(&mut self, flavour: Flavour, round: u64) {
+        if self
+            .flavour_tokens
+            .get(&flavour)
+            .is_some_and(|token| token.round() <= round)
+        {
+            self.flavour_tokens.remove(&flavour);
+        }
     }
 
-    /// Takes all the FlavourTokens from the TokenBox.
+    /// Takes all the FlavourTokens from the TokenBox, sorted by flavour so that they are
+    /// always sent again in the same order.
     ///
     /// # Arguments
     ///
@@ -67,7 +98,13 @@ impl TokenBox {
     /// # Returns
     ///
     /// An iterator over the FlavourTokens.
-    pub fn take_flavour_tokens(&mut self) -> impl Iterator<Item = FlavourToken> + '_ {
-        self.flavour_tokens.drain().map(|(_, token)| token)
+    pub fn take_flavour_tokens(&mut self) -> impl Iterator<Item = FlavourToken> {
+        let mut tokens: Vec<_> = self
+            .flavour_tokens
+            .drain()
+            .map(|(_, token)| token)
+            .collect();
+        tokens.sort_by_key(|token| token.flavour().name());
+        tokens.into_iter()
     }
 }
@@ -0,0 +1,328 @@
+use super::{
+    BoxFuture, Connection, Endpoint, FrameReceiver, FrameSender, Listener, RingTransport,
+    SharedTransport,
+};
+use ice_cream_shop::messages::{
+    admin_msg::AdminMsg,
+    auth::{permits, AuthError, Keyring, Role, Route},
+    federation_msg::FederationMsg,
+    gateway_msg::GatewayMsg,
+    protocol::Handshake,
+    robot_msg::RobotMsg,
+    screen_msg::ScreenMsg,
+};
+use std::{
+    io,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// Decides whether a role may send an opened frame through a connection.
+type Policy = fn(Role, &[u8]) -> bool;
+
+/// The route the answers of a connection go through, once its first frame was sent.
+type Answers = Arc<Mutex<Option<Route>>>;
+
+/// The end of a connection a half belongs to.
+/// - `Opener`: The component that connected to the endpoint.
+/// - `Listener`: The component listening at the endpoint.
+#[derive(Debug, Clone, Copy)]
+enum Side {
+    Opener(Endpoint),
+    Listener(Endpoint),
+}
+
+/// A transport that signs every frame it sends with the key of the shop and only lets
+/// through the frames signed by a role allowed to send them.
+#[derive(Debug)]
+pub struct AuthTransport {
+    inner: SharedTransport,
+    keyring: Keyring,
+}
+
+/// The sending half of an authenticated connection. The frames of the opener are bound to
+/// the endpoint, and the ones of the listener to the first frame of the opener.
+#[derive(Debug)]
+struct SealingSender {
+    tx: Box<dyn FrameSender>,
+    keyring: Keyring,
+    side: Side,
+    answers: Answers,
+}
+
+/// The receiving half of an authenticated connection. A frame that is rejected closes it.
+#[derive(Debug)]
+struct OpeningReceiver {
+    rx: Box<dyn FrameReceiver>,
+    keyring: Keyring,
+    policy: Policy,
+    side: Side,
+    answers: Answers,
+}
+
+/// The connections made to an authenticated endpoint.
+#[derive(Debug)]
+struct AuthListener {
+    listener: Box<dyn Listener>,
+    keyring: Keyring,
+    policy: Policy,
+    endpoint: Endpoint,
+}
+
+impl AuthTransport {
+    /// Wraps a transport so that its connections are authenticated.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The transport that carries the frames.
+    /// * `keyring` - The key of the shop and the role of this component.
+    pub fn new(inner: SharedTransport, keyring: Keyring) -> Self {
+        Self { inner, keyring }
+    }
+}
+
+/// Wraps both halves of a connection so that it signs and checks every frame.
+fn wrap(connection: Connection, keyring: &Keyring, policy: Policy, side: Side) -> Connection {
+    let answers = Answers::default();
+    Connection {
+        tx: Box::new(SealingSender {
+            tx: connection.tx,
+            keyring: keyring.clone(),
+            side,
+            answers: answers.clone(),
+        }),
+        rx: Box::new(OpeningReceiver {
+            rx: connection.rx,
+            keyring: keyring.clone(),
+            policy,
+            side,
+            answers,
+        }),
+    }
+}
+
+/// Locks the route of the answers of a connection, even if a thread panicked while holding it.
+fn lock(answers: &Answers) -> MutexGuard<'_, Option<Route>> {
+    answers
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Returns the error for a frame that goes back through a connection before its first frame.
+fn unanswerable() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, AuthError::Forged)
+}
+
+/// Returns what the components that connect to an endpoint may send through it.
+fn accepted(endpoint: Endpoint) -> Policy {
+    match endpoint {
+        Endpoint::Robot(_) => {
+            |role, frame| permits::<RobotMsg>(role, frame) || permits::<Handshake>(role, frame)
+        }
+        Endpoint::RobotOrders(_) => permits::<RobotMsg>,
+        Endpoint::RobotAdmin(_) => permits::<AdminMsg>,
+        Endpoint::Screen(_) => permits::<ScreenMsg>,
+        Endpoint::Gateway => permits::<GatewayMsg>,
+        Endpoint::Federation(_) => permits::<FederationMsg>,
+    }
+}
+
+/// Returns what the component listening at an endpoint may answer through it, which is
+/// anything as long as it has the role expected there.
+fn answered(endpoint: Endpoint) -> Policy {
+    match endpoint {
+        Endpoint::Robot(_)
+        | Endpoint::RobotOrders(_)
+        | Endpoint::RobotAdmin(_)
+        | Endpoint::Federation(_) => |role, _| role == Role::Robot,
+        Endpoint::Screen(_) => |role, _| role == Role::Screen,
+        Endpoint::Gateway => |role, _| role == Role::Gateway,
+    }
+}
+
+impl RingTransport for AuthTransport {
+    fn listen(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Box<dyn Listener>>> {
+        Box::pin(async move {
+            let listener = self.inner.listen(endpoint).await?;
+            Ok(Box::new(AuthListener {
+                listener,
+                keyring: self.keyring.clone(),
+                policy: accepted(endpoint),
+                endpoint,
+            }) as Box<dyn Listener>)
+        })
+    }
+
+    fn connect(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Connection>> {
+        Box::pin(async move {
+            let connection = self.inner.connect(endpoint).await?;
+            let side = Side::Opener(endpoint);
+            Ok(wrap(connection, &self.keyring, answered(endpoint), side))
+        })
+    }
+}
+
+impl Listener for AuthListener {
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<Connection>> {
+        Box::pin(async move {
+            let connection = self.listener.accept().await?;
+            let side = Side::Listener(self.endpoint);
+            Ok(wrap(connection, &self.keyring, self.policy, side))
+        })
+    }
+}
+
+impl FrameSender for SealingSender {
+    fn send<'a>(&'a mut self, frame: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let signed = match self.side {
+                Side::Opener(endpoint) => {
+                    let mut answers = lock(&self.answers);
+                    match *answers {
+                        Some(_) => self.keyring.seal(frame, Route::To(endpoint)),
+                        None => {
+                            let (signed, route) = self.keyring.seal_request(frame, endpoint);
+                            *answers = Some(route);
+                            signed
+                        }
+                    }
+                }
+                Side::Listener(_) => {
+                    let route = lock(&self.answers).ok_or_else(unanswerable)?;
+                    self.keyring.seal(frame, route)
+                }
+            };
+
+            self.tx.send(&signed).await
+        })
+    }
+}
+
+impl FrameReceiver for OpeningReceiver {
+    fn recv(&mut self) -> BoxFuture<'_, io::Result<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            let Some(signed) = self.rx.recv().await? else {
+                return Ok(None);
+            };
+
+            let route = match self.side {
+                Side::Opener(_) => lock(&self.answers).ok_or_else(unanswerable)?,
+                Side::Listener(endpoint) => Route::To(endpoint),
+            };
+            let opened = self
+                .keyring
+                .open(&signed, route)
+                .map_err(|e| io::Error::new(io::ErrorKind::PermissionDenied, e))?;
+
+            if !(self.policy)(opened.role, &opened.frame) {
+                let error = AuthError::Forbidden(opened.role);
+                error.count();
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, error));
+            }
+
+            if let Side::Listener(_) = self.side {
+                lock(&self.answers).get_or_insert(Route::Back(opened.nonce));
+            }
+
+            Ok(Some(opened.frame))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::ChannelTransport;
+    use ice_cream_shop::{
+        messages::{fmt_msg, protocol::decode},
+        orders::{Order, OrderId},
+    };
+    use std::collections::HashMap;
+
+    fn transport(network: &ChannelTransport, role: Role) -> AuthTransport {
+        let inner = SharedTransport::new(network.clone());
+        AuthTransport::new(inner, Keyring::new("secret", role))
+    }
+
+    #[tokio::test]
+    async fn test01_components_with_the_key_talk_as_usual() {
+        let network = ChannelTransport::default();
+        let robot = transport(&network, Role::Robot);
+        let screen = transport(&network, Role::Screen);
+
+        let mut listener = robot.listen(Endpoint::RobotOrders(0)).await.unwrap();
+        let mut client = screen.connect(Endpoint::RobotOrders(0)).await.unwrap();
+        let mut server = listener.accept().await.unwrap();
+
+        let order = Order::new(OrderId::new(0, 1), HashMap::new());
+        client
+            .tx
+            .send(&fmt_msg(RobotMsg::RecvOrder(order)))
+            .await
+            .unwrap();
+        let frame = server.rx.recv().await.unwrap().unwrap();
+
+        assert!(matches!(decode(&frame), Ok(RobotMsg::RecvOrder(_))));
+    }
+
+    #[tokio::test]
+    async fn test02_frames_without_the_key_close_the_connection() {
+        let network = ChannelTransport::default();
+        let screen = transport(&network, Role::Screen);
+
+        let mut listener = screen.listen(Endpoint::Screen(0)).await.unwrap();
+        let mut intruder = network.connect(Endpoint::Screen(0)).await.unwrap();
+        let mut server = listener.accept().await.unwrap();
+
+        intruder.tx.send(b"{\"ConfirmOrder\":[]}").await.unwrap();
+        let error = server.rx.recv().await.unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn test03_roles_can_not_send_what_other_roles_send() {
+        let network = ChannelTransport::default();
+        let robot = transport(&network, Role::Robot);
+        let screen = transport(&network, Role::Screen);
+
+        let mut listener = robot.listen(Endpoint::Robot(0)).await.unwrap();
+        let mut client = screen.connect(Endpoint::Robot(0)).await.unwrap();
+        let mut server = listener.accept().await.unwrap();
+
+        client.tx.send(&fmt_msg(RobotMsg::Link(1))).await.unwrap();
+        let error = server.rx.recv().await.unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn test04_frames_are_only_taken_by_the_endpoint_they_were_sent_to() {
+        let network = ChannelTransport::default();
+        let mut first = transport(&network, Role::Robot)
+            .listen(Endpoint::RobotOrders(0))
+            .await
+            .unwrap();
+        let mut second = transport(&network, Role::Robot)
+            .listen(Endpoint::RobotOrders(1))
+            .await
+            .unwrap();
+
+        let order = Order::new(OrderId::new(0, 1), HashMap::new());
+        let (signed, _) = Keyring::new("secret", Role::Screen).seal_request(
+            &fmt_msg(RobotMsg::RecvOrder(order)),
+            Endpoint::RobotOrders(0),
+        );
+
+        let mut client = network.connect(Endpoint::RobotOrders(0)).await.unwrap();
+        let mut server = first.accept().await.unwrap();
+        client.tx.send(&signed).await.unwrap();
+        assert!(server.rx.recv().await.unwrap().is_some());
+
+        let mut replay = network.connect(Endpoint::RobotOrders(1)).await.unwrap();
+        let mut victim = second.accept().await.unwrap();
+        replay.tx.send(&signed).await.unwrap();
+        let error = victim.rx.recv().await.unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+    }
+}
@@ -0,0 +1,154 @@
+use super::{BoxFuture, Connection, Endpoint, FrameReceiver, FrameSender, Listener, RingTransport};
+use ice_cream_shop::messages::protocol::BINARY_MARKER;
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// An in-memory transport for robots that run in the same process.
+/// Clones share the same endpoints, so every robot needs a clone of the same transport.
+#[derive(Debug, Clone, Default)]
+pub struct ChannelTransport {
+    listeners: Arc<Mutex<HashMap<Endpoint, UnboundedSender<Connection>>>>,
+}
+
+/// The sending half of an in-memory connection.
+#[derive(Debug)]
+struct ChannelSender(UnboundedSender<Vec<u8>>);
+
+/// The receiving half of an in-memory connection.
+#[derive(Debug)]
+struct ChannelReceiver(UnboundedReceiver<Vec<u8>>);
+
+/// The connections made to an in-memory endpoint.
+#[derive(Debug)]
+struct ChannelListener(UnboundedReceiver<Connection>);
+
+/// Creates both ends of an in-memory connection.
+fn pair() -> (Connection, Connection) {
+    let (a_tx, a_rx) = mpsc::unbounded_channel();
+    let (b_tx, b_rx) = mpsc::unbounded_channel();
+
+    let a = Connection {
+        tx: Box::new(ChannelSender(a_tx)),
+        rx: Box::new(ChannelReceiver(b_rx)),
+    };
+    let b = Connection {
+        tx: Box::new(ChannelSender(b_tx)),
+        rx: Box::new(ChannelReceiver(a_rx)),
+    };
+
+    (a, b)
+}
+
+impl FrameSender for ChannelSender {
+    fn send<'a>(&'a mut self, frame: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        let frame = match frame.first() {
+            Some(&BINARY_MARKER) => frame.to_vec(),
+            _ => frame.strip_suffix(b"\n").unwrap_or(frame).to_vec(),
+        };
+        let sent = self
+            .0
+            .send(frame)
+            .map_err(|_| io::ErrorKind::BrokenPipe.into());
+        Box::pin(async move { sent })
+    }
+}
+
+impl FrameReceiver for ChannelReceiver {
+    fn recv(&mut self) -> BoxFuture<'_, io::Result<Option<Vec<u8>>>> {
+        Box::pin(async move { Ok(self.0.recv().await) })
+    }
+}
+
+impl Listener for ChannelListener {
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<Connection>> {
+        Box::pin(async move {
+            self.0
+                .recv()
+                .await
+                .ok_or_else(|| io::ErrorKind::NotConnected.into())
+        })
+    }
+}
+
+impl RingTransport for ChannelTransport {
+    fn listen(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Box<dyn Listener>>> {
+        let mut listeners = self
+            .listeners
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let listener = match listeners.get(&endpoint) {
+            Some(listener) if !listener.is_closed() => Err(io::ErrorKind::AddrInUse.into()),
+            _ => {
+                let (tx, rx) = mpsc::unbounded_channel();
+                listeners.insert(endpoint, tx);
+                Ok(Box::new(ChannelListener(rx)) as Box<dyn Listener>)
+            }
+        };
+
+        Box::pin(async move { listener })
+    }
+
+    fn connect(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Connection>> {
+        let mut listeners = self
+            .listeners
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let (client, server) = pair();
+        let connection = match listeners.get(&endpoint).map(|tx| tx.send(server)) {
+            Some(Ok(())) => Ok(client),
+            Some(Err(_)) => {
+                listeners.remove(&endpoint);
+                Err(io::ErrorKind::ConnectionRefused.into())
+            }
+            None => Err(io::ErrorKind::ConnectionRefused.into()),
+        };
+
+        Box::pin(async move { connection })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test01_frames_go_both_ways() {
+        let transport = ChannelTransport::default();
+        let mut listener = transport.listen(Endpoint::Robot(0)).await.unwrap();
+
+        let mut client = transport.clone().connect(Endpoint::Robot(0)).await.unwrap();
+        let mut server = listener.accept().await.unwrap();
+
+        client.tx.send(b"ping\n").await.unwrap();
+        assert_eq!(server.rx.recv().await.unwrap(), Some(b"ping".to_vec()));
+
+        let binary = [BINARY_MARKER, 1, b'\n'];
+        client.tx.send(&binary).await.unwrap();
+        assert_eq!(server.rx.recv().await.unwrap(), Some(binary.to_vec()));
+
+        server.tx.send(b"pong").await.unwrap();
+        assert_eq!(client.rx.recv().await.unwrap(), Some(b"pong".to_vec()));
+
+        drop(server);
+        assert_eq!(client.rx.recv().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test02_nobody_listens_after_the_listener_is_dropped() {
+        let transport = ChannelTransport::default();
+        assert!(transport.connect(Endpoint::Gateway).await.is_err());
+
+        let listener = transport.listen(Endpoint::Gateway).await.unwrap();
+        assert!(transport.listen(Endpoint::Gateway).await.is_err());
+
+        drop(listener);
+        assert!(transport.connect(Endpoint::Gateway).await.is_err());
+        assert!(transport.listen(Endpoint::Gateway).await.is_ok());
+    }
+}
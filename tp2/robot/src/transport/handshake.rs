@@ -0,0 +1,155 @@
+use super::{Connection, FrameSender};
+use ice_cream_shop::messages::protocol::{decode, Codec, Encoding, Handshake};
+use serde::Serialize;
+use std::{io, time::Duration};
+use tokio::time;
+
+/// How long a robot waits for the robot it connected to to answer its hello, so that a robot
+/// that hung isn't waited for forever.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The sending half of a connection between robots, which encodes the messages as agreed on
+/// in the handshake.
+#[derive(Debug)]
+pub struct MsgSender {
+    tx: Box<dyn FrameSender>,
+    codec: Codec,
+}
+
+impl MsgSender {
+    /// Wraps the sending half of a connection.
+    ///
+    /// # Arguments
+    ///
+    /// * `tx` - The sending half.
+    /// * `codec` - How the messages are encoded.
+    pub fn new(tx: Box<dyn FrameSender>, codec: Codec) -> Self {
+        Self { tx, codec }
+    }
+
+    /// Returns how the messages are encoded.
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// Encodes and sends a message.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The message to send.
+    pub async fn send<M: Serialize>(&mut self, msg: M) -> io::Result<()> {
+        let frame = self.codec.encode(msg);
+        self.tx.send(&frame).await
+    }
+}
+
+/// Says hello through a connection that was just opened to a robot and waits for its answer.
+///
+/// # Arguments
+///
+/// * `connection` - The connection.
+/// * `encoding` - The encoding this robot prefers.
+///
+/// # Returns
+///
+/// The codec agreed on, or an error if the robot rejected the connection, closed it or didn't
+/// answer in time.
+pub async fn hello(connection: &mut Connection, encoding: Encoding) -> io::Result<Codec> {
+    let hello = Codec::BARE.encode(Handshake::hello(encoding));
+    connection.tx.send(&hello).await?;
+
+    let answer = time::timeout(HANDSHAKE_TIMEOUT, connection.rx.recv())
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??
+        .ok_or(io::ErrorKind::UnexpectedEof)?;
+
+    match decode(&answer) {
+        Ok(Handshake::Accept(codec)) => Ok(codec),
+        Ok(Handshake::Reject(reason)) => Err(io::Error::new(io::ErrorKind::Unsupported, reason)),
+        _ => Err(io::ErrorKind::InvalidData.into()),
+    }
+}
+
+/// Answers the hello of a robot that connected to this one.
+/// Robots that speak the first version of the protocol introduce themselves right away
+/// without a hello, so they are turned away.
+///
+/// # Arguments
+///
+/// * `connection` - The connection.
+///
+/// # Returns
+///
+/// The codec agreed on and the frame the robot introduced itself with, or an error if the
+/// connection was closed, the robot didn't say hello or there's nothing in common with it.
+pub async fn welcome(connection: &mut Connection) -> io::Result<(Codec, Vec<u8>)> {
+    let frame = connection
+        .rx
+        .recv()
+        .await?
+        .ok_or(io::ErrorKind::UnexpectedEof)?;
+
+    let Ok(hello) = decode::<Handshake>(&frame) else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "The robot didn't say hello, so it speaks version 1 of the protocol, which is no longer supported",
+        ));
+    };
+
+    let answer = hello.answer();
+    connection.tx.send(&Codec::BARE.encode(&answer)).await?;
+    let Handshake::Accept(codec) = answer else {
+        return Err(io::ErrorKind::Unsupported.into());
+    };
+
+    let introduction = connection
+        .rx
+        .recv()
+        .await?
+        .ok_or(io::ErrorKind::UnexpectedEof)?;
+
+    Ok((codec, introduction))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{ChannelTransport, Endpoint, RingTransport};
+    use ice_cream_shop::messages::{fmt_msg, protocol::PROTOCOL_VERSION, robot_msg::RobotMsg};
+
+    #[tokio::test]
+    async fn test01_robots_agree_on_the_encoding_of_the_one_that_connects() {
+        let transport = ChannelTransport::default();
+        let mut listener = transport.listen(Endpoint::Robot(0)).await.unwrap();
+        let mut client = transport.connect(Endpoint::Robot(0)).await.unwrap();
+        let mut server = listener.accept().await.unwrap();
+
+        let accepted = tokio::spawn(async move {
+            let (codec, frame) = welcome(&mut server).await.unwrap();
+            (codec, decode::<RobotMsg>(&frame).unwrap())
+        });
+
+        let codec = hello(&mut client, Encoding::Binary).await.unwrap();
+        let mut tx = MsgSender::new(client.tx, codec);
+        tx.send(RobotMsg::Link(1)).await.unwrap();
+
+        let (accepted_codec, introduction) = accepted.await.unwrap();
+        assert_eq!(codec.version, PROTOCOL_VERSION);
+        assert_eq!(codec.encoding, Encoding::Binary);
+        assert_eq!(accepted_codec, codec);
+        assert!(matches!(introduction, RobotMsg::Link(1)));
+    }
+
+    #[tokio::test]
+    async fn test02_robots_that_do_not_say_hello_are_turned_away() {
+        let transport = ChannelTransport::default();
+        let mut listener = transport.listen(Endpoint::Robot(0)).await.unwrap();
+        let mut client = transport.connect(Endpoint::Robot(0)).await.unwrap();
+        let mut server = listener.accept().await.unwrap();
+
+        client.tx.send(&fmt_msg(RobotMsg::Join(1))).await.unwrap();
+        let error = welcome(&mut server).await.unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+}
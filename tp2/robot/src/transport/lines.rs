@@ -0,0 +1,154 @@
+use super::{BoxFuture, Connection, FrameReceiver, FrameSender};
+use ice_cream_shop::messages::protocol::BINARY_MARKER;
+use std::{fmt::Debug, io};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+/// The longest frame a receiver accepts, in bytes. The length of a binary frame comes from the
+/// peer before it's authenticated, so it's checked before the frame is allocated.
+pub const MAX_FRAME: usize = 1 << 20;
+
+/// The write half of a byte stream, where every JSON frame is sent as a line and every binary
+/// frame is sent after its length.
+#[derive(Debug)]
+struct LineSender<W>(W);
+
+/// The read half of a byte stream, split in lines and binary frames.
+#[derive(Debug)]
+struct LineReceiver<R>(BufReader<R>);
+
+/// Splits a byte stream in the halves of a connection that sends a frame per line, or a
+/// frame after its length for binary frames.
+///
+/// # Arguments
+///
+/// * `stream` - The stream to split, e.g. a `TcpStream` or a `UnixStream`.
+pub fn connection<S>(stream: S) -> Connection
+where
+    S: AsyncRead + AsyncWrite + Send + Debug + 'static,
+{
+    let (rx, tx) = tokio::io::split(stream);
+
+    Connection {
+        tx: Box::new(LineSender(tx)),
+        rx: Box::new(LineReceiver(BufReader::new(rx))),
+    }
+}
+
+impl<W: AsyncWrite + Unpin + Send + Debug> FrameSender for LineSender<W> {
+    fn send<'a>(&'a mut self, frame: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            if let Some((&BINARY_MARKER, bytes)) = frame.split_first() {
+                let len = u32::try_from(bytes.len())
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Frame too long"))?;
+
+                self.0.write_all(&[BINARY_MARKER]).await?;
+                self.0.write_all(&len.to_be_bytes()).await?;
+                return self.0.write_all(bytes).await;
+            }
+
+            self.0.write_all(frame).await?;
+            if !frame.ends_with(b"\n") {
+                self.0.write_all(b"\n").await?;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+impl<R: AsyncRead + Unpin + Send + Debug> FrameReceiver for LineReceiver<R> {
+    fn recv(&mut self) -> BoxFuture<'_, io::Result<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            let Some(&first) = self.0.fill_buf().await?.first() else {
+                return Ok(None);
+            };
+
+            if first == BINARY_MARKER {
+                let mut len = [0; 4];
+                self.0.consume(1);
+                self.0.read_exact(&mut len).await?;
+
+                let len = u32::from_be_bytes(len) as usize;
+                if len > MAX_FRAME {
+                    return Err(too_long());
+                }
+
+                let mut frame = vec![0; len + 1];
+                frame[0] = BINARY_MARKER;
+                self.0.read_exact(&mut frame[1..]).await?;
+                return Ok(Some(frame));
+            }
+
+            let mut line = Vec::new();
+            let limit = MAX_FRAME as u64 + 1;
+            (&mut self.0)
+                .take(limit)
+                .read_until(b'\n', &mut line)
+                .await?;
+            if line.len() > MAX_FRAME && !line.ends_with(b"\n") {
+                return Err(too_long());
+            }
+
+            if line.ends_with(b"\n") {
+                line.pop();
+                if line.ends_with(b"\r") {
+                    line.pop();
+                }
+            }
+
+            Ok(Some(line))
+        })
+    }
+}
+
+/// The error returned for a frame longer than `MAX_FRAME`.
+fn too_long() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Frame too long")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test01_lines_and_binary_frames_are_received_whole() {
+        let (a, b) = tokio::io::duplex(64);
+        let mut a = connection(a);
+        let mut b = connection(b);
+        let binary = [BINARY_MARKER, b'\n', 0, 1, 2, b'\n'];
+
+        a.tx.send(b"{\"Heartbeat\":1}\n").await.unwrap();
+        a.tx.send(&binary).await.unwrap();
+        a.tx.send(b"\"Disconnect\"").await.unwrap();
+        drop(a);
+
+        assert_eq!(b.rx.recv().await.unwrap().unwrap(), b"{\"Heartbeat\":1}");
+        assert_eq!(b.rx.recv().await.unwrap().unwrap(), binary);
+        assert_eq!(b.rx.recv().await.unwrap().unwrap(), b"\"Disconnect\"");
+        assert_eq!(b.rx.recv().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test02_frames_longer_than_the_limit_are_refused() {
+        let (mut a, b) = tokio::io::duplex(64);
+        let mut b = connection(b);
+
+        let len = (MAX_FRAME as u32 + 1).to_be_bytes();
+        a.write_all(&[BINARY_MARKER]).await.unwrap();
+        a.write_all(&len).await.unwrap();
+
+        let err = b.rx.recv().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn test03_lines_longer_than_the_limit_are_refused() {
+        let (mut a, b) = tokio::io::duplex(MAX_FRAME * 2);
+        let mut b = connection(b);
+
+        a.write_all(&vec![b'a'; MAX_FRAME + 1]).await.unwrap();
+
+        let err = b.rx.recv().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
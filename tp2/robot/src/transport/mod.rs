@@ -0,0 +1,231 @@
+pub mod auth;
+pub mod channel;
+pub mod handshake;
+mod lines;
+pub mod record;
+pub mod tcp;
+pub mod unix;
+
+pub use auth::AuthTransport;
+pub use channel::ChannelTransport;
+pub use handshake::MsgSender;
+pub use record::RecordingTransport;
+pub use tcp::TcpTransport;
+pub use unix::UnixTransport;
+
+pub use ice_cream_shop::endpoint::Endpoint;
+
+use crate::record::Recorder;
+use ice_cream_shop::messages::auth::Keyring;
+use std::{
+    env, fmt::Debug, future::Future, io, ops::Deref, path::PathBuf, pin::Pin, str::FromStr,
+    sync::Arc,
+};
+
+/// Environment variable that selects the transport of the ring: `tcp`, `unix`, `unix:<dir>`
+/// or `channel`. TCP is used when it isn't set.
+pub const TRANSPORT_VAR: &str = "SHOP_TRANSPORT";
+
+/// A boxed future, so that the transport traits can be used as trait objects.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// The sending half of a connection. Every frame is a single message.
+pub trait FrameSender: Send + Debug {
+    /// Sends a frame to the other end of the connection.
+    ///
+    /// # Arguments
+    ///
+    /// * `frame` - The message to send.
+    fn send<'a>(&'a mut self, frame: &'a [u8]) -> BoxFuture<'a, io::Result<()>>;
+}
+
+/// The receiving half of a connection. Every frame is a single message.
+pub trait FrameReceiver: Send + Debug {
+    /// Receives the next frame from the other end of the connection.
+    ///
+    /// # Returns
+    ///
+    /// The next frame, or `None` if the other end closed the connection.
+    fn recv(&mut self) -> BoxFuture<'_, io::Result<Option<Vec<u8>>>>;
+}
+
+/// Accepts the connections made to an endpoint.
+pub trait Listener: Send + Debug {
+    /// Waits for the next connection.
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<Connection>>;
+}
+
+/// Both halves of a connection.
+#[derive(Debug)]
+pub struct Connection {
+    pub tx: Box<dyn FrameSender>,
+    pub rx: Box<dyn FrameReceiver>,
+}
+
+/// The network the robots use to talk to each other and to the screens.
+pub trait RingTransport: Send + Sync + Debug {
+    /// Starts listening for connections at the given endpoint.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint to listen at.
+    fn listen(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Box<dyn Listener>>>;
+
+    /// Connects to the given endpoint.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint to connect to.
+    fn connect(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Connection>>;
+}
+
+/// A transport shared by a robot and its tasks. It uses TCP by default.
+#[derive(Debug, Clone)]
+pub struct SharedTransport(Arc<dyn RingTransport>);
+
+impl SharedTransport {
+    /// Wraps the given transport so that it can be shared.
+    pub fn new(transport: impl RingTransport + 'static) -> Self {
+        Self(Arc::new(transport))
+    }
+
+    /// Signs and checks every frame sent through the transport if there's a keyring.
+    ///
+    /// # Arguments
+    ///
+    /// * `keyring` - The key of the shop and the role of this component, if any.
+    pub fn authenticated(self, keyring: Option<Keyring>) -> Self {
+        match keyring {
+            Some(keyring) => Self::new(AuthTransport::new(self, keyring)),
+            None => self,
+        }
+    }
+
+    /// Writes every message between robots that goes through the transport to a trace if
+    /// there's a recorder. It needs to be called after `authenticated`.
+    ///
+    /// # Arguments
+    ///
+    /// * `recorder` - The trace of the robot, if any.
+    pub fn recorded(self, recorder: Option<Recorder>) -> Self {
+        match recorder {
+            Some(recorder) => Self::new(RecordingTransport::new(self, recorder)),
+            None => self,
+        }
+    }
+}
+
+impl Default for SharedTransport {
+    fn default() -> Self {
+        Self::new(TcpTransport)
+    }
+}
+
+impl Deref for SharedTransport {
+    type Target = dyn RingTransport;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+/// The transports the robots can use to talk to each other.
+/// - `Tcp`: Every robot listens on its own port of localhost.
+/// - `Unix`: Every robot listens on a Unix domain socket in the given directory.
+/// - `Channel`: The robots run in the same process and talk through in-memory channels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportConfig {
+    Tcp,
+    Unix(PathBuf),
+    Channel,
+}
+
+/// A transport that sends the connections between robots through `ring` and the rest of
+/// them through TCP, since the screens and the gateway always use TCP.
+#[derive(Debug)]
+struct RingOnly {
+    ring: SharedTransport,
+    edges: SharedTransport,
+}
+
+impl TransportConfig {
+    /// Reads the transport from `SHOP_TRANSPORT`.
+    ///
+    /// # Returns
+    ///
+    /// The selected transport, TCP if the variable isn't set, or an error if it has an unknown value.
+    pub fn from_env() -> Result<Self, &'static str> {
+        match env::var(TRANSPORT_VAR) {
+            Ok(value) => value.parse(),
+            Err(_) => Ok(Self::Tcp),
+        }
+    }
+
+    /// Builds the selected transport. Only the connections between robots change, the ones
+    /// with the screens and the gateway always go through TCP.
+    /// Every call to `build` with `Channel` creates a separate in-memory network, so the robots
+    /// of the same ring need clones of the same transport.
+    pub fn build(&self) -> SharedTransport {
+        let ring = match self {
+            Self::Tcp => return SharedTransport::default(),
+            Self::Unix(dir) => SharedTransport::new(UnixTransport::new(dir)),
+            Self::Channel => SharedTransport::new(ChannelTransport::default()),
+        };
+
+        SharedTransport::new(RingOnly {
+            ring,
+            edges: SharedTransport::default(),
+        })
+    }
+}
+
+impl FromStr for TransportConfig {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            None if value == "tcp" => Ok(Self::Tcp),
+            None if value == "channel" => Ok(Self::Channel),
+            None if value == "unix" => Ok(Self::Unix(env::temp_dir().join("ice_cream_shop"))),
+            Some(("unix", dir)) if !dir.is_empty() => Ok(Self::Unix(PathBuf::from(dir))),
+            _ => Err("SHOP_TRANSPORT needs to be tcp, unix, unix:<dir> or channel"),
+        }
+    }
+}
+
+impl RingOnly {
+    /// Returns the transport used to reach the given endpoint.
+    fn route(&self, endpoint: Endpoint) -> &SharedTransport {
+        match endpoint {
+            Endpoint::Robot(_) => &self.ring,
+            _ => &self.edges,
+        }
+    }
+}
+
+impl RingTransport for RingOnly {
+    fn listen(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Box<dyn Listener>>> {
+        self.route(endpoint).listen(endpoint)
+    }
+
+    fn connect(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Connection>> {
+        self.route(endpoint).connect(endpoint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test01_the_transport_is_parsed_from_its_name() {
+        assert_eq!("tcp".parse(), Ok(TransportConfig::Tcp));
+        assert_eq!("channel".parse(), Ok(TransportConfig::Channel));
+        assert_eq!(
+            "unix:/tmp/ring".parse(),
+            Ok(TransportConfig::Unix(PathBuf::from("/tmp/ring")))
+        );
+        assert!("unix:".parse::<TransportConfig>().is_err());
+        assert!("udp".parse::<TransportConfig>().is_err());
+    }
+}
@@ -0,0 +1,210 @@
+use super::{
+    BoxFuture, Connection, Endpoint, FrameReceiver, FrameSender, Listener, RingTransport,
+    SharedTransport,
+};
+use crate::record::{Channel, Direction, Recorder};
+use ice_cream_shop::messages::{protocol::decode, robot_msg::RobotMsg};
+use std::{
+    io,
+    sync::{Arc, OnceLock},
+};
+
+/// A transport that writes every `RobotMsg` that goes through the connections of a robot to
+/// its trace. It needs to wrap the authenticated transport, so that it sees the messages
+/// instead of the signed frames.
+#[derive(Debug)]
+pub struct RecordingTransport {
+    inner: SharedTransport,
+    recorder: Recorder,
+}
+
+/// The sending half of a recorded connection.
+#[derive(Debug)]
+struct RecordingSender {
+    tx: Box<dyn FrameSender>,
+    recorder: Recorder,
+    channel: Channel,
+    peer: Arc<OnceLock<u16>>,
+}
+
+/// The receiving half of a recorded connection. The robot at the other end of a connection
+/// it didn't open is learned from the `Join` or `Link` it introduces itself with.
+#[derive(Debug)]
+struct RecordingReceiver {
+    rx: Box<dyn FrameReceiver>,
+    recorder: Recorder,
+    channel: Channel,
+    peer: Arc<OnceLock<u16>>,
+}
+
+/// The connections made to a recorded endpoint.
+#[derive(Debug)]
+struct RecordingListener {
+    listener: Box<dyn Listener>,
+    recorder: Recorder,
+    channel: Channel,
+}
+
+impl RecordingTransport {
+    /// Wraps a transport so that the messages of a robot are recorded.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The transport that carries the frames.
+    /// * `recorder` - The trace of the robot.
+    pub fn new(inner: SharedTransport, recorder: Recorder) -> Self {
+        Self { inner, recorder }
+    }
+}
+
+/// Wraps both halves of a connection so that it records every message.
+fn wrap(
+    connection: Connection,
+    recorder: &Recorder,
+    channel: Channel,
+    peer: Option<u16>,
+) -> Connection {
+    let known = OnceLock::new();
+    if let Some(peer) = peer {
+        let _ = known.set(peer);
+    }
+
+    let peer = Arc::new(known);
+    Connection {
+        tx: Box::new(RecordingSender {
+            tx: connection.tx,
+            recorder: recorder.clone(),
+            channel,
+            peer: peer.clone(),
+        }),
+        rx: Box::new(RecordingReceiver {
+            rx: connection.rx,
+            recorder: recorder.clone(),
+            channel,
+            peer,
+        }),
+    }
+}
+
+impl RingTransport for RecordingTransport {
+    fn listen(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Box<dyn Listener>>> {
+        Box::pin(async move {
+            let listener = self.inner.listen(endpoint).await?;
+            let channel = match endpoint {
+                Endpoint::Robot(_) => Channel::Prev,
+                Endpoint::RobotOrders(_) => Channel::Orders,
+                _ => return Ok(listener),
+            };
+
+            Ok(Box::new(RecordingListener {
+                listener,
+                recorder: self.recorder.clone(),
+                channel,
+            }) as Box<dyn Listener>)
+        })
+    }
+
+    fn connect(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Connection>> {
+        Box::pin(async move {
+            let connection = self.inner.connect(endpoint).await?;
+            match endpoint {
+                Endpoint::Robot(id) => {
+                    Ok(wrap(connection, &self.recorder, Channel::Next, Some(id)))
+                }
+                _ => Ok(connection),
+            }
+        })
+    }
+}
+
+impl Listener for RecordingListener {
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<Connection>> {
+        Box::pin(async move {
+            let connection = self.listener.accept().await?;
+            Ok(wrap(connection, &self.recorder, self.channel, None))
+        })
+    }
+}
+
+impl FrameSender for RecordingSender {
+    fn send<'a>(&'a mut self, frame: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            self.tx.send(frame).await?;
+            if let Ok(msg) = decode::<RobotMsg>(frame) {
+                let peer = self.peer.get().copied();
+                self.recorder
+                    .record(Direction::Out, self.channel, peer, msg);
+            }
+
+            Ok(())
+        })
+    }
+}
+
+impl FrameReceiver for RecordingReceiver {
+    fn recv(&mut self) -> BoxFuture<'_, io::Result<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            let frame = self.rx.recv().await?;
+            if let Some(Ok(msg)) = frame.as_deref().map(decode::<RobotMsg>) {
+                if let RobotMsg::Join(id) | RobotMsg::Link(id) = msg {
+                    let _ = self.peer.set(id);
+                }
+
+                let peer = self.peer.get().copied();
+                self.recorder.record(Direction::In, self.channel, peer, msg);
+            }
+
+            Ok(frame)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        record::read_trace,
+        transport::{handshake, ChannelTransport, MsgSender},
+    };
+    use ice_cream_shop::messages::protocol::Encoding;
+    use std::{env, fs, process};
+
+    #[tokio::test]
+    async fn test01_the_messages_between_robots_are_recorded_with_their_peer() {
+        let path = env::temp_dir().join(format!("record-test01-{}.jsonl", process::id()));
+        let network = SharedTransport::new(ChannelTransport::default());
+        let recorder = Recorder::create(&path).unwrap();
+        let recorded = SharedTransport::new(RecordingTransport::new(network.clone(), recorder));
+
+        let mut listener = recorded.listen(Endpoint::Robot(0)).await.unwrap();
+        let mut prev = network.connect(Endpoint::Robot(0)).await.unwrap();
+        let mut server = listener.accept().await.unwrap();
+
+        let introduce = async move {
+            let codec = handshake::hello(&mut prev, Encoding::Json).await.unwrap();
+            let mut prev_tx = MsgSender::new(prev.tx, codec);
+            prev_tx.send(RobotMsg::Link(2)).await.unwrap();
+            (prev_tx, prev.rx)
+        };
+        let (_prev, welcome) = tokio::join!(introduce, handshake::welcome(&mut server));
+        let (codec, _) = welcome.unwrap();
+
+        let mut server_tx = MsgSender::new(server.tx, codec);
+        server_tx.send(RobotMsg::Heartbeat(0)).await.unwrap();
+
+        let trace = read_trace(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let seen: Vec<_> = trace
+            .iter()
+            .map(|recorded| (recorded.direction, recorded.channel, recorded.peer))
+            .collect();
+        assert_eq!(
+            seen,
+            vec![
+                (Direction::In, Channel::Prev, Some(2)),
+                (Direction::Out, Channel::Prev, Some(2)),
+            ]
+        );
+    }
+}
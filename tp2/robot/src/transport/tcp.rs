@@ -0,0 +1,50 @@
+use super::{lines, BoxFuture, Connection, Endpoint, Listener, RingTransport};
+use ice_cream_shop::{
+    id_to_addr,
+    shop_values::{
+        FEDERATION_STARTING_PORT, GATEWAY_PORT, ROBOT_ADMIN_STARTING_PORT,
+        ROBOT_SCREEN_STARTING_PORT, ROBOT_STARTING_PORT, SCREEN_STARTING_PORT,
+    },
+};
+use std::io;
+use tokio::net::{TcpListener, TcpStream};
+
+/// A transport over TCP on localhost, where every endpoint has its own port.
+/// Frames are sent as lines.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpTransport;
+
+/// A listener of TCP connections.
+#[derive(Debug)]
+struct TcpAcceptor(TcpListener);
+
+/// Returns the address of the given endpoint.
+fn addr(endpoint: Endpoint) -> String {
+    match endpoint {
+        Endpoint::Robot(id) => id_to_addr(ROBOT_STARTING_PORT, id),
+        Endpoint::RobotOrders(id) => id_to_addr(ROBOT_SCREEN_STARTING_PORT, id),
+        Endpoint::RobotAdmin(id) => id_to_addr(ROBOT_ADMIN_STARTING_PORT, id),
+        Endpoint::Screen(id) => id_to_addr(SCREEN_STARTING_PORT, id),
+        Endpoint::Gateway => id_to_addr(GATEWAY_PORT, 0),
+        Endpoint::Federation(shop) => id_to_addr(FEDERATION_STARTING_PORT, shop),
+    }
+}
+
+impl Listener for TcpAcceptor {
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<Connection>> {
+        Box::pin(async move { Ok(lines::connection(self.0.accept().await?.0)) })
+    }
+}
+
+impl RingTransport for TcpTransport {
+    fn listen(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Box<dyn Listener>>> {
+        Box::pin(async move {
+            let listener = TcpListener::bind(addr(endpoint)).await?;
+            Ok(Box::new(TcpAcceptor(listener)) as Box<dyn Listener>)
+        })
+    }
+
+    fn connect(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Connection>> {
+        Box::pin(async move { Ok(lines::connection(TcpStream::connect(addr(endpoint)).await?)) })
+    }
+}
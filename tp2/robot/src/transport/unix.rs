@@ -0,0 +1,114 @@
+use super::{lines, BoxFuture, Connection, Endpoint, Listener, RingTransport};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+use tokio::net::{UnixListener, UnixStream};
+
+/// A transport over Unix domain sockets, where every endpoint is a socket file in the same
+/// directory. Frames are sent as lines.
+#[derive(Debug, Clone)]
+pub struct UnixTransport {
+    dir: PathBuf,
+}
+
+/// A listener of Unix socket connections.
+#[derive(Debug)]
+struct UnixAcceptor(UnixListener);
+
+impl UnixTransport {
+    /// Creates a transport whose sockets live in the given directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory of the sockets. It is created when the first endpoint listens.
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Returns the path of the socket of the given endpoint.
+    fn path(&self, endpoint: Endpoint) -> PathBuf {
+        let name = match endpoint {
+            Endpoint::Robot(id) => format!("robot-{id}.sock"),
+            Endpoint::RobotOrders(id) => format!("robot-orders-{id}.sock"),
+            Endpoint::RobotAdmin(id) => format!("robot-admin-{id}.sock"),
+            Endpoint::Screen(id) => format!("screen-{id}.sock"),
+            Endpoint::Gateway => "gateway.sock".to_string(),
+            Endpoint::Federation(shop) => format!("federation-{shop}.sock"),
+        };
+
+        self.dir.join(name)
+    }
+}
+
+impl Listener for UnixAcceptor {
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<Connection>> {
+        Box::pin(async move { Ok(lines::connection(self.0.accept().await?.0)) })
+    }
+}
+
+impl RingTransport for UnixTransport {
+    /// Binds the socket of the endpoint. A socket file left by a previous run is
+    /// removed first, unless someone is still listening on it.
+    fn listen(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Box<dyn Listener>>> {
+        Box::pin(async move {
+            let path = self.path(endpoint);
+            fs::create_dir_all(&self.dir)?;
+            if path.exists() {
+                if UnixStream::connect(&path).await.is_ok() {
+                    return Err(io::ErrorKind::AddrInUse.into());
+                }
+
+                fs::remove_file(&path)?;
+            }
+
+            let listener = UnixListener::bind(path)?;
+            Ok(Box::new(UnixAcceptor(listener)) as Box<dyn Listener>)
+        })
+    }
+
+    fn connect(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Connection>> {
+        Box::pin(async move {
+            Ok(lines::connection(
+                UnixStream::connect(self.path(endpoint)).await?,
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test01_frames_go_both_ways() {
+        let dir = std::env::temp_dir().join(format!("shop-unix-{}", std::process::id()));
+        let transport = UnixTransport::new(&dir);
+        let mut listener = transport.listen(Endpoint::Robot(1)).await.unwrap();
+
+        let mut client = transport.connect(Endpoint::Robot(1)).await.unwrap();
+        let mut server = listener.accept().await.unwrap();
+
+        client.tx.send(b"ping").await.unwrap();
+        assert_eq!(server.rx.recv().await.unwrap(), Some(b"ping".to_vec()));
+
+        server.tx.send(b"pong\n").await.unwrap();
+        assert_eq!(client.rx.recv().await.unwrap(), Some(b"pong".to_vec()));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test02_a_stale_socket_is_replaced() {
+        let dir = std::env::temp_dir().join(format!("shop-unix-stale-{}", std::process::id()));
+        let transport = UnixTransport::new(&dir);
+
+        drop(transport.listen(Endpoint::Gateway).await.unwrap());
+        let _listener = transport.listen(Endpoint::Gateway).await.unwrap();
+        assert!(transport.listen(Endpoint::Gateway).await.is_err());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,55 @@
+use ice_cream_shop::trace::trace_context::now_us;
+use tokio::time::Instant;
+
+/// The wall clock a robot reads the time from, counted from the instant it was started, so
+/// that it follows the clock of the runtime. A replay pauses that clock and starts it at the
+/// time the trace starts, so the robot sees the times it saw when it recorded it.
+/// - `start`: The instant the clock was started.
+/// - `start_us`: The time at that instant, in microseconds since the unix epoch.
+#[derive(Debug, Clone, Copy)]
+pub struct WallClock {
+    start: Instant,
+    start_us: u64,
+}
+
+impl WallClock {
+    /// Starts a clock at the given time.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_us` - The time the clock starts at, in microseconds since the unix epoch.
+    pub fn starting_at(start_us: u64) -> Self {
+        Self {
+            start: Instant::now(),
+            start_us,
+        }
+    }
+
+    /// Returns the time of the clock, in microseconds since the unix epoch.
+    pub fn now_us(&self) -> u64 {
+        self.start_us + self.start.elapsed().as_micros() as u64
+    }
+}
+
+/// Starts a clock at the current time of the system.
+impl Default for WallClock {
+    fn default() -> Self {
+        Self::starting_at(now_us())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time;
+
+    #[tokio::test(start_paused = true)]
+    async fn test01_the_clock_follows_the_clock_of_the_runtime() {
+        let clock = WallClock::starting_at(1_000);
+        assert_eq!(clock.now_us(), 1_000);
+
+        time::advance(Duration::from_millis(2)).await;
+        assert_eq!(clock.now_us(), 3_000);
+    }
+}
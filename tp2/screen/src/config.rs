@@ -0,0 +1,46 @@
+use ice_cream_shop::{
+    messages::auth::{Keyring, Role},
+    stock::StockCheck,
+};
+use std::{env, time::Duration};
+
+/// Environment variable with how many seconds a screen waits for an order to end before it
+/// cancels its payment. Screens wait forever when it isn't set.
+pub const ORDER_TIMEOUT_VAR: &str = "SHOP_ORDER_TIMEOUT";
+
+/// How a screen behaves, besides its id.
+/// - `keyring`: The keyring the screen signs and checks its messages with, if the shop has a key.
+/// - `stock_check`: What the screen does with orders that ask for more servings than are left.
+/// - `order_timeout`: How long the screen waits for an order to end, if it gives up on them.
+#[derive(Debug, Clone, Default)]
+pub struct ScreenConfig {
+    pub keyring: Option<Keyring>,
+    pub stock_check: StockCheck,
+    pub order_timeout: Option<Duration>,
+}
+
+impl ScreenConfig {
+    /// Reads the configuration from the `SHOP_KEY_*` variables, `SHOP_STOCK_CHECK` and `SHOP_ORDER_TIMEOUT`.
+    ///
+    /// # Returns
+    ///
+    /// The configuration, or an error if any of the variables has an invalid value.
+    pub fn from_env() -> Result<Self, &'static str> {
+        Ok(Self {
+            keyring: Keyring::from_env(Role::Screen)?,
+            stock_check: StockCheck::from_env()?,
+            order_timeout: order_timeout_from_env()?,
+        })
+    }
+}
+
+/// Reads how long a screen waits for an order to end from `SHOP_ORDER_TIMEOUT`.
+fn order_timeout_from_env() -> Result<Option<Duration>, &'static str> {
+    match env::var(ORDER_TIMEOUT_VAR) {
+        Ok(value) => match value.parse::<f64>() {
+            Ok(seconds) if seconds > 0.0 => Ok(Some(Duration::from_secs_f64(seconds))),
+            _ => Err("SHOP_ORDER_TIMEOUT needs to be a positive number of seconds"),
+        },
+        Err(_) => Ok(None),
+    }
+}
@@ -0,0 +1,648 @@
+use crate::{expire_orders, receiver_with, screen::Screen};
+use ice_cream_shop::{
+    flavour::Flavour,
+    messages::screen_msg::Admission,
+    metrics::{registry, shop_metrics::SCREEN_ORDERS},
+    orders::{ClientOrder, Order},
+    shop_values::GATEWAY_PORT,
+    stock::StockCheck,
+    trace::TraceContext,
+};
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, BufRead, BufReader, Read, Take, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+/// How long the server waits for a client to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The largest body a request may have.
+const MAX_BODY: usize = 64 * 1024;
+
+/// The largest head a request may have, counting its request line and its headers.
+const MAX_HEAD: u64 = 8 * 1024;
+
+/// How many orders `GET /orders` lists.
+const RECENT_ORDERS: usize = 50;
+
+/// How many orders the server remembers. The oldest ones that ended are forgotten first.
+const KEPT_ORDERS: usize = 1000;
+
+/// How an order sent through the API is doing.
+/// - `Pending`: A robot took the order and didn't say how it ended yet.
+/// - `Confirmed`: A robot delivered the order.
+/// - `Cancelled`: A robot cancelled the order because there wasn't enough ice cream.
+/// - `TimedOut`: The screen gave up on the order because it didn't end before its deadline.
+/// - `Withdrawn`: The customer withdrew the order before it ended.
+/// - `Rejected`: The gateway rejected the payment.
+/// - `Busy`: The shop was too busy to take the order.
+/// - `OutOfStock`: The order asked for more servings than the robots last said were left.
+/// - `Undelivered`: The screen couldn't reach the gateway or any robot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    Pending,
+    Confirmed,
+    Cancelled,
+    TimedOut,
+    Withdrawn,
+    Rejected,
+    Busy,
+    OutOfStock,
+    Undelivered,
+}
+
+/// What the API tells about an order.
+/// - `number`: The number of the order in the screen.
+/// - `flavours`: The servings of every flavour the order asks for.
+/// - `status`: How the order is doing.
+/// - `wait_secs`: How long the robots expected the order to take when they took it.
+#[derive(Debug, Clone, Serialize)]
+pub struct OrderRecord {
+    pub number: usize,
+    pub flavours: HashMap<Flavour, usize>,
+    pub status: OrderStatus,
+    pub wait_secs: Option<f64>,
+}
+
+/// The orders sent through the API, by number.
+#[derive(Debug, Default)]
+pub struct OrderBook {
+    records: BTreeMap<usize, OrderRecord>,
+    next: usize,
+}
+
+/// What a request asks for.
+#[derive(Debug, PartialEq, Eq)]
+enum Route {
+    Submit,
+    List,
+    Status(usize),
+    Withdraw(usize),
+    MethodNotAllowed,
+    NotFound,
+}
+
+/// An HTTP request, with only the parts the API looks at.
+#[derive(Debug)]
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+/// An HTTP response with a JSON body.
+/// - `code`: The status code.
+/// - `body`: The body.
+/// - `retry_after`: How long the client should wait before trying again, if it should.
+#[derive(Debug)]
+struct Response {
+    code: u16,
+    body: String,
+    retry_after: Option<Duration>,
+}
+
+/// The state the connections of the API share.
+/// - `screen`: The screen the orders go through.
+/// - `gateway`: The connection to the gateway, used by a single request at a time.
+/// - `book`: The orders sent through the API.
+struct Api {
+    screen: Screen,
+    gateway: Mutex<TcpStream>,
+    book: Arc<Mutex<OrderBook>>,
+}
+
+impl OrderBook {
+    /// Records a new pending order.
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - The order of the client.
+    ///
+    /// # Returns
+    ///
+    /// The number the order gets in the screen.
+    pub fn open(&mut self, order: &ClientOrder) -> usize {
+        let number = self.next;
+        self.next += 1;
+        self.records.insert(
+            number,
+            OrderRecord {
+                number,
+                flavours: order.flavours.clone(),
+                status: OrderStatus::Pending,
+                wait_secs: None,
+            },
+        );
+
+        if self.records.len() > KEPT_ORDERS {
+            let ended = self
+                .records
+                .values()
+                .find(|record| record.status != OrderStatus::Pending)
+                .map(|record| record.number);
+            if let Some(number) = ended {
+                self.records.remove(&number);
+            }
+        }
+
+        number
+    }
+
+    /// Records how a pending order ended. Orders that already ended keep their status.
+    ///
+    /// # Arguments
+    ///
+    /// * `number` - The number of the order.
+    /// * `status` - How the order ended.
+    pub fn end(&mut self, number: usize, status: OrderStatus) {
+        if let Some(record) = self.records.get_mut(&number) {
+            if record.status == OrderStatus::Pending {
+                record.status = status;
+            }
+        }
+    }
+
+    /// Records how long the robots expect a pending order to take.
+    pub fn expect(&mut self, number: usize, wait: Duration) {
+        if let Some(record) = self.records.get_mut(&number) {
+            record.wait_secs = Some(wait.as_secs_f64());
+        }
+    }
+
+    /// Returns what the API tells about an order, if it remembers it.
+    pub fn get(&self, number: usize) -> Option<&OrderRecord> {
+        self.records.get(&number)
+    }
+
+    /// Returns the latest orders, the newest last.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - How many orders to return at most.
+    pub fn recent(&self, count: usize) -> Vec<&OrderRecord> {
+        let skip = self.records.len().saturating_sub(count);
+        self.records.values().skip(skip).collect()
+    }
+}
+
+impl Route {
+    /// Finds what a request asks for from its method and path.
+    fn of(method: &str, path: &str) -> Self {
+        let segments: Vec<_> = path.trim_matches('/').split('/').collect();
+
+        match segments.as_slice() {
+            ["orders"] => match method {
+                "POST" => Self::Submit,
+                "GET" => Self::List,
+                _ => Self::MethodNotAllowed,
+            },
+            ["orders", number] => match (method, number.parse()) {
+                (_, Err(_)) => Self::NotFound,
+                ("GET", Ok(number)) => Self::Status(number),
+                ("DELETE", Ok(number)) => Self::Withdraw(number),
+                _ => Self::MethodNotAllowed,
+            },
+            _ => Self::NotFound,
+        }
+    }
+}
+
+impl Request {
+    /// Reads a request, with its body if it has a `Content-Length`.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Where the request is read from.
+    ///
+    /// # Returns
+    ///
+    /// The request, or the response that tells the client why it can't be read: `431` if
+    /// its head is too large and `400` if it's malformed or its body is too large.
+    fn read<R: BufRead>(reader: &mut R) -> Result<Self, Response> {
+        let mut head = reader.by_ref().take(MAX_HEAD);
+        let line = Self::read_line(&mut head)?;
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+            return Err(Response::error(400, "Malformed request"));
+        };
+        let (method, path) = (method.to_string(), path.to_string());
+
+        let mut length = 0;
+        loop {
+            let line = Self::read_line(&mut head)?;
+            if line.trim().is_empty() {
+                break;
+            }
+
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("content-length") {
+                    length = value
+                        .trim()
+                        .parse()
+                        .map_err(|_| Response::error(400, "Invalid Content-Length"))?;
+                }
+            }
+        }
+
+        if length > MAX_BODY {
+            return Err(Response::error(400, "Body too large"));
+        }
+
+        let mut body = vec![0; length];
+        reader
+            .read_exact(&mut body)
+            .map_err(|e| Response::error(400, e))?;
+        Ok(Self { method, path, body })
+    }
+
+    /// Reads a line of the head of a request, which is empty once the request ends.
+    ///
+    /// # Arguments
+    ///
+    /// * `head` - The request, limited to what's left of `MAX_HEAD`.
+    ///
+    /// # Returns
+    ///
+    /// The line, or `431` if it doesn't end before the head reaches `MAX_HEAD`.
+    fn read_line<R: BufRead>(head: &mut Take<R>) -> Result<String, Response> {
+        let mut line = String::new();
+        head.read_line(&mut line)
+            .map_err(|e| Response::error(400, e))?;
+        if head.limit() == 0 && !line.ends_with('\n') {
+            return Err(Response::error(431, "Request header fields too large"));
+        }
+        Ok(line)
+    }
+}
+
+impl Response {
+    /// Creates a response with the given value as its body.
+    fn json(code: u16, value: impl Serialize) -> Self {
+        Self {
+            code,
+            body: serde_json::to_string(&value).unwrap_or_default(),
+            retry_after: None,
+        }
+    }
+
+    /// Creates a response that tells the client what went wrong.
+    fn error(code: u16, message: impl ToString) -> Self {
+        Self::json(code, json!({ "error": message.to_string() }))
+    }
+
+    /// Tells the client how long to wait before trying again.
+    fn retry_after(mut self, wait: Duration) -> Self {
+        self.retry_after = Some(wait);
+        self
+    }
+
+    /// Writes the response.
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let reason = match self.code {
+            200 => "OK",
+            202 => "Accepted",
+            400 => "Bad Request",
+            402 => "Payment Required",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            431 => "Request Header Fields Too Large",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            _ => "",
+        };
+
+        let retry_after = self
+            .retry_after
+            .map(|wait| format!("Retry-After: {}\r\n", wait.as_secs_f64().ceil() as u64))
+            .unwrap_or_default();
+
+        let response = format!(
+            "HTTP/1.1 {} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{retry_after}Connection: close\r\n\r\n{}",
+            self.code,
+            self.body.len(),
+            self.body
+        );
+
+        writer.write_all(response.as_bytes())
+    }
+}
+
+impl Api {
+    /// Answers a request.
+    fn handle(&self, request: &Request) -> Response {
+        match Route::of(&request.method, &request.path) {
+            Route::Submit => self.submit(&request.body),
+            Route::List => {
+                Response::json(200, json!({ "orders": self.book().recent(RECENT_ORDERS) }))
+            }
+            Route::Status(number) => match self.book().get(number) {
+                Some(record) => Response::json(200, record),
+                None => Response::error(404, "Unknown order"),
+            },
+            Route::Withdraw(number) => self.withdraw(number),
+            Route::MethodNotAllowed => Response::error(405, "Method not allowed"),
+            Route::NotFound => Response::error(404, "Not found"),
+        }
+    }
+
+    /// Takes an order the same way the screen takes the orders of its file: it turns it away
+    /// while the shop is busy or if it asks for more than is left, validates its payment with
+    /// the gateway and hands it to the robots.
+    ///
+    /// # Arguments
+    ///
+    /// * `body` - The body of the request, with a `ClientOrder` in JSON.
+    ///
+    /// # Returns
+    ///
+    /// `202` with the order if a robot took it, or the error that tells why it wasn't taken.
+    fn submit(&self, body: &[u8]) -> Response {
+        let Ok(order) = serde_json::from_slice::<ClientOrder>(body) else {
+            return Response::error(400, "The body needs to be an order in JSON");
+        };
+
+        let number = self.book().open(&order);
+        self.take(order, number, &mut TraceContext::new())
+    }
+
+    /// Takes a new order, recording how it ended and finishing its trace if it wasn't handed
+    /// to the robots. Otherwise the trace is finished once a robot says how the order ended.
+    fn take(&self, order: ClientOrder, number: usize, trace: &mut TraceContext) -> Response {
+        if let Some(wait) = self.screen.busy() {
+            trace.finish();
+            return self.turn_away(number).retry_after(wait);
+        }
+
+        if let Some(shortage) = self.screen.shortage(&order) {
+            if self.screen.stock_check() == StockCheck::Reject {
+                registry().inc(SCREEN_ORDERS, &[("result", "out_of_stock")], 1);
+                self.book().end(number, OrderStatus::OutOfStock);
+                trace.finish();
+                return Response::error(409, format!("{shortage} (advisory)"));
+            }
+        }
+
+        let valid = self
+            .screen
+            .validate(&order, number, *trace, &mut *self.gateway());
+        trace.step("gateway_validation");
+
+        match valid {
+            Ok(true) => registry().inc(SCREEN_ORDERS, &[("result", "valid")], 1),
+            Ok(false) => {
+                registry().inc(SCREEN_ORDERS, &[("result", "invalid")], 1);
+                self.book().end(number, OrderStatus::Rejected);
+                trace.finish();
+                return Response::error(402, "The payment was rejected");
+            }
+            Err(_) => {
+                self.book().end(number, OrderStatus::Undelivered);
+                trace.finish();
+                return Response::error(502, "Couldn't reach the gateway");
+            }
+        }
+
+        let order = Order::from(order, self.screen.id, number).with_trace(*trace);
+        match self.screen.notify_order(order) {
+            Ok(Admission::Accepted(wait)) => {
+                self.book().expect(number, wait);
+                Response::json(202, self.book().get(number))
+            }
+            Ok(Admission::Busy(wait)) => {
+                self.release(number, *trace);
+                trace.finish();
+                self.turn_away(number).retry_after(wait)
+            }
+            Err(_) => {
+                self.release(number, *trace);
+                self.book().end(number, OrderStatus::Undelivered);
+                trace.finish();
+                Response::error(502, "Couldn't reach any robot")
+            }
+        }
+    }
+
+    /// Records that an order was turned away because the shop is busy.
+    fn turn_away(&self, number: usize) -> Response {
+        registry().inc(SCREEN_ORDERS, &[("result", "busy")], 1);
+        self.book().end(number, OrderStatus::Busy);
+        Response::error(503, "The shop is busy")
+    }
+
+    /// Withdraws a pending order, cancelling its payment. The robots may still scoop it,
+    /// but whatever they say about it is ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `number` - The number of the order.
+    ///
+    /// # Returns
+    ///
+    /// `200` with the order if it was withdrawn, `404` if it's unknown or `409` if it already ended.
+    fn withdraw(&self, number: usize) -> Response {
+        let Some(status) = self.book().get(number).map(|record| record.status) else {
+            return Response::error(404, "Unknown order");
+        };
+
+        if status != OrderStatus::Pending || !self.release(number, TraceContext::new()) {
+            return Response::error(409, "The order already ended");
+        }
+
+        println!("Order [{number}] withdrawn by its customer");
+        registry().inc(SCREEN_ORDERS, &[("result", "withdrawn")], 1);
+        self.book().end(number, OrderStatus::Withdrawn);
+        Response::json(200, self.book().get(number))
+    }
+
+    /// Gives up on an order and cancels its payment, unless a robot already said how it
+    /// ended or the screen already gave up on it.
+    ///
+    /// # Returns
+    ///
+    /// True if the screen gave up on the order.
+    fn release(&self, number: usize, trace: TraceContext) -> bool {
+        if !self.screen.withdraw(number) {
+            return false;
+        }
+
+        if let Err(e) = self.screen.cancel(number, trace, &mut self.gateway()) {
+            eprintln!("Couldn't cancel the payment of order [{number}]: {e}");
+        }
+        true
+    }
+
+    /// Locks the orders sent through the API, even if a thread panicked while holding them.
+    fn book(&self) -> MutexGuard<'_, OrderBook> {
+        lock(&self.book)
+    }
+
+    /// Locks the connection to the gateway, even if a thread panicked while holding it.
+    fn gateway(&self) -> MutexGuard<'_, TcpStream> {
+        self.gateway
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Locks the orders sent through the API, even if a thread panicked while holding them.
+fn lock(book: &Mutex<OrderBook>) -> MutexGuard<'_, OrderBook> {
+    book.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Answers a single request of a connection.
+fn respond(api: &Api, stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+
+    let response = match Request::read(&mut reader) {
+        Ok(request) => api.handle(&request),
+        Err(response) => response,
+    };
+
+    response.write(reader.get_mut())
+}
+
+/// Takes orders through HTTP instead of a file, at `http://127.0.0.1:<port>`. The orders
+/// take the same steps as the ones of a file, and the screen receives their results and
+/// times them out as usual. Every body is JSON:
+/// - `POST /orders`: Takes a `ClientOrder` and answers `202` with the order once a robot took
+///   it, `400` if it's malformed, `402` if the payment was rejected, `409` if it asks for more
+///   than is left, `503` with `Retry-After` if the shop is busy or `502` if the screen couldn't
+///   reach the gateway or any robot.
+/// - `GET /orders`: Lists the latest orders.
+/// - `GET /orders/<number>`: Tells how an order is doing.
+/// - `DELETE /orders/<number>`: Withdraws a pending order, cancelling its payment.
+///
+/// Any request whose request line and headers take more than `MAX_HEAD` bytes is answered
+/// with `431`.
+///
+/// # Arguments
+///
+/// * `screen` - The screen the orders go through.
+/// * `port` - The local port to listen on.
+///
+/// # Returns
+///
+/// An io::Result indicating if the port could be bound and the gateway reached.
+pub fn serve(screen: Screen, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(format!("127.0.0.1:{port}"))?;
+    let gateway = TcpStream::connect(format!("127.0.0.1:{GATEWAY_PORT}"))?;
+    let book = Arc::<Mutex<OrderBook>>::default();
+
+    {
+        let (screen, book) = (screen.clone(), book.clone());
+        thread::spawn(move || {
+            let ended = |number, confirmed| match confirmed {
+                true => lock(&book).end(number, OrderStatus::Confirmed),
+                false => lock(&book).end(number, OrderStatus::Cancelled),
+            };
+
+            if let Err(e) = receiver_with(screen, ended) {
+                eprintln!("{e}");
+            }
+        });
+    }
+
+    {
+        let (screen, book) = (screen.clone(), book.clone());
+        thread::spawn(move || {
+            let timed_out = |number| lock(&book).end(number, OrderStatus::TimedOut);
+            if let Err(e) = expire_orders(screen, timed_out) {
+                eprintln!("{e}");
+            }
+        });
+    }
+
+    let api = Arc::new(Api {
+        screen,
+        gateway: Mutex::new(gateway),
+        book,
+    });
+
+    println!("Taking orders at http://127.0.0.1:{port}/orders");
+    for stream in listener.incoming().flatten() {
+        let api = api.clone();
+        thread::spawn(move || {
+            if let Err(e) = respond(&api, stream) {
+                eprintln!("Couldn't answer a request: {e}");
+            }
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order() -> ClientOrder {
+        ClientOrder {
+            flavours: [(Flavour::Chocolate, 2)].into(),
+            card_number: "6666-1111-2222-3333".to_string(),
+        }
+    }
+
+    #[test]
+    fn test01_requests_are_routed_by_method_and_path() {
+        assert_eq!(Route::of("POST", "/orders"), Route::Submit);
+        assert_eq!(Route::of("GET", "/orders/"), Route::List);
+        assert_eq!(Route::of("GET", "/orders/7"), Route::Status(7));
+        assert_eq!(Route::of("DELETE", "/orders/7"), Route::Withdraw(7));
+        assert_eq!(Route::of("PUT", "/orders/7"), Route::MethodNotAllowed);
+        assert_eq!(Route::of("GET", "/orders/seven"), Route::NotFound);
+        assert_eq!(Route::of("GET", "/metrics"), Route::NotFound);
+    }
+
+    #[test]
+    fn test02_a_request_is_read_with_its_body() {
+        let raw = "POST /orders HTTP/1.1\r\nHost: screen\r\ncontent-length: 4\r\n\r\n{}{}";
+        let request = Request::read(&mut raw.as_bytes()).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/orders");
+        assert_eq!(request.body, b"{}{}");
+
+        let raw = format!(
+            "POST /orders HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY + 1
+        );
+        assert_eq!(Request::read(&mut raw.as_bytes()).unwrap_err().code, 400);
+    }
+
+    #[test]
+    fn test03_an_order_ends_only_once() {
+        let mut book = OrderBook::default();
+        let first = book.open(&order());
+        let second = book.open(&order());
+        assert_eq!((first, second), (0, 1));
+
+        book.end(first, OrderStatus::TimedOut);
+        book.end(first, OrderStatus::Confirmed);
+        assert_eq!(book.get(first).unwrap().status, OrderStatus::TimedOut);
+        assert_eq!(book.get(second).unwrap().status, OrderStatus::Pending);
+
+        let recent: Vec<_> = book.recent(1).iter().map(|record| record.number).collect();
+        assert_eq!(recent, vec![second]);
+    }
+
+    #[test]
+    fn test04_a_request_with_a_head_too_large_is_refused() {
+        let raw = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_HEAD as usize));
+        assert_eq!(Request::read(&mut raw.as_bytes()).unwrap_err().code, 431);
+
+        let header = format!("X-Padding: {}\r\n", "a".repeat(100));
+        let raw = format!("GET /orders HTTP/1.1\r\n{}\r\n", header.repeat(100));
+        assert_eq!(Request::read(&mut raw.as_bytes()).unwrap_err().code, 431);
+
+        let raw = format!("GET /orders HTTP/1.1\r\n{}\r\n", header.repeat(10));
+        assert_eq!(Request::read(&mut raw.as_bytes()).unwrap().path, "/orders");
+    }
+}
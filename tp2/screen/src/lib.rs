@@ -1,10 +1,19 @@
+pub mod config;
+pub mod http;
 pub mod screen;
 
 use ice_cream_shop::{
+    endpoint::Endpoint,
     id_to_addr,
-    messages::screen_msg::ScreenMsg,
+    messages::{
+        auth::{open, Route},
+        screen_msg::{Admission, ScreenMsg},
+    },
+    metrics::{registry, shop_metrics::SCREEN_ORDERS},
     orders::Order,
     shop_values::{GATEWAY_PORT, SCREEN_STARTING_PORT},
+    stock::StockCheck,
+    trace::TraceContext,
 };
 use screen::Screen;
 use std::{
@@ -12,10 +21,20 @@ use std::{
     fs::File,
     io::{self, BufRead, BufReader, Read},
     net::{TcpListener, TcpStream},
+    thread,
+    time::Duration,
 };
 
+/// How long a screen waits for a robot that connected to it to send its message.
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often a screen looks for orders whose deadline passed, at most.
+const EXPIRE_INTERVAL: Duration = Duration::from_millis(500);
+
 /// Function that receives messages from the gateway and different robots.
 /// It will commit or cancel orders depending on the message received.
+/// The results of orders the screen already gave up on, because they timed out or were
+/// withdrawn, are ignored, since their payment was cancelled and their customer was told.
 ///
 /// # Arguments
 ///
@@ -25,37 +44,76 @@ use std::{
 ///
 /// An io::Result indicating if the function was successful.
 pub fn receiver(screen: Screen) -> io::Result<()> {
+    receiver_with(screen, |_, _| {})
+}
+
+/// Same as `receiver`, but it also tells how every order ended.
+///
+/// # Arguments
+///
+/// * `screen` - The screen that will receive the messages.
+/// * `on_end` - Called with the number of every order that ends and whether it was confirmed.
+///
+/// # Returns
+///
+/// An io::Result indicating if the function was successful.
+pub fn receiver_with(screen: Screen, mut on_end: impl FnMut(usize, bool)) -> io::Result<()> {
     let mut gateway = TcpStream::connect(format!("127.0.0.1:{GATEWAY_PORT}"))?;
     let mut bytes = [0; 2048];
 
     let screen_ip = id_to_addr(SCREEN_STARTING_PORT, screen.id);
     let listener = TcpListener::bind(screen_ip)?;
 
+    let to_screen = Route::To(Endpoint::Screen(screen.id));
     for mut stream in listener.incoming().flatten() {
+        // A robot that hangs after connecting can't hold up the messages of the others.
+        let _ = stream.set_read_timeout(Some(RECEIVE_TIMEOUT));
         let Ok(n) = stream.read(&mut bytes) else {
             continue;
         };
 
         for line in bytes[..n].lines().map_while(Result::ok) {
-            match serde_json::from_str(&line) {
-                Ok(ScreenMsg::ConfirmOrder(order)) => {
+            match open(screen.keyring(), line.as_bytes(), to_screen) {
+                Ok(ScreenMsg::ConfirmOrder(order, _) | ScreenMsg::CancelOrder(order, _))
+                    if !screen.settle(order) =>
+                {
                     println!(
-                        "Order done: Screen {} - Order: {}",
+                        "Order ended after the screen gave up on it, ignored: Screen {} - Order: {}",
                         order.screen_id(),
                         order.order_number()
                     );
-                    screen.commit(order.order_number(), &mut gateway)?;
                 }
 
-                Ok(ScreenMsg::CancelOrder(order)) => {
+                Ok(ScreenMsg::ConfirmOrder(order, mut trace)) => {
                     println!(
-                        "Order canceled: Screen {} - Order: {}",
+                        "Order done: Screen {} - Order: {} ({trace})",
                         order.screen_id(),
                         order.order_number()
                     );
-                    screen.cancel(order.order_number(), &mut gateway)?;
+                    trace.step("robot_to_screen");
+                    registry().inc(SCREEN_ORDERS, &[("result", "confirmed")], 1);
+                    on_end(order.order_number(), true);
+                    screen.commit(order.order_number(), trace, &mut gateway)?;
+                    trace.step("commit_payment");
+                    trace.finish();
                 }
 
+                Ok(ScreenMsg::CancelOrder(order, mut trace)) => {
+                    println!(
+                        "Order canceled: Screen {} - Order: {} ({trace})",
+                        order.screen_id(),
+                        order.order_number()
+                    );
+                    trace.step("robot_to_screen");
+                    registry().inc(SCREEN_ORDERS, &[("result", "cancelled")], 1);
+                    on_end(order.order_number(), false);
+                    screen.cancel(order.order_number(), trace, &mut gateway)?;
+                    trace.step("cancel_payment");
+                    trace.finish();
+                }
+
+                Ok(ScreenMsg::Stock(summary)) => screen.update_stock(summary),
+
                 Err(e) => eprintln!("Received an invalid message: {e}"),
             }
         }
@@ -64,9 +122,47 @@ pub fn receiver(screen: Screen) -> io::Result<()> {
     Ok(())
 }
 
+/// Function that gives up on the orders of the screen whose deadline passed, cancelling
+/// their payment. It returns right away if the screen has no order timeout, and never
+/// returns otherwise.
+///
+/// # Arguments
+///
+/// * `screen` - The screen whose orders time out.
+/// * `on_expire` - Called with the number of every order that times out.
+///
+/// # Returns
+///
+/// An io::Result indicating if the function was successful.
+pub fn expire_orders(screen: Screen, mut on_expire: impl FnMut(usize)) -> io::Result<()> {
+    let Some(timeout) = screen.order_timeout() else {
+        return Ok(());
+    };
+
+    let mut gateway = TcpStream::connect(format!("127.0.0.1:{GATEWAY_PORT}"))?;
+    let interval = EXPIRE_INTERVAL.min(timeout / 4);
+
+    loop {
+        thread::sleep(interval);
+        for (number, mut trace) in screen.expire() {
+            println!("Order [{number}] timed out, cancelling its payment ({trace})");
+            registry().inc(SCREEN_ORDERS, &[("result", "timed_out")], 1);
+            on_expire(number);
+            screen.cancel(number, trace, &mut gateway)?;
+            trace.step("cancel_payment");
+            trace.finish();
+        }
+    }
+}
+
 /// Function that processes a file with orders and validates them with the gateway.
+/// A trace is started for every order so that its steps can be followed across the shop.
 /// If the order is valid, it will notify the robots.
 /// If the order is invalid, it will print a message.
+/// While the shop is busy, orders are turned away before their payment is captured, and
+/// the payment of an order every robot refused is cancelled. Orders that ask for more
+/// servings than the robots last said were left are turned away or warned about, depending
+/// on the stock check of the screen.
 ///
 /// # Arguments
 ///
@@ -82,15 +178,63 @@ pub fn process_file(orders_path: String, screen: Screen, screen_id: u16) -> io::
     let mut gateway = TcpStream::connect(format!("127.0.0.1:{GATEWAY_PORT}"))?;
 
     for (number, order) in screen::orders(reader).enumerate() {
-        if screen
-            .validate(&order, number, &mut gateway)
-            .is_ok_and(identity)
-        {
-            println!("Order [{}] is valid", number);
-            let order = Order::from(order, screen_id, number);
-            screen.notify_order(order)?;
+        let mut trace = TraceContext::new();
+        if let Some(wait) = screen.busy() {
+            println!(
+                "Order [{}] turned away, the shop is busy for {:.1}s ({trace})",
+                number,
+                wait.as_secs_f64()
+            );
+            registry().inc(SCREEN_ORDERS, &[("result", "busy")], 1);
+            trace.finish();
+            continue;
+        }
+
+        if let Some(shortage) = screen.shortage(&order) {
+            match screen.stock_check() {
+                StockCheck::Reject => {
+                    println!("Order [{number}] turned away, {shortage} (advisory) ({trace})");
+                    registry().inc(SCREEN_ORDERS, &[("result", "out_of_stock")], 1);
+                    trace.finish();
+                    continue;
+                }
+                _ => println!("Order [{number}] will probably be cancelled, {shortage} ({trace})"),
+            }
+        }
+
+        let valid = screen
+            .validate(&order, number, trace, &mut gateway)
+            .is_ok_and(identity);
+        trace.step("gateway_validation");
+
+        if valid {
+            println!("Order [{}] is valid ({trace})", number);
+            registry().inc(SCREEN_ORDERS, &[("result", "valid")], 1);
+            let order = Order::from(order, screen_id, number).with_trace(trace);
+            match screen.notify_order(order)? {
+                Admission::Accepted(wait) => {
+                    println!(
+                        "Order [{}] ready in about {:.1}s",
+                        number,
+                        wait.as_secs_f64()
+                    )
+                }
+                Admission::Busy(wait) => {
+                    println!(
+                        "Order [{}] refused, the shop is busy for {:.1}s ({trace})",
+                        number,
+                        wait.as_secs_f64()
+                    );
+                    registry().inc(SCREEN_ORDERS, &[("result", "busy")], 1);
+                    screen.cancel(number, trace, &mut gateway)?;
+                    trace.step("cancel_payment");
+                    trace.finish();
+                }
+            }
         } else {
-            println!("Order [{}] is invalid", number);
+            println!("Order [{}] is invalid ({trace})", number);
+            registry().inc(SCREEN_ORDERS, &[("result", "invalid")], 1);
+            trace.finish();
         }
     }
 
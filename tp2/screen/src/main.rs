@@ -1,21 +1,47 @@
-use screen::{process_file, receiver, screen::Screen};
+use ice_cream_shop::{metrics, shop_values::SCREEN_METRICS_STARTING_PORT, trace::Tracer};
+use screen::{config::ScreenConfig, expire_orders, http, process_file, receiver, screen::Screen};
 use std::{env, error::Error, thread};
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args: Vec<_> = env::args().skip(1).collect();
-    let [id, orders_path] = args.as_slice() else {
-        Err("Use: cargo run <id> <path_to_orders_file>")?
+    let (id, orders_path, http_port) = match args.as_slice() {
+        [id, flag, port] if flag == "--http" => (id, None, Some(port)),
+        [id, orders_path] => (id, Some(orders_path), None),
+        _ => Err("Use: cargo run <id> <path_to_orders_file> | <id> --http <port>")?,
     };
 
     let screen_id: u16 = id.parse().map_err(|_| "id needs to be a number")?;
-    let screen = Screen::new(screen_id);
+    let screen = Screen::new(screen_id).with_config(ScreenConfig::from_env()?);
+    if let Err(e) = Tracer::init(&format!("screen-{screen_id}")) {
+        eprintln!("Couldn't start tracing: {e}");
+    }
+    if let Err(e) = metrics::serve(SCREEN_METRICS_STARTING_PORT + screen_id) {
+        eprintln!("Couldn't start the metrics endpoint: {e}");
+    }
+
+    if let Some(port) = http_port {
+        let port: u16 = port.parse().map_err(|_| "port needs to be a number")?;
+        http::serve(screen, port)?;
+        return Ok(());
+    }
 
     let receiver = {
         let screen = screen.clone();
         thread::spawn(move || receiver(screen))
     };
 
-    process_file(orders_path.to_string(), screen, screen_id)?;
+    {
+        let screen = screen.clone();
+        thread::spawn(move || {
+            if let Err(e) = expire_orders(screen, |_| {}) {
+                eprintln!("{e}");
+            }
+        });
+    }
+
+    if let Some(orders_path) = orders_path {
+        process_file(orders_path.to_string(), screen, screen_id)?;
+    }
 
     match receiver.join() {
         Ok(Err(e)) => eprintln!("{e}"),
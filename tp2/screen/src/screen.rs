@@ -1,14 +1,31 @@
+use crate::config::ScreenConfig;
 use ice_cream_shop::{
+    endpoint::Endpoint,
     id_to_addr, io_err,
-    messages::{fmt_msg, gateway_msg::GatewayMsg, robot_msg::RobotMsg},
-    orders::{ClientOrder, Order, OrderId},
+    messages::{
+        auth::{open, seal, seal_request, Keyring, Route},
+        gateway_msg::GatewayMsg,
+        protocol::Codec,
+        robot_msg::RobotMsg,
+        screen_msg::Admission,
+    },
+    metrics::{registry, shop_metrics::VALIDATE_LATENCY},
+    orders::{ClientOrder, Deadlines, Order, OrderId},
     shop_values::{N_ROBOTS, ROBOT_SCREEN_STARTING_PORT},
+    stock::{Shortage, StockBoard, StockCheck, StockSummary},
+    trace::{trace_context::now_us, TraceContext},
 };
 use std::{
     io::{self, BufRead, Read, Write},
     net::TcpStream,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
 };
 
+/// How long a screen waits for a robot to answer an order. A robot that doesn't answer in
+/// time, like one from before robots answered, is taken to have accepted it.
+const ADMISSION_TIMEOUT: Duration = Duration::from_secs(2);
+
 /// Function that takes a BufRead and returns an iterator of ClientOrders.
 pub fn orders<R: BufRead>(reader: R) -> impl Iterator<Item = ClientOrder> {
     reader
@@ -18,15 +35,125 @@ pub fn orders<R: BufRead>(reader: R) -> impl Iterator<Item = ClientOrder> {
 }
 
 /// Struct that represents a screen that will communicate with the gateway and the robots.
+/// It signs its messages and checks the ones it receives if it has a keyring.
+/// Once every robot refused an order because the shop was busy, the screen turns new orders
+/// away until the robots expect to have room again. It also keeps the stock the robots
+/// publish, to catch orders that ask for more than is left before capturing their payment.
+/// If it has an order timeout, it gives up on the orders the robots take too long with.
 #[derive(Clone)]
 pub struct Screen {
     pub id: u16,
+    config: ScreenConfig,
+    busy_until: Arc<Mutex<Option<Instant>>>,
+    stock: Arc<Mutex<StockBoard>>,
+    deadlines: Arc<Mutex<Deadlines>>,
 }
 
 impl Screen {
     /// Creates a new screen with the given id.
     pub fn new(id: u16) -> Self {
-        Self { id }
+        Self {
+            id,
+            config: ScreenConfig::default(),
+            busy_until: Arc::default(),
+            stock: Arc::default(),
+            deadlines: Arc::default(),
+        }
+    }
+
+    /// Sets how the screen behaves.
+    pub fn with_config(mut self, config: ScreenConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Returns what the screen does with orders that ask for more servings than are left.
+    pub fn stock_check(&self) -> StockCheck {
+        self.config.stock_check
+    }
+
+    /// Returns how long the screen waits for an order to end, if it gives up on them.
+    pub fn order_timeout(&self) -> Option<Duration> {
+        self.config.order_timeout
+    }
+
+    /// Records that a robot said how an order ended.
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - The id of the order.
+    ///
+    /// # Returns
+    ///
+    /// False if the order already timed out, so that the result has to be ignored.
+    pub fn settle(&self, order: OrderId) -> bool {
+        order.screen_id() != self.id || self.lock_deadlines().settle(order.order_number())
+    }
+
+    /// Gives up on an order before it ends, like one the customer withdrew or one no robot
+    /// took. Whatever the robots say about it later is ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `order_number` - The number of the order.
+    ///
+    /// # Returns
+    ///
+    /// False if a robot already said how the order ended or the screen already gave up on it.
+    pub fn withdraw(&self, order_number: usize) -> bool {
+        self.lock_deadlines().give_up(order_number)
+    }
+
+    /// Gives up on the orders whose deadline passed. The robots may still say how they ended,
+    /// but it will be ignored.
+    ///
+    /// # Returns
+    ///
+    /// The number and the trace of every order that timed out.
+    pub fn expire(&self) -> Vec<(usize, TraceContext)> {
+        self.lock_deadlines().expire(Instant::now())
+    }
+
+    /// Takes in the stock of the flavours sent by a robot.
+    pub fn update_stock(&self, summary: StockSummary) {
+        self.stock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .update(summary);
+    }
+
+    /// Returns the first flavour of the order that asks for more servings than the robots
+    /// last said were left, unless the check is off. It's advisory: the stock may have
+    /// changed since then, so an order without a shortage may still be cancelled.
+    pub fn shortage(&self, order: &ClientOrder) -> Option<Shortage> {
+        if self.stock_check() == StockCheck::Off {
+            return None;
+        }
+
+        self.stock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .shortage(order)
+    }
+
+    /// Sets the keyring the screen signs and checks its messages with.
+    pub fn with_keyring(mut self, keyring: Option<Keyring>) -> Self {
+        self.config.keyring = keyring;
+        self
+    }
+
+    /// Returns the keyring of the screen, if the shop has a key.
+    pub fn keyring(&self) -> Option<&Keyring> {
+        self.config.keyring.as_ref()
+    }
+
+    /// Returns how long the shop is expected to stay busy, if the robots refused the last
+    /// order of the screen and that time didn't pass yet. Orders that arrive meanwhile
+    /// should be turned away before capturing their payment.
+    pub fn busy(&self) -> Option<Duration> {
+        self.lock_busy()
+            .map(|until| until.saturating_duration_since(Instant::now()))
+            .filter(|wait| !wait.is_zero())
     }
 
     /// Validates the given order with the gateway.
@@ -35,6 +162,7 @@ impl Screen {
     ///
     /// * `order` - The order to validate.
     /// * `order_number` - The number of the order.
+    /// * `trace` - The trace of the order.
     /// * `gateway` - The gateway to communicate with.
     ///
     /// # Returns
@@ -44,19 +172,21 @@ impl Screen {
         &self,
         order: &ClientOrder,
         order_number: usize,
+        trace: TraceContext,
         gateway: &mut T,
     ) -> io::Result<bool> {
         let card_number = order.card_number.to_string();
         let order = OrderId::new(self.id, order_number);
-        let msg = fmt_msg(GatewayMsg::CapturePayment(order, card_number));
+        let msg = Codec::default().encode(GatewayMsg::CapturePayment(order, card_number, trace));
+        let start = Instant::now();
+        let (msg, answers) = seal_request(self.keyring(), msg, Endpoint::Gateway);
         gateway.write_all(&msg)?;
 
-        let mut buffer = [0; 128];
+        let mut buffer = [0; 256];
         let n = gateway.read(&mut buffer)?;
+        registry().observe(VALIDATE_LATENCY, &[], start.elapsed().as_secs_f64());
 
-        String::from_utf8_lossy(&buffer[..n])
-            .parse()
-            .map_err(|_| io_err!("Invalid gateway response"))
+        open(self.keyring(), &buffer[..n], answers).map_err(|_| io_err!("Invalid gateway response"))
     }
 
     /// Commits the given order with the gateway.
@@ -64,15 +194,21 @@ impl Screen {
     /// # Arguments
     ///
     /// * `order_number` - The number of the order.
+    /// * `trace` - The trace of the order.
     /// * `gateway` - The gateway to communicate with.
     ///
     /// # Returns
     ///
     /// An io::Result indicating if the commit was successful.
-    pub fn commit(&self, order_number: usize, gateway: &mut TcpStream) -> io::Result<()> {
+    pub fn commit(
+        &self,
+        order_number: usize,
+        trace: TraceContext,
+        gateway: &mut TcpStream,
+    ) -> io::Result<()> {
         let order = OrderId::new(self.id, order_number);
-        let msg = fmt_msg(GatewayMsg::CommitPayment(order));
-        gateway.write_all(&msg)
+        let msg = Codec::default().encode(GatewayMsg::CommitPayment(order, trace));
+        gateway.write_all(&seal(self.keyring(), msg, Route::To(Endpoint::Gateway)))
     }
 
     /// Cancels the given order with the gateway.
@@ -80,18 +216,28 @@ impl Screen {
     /// # Arguments
     ///
     /// * `order_number` - The number of the order.
+    /// * `trace` - The trace of the order.
     /// * `gateway` - The gateway to communicate with.
     ///
     /// # Returns
     ///
     /// An io::Result indicating if the cancel was successful.
-    pub fn cancel(&self, order_number: usize, gateway: &mut TcpStream) -> io::Result<()> {
+    pub fn cancel(
+        &self,
+        order_number: usize,
+        trace: TraceContext,
+        gateway: &mut TcpStream,
+    ) -> io::Result<()> {
         let order = OrderId::new(self.id, order_number);
-        let msg = fmt_msg(GatewayMsg::CancelPayment(order));
-        gateway.write_all(&msg)
+        let msg = Codec::default().encode(GatewayMsg::CancelPayment(order, trace));
+        gateway.write_all(&seal(self.keyring(), msg, Route::To(Endpoint::Gateway)))
     }
 
     /// Notifies the given order to the robots.
+    /// If a robot refuses it because the shop is busy, the next one is tried. If every robot
+    /// refuses it, the screen stays busy for the shortest wait they answered with.
+    /// If the screen has an order timeout, the order carries its deadline and the screen starts
+    /// waiting on it before any robot can take it, so that a fast result isn't missed.
     ///
     /// # Arguments
     ///
@@ -99,32 +245,114 @@ impl Screen {
     ///
     /// # Returns
     ///
-    /// An io::Result indicating if the notification was successful.
-    pub fn notify_order(&self, order: Order) -> io::Result<()> {
+    /// How the robots answered, or an error if no robot could be reached.
+    pub fn notify_order(&self, order: Order) -> io::Result<Admission> {
+        let order_number = order.id().order_number();
+        let order = match self.order_timeout() {
+            Some(timeout) => {
+                let deadline = Instant::now() + timeout;
+                let trace = order.trace();
+                self.lock_deadlines().track(order_number, deadline, trace);
+                order.with_deadline(now_us() + timeout.as_micros() as u64)
+            }
+            None => order,
+        };
+
+        let admission = self.hand_order(order);
+        if !matches!(admission, Ok(Admission::Accepted(_))) {
+            self.lock_deadlines().forget(order_number);
+        }
+        admission
+    }
+
+    /// Hands the order to the first robot that takes it.
+    fn hand_order(&self, order: Order) -> io::Result<Admission> {
         let order_number = order.id().order_number();
-        let order = fmt_msg(RobotMsg::RecvOrder(order));
+        let order = Codec::default().encode(RobotMsg::RecvOrder(order));
+        let mut busy: Option<Duration> = None;
 
         for offset in 0..N_ROBOTS {
             let id = (order_number as u16 + offset) % N_ROBOTS;
             let ip = id_to_addr(ROBOT_SCREEN_STARTING_PORT, id);
 
-            if let Ok(mut stream) = TcpStream::connect(ip) {
-                if stream.write_all(&order).is_ok() {
-                    return Ok(());
+            let Ok(mut stream) = TcpStream::connect(ip) else {
+                continue;
+            };
+            let (order, answers) =
+                seal_request(self.keyring(), order.clone(), Endpoint::RobotOrders(id));
+            if stream.write_all(&order).is_err() {
+                continue;
+            }
+
+            match self.admission(&mut stream, answers) {
+                None => continue,
+                Some(Admission::Busy(wait)) => {
+                    busy = Some(busy.map_or(wait, |busy| busy.min(wait)))
+                }
+                Some(accepted) => {
+                    *self.lock_busy() = None;
+                    return Ok(accepted);
                 }
             }
         }
 
-        Err(io_err!("Could not notify any robot"))
+        let wait = busy.ok_or_else(|| io_err!("Could not notify any robot"))?;
+        *self.lock_busy() = Some(Instant::now() + wait);
+        Ok(Admission::Busy(wait))
+    }
+
+    /// Reads the answer of a robot to an order. A robot that closes the connection or doesn't
+    /// answer in time is taken to have accepted it without an estimate.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The connection the order was sent through.
+    /// * `answers` - The route the answer to the order comes back through.
+    ///
+    /// # Returns
+    ///
+    /// The answer of the robot, or `None` if it can't be trusted or understood, so that the
+    /// order is handed to another robot.
+    fn admission(&self, stream: &mut TcpStream, answers: Route) -> Option<Admission> {
+        let mut buffer = [0; 512];
+        let n = stream
+            .set_read_timeout(Some(ADMISSION_TIMEOUT))
+            .and_then(|_| stream.read(&mut buffer))
+            .unwrap_or_default();
+        if n == 0 {
+            return Some(Admission::Accepted(Duration::ZERO));
+        }
+
+        open(self.keyring(), &buffer[..n], answers)
+            .inspect_err(|e| eprintln!("Invalid answer to an order: {e}"))
+            .ok()
+    }
+
+    /// Locks the orders the screen is waiting on, even if a thread panicked while holding them.
+    fn lock_deadlines(&self) -> MutexGuard<'_, Deadlines> {
+        self.deadlines
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Locks when the screen stops being busy, even if a thread panicked while holding it.
+    fn lock_busy(&self) -> MutexGuard<'_, Option<Instant>> {
+        self.busy_until
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
     }
 }
 
+#[allow(clippy::empty_line_after_outer_attr)]
 #[cfg(test)]
 
 mod tests {
     use super::*;
-    use ice_cream_shop::flavour::Flavour;
-    use std::cmp;
+    use ice_cream_shop::{
+        flavour::Flavour,
+        messages::{auth::Role, fmt_msg},
+    };
+    use std::{cmp, net::TcpListener, thread};
 
     struct MockStream {
         pub data: Vec<u8>,
@@ -139,6 +367,40 @@ mod tests {
         }
     }
 
+    /// A gateway that answers every request it's sent with `true`, signed by the given role
+    /// for that request or for another one, or unsigned if there's no role.
+    struct SignedGateway {
+        role: Option<Role>,
+        other_request: bool,
+        data: Vec<u8>,
+    }
+
+    impl Read for SignedGateway {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = cmp::min(buf.len(), self.data.len());
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data.drain(..len);
+            Ok(len)
+        }
+    }
+
+    impl Write for SignedGateway {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let gateway = Keyring::new("secret", Role::Gateway);
+            let request = gateway.open(buf, Route::To(Endpoint::Gateway)).unwrap();
+            let nonce = request.nonce.wrapping_add(self.other_request as u64);
+            self.data = match self.role {
+                Some(role) => Keyring::new("secret", role).seal(b"true", Route::Back(nonce)),
+                None => b"true".to_vec(),
+            };
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
     impl Write for MockStream {
         fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
             //self.data.extend_from_slice(buf);
@@ -151,6 +413,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test01_i_can_validate_a_valid_order() {
         let client_order = ClientOrder {
             flavours: vec![Flavour::Chocolate, Flavour::DulceDeLeche]
@@ -166,11 +429,12 @@ mod tests {
             data: "true".to_string().into_bytes(),
         };
 
-        let result = screen.validate(&client_order, 1, &mut mock_stream);
+        let result = screen.validate(&client_order, 1, TraceContext::default(), &mut mock_stream);
         assert_eq!(result.unwrap(), true);
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test02_i_can_validate_an_invalid_order() {
         let client_order = ClientOrder {
             flavours: vec![Flavour::Chocolate, Flavour::DulceDeLeche]
@@ -186,9 +450,76 @@ mod tests {
             data: "false".to_string().into_bytes(),
         };
 
-        let result = screen.validate(&client_order, 1, &mut mock_stream);
+        let result = screen.validate(&client_order, 1, TraceContext::default(), &mut mock_stream);
         assert_eq!(result.unwrap(), false);
     }
+
+    #[test]
+    fn test03_only_the_gateway_can_validate_an_order_when_there_is_a_key() {
+        let client_order = ClientOrder {
+            flavours: vec![(Flavour::Chocolate, 1)].into_iter().collect(),
+            card_number: "6666-1111-2222-3333".to_string(),
+        };
+
+        let screen = Screen::new(1).with_keyring(Some(Keyring::new("secret", Role::Screen)));
+        let gateway = |role, other_request| SignedGateway {
+            role,
+            other_request,
+            data: Vec::new(),
+        };
+
+        let mut mock_stream = gateway(Some(Role::Gateway), false);
+        let result = screen.validate(&client_order, 1, TraceContext::default(), &mut mock_stream);
+        assert!(result.unwrap());
+
+        for mut mock_stream in [
+            gateway(Some(Role::Robot), false),
+            gateway(Some(Role::Gateway), true),
+            gateway(None, false),
+        ] {
+            let result =
+                screen.validate(&client_order, 1, TraceContext::default(), &mut mock_stream);
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn test04_only_a_trusted_answer_or_no_answer_admits_an_order() {
+        let screen = Screen::new(1).with_keyring(Some(Keyring::new("secret", Role::Screen)));
+        let busy = fmt_msg(Admission::Busy(Duration::from_secs(1)));
+        let answers = [
+            Keyring::new("secret", Role::Robot).seal(&busy, Route::Back(7)),
+            Keyring::new("secret", Role::Robot).seal(&busy, Route::Back(8)),
+            Keyring::new("other", Role::Robot).seal(&busy, Route::Back(7)),
+            busy.clone(),
+            Vec::new(),
+        ];
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let robot = thread::spawn(move || {
+            for answer in answers {
+                let (mut stream, _) = listener.accept().unwrap();
+                stream.write_all(&answer).unwrap();
+            }
+        });
+
+        let admissions: Vec<_> = (0..5)
+            .map(|_| screen.admission(&mut TcpStream::connect(addr).unwrap(), Route::Back(7)))
+            .collect();
+        robot.join().unwrap();
+
+        assert_eq!(
+            admissions,
+            vec![
+                Some(Admission::Busy(Duration::from_secs(1))),
+                None,
+                None,
+                None,
+                Some(Admission::Accepted(Duration::ZERO)),
+            ]
+        );
+    }
 }
 
 // #[cfg(test)]
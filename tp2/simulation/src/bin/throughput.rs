@@ -0,0 +1,44 @@
+use simulation::{ledger::Outcome, simulation::Simulation};
+use std::{env, error::Error, time::Duration};
+
+/// Seeds every configuration is run with.
+const SEEDS: u64 = 5;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let orders = args.first().map(|n| n.parse()).transpose()?.unwrap_or(4);
+
+    // The robots print to stdout as well, so the table is printed once every run ended.
+    let mut rows = Vec::new();
+    for robots in 1..=5 {
+        let mut elapsed = Duration::ZERO;
+        let mut confirmed = 0;
+
+        for seed in 0..SEEDS {
+            let report = Simulation::new(seed)
+                .with_robots(robots)
+                .with_screens(3, orders)
+                .with_interval(Duration::ZERO)
+                .run()?;
+
+            if !report.problems().is_empty() {
+                Err(format!("Some orders didn't end exactly once\n{report}"))?
+            }
+
+            elapsed += report.elapsed;
+            confirmed += report.count(Outcome::Confirmed);
+        }
+
+        let seconds = elapsed.as_secs_f64() / SEEDS as f64;
+        let per_minute = confirmed as f64 * 60.0 / elapsed.as_secs_f64();
+        rows.push(format!("{robots:>6}  {seconds:>7.1}  {per_minute:>13.1}"));
+    }
+
+    println!("Orders per screen: {orders}, seeds: {SEEDS}");
+    println!("robots  seconds  confirmed/min");
+    for row in rows {
+        println!("{row}");
+    }
+
+    Ok(())
+}
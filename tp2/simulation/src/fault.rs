@@ -0,0 +1,113 @@
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::time::Duration;
+
+/// A fault injected in the simulation.
+/// - `Crash`: The robot crashes and doesn't come back unless it's restarted.
+/// - `Restart`: A crashed robot is started again with the same id and none of its state.
+/// - `Hang`: The robot stops sending and receiving messages, but its connections stay open.
+/// - `Wake`: A robot that hung goes on as if nothing happened.
+/// - `Partition`: The robots can't reach each other until the network heals.
+/// - `Heal`: All the partitions are removed.
+/// - `Drain`: The robot is asked to leave the ring, as if it received a SIGTERM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    Crash(u16),
+    Restart(u16),
+    Hang(u16),
+    Wake(u16),
+    Partition(u16, u16),
+    Heal,
+    Drain(u16),
+}
+
+/// The faults of a simulation and when they happen, measured from its start.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FaultPlan(Vec<(Duration, Fault)>);
+
+impl FaultPlan {
+    /// Creates an empty plan.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a fault to the plan.
+    ///
+    /// # Arguments
+    ///
+    /// * `at` - When the fault happens.
+    /// * `fault` - The fault.
+    pub fn with(mut self, at: Duration, fault: Fault) -> Self {
+        self.0.push((at, fault));
+        self.0.sort_by_key(|(at, _)| *at);
+        self
+    }
+
+    /// Generates a plan from the given seed. Robots crash or leave one at a time, spaced
+    /// so that the ring can repair itself in between, and at least two robots are left.
+    /// Partitions between two robots heal after a few seconds.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The seed the plan is drawn from.
+    /// * `robots` - The number of robots of the simulation.
+    /// * `faults` - The number of faults to inject.
+    pub fn random(seed: u64, robots: u16, faults: usize) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut alive: Vec<u16> = (0..robots).collect();
+        let mut plan = Self::new();
+        let mut at = Duration::from_secs(1);
+
+        for _ in 0..faults {
+            at += Duration::from_millis(rng.gen_range(2_000..8_000));
+
+            if alive.len() > 2 && rng.gen_bool(0.5) {
+                let robot = alive.remove(rng.gen_range(0..alive.len()));
+                let fault = match rng.gen_bool(0.5) {
+                    true => Fault::Crash(robot),
+                    false => Fault::Drain(robot),
+                };
+
+                plan = plan.with(at, fault);
+            } else if alive.len() >= 2 {
+                let pair: Vec<_> = alive.choose_multiple(&mut rng, 2).copied().collect();
+                let heal = at + Duration::from_millis(rng.gen_range(200..3_000));
+                plan = plan
+                    .with(at, Fault::Partition(pair[0], pair[1]))
+                    .with(heal, Fault::Heal);
+                at = heal;
+            }
+        }
+
+        plan
+    }
+
+    /// Returns the faults in the order they happen.
+    pub fn faults(&self) -> &[(Duration, Fault)] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test01_the_same_seed_gives_the_same_plan() {
+        assert_eq!(FaultPlan::random(7, 5, 6), FaultPlan::random(7, 5, 6));
+        assert_ne!(FaultPlan::random(7, 5, 6), FaultPlan::random(8, 5, 6));
+    }
+
+    #[test]
+    fn test02_at_least_two_robots_are_left() {
+        for seed in 0..50 {
+            let plan = FaultPlan::random(seed, 4, 10);
+            let gone = plan
+                .faults()
+                .iter()
+                .filter(|(_, fault)| matches!(fault, Fault::Crash(_) | Fault::Drain(_)))
+                .count();
+
+            assert!(gone <= 2);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use gateway::gateway::Gateway;
+use ice_cream_shop::messages::{gateway_msg::GatewayMsg, protocol::decode};
+use robot::transport::{Connection, Endpoint, SharedTransport};
+use std::io;
+use tokio::task;
+
+/// Answers the payments of a single screen, with the same rules as the real gateway.
+///
+/// # Arguments
+///
+/// * `connection` - The connection to the screen.
+async fn handle_screen(mut connection: Connection) {
+    while let Ok(Some(frame)) = connection.rx.recv().await {
+        if let Ok(GatewayMsg::CapturePayment(_, credit_card, _)) = decode(&frame) {
+            let response = Gateway::is_order_valid(&credit_card).to_string();
+            if connection.tx.send(response.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Starts the simulated gateway, which takes a connection from every screen.
+///
+/// # Arguments
+///
+/// * `transport` - The transport of the gateway node.
+///
+/// # Returns
+///
+/// An io::Result indicating if the gateway could start listening.
+pub async fn start(transport: SharedTransport) -> io::Result<()> {
+    let mut listener = transport.listen(Endpoint::Gateway).await?;
+
+    task::spawn(async move {
+        while let Ok(connection) = listener.accept().await {
+            task::spawn(handle_screen(connection));
+        }
+    });
+
+    Ok(())
+}
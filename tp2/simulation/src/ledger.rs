@@ -0,0 +1,59 @@
+use ice_cream_shop::orders::OrderId;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+/// How an order of the simulation ended.
+/// - `Rejected`: The gateway rejected the payment.
+/// - `Undelivered`: The screen couldn't hand the order to any robot.
+/// - `Busy`: The shop was too busy to take the order, so its payment was cancelled or never
+///   captured.
+/// - `OutOfStock`: The screen turned the order away before capturing its payment, because it
+///   asked for more servings than the robots last said were left.
+/// - `TimedOut`: The screen gave up on the order and cancelled its payment, because no robot
+///   said how it ended before its deadline.
+/// - `Confirmed`: A robot delivered the order.
+/// - `Cancelled`: A robot cancelled the order because there wasn't enough ice cream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Outcome {
+    Rejected,
+    Undelivered,
+    Busy,
+    OutOfStock,
+    TimedOut,
+    Confirmed,
+    Cancelled,
+}
+
+/// The outcomes of every order, by screen and order number.
+pub type Outcomes = BTreeMap<(u16, usize), Vec<Outcome>>;
+
+/// The outcomes the screens saw for every order, in the order they saw them.
+#[derive(Debug, Clone, Default)]
+pub struct Ledger(Arc<Mutex<Outcomes>>);
+
+impl Ledger {
+    /// Records an outcome of the given order.
+    pub fn record(&self, order_id: OrderId, outcome: Outcome) {
+        let key = (order_id.screen_id(), order_id.order_number());
+        let mut outcomes = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        outcomes.entry(key).or_default().push(outcome);
+    }
+
+    /// Returns the number of orders that ended.
+    pub fn resolved(&self) -> usize {
+        self.snapshot().len()
+    }
+
+    /// Returns the outcomes of every order, by screen and order number.
+    pub fn snapshot(&self) -> Outcomes {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+}
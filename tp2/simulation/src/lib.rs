@@ -0,0 +1,6 @@
+pub mod fault;
+pub mod gateway;
+pub mod ledger;
+pub mod network;
+pub mod screen;
+pub mod simulation;
@@ -0,0 +1,26 @@
+use simulation::simulation::Simulation;
+use std::{env, error::Error};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let Some(seed) = args.first() else {
+        Err("Use: cargo run --bin simulation <seed> [robots] [faults]")?
+    };
+
+    let seed: u64 = seed.parse().map_err(|_| "seed needs to be a number")?;
+    let robots = args.get(1).map(|n| n.parse()).transpose()?.unwrap_or(5);
+    let faults = args.get(2).map(|n| n.parse()).transpose()?.unwrap_or(3);
+
+    let report = Simulation::new(seed)
+        .with_robots(robots)
+        .with_screens(3, 5)
+        .with_random_faults(faults)
+        .run()?;
+
+    println!("{report}");
+    if !report.problems().is_empty() {
+        Err("Some orders didn't end exactly once or the tokens broke an invariant")?
+    }
+
+    Ok(())
+}
@@ -0,0 +1,504 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use robot::transport::{
+    BoxFuture, Connection, Endpoint, FrameReceiver, FrameSender, Listener, RingTransport,
+    SharedTransport,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, watch},
+    time::{self, Instant},
+};
+
+/// A process of the simulated shop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Node {
+    Robot(u16),
+    Screen(u16),
+    Gateway,
+}
+
+/// A life of a node: a node that crashes and restarts comes back as a new process, and
+/// whatever the old one still tries to send is lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Process {
+    node: Node,
+    life: u64,
+}
+
+/// The state of the simulated network.
+/// - `rng`: The generator the latency of every message is drawn from.
+/// - `latency`: The minimum and maximum latency of a message.
+/// - `listeners`: The process listening at every endpoint and the channel its connections go to.
+/// - `crashed`: The nodes that crashed.
+/// - `lives`: The current life of the nodes that restarted.
+/// - `partitions`: The pairs of nodes that can't reach each other.
+/// - `hung`: The nodes that stopped sending and receiving messages.
+/// - `delivered`: The number of frames delivered so far.
+#[derive(Debug)]
+struct State {
+    rng: StdRng,
+    latency: (Duration, Duration),
+    listeners: HashMap<Endpoint, (Process, mpsc::UnboundedSender<Connection>)>,
+    crashed: HashSet<Node>,
+    lives: HashMap<Node, u64>,
+    partitions: HashSet<(Node, Node)>,
+    hung: HashSet<Node>,
+    delivered: u64,
+}
+
+/// A network that lives in a single process and runs on the tokio clock,
+/// so that it can be driven by a paused runtime.
+/// Every message is delayed by a latency drawn from a seeded generator. Messages on the same
+/// connection keep their order, like in TCP, but messages on different connections can overtake
+/// each other. Crashed nodes lose all their connections once the messages they already sent
+/// arrive, and partitioned nodes can't connect to each other, while the messages already on
+/// their way are held until the partition heals. A crashed node can restart as a new process.
+/// A hung node keeps its connections, and even accepts new ones, but its messages are held
+/// until it wakes up.
+#[derive(Debug, Clone)]
+pub struct SimNetwork {
+    state: Arc<Mutex<State>>,
+    faults: Arc<watch::Sender<u64>>,
+}
+
+/// A transport for a single node of the simulated network.
+#[derive(Debug, Clone)]
+pub struct SimTransport {
+    network: SimNetwork,
+    process: Process,
+}
+
+/// The sending half of a simulated connection.
+#[derive(Debug)]
+struct SimSender {
+    network: SimNetwork,
+    from: Process,
+    frames: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+/// The receiving half of a simulated connection.
+#[derive(Debug)]
+struct SimReceiver(mpsc::UnboundedReceiver<Vec<u8>>);
+
+/// A listener of simulated connections.
+#[derive(Debug)]
+struct SimListener(mpsc::UnboundedReceiver<Connection>);
+
+/// Returns the pair of nodes in the order partitions are stored in.
+fn pair(a: Node, b: Node) -> (Node, Node) {
+    (a.min(b), a.max(b))
+}
+
+impl SimNetwork {
+    /// Creates a network whose latencies are drawn from the given seed.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The seed of the latencies.
+    /// * `latency` - The minimum and maximum latency of a message.
+    pub fn new(seed: u64, latency: (Duration, Duration)) -> Self {
+        let state = State {
+            rng: StdRng::seed_from_u64(seed),
+            latency,
+            listeners: HashMap::new(),
+            crashed: HashSet::new(),
+            lives: HashMap::new(),
+            partitions: HashSet::new(),
+            hung: HashSet::new(),
+            delivered: 0,
+        };
+
+        Self {
+            state: Arc::new(Mutex::new(state)),
+            faults: Arc::new(watch::channel(0).0),
+        }
+    }
+
+    /// Returns a transport for the given node, to be used by a robot or a simulated component.
+    pub fn transport(&self, node: Node) -> SharedTransport {
+        let life = self.state().lives.get(&node).copied().unwrap_or_default();
+        SharedTransport::new(SimTransport {
+            network: self.clone(),
+            process: Process { node, life },
+        })
+    }
+
+    /// Crashes the given node. Its listeners and connections are closed and it can't
+    /// connect to anyone anymore.
+    pub fn crash(&self, node: Node) {
+        let mut state = self.state();
+        state.crashed.insert(node);
+        state.listeners.retain(|_, (owner, _)| owner.node != node);
+        drop(state);
+
+        self.notify();
+    }
+
+    /// Restarts a crashed node as a new process. The transports handed out before the crash
+    /// stay crashed, so the returned one has to be used instead.
+    pub fn restart(&self, node: Node) -> SharedTransport {
+        let mut state = self.state();
+        if state.crashed.remove(&node) {
+            *state.lives.entry(node).or_default() += 1;
+        }
+
+        drop(state);
+        self.transport(node)
+    }
+
+    /// Stops the given nodes from reaching each other until the network heals.
+    pub fn partition(&self, a: Node, b: Node) {
+        self.state().partitions.insert(pair(a, b));
+        self.notify();
+    }
+
+    /// Hangs the given node: the messages it sends or that are sent to it are held until it
+    /// wakes up, but its connections stay open, like those of a process that stopped.
+    pub fn hang(&self, node: Node) {
+        self.state().hung.insert(node);
+        self.notify();
+    }
+
+    /// Wakes up a node that hung, delivering the messages that were held.
+    pub fn wake(&self, node: Node) {
+        self.state().hung.remove(&node);
+        self.notify();
+    }
+
+    /// Removes all the partitions.
+    pub fn heal(&self) {
+        self.state().partitions.clear();
+        self.notify();
+    }
+
+    /// Returns the number of frames delivered so far.
+    pub fn delivered(&self) -> u64 {
+        self.state().delivered
+    }
+
+    /// Returns whether the given node crashed.
+    pub fn is_crashed(&self, node: Node) -> bool {
+        self.state().crashed.contains(&node)
+    }
+
+    /// Returns whether the given process is gone, because its node crashed or restarted.
+    fn is_down(&self, process: Process) -> bool {
+        let state = self.state();
+        let life = state.lives.get(&process.node).copied().unwrap_or_default();
+        state.crashed.contains(&process.node) || life != process.life
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn notify(&self) {
+        self.faults.send_modify(|faults| *faults += 1);
+    }
+
+    fn latency(&self) -> Duration {
+        let mut state = self.state();
+        let (min, max) = state.latency;
+        state.rng.gen_range(min..=max)
+    }
+
+    fn is_held(&self, from: Process, to: Process) -> bool {
+        let state = self.state();
+        state.partitions.contains(&pair(from.node, to.node))
+            || state.hung.contains(&from.node)
+            || state.hung.contains(&to.node)
+    }
+
+    /// Opens one direction of a connection and spawns the task that delivers its frames.
+    fn pipe(&self, from: Process, to: Process) -> (Box<dyn FrameSender>, Box<dyn FrameReceiver>) {
+        let (frames_tx, frames_rx) = mpsc::unbounded_channel();
+        let (delivered_tx, delivered_rx) = mpsc::unbounded_channel();
+        let faults = self.faults.subscribe();
+        tokio::spawn(
+            self.clone()
+                .deliver(from, to, faults, frames_rx, delivered_tx),
+        );
+
+        let sender = SimSender {
+            network: self.clone(),
+            from,
+            frames: frames_tx,
+        };
+
+        (Box::new(sender), Box::new(SimReceiver(delivered_rx)))
+    }
+
+    /// Delivers the frames sent from one node to another after their latency.
+    /// Frames are delivered in the order they were sent, and frames that would cross a
+    /// partition wait for it to heal, like the ones from or to a hung node.
+    /// If the receiver crashes the connection closes right away. If the sender crashes, the
+    /// frames it already sent are still delivered, once they are no longer held, before the
+    /// connection closes, like the bytes a process wrote to a socket before dying.
+    async fn deliver(
+        self,
+        from: Process,
+        to: Process,
+        mut faults: watch::Receiver<u64>,
+        mut frames: mpsc::UnboundedReceiver<Vec<u8>>,
+        delivered: mpsc::UnboundedSender<Vec<u8>>,
+    ) {
+        let mut last = Instant::now();
+
+        while !self.is_down(to) {
+            let frame = match self.is_down(from) {
+                true => match frames.try_recv() {
+                    Ok(frame) => frame,
+                    Err(_) => return,
+                },
+                false => tokio::select! {
+                    biased;
+                    _ = faults.changed() => continue,
+                    frame = frames.recv() => match frame {
+                        Some(frame) => frame,
+                        None => return,
+                    },
+                },
+            };
+
+            last = last.max(Instant::now() + self.latency());
+            loop {
+                tokio::select! {
+                    biased;
+                    _ = faults.changed() => {}
+                    _ = time::sleep_until(last) => break,
+                }
+
+                if self.is_down(to) {
+                    return;
+                }
+            }
+
+            while self.is_held(from, to) {
+                if faults.changed().await.is_err() || self.is_down(to) {
+                    return;
+                }
+            }
+
+            if delivered.send(frame).is_err() {
+                return;
+            }
+
+            self.state().delivered += 1;
+        }
+    }
+
+    async fn listen(&self, process: Process, endpoint: Endpoint) -> io::Result<Box<dyn Listener>> {
+        if self.is_down(process) {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+
+        let mut state = self.state();
+        if state.listeners.contains_key(&endpoint) {
+            return Err(io::ErrorKind::AddrInUse.into());
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        state.listeners.insert(endpoint, (process, tx));
+        Ok(Box::new(SimListener(rx)))
+    }
+
+    async fn connect(&self, from: Process, endpoint: Endpoint) -> io::Result<Connection> {
+        time::sleep(self.latency()).await;
+
+        let refused = || io::Error::from(io::ErrorKind::ConnectionRefused);
+        if self.is_down(from) {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+
+        let state = self.state();
+        let (to, acceptor) = state.listeners.get(&endpoint).ok_or_else(refused)?;
+        let to = *to;
+        if state.partitions.contains(&pair(from.node, to.node)) {
+            return Err(refused());
+        }
+
+        let acceptor = acceptor.clone();
+        drop(state);
+
+        let (tx, remote_rx) = self.pipe(from, to);
+        let (remote_tx, rx) = self.pipe(to, from);
+        let remote = Connection {
+            tx: remote_tx,
+            rx: remote_rx,
+        };
+
+        acceptor.send(remote).map_err(|_| refused())?;
+        Ok(Connection { tx, rx })
+    }
+}
+
+impl FrameSender for SimSender {
+    fn send<'a>(&'a mut self, frame: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let broken = || io::Error::from(io::ErrorKind::BrokenPipe);
+            if self.network.is_down(self.from) {
+                return Err(broken());
+            }
+
+            self.frames.send(frame.to_vec()).map_err(|_| broken())
+        })
+    }
+}
+
+impl FrameReceiver for SimReceiver {
+    fn recv(&mut self) -> BoxFuture<'_, io::Result<Option<Vec<u8>>>> {
+        Box::pin(async move { Ok(self.0.recv().await) })
+    }
+}
+
+impl Listener for SimListener {
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<Connection>> {
+        Box::pin(async move {
+            self.0
+                .recv()
+                .await
+                .ok_or_else(|| io::ErrorKind::NotConnected.into())
+        })
+    }
+}
+
+impl RingTransport for SimTransport {
+    fn listen(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Box<dyn Listener>>> {
+        Box::pin(self.network.listen(self.process, endpoint))
+    }
+
+    fn connect(&self, endpoint: Endpoint) -> BoxFuture<'_, io::Result<Connection>> {
+        Box::pin(self.network.connect(self.process, endpoint))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LATENCY: (Duration, Duration) = (Duration::from_millis(1), Duration::from_millis(5));
+
+    #[tokio::test(start_paused = true)]
+    async fn test01_frames_arrive_in_order() {
+        let network = SimNetwork::new(1, LATENCY);
+        let server = network.transport(Node::Gateway);
+        let client = network.transport(Node::Screen(0));
+
+        let mut listener = server.listen(Endpoint::Gateway).await.unwrap();
+        let mut connection = client.connect(Endpoint::Gateway).await.unwrap();
+        let mut accepted = listener.accept().await.unwrap();
+
+        for frame in [b"1", b"2", b"3"] {
+            connection.tx.send(frame).await.unwrap();
+        }
+
+        for frame in [b"1", b"2", b"3"] {
+            assert_eq!(accepted.rx.recv().await.unwrap().unwrap(), frame);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test02_a_crashed_node_loses_its_connections() {
+        let network = SimNetwork::new(1, LATENCY);
+        let robot = network.transport(Node::Robot(0));
+        let screen = network.transport(Node::Screen(0));
+
+        let mut listener = robot.listen(Endpoint::RobotOrders(0)).await.unwrap();
+        let mut connection = screen.connect(Endpoint::RobotOrders(0)).await.unwrap();
+        let _accepted = listener.accept().await.unwrap();
+
+        network.crash(Node::Robot(0));
+
+        assert!(connection.rx.recv().await.unwrap().is_none());
+        assert!(listener.accept().await.is_err());
+        assert!(screen.connect(Endpoint::RobotOrders(0)).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test03_partitions_hold_frames_until_they_heal() {
+        let network = SimNetwork::new(1, LATENCY);
+        let a = network.transport(Node::Robot(0));
+        let b = network.transport(Node::Robot(1));
+
+        let mut listener = a.listen(Endpoint::Robot(0)).await.unwrap();
+        let mut connection = b.connect(Endpoint::Robot(0)).await.unwrap();
+        let mut accepted = listener.accept().await.unwrap();
+
+        network.partition(Node::Robot(0), Node::Robot(1));
+        connection.tx.send(b"token").await.unwrap();
+        assert!(b.connect(Endpoint::Robot(0)).await.is_err());
+
+        let held = time::timeout(Duration::from_secs(10), accepted.rx.recv()).await;
+        assert!(held.is_err());
+
+        network.heal();
+        assert_eq!(accepted.rx.recv().await.unwrap().unwrap(), b"token");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test04_a_restarted_node_is_a_new_process() {
+        let network = SimNetwork::new(1, LATENCY);
+        let old = network.transport(Node::Robot(0));
+        let screen = network.transport(Node::Screen(0));
+
+        let _listener = old.listen(Endpoint::RobotOrders(0)).await.unwrap();
+        network.crash(Node::Robot(0));
+        let new = network.restart(Node::Robot(0));
+
+        assert!(old.listen(Endpoint::RobotOrders(0)).await.is_err());
+        let mut listener = new.listen(Endpoint::RobotOrders(0)).await.unwrap();
+        let mut connection = screen.connect(Endpoint::RobotOrders(0)).await.unwrap();
+        let mut accepted = listener.accept().await.unwrap();
+
+        connection.tx.send(b"order").await.unwrap();
+        assert_eq!(accepted.rx.recv().await.unwrap().unwrap(), b"order");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test05_a_hung_node_keeps_its_connections() {
+        let network = SimNetwork::new(1, LATENCY);
+        let a = network.transport(Node::Robot(0));
+        let b = network.transport(Node::Robot(1));
+
+        let mut listener = a.listen(Endpoint::Robot(0)).await.unwrap();
+        network.hang(Node::Robot(0));
+        let mut connection = b.connect(Endpoint::Robot(0)).await.unwrap();
+        let mut accepted = listener.accept().await.unwrap();
+
+        connection.tx.send(b"heartbeat").await.unwrap();
+        let held = time::timeout(Duration::from_secs(10), accepted.rx.recv()).await;
+        assert!(held.is_err());
+
+        network.wake(Node::Robot(0));
+        assert_eq!(accepted.rx.recv().await.unwrap().unwrap(), b"heartbeat");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test06_frames_a_crashed_node_sent_across_a_partition_arrive_once_it_heals() {
+        let network = SimNetwork::new(1, LATENCY);
+        let a = network.transport(Node::Robot(0));
+        let b = network.transport(Node::Robot(1));
+
+        let mut listener = a.listen(Endpoint::Robot(0)).await.unwrap();
+        let mut connection = b.connect(Endpoint::Robot(0)).await.unwrap();
+        let mut accepted = listener.accept().await.unwrap();
+
+        network.partition(Node::Robot(0), Node::Robot(1));
+        connection.tx.send(b"token").await.unwrap();
+        network.crash(Node::Robot(1));
+
+        let held = time::timeout(Duration::from_secs(10), accepted.rx.recv()).await;
+        assert!(held.is_err());
+
+        network.heal();
+        assert_eq!(accepted.rx.recv().await.unwrap().unwrap(), b"token");
+        assert!(accepted.rx.recv().await.unwrap().is_none());
+    }
+}
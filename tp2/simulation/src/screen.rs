@@ -0,0 +1,324 @@
+use crate::ledger::{Ledger, Outcome};
+use ice_cream_shop::{
+    messages::{
+        gateway_msg::GatewayMsg,
+        protocol::{decode, Codec},
+        robot_msg::RobotMsg,
+        screen_msg::{Admission, ScreenMsg},
+    },
+    orders::{ClientOrder, Deadlines, Order, OrderId},
+    shop_values::N_ROBOTS,
+    stock::{StockBoard, StockCheck},
+    trace::TraceContext,
+};
+use robot::transport::{Connection, Endpoint, SharedTransport};
+use std::{
+    io,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc,
+    task,
+    time::{self, Instant},
+};
+
+/// How long a screen waits for a robot to answer an order before taking it as accepted.
+const ADMISSION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often a screen looks for orders whose deadline passed.
+const EXPIRE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A screen of the simulation. It takes the same steps as the real screen: it validates every
+/// order with the gateway, hands the valid ones to a robot and commits or cancels the payment
+/// once a robot tells it how the order ended. While the shop is busy, it turns orders away
+/// before capturing their payment, and so does it with the orders that ask for more servings
+/// than the robots last said were left, unless its stock check is off.
+/// If it has an order timeout, it gives up on the orders that don't end in time on the
+/// virtual clock. The orders don't carry their deadline, since the robots check it against
+/// the wall clock, which doesn't follow the virtual one.
+#[derive(Debug)]
+pub struct SimScreen {
+    id: u16,
+    transport: SharedTransport,
+    ledger: Ledger,
+    busy_until: Option<Instant>,
+    stock: Arc<Mutex<StockBoard>>,
+    stock_check: StockCheck,
+    order_timeout: Option<Duration>,
+    deadlines: Arc<Mutex<Deadlines>>,
+}
+
+impl SimScreen {
+    /// Creates a new screen with the given id.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the screen.
+    /// * `transport` - The transport of the screen node.
+    /// * `ledger` - Where the outcome of the orders is recorded.
+    /// * `stock_check` - What the screen does with orders that ask for more than is left.
+    pub fn new(
+        id: u16,
+        transport: SharedTransport,
+        ledger: Ledger,
+        stock_check: StockCheck,
+    ) -> Self {
+        Self {
+            id,
+            transport,
+            ledger,
+            busy_until: None,
+            stock: Arc::default(),
+            stock_check,
+            order_timeout: None,
+            deadlines: Arc::default(),
+        }
+    }
+
+    /// Sets how long the screen waits for an order to end before cancelling its payment.
+    pub fn with_order_timeout(mut self, order_timeout: Option<Duration>) -> Self {
+        self.order_timeout = order_timeout;
+        self
+    }
+
+    /// Starts receiving the results of the orders and then processes the given orders,
+    /// waiting the given interval between them.
+    ///
+    /// # Arguments
+    ///
+    /// * `orders` - The orders of the clients of the screen.
+    /// * `interval` - How long the screen waits between orders.
+    ///
+    /// # Returns
+    ///
+    /// An io::Result indicating if the screen could reach the gateway.
+    pub async fn run(mut self, orders: Vec<ClientOrder>, interval: Duration) -> io::Result<()> {
+        let mut listener = self.transport.listen(Endpoint::Screen(self.id)).await?;
+        let mut gateway = self.transport.connect(Endpoint::Gateway).await?;
+
+        let transport = self.transport.clone();
+        let (payments, mut to_settle) = mpsc::unbounded_channel();
+        task::spawn(async move {
+            let Ok(mut gateway) = transport.connect(Endpoint::Gateway).await else {
+                return;
+            };
+
+            while let Some(msg) = to_settle.recv().await {
+                let _ = gateway.tx.send(&Codec::default().encode(msg)).await;
+            }
+        });
+
+        let ledger = self.ledger.clone();
+        let stock = self.stock.clone();
+        let deadlines = self.deadlines.clone();
+        let received = payments.clone();
+        task::spawn(async move {
+            while let Ok(connection) = listener.accept().await {
+                let receiver = receive(
+                    connection,
+                    ledger.clone(),
+                    stock.clone(),
+                    deadlines.clone(),
+                    received.clone(),
+                );
+                task::spawn(receiver);
+            }
+        });
+
+        if self.order_timeout.is_some() {
+            let expired = expire(
+                self.id,
+                self.ledger.clone(),
+                self.deadlines.clone(),
+                payments,
+            );
+            task::spawn(expired);
+        }
+
+        for (number, order) in orders.into_iter().enumerate() {
+            let order_id = OrderId::new(self.id, number);
+            if self.busy_until.is_some_and(|until| Instant::now() < until) {
+                self.ledger.record(order_id, Outcome::Busy);
+                time::sleep(interval).await;
+                continue;
+            }
+
+            let shortage = lock(&self.stock).shortage(&order);
+            if shortage.is_some() && self.stock_check == StockCheck::Reject {
+                self.ledger.record(order_id, Outcome::OutOfStock);
+                time::sleep(interval).await;
+                continue;
+            }
+
+            let card_number = order.card_number.clone();
+            let msg = Codec::default().encode(GatewayMsg::CapturePayment(
+                order_id,
+                card_number,
+                TraceContext::default(),
+            ));
+
+            gateway.tx.send(&msg).await?;
+            let valid = gateway
+                .rx
+                .recv()
+                .await?
+                .is_some_and(|answer| answer == b"true");
+
+            if !valid {
+                self.ledger.record(order_id, Outcome::Rejected);
+            } else {
+                if let Some(timeout) = self.order_timeout {
+                    let deadline = (Instant::now() + timeout).into_std();
+                    lock(&self.deadlines).track(number, deadline, TraceContext::default());
+                }
+
+                let admission = self.notify_order(Order::from(order, self.id, number)).await;
+                if !matches!(admission, Some(Admission::Accepted(_))) {
+                    lock(&self.deadlines).forget(number);
+                }
+
+                match admission {
+                    Some(Admission::Accepted(_)) => {}
+                    Some(Admission::Busy(_)) => {
+                        self.ledger.record(order_id, Outcome::Busy);
+                        let msg = GatewayMsg::CancelPayment(order_id, TraceContext::default());
+                        gateway.tx.send(&Codec::default().encode(msg)).await?;
+                    }
+                    None => self.ledger.record(order_id, Outcome::Undelivered),
+                }
+            }
+
+            time::sleep(interval).await;
+        }
+
+        Ok(())
+    }
+
+    /// Hands the order to the first robot that takes it, starting from the same robot
+    /// the real screen would. If every robot that answers is busy, the screen stays busy
+    /// for the shortest wait they answered with.
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - The order to hand.
+    ///
+    /// # Returns
+    ///
+    /// How the robots answered, or `None` if no robot could be reached.
+    async fn notify_order(&mut self, order: Order) -> Option<Admission> {
+        let order_number = order.id().order_number();
+        let msg = Codec::default().encode(RobotMsg::RecvOrder(order));
+        let mut busy: Option<Duration> = None;
+
+        for offset in 0..N_ROBOTS {
+            let id = (order_number as u16 + offset) % N_ROBOTS;
+            let Ok(mut connection) = self.transport.connect(Endpoint::RobotOrders(id)).await else {
+                continue;
+            };
+            if connection.tx.send(&msg).await.is_err() {
+                continue;
+            }
+
+            let answer = time::timeout(ADMISSION_TIMEOUT, connection.rx.recv()).await;
+            let admission = match answer {
+                Ok(Ok(Some(frame))) => decode(&frame).ok(),
+                _ => None,
+            };
+
+            match admission.unwrap_or(Admission::Accepted(Duration::ZERO)) {
+                Admission::Busy(wait) => busy = Some(busy.map_or(wait, |busy| busy.min(wait))),
+                accepted => {
+                    self.busy_until = None;
+                    return Some(accepted);
+                }
+            }
+        }
+
+        let wait = busy?;
+        self.busy_until = Some(Instant::now() + wait);
+        Some(Admission::Busy(wait))
+    }
+}
+
+/// Receives a message sent to the screen through a connection, in a task of its own so that
+/// a robot that hangs before sending it doesn't hold up the others. The results of orders
+/// that already timed out are ignored.
+///
+/// # Arguments
+///
+/// * `connection` - The connection the message arrives through.
+/// * `ledger` - Where the outcome of the orders is recorded.
+/// * `stock` - The stock the screen heard of.
+/// * `deadlines` - The orders the screen is waiting on.
+/// * `payments` - Where the payments to commit or cancel are sent to the gateway.
+async fn receive(
+    mut connection: Connection,
+    ledger: Ledger,
+    stock: Arc<Mutex<StockBoard>>,
+    deadlines: Arc<Mutex<Deadlines>>,
+    payments: mpsc::UnboundedSender<GatewayMsg>,
+) {
+    let Ok(Some(frame)) = connection.rx.recv().await else {
+        return;
+    };
+
+    let msg = match decode(&frame) {
+        Ok(ScreenMsg::ConfirmOrder(order_id, _) | ScreenMsg::CancelOrder(order_id, _))
+            if !lock(&deadlines).settle(order_id.order_number()) =>
+        {
+            return;
+        }
+
+        Ok(ScreenMsg::ConfirmOrder(order_id, trace)) => {
+            ledger.record(order_id, Outcome::Confirmed);
+            GatewayMsg::CommitPayment(order_id, trace)
+        }
+
+        Ok(ScreenMsg::CancelOrder(order_id, trace)) => {
+            ledger.record(order_id, Outcome::Cancelled);
+            GatewayMsg::CancelPayment(order_id, trace)
+        }
+
+        Ok(ScreenMsg::Stock(summary)) => {
+            lock(&stock).update(summary);
+            return;
+        }
+
+        Err(_) => return,
+    };
+
+    let _ = payments.send(msg);
+}
+
+/// Gives up on the orders of a screen whose deadline passed, cancelling their payment.
+///
+/// # Arguments
+///
+/// * `id` - The id of the screen.
+/// * `ledger` - Where the outcome of the orders is recorded.
+/// * `deadlines` - The orders the screen is waiting on.
+/// * `payments` - Where the payments to cancel are sent to the gateway.
+async fn expire(
+    id: u16,
+    ledger: Ledger,
+    deadlines: Arc<Mutex<Deadlines>>,
+    payments: mpsc::UnboundedSender<GatewayMsg>,
+) {
+    loop {
+        time::sleep(EXPIRE_INTERVAL).await;
+        let expired = lock(&deadlines).expire(Instant::now().into_std());
+        for (number, trace) in expired {
+            let order_id = OrderId::new(id, number);
+            ledger.record(order_id, Outcome::TimedOut);
+            let _ = payments.send(GatewayMsg::CancelPayment(order_id, trace));
+        }
+    }
+}
+
+/// Locks state shared by the tasks of the screen, even if a task panicked while holding it.
+fn lock<T>(shared: &Mutex<T>) -> MutexGuard<'_, T> {
+    shared
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
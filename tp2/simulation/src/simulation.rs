@@ -0,0 +1,596 @@
+use crate::{
+    fault::{Fault, FaultPlan},
+    gateway,
+    ledger::{Ledger, Outcome, Outcomes},
+    network::{Node, SimNetwork},
+    screen::SimScreen,
+};
+use actix::prelude::*;
+use ice_cream_shop::{
+    flavour::Flavour,
+    messages::{
+        auth::{Keyring, Role},
+        protocol::Encoding,
+    },
+    orders::ClientOrder,
+    shop_values::{N_ROBOTS, N_SCREEN},
+    stock::StockCheck,
+};
+use rand::{rngs::StdRng, seq::IteratorRandom, Rng, SeedableRng};
+use robot::{
+    config::RobotConfig, detector::DetectorConfig, invariants::InvariantChecker, message::Drain,
+    robot::Robot, service::ServiceModel,
+};
+use std::{collections::BTreeMap, error::Error, fmt, time::Duration};
+use tokio::{
+    runtime, task,
+    time::{self, Instant},
+};
+
+/// A run of the whole shop in a single process, on a virtual clock.
+/// Robots, screens and the gateway talk through a simulated network, and the orders,
+/// the latencies and the faults are all drawn from the seed.
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    seed: u64,
+    robots: u16,
+    screens: u16,
+    orders: usize,
+    interval: Duration,
+    latency: (Duration, Duration),
+    faults: FaultPlan,
+    config: RobotConfig,
+    key: Option<String>,
+    stock_check: StockCheck,
+    order_timeout: Option<Duration>,
+    deadline: Duration,
+}
+
+/// What happened in a simulation.
+/// - `seed`: The seed of the simulation.
+/// - `screens`: The number of screens.
+/// - `orders`: The number of orders each screen had.
+/// - `outcomes`: The outcomes the screens saw for every order, by screen and order number.
+/// - `elapsed`: How long the simulation took on the virtual clock.
+/// - `delivered`: The number of messages the network delivered.
+/// - `faults`: The faults that were injected.
+/// - `violations`: The invariants of the tokens that were broken.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub seed: u64,
+    pub faults: FaultPlan,
+    pub screens: u16,
+    pub orders: usize,
+    pub outcomes: Outcomes,
+    pub elapsed: Duration,
+    pub delivered: u64,
+    pub violations: Vec<String>,
+}
+
+impl Simulation {
+    /// Creates a simulation with three robots, two screens with five orders each and no faults.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The seed the orders, the latencies and the random faults are drawn from.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            robots: 3,
+            screens: 2,
+            orders: 5,
+            interval: Duration::from_millis(500),
+            latency: (Duration::from_millis(1), Duration::from_millis(10)),
+            faults: FaultPlan::new(),
+            config: RobotConfig::default(),
+            key: None,
+            stock_check: StockCheck::default(),
+            order_timeout: None,
+            deadline: Duration::from_secs(600),
+        }
+    }
+
+    /// Sets the number of robots, up to `N_ROBOTS`.
+    pub fn with_robots(mut self, robots: u16) -> Self {
+        self.robots = robots.clamp(1, N_ROBOTS);
+        self
+    }
+
+    /// Sets the number of screens, up to `N_SCREEN`, and how many orders each one has.
+    pub fn with_screens(mut self, screens: u16, orders: usize) -> Self {
+        self.screens = screens.clamp(1, N_SCREEN);
+        self.orders = orders;
+        self
+    }
+
+    /// Sets how long each screen waits between two orders.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the minimum and maximum latency of a message.
+    pub fn with_latency(mut self, min: Duration, max: Duration) -> Self {
+        self.latency = (min, max.max(min));
+        self
+    }
+
+    /// Sets the faults of the simulation.
+    pub fn with_faults(mut self, faults: FaultPlan) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    /// Injects the given number of faults drawn from the seed.
+    pub fn with_random_faults(self, faults: usize) -> Self {
+        let plan = FaultPlan::random(self.seed, self.robots, faults);
+        self.with_faults(plan)
+    }
+
+    /// Sets how the robots decide that the next robot is dead.
+    pub fn with_detector(mut self, detector: DetectorConfig) -> Self {
+        self.config.detector = detector;
+        self
+    }
+
+    /// Sets how long the robots take to scoop.
+    pub fn with_service(mut self, service: ServiceModel) -> Self {
+        self.config.service = service;
+        self
+    }
+
+    /// Sets the encoding the robots prefer for the connections between them.
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.config.encoding = encoding;
+        self
+    }
+
+    /// Sets how many orders may wait in the order token before the robots refuse new ones.
+    pub fn with_max_queue(mut self, max_queue: usize) -> Self {
+        self.config.max_queue = Some(max_queue);
+        self
+    }
+
+    /// Sets the key every robot, screen and the gateway sign their frames with.
+    pub fn with_key(mut self, key: &str) -> Self {
+        self.key = Some(key.to_string());
+        self
+    }
+
+    /// Sets what the screens do with orders that ask for more servings than are left.
+    pub fn with_stock_check(mut self, stock_check: StockCheck) -> Self {
+        self.stock_check = stock_check;
+        self
+    }
+
+    /// Sets how long the screens wait for an order to end before cancelling its payment.
+    pub fn with_order_timeout(mut self, order_timeout: Duration) -> Self {
+        self.order_timeout = Some(order_timeout);
+        self
+    }
+
+    /// Sets how long the simulation waits for the orders to end on the virtual clock.
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Runs the simulation on a paused single-threaded runtime, so that time only moves
+    /// forward when every robot is waiting on the network or on a timer.
+    ///
+    /// # Returns
+    ///
+    /// The report of the simulation, or an error if a component couldn't start.
+    pub fn run(self) -> Result<Report, Box<dyn Error>> {
+        let runtime = || {
+            runtime::Builder::new_current_thread()
+                .enable_all()
+                .start_paused(true)
+                .build()
+                .expect("Couldn't build the simulation runtime")
+        };
+
+        System::with_tokio_rt(runtime).block_on(self.simulate())
+    }
+
+    /// Generates the orders of a screen from the seed.
+    /// Some cards start with a 3, so that the gateway rejects them.
+    fn client_orders(&self, rng: &mut StdRng) -> Vec<ClientOrder> {
+        (0..self.orders)
+            .map(|_| {
+                let n_flavours = rng.gen_range(1..=3);
+                let flavours = Flavour::flavours()
+                    .choose_multiple(rng, n_flavours)
+                    .into_iter()
+                    .map(|flavour| (flavour, rng.gen_range(1..=2)))
+                    .collect();
+
+                let first_digit = match rng.gen_bool(0.15) {
+                    true => 3,
+                    false => rng.gen_range(4..=9),
+                };
+
+                ClientOrder {
+                    flavours,
+                    card_number: format!("{first_digit}666-1111-2222-3333"),
+                }
+            })
+            .collect()
+    }
+
+    async fn simulate(mut self) -> Result<Report, Box<dyn Error>> {
+        let checker = InvariantChecker::default();
+        self.config.invariants = Some(checker.clone());
+        let network = SimNetwork::new(self.seed, self.latency);
+        let ledger = Ledger::default();
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let start = Instant::now();
+
+        let key = self.key.clone();
+        let keyring = move |role| key.as_ref().map(|key| Keyring::new(key, role));
+
+        let transport = network.transport(Node::Gateway);
+        gateway::start(transport.authenticated(keyring(Role::Gateway))).await?;
+
+        let mut robots = BTreeMap::new();
+        for id in 0..self.robots {
+            let transport = network.transport(Node::Robot(id));
+            let transport = transport.authenticated(keyring(Role::Robot));
+            let (addr, _) = Robot::start(id, transport, self.config.clone()).await?;
+            robots.insert(id, addr);
+        }
+
+        for id in 0..self.screens {
+            let orders = self.client_orders(&mut rng);
+            let transport = network.transport(Node::Screen(id));
+            let transport = transport.authenticated(keyring(Role::Screen));
+            let screen = SimScreen::new(id, transport, ledger.clone(), self.stock_check)
+                .with_order_timeout(self.order_timeout);
+            task::spawn(screen.run(orders, self.interval));
+        }
+
+        let faults = self.faults.clone();
+        let injector = network.clone();
+        let config = self.config.clone();
+        task::spawn_local(async move {
+            for (at, fault) in faults.faults() {
+                time::sleep_until(start + *at).await;
+                match *fault {
+                    Fault::Crash(id) => injector.crash(Node::Robot(id)),
+                    Fault::Restart(id) => {
+                        let transport = injector.restart(Node::Robot(id));
+                        let transport = transport.authenticated(keyring(Role::Robot));
+                        if let Ok((addr, _)) = Robot::start(id, transport, config.clone()).await {
+                            robots.insert(id, addr);
+                        }
+                    }
+                    Fault::Partition(a, b) => injector.partition(Node::Robot(a), Node::Robot(b)),
+                    Fault::Hang(id) => injector.hang(Node::Robot(id)),
+                    Fault::Wake(id) => injector.wake(Node::Robot(id)),
+                    Fault::Heal => injector.heal(),
+                    Fault::Drain(id) => {
+                        if let Some(robot) = robots.get(&id) {
+                            robot.do_send(Drain);
+                        }
+                    }
+                }
+            }
+        });
+
+        let orders = self.orders * self.screens as usize;
+        while ledger.resolved() < orders && start.elapsed() < self.deadline {
+            time::sleep(Duration::from_millis(100)).await;
+        }
+
+        Ok(Report {
+            seed: self.seed,
+            faults: self.faults,
+            screens: self.screens,
+            orders: self.orders,
+            outcomes: ledger.snapshot(),
+            elapsed: start.elapsed(),
+            delivered: network.delivered(),
+            violations: checker
+                .violations()
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+        })
+    }
+}
+
+impl Report {
+    /// Returns what went wrong in the simulation: orders that never ended, that ended more
+    /// than once or that couldn't be handed to any robot, and invariants of the tokens that
+    /// were broken.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for screen in 0..self.screens {
+            for number in 0..self.orders {
+                if !self.outcomes.contains_key(&(screen, number)) {
+                    problems.push(format!("Order {number} of screen {screen} never ended"));
+                }
+            }
+        }
+
+        for ((screen, number), outcomes) in &self.outcomes {
+            if outcomes.len() > 1 {
+                problems.push(format!(
+                    "Order {number} of screen {screen} ended {} times: {outcomes:?}",
+                    outcomes.len()
+                ));
+            }
+
+            if outcomes.contains(&Outcome::Undelivered) {
+                problems.push(format!(
+                    "Order {number} of screen {screen} couldn't be handed to any robot"
+                ));
+            }
+        }
+
+        problems.extend(self.violations.iter().cloned());
+        problems
+    }
+
+    /// Returns the number of orders that ended with the given outcome.
+    pub fn count(&self, outcome: Outcome) -> usize {
+        self.outcomes
+            .values()
+            .filter(|outcomes| outcomes.first() == Some(&outcome))
+            .count()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Seed {}: {} orders in {:.1}s, {} messages",
+            self.seed,
+            self.orders * self.screens as usize,
+            self.elapsed.as_secs_f64(),
+            self.delivered
+        )?;
+
+        for (at, fault) in self.faults.faults() {
+            writeln!(f, "  {:.1}s: {fault:?}", at.as_secs_f64())?;
+        }
+
+        for outcome in [
+            Outcome::Confirmed,
+            Outcome::Cancelled,
+            Outcome::Rejected,
+            Outcome::Busy,
+            Outcome::OutOfStock,
+            Outcome::TimedOut,
+        ] {
+            writeln!(f, "  {outcome:?}: {}", self.count(outcome))?;
+        }
+
+        for problem in self.problems() {
+            writeln!(f, "  {problem}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_every_order_ends_once(report: &Report) {
+        assert_eq!(report.problems(), Vec::<String>::new(), "{report}");
+    }
+
+    #[test]
+    fn test01_without_faults_every_order_ends_once() {
+        let report = Simulation::new(1).run().unwrap();
+        assert_every_order_ends_once(&report);
+        assert!(report.count(Outcome::Confirmed) > 0);
+    }
+
+    #[test]
+    fn test02_the_orders_of_a_crashed_robot_are_recovered() {
+        let faults = FaultPlan::new().with(Duration::from_secs(3), Fault::Crash(1));
+        let report = Simulation::new(2)
+            .with_robots(4)
+            .with_faults(faults)
+            .run()
+            .unwrap();
+
+        assert_every_order_ends_once(&report);
+    }
+
+    #[test]
+    fn test03_a_draining_robot_hands_its_orders_back() {
+        let faults = FaultPlan::new().with(Duration::from_secs(3), Fault::Drain(1));
+        let report = Simulation::new(3).with_faults(faults).run().unwrap();
+
+        assert_every_order_ends_once(&report);
+    }
+
+    #[test]
+    fn test04_partitions_only_delay_the_orders() {
+        let faults = FaultPlan::new()
+            .with(Duration::from_secs(2), Fault::Partition(0, 1))
+            .with(Duration::from_secs(4), Fault::Heal);
+        let report = Simulation::new(4).with_faults(faults).run().unwrap();
+
+        assert_every_order_ends_once(&report);
+    }
+
+    #[test]
+    fn test05_every_order_ends_once_with_random_faults() {
+        for seed in 0..4 {
+            let report = Simulation::new(seed)
+                .with_robots(5)
+                .with_screens(3, 4)
+                .with_random_faults(3)
+                .run()
+                .unwrap();
+
+            assert_every_order_ends_once(&report);
+        }
+    }
+
+    #[test]
+    fn test06_a_restarted_robot_rejoins_the_ring() {
+        let faults = FaultPlan::new()
+            .with(Duration::from_secs(3), Fault::Crash(1))
+            .with(Duration::from_millis(3_050), Fault::Restart(1))
+            .with(Duration::from_secs(8), Fault::Crash(2))
+            .with(Duration::from_secs(12), Fault::Restart(2));
+        let report = Simulation::new(5)
+            .with_robots(4)
+            .with_faults(faults)
+            .run()
+            .unwrap();
+
+        assert_every_order_ends_once(&report);
+    }
+
+    #[test]
+    fn test07_a_hung_robot_is_bypassed() {
+        let faults = FaultPlan::new()
+            .with(Duration::from_secs(3), Fault::Hang(1))
+            .with(Duration::from_secs(40), Fault::Wake(1));
+        let report = Simulation::new(7)
+            .with_robots(4)
+            .with_faults(faults)
+            .with_deadline(Duration::from_secs(35))
+            .run()
+            .unwrap();
+
+        assert_every_order_ends_once(&report);
+    }
+
+    #[test]
+    fn test08_more_robots_serve_the_orders_sooner() {
+        let run = |robots| {
+            Simulation::new(8)
+                .with_robots(robots)
+                .with_screens(3, 4)
+                .with_interval(Duration::ZERO)
+                .run()
+                .unwrap()
+        };
+
+        let (one, four) = (run(1), run(4));
+        assert_every_order_ends_once(&four);
+        assert!(four.elapsed * 2 < one.elapsed, "{one}{four}");
+    }
+
+    #[test]
+    fn test09_the_same_seed_gives_the_same_outcomes() {
+        let run = || Simulation::new(6).with_random_faults(2).run().unwrap();
+        assert_eq!(run().outcomes, run().outcomes);
+    }
+
+    #[test]
+    fn test10_faster_scoops_serve_the_orders_sooner() {
+        let run = |service| {
+            Simulation::new(10)
+                .with_screens(2, 4)
+                .with_interval(Duration::ZERO)
+                .with_service(service)
+                .run()
+                .unwrap()
+        };
+
+        let usual = run(ServiceModel::default());
+        let fast = run(ServiceModel::default()
+            .with_jitter(0.2)
+            .with_acceleration(4.0));
+        assert_every_order_ends_once(&fast);
+        assert!(fast.elapsed * 2 < usual.elapsed, "{usual}{fast}");
+    }
+
+    #[test]
+    fn test11_robots_can_talk_in_binary() {
+        let faults = FaultPlan::new().with(Duration::from_secs(3), Fault::Crash(1));
+        let report = Simulation::new(11)
+            .with_robots(4)
+            .with_encoding(Encoding::Binary)
+            .with_faults(faults)
+            .run()
+            .unwrap();
+
+        assert_every_order_ends_once(&report);
+        assert!(report.count(Outcome::Confirmed) > 0);
+    }
+
+    #[test]
+    fn test12_a_shop_with_a_key_serves_every_order() {
+        let faults = FaultPlan::new().with(Duration::from_secs(3), Fault::Crash(1));
+        let report = Simulation::new(12)
+            .with_robots(4)
+            .with_key("secret")
+            .with_encoding(Encoding::Binary)
+            .with_faults(faults)
+            .run()
+            .unwrap();
+
+        assert_every_order_ends_once(&report);
+        assert!(report.count(Outcome::Confirmed) > 0);
+    }
+
+    #[test]
+    fn test13_a_full_queue_turns_orders_away() {
+        let simulation = Simulation::new(13)
+            .with_robots(2)
+            .with_screens(3, 10)
+            .with_interval(Duration::from_millis(100));
+
+        let unlimited = simulation.clone().run().unwrap();
+        assert_every_order_ends_once(&unlimited);
+        assert_eq!(unlimited.count(Outcome::Busy), 0);
+
+        let limited = simulation.with_max_queue(2).run().unwrap();
+        assert_every_order_ends_once(&limited);
+        assert!(limited.count(Outcome::Busy) > 0, "{limited}");
+        assert!(limited.count(Outcome::Confirmed) > 0);
+        assert!(limited.elapsed < unlimited.elapsed);
+    }
+
+    #[test]
+    fn test14_orders_for_flavours_that_ran_out_are_turned_away() {
+        let simulation = Simulation::new(14)
+            .with_screens(3, 20)
+            .with_service(ServiceModel::default().with_per_serving(Duration::from_millis(100)));
+
+        let unchecked = simulation
+            .clone()
+            .with_stock_check(StockCheck::Off)
+            .run()
+            .unwrap();
+        assert_every_order_ends_once(&unchecked);
+        assert_eq!(unchecked.count(Outcome::OutOfStock), 0);
+
+        let checked = simulation.run().unwrap();
+        assert_every_order_ends_once(&checked);
+        assert!(checked.count(Outcome::OutOfStock) > 0, "{checked}");
+        assert!(
+            checked.count(Outcome::Cancelled) < unchecked.count(Outcome::Cancelled),
+            "{checked}{unchecked}"
+        );
+    }
+
+    #[test]
+    fn test15_orders_of_a_hung_robot_time_out_once() {
+        let faults = FaultPlan::new()
+            .with(Duration::from_secs(3), Fault::Hang(1))
+            .with(Duration::from_secs(40), Fault::Wake(1));
+        let report = Simulation::new(7)
+            .with_robots(4)
+            .with_faults(faults)
+            .with_order_timeout(Duration::from_secs(4))
+            .with_deadline(Duration::from_secs(35))
+            .run()
+            .unwrap();
+
+        assert_every_order_ends_once(&report);
+        assert!(report.count(Outcome::TimedOut) > 0, "{report}");
+        assert!(report.count(Outcome::Confirmed) > 0);
+    }
+}